@@ -0,0 +1,192 @@
+//! Provides `#[derive(CType)]` for [libffi](https://docs.rs/libffi).
+//!
+//! Enable it through the `derive` feature of `libffi`, which re-exports
+//! the macro as `libffi::high::CType`, next to the trait it implements:
+//!
+//! ```toml
+//! [dependencies]
+//! libffi = { version = "3.2.0", features = ["derive"] }
+//! ```
+//!
+//! The derived implementation describes the struct to libffi as a
+//! structure of its fields’ types, in order, so the struct can be
+//! passed and returned by value:
+//!
+//!   - fields whose types implement `CType`, including pointers and
+//!     other derived structs, are described by `Type::of`;
+//!   - array fields, which may be nested, are described by
+//!     `Type::array`.
+//!
+//! The struct must be `#[repr(C)]`, not packed or over-aligned, and
+//! must have at least one field; anything else is a compile error.
+//! Since `CType` requires `Copy`, the struct must also derive `Clone`
+//! and `Copy`.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::high::{CType, Closure1};
+//!
+//! #[derive(Clone, Copy, CType)]
+//! #[repr(C)]
+//! struct Point {
+//!     x: f64,
+//!     y: f64,
+//! }
+//!
+//! #[derive(Clone, Copy, CType)]
+//! #[repr(C)]
+//! struct Polygon {
+//!     corners: [Point; 3],
+//!     name: *const u8,
+//! }
+//!
+//! let width = |p: Polygon| {
+//!     let xs = p.corners.iter().map(|c| c.x);
+//!     xs.clone().fold(f64::MIN, f64::max) - xs.fold(f64::MAX, f64::min)
+//! };
+//! let closure = Closure1::new(&width);
+//!
+//! let corner = |x| Point { x, y: 0.0 };
+//! let triangle = Polygon {
+//!     corners: [corner(1.0), corner(4.5), corner(2.0)],
+//!     name: b"triangle\0".as_ptr(),
+//! };
+//! assert_eq!(3.5, closure.code_ptr().call(triangle));
+//! ```
+//!
+//! A struct without `#[repr(C)]` is rejected, since Rust may reorder
+//! its fields:
+//!
+//! ```compile_fail
+//! use libffi::high::CType;
+//!
+//! #[derive(Clone, Copy, CType)]
+//! struct Point {
+//!     x: f64,
+//!     y: f64,
+//! }
+//! ```
+//!
+//! So is a packed struct, whose layout libffi cannot describe:
+//!
+//! ```compile_fail
+//! use libffi::high::CType;
+//!
+//! #[derive(Clone, Copy, CType)]
+//! #[repr(C, packed)]
+//! struct Header {
+//!     tag: u8,
+//!     len: u32,
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Type};
+
+/// Derives `libffi::high::CType` for a `#[repr(C)]` struct.
+///
+/// See [the crate documentation](crate) for details.
+#[proc_macro_derive(CType)]
+pub fn derive_ctype(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    check_repr(input)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            Fields::Unnamed(fields) => &fields.unnamed,
+            Fields::Unit => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "#[derive(CType)] requires a struct with at least one field",
+                ))
+            }
+        },
+        Data::Enum(data) => {
+            return Err(Error::new(
+                data.enum_token.span,
+                "#[derive(CType)] only supports structs",
+            ))
+        }
+        Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "#[derive(CType)] only supports structs",
+            ))
+        }
+    };
+    if fields.is_empty() {
+        return Err(Error::new(
+            input.ident.span(),
+            "#[derive(CType)] requires a struct with at least one field",
+        ));
+    }
+
+    let types = fields.iter().map(|field| describe(&field.ty));
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        #[automatically_derived]
+        unsafe impl #impl_generics ::libffi::high::CType for #name #ty_generics #where_clause {
+            fn reify() -> ::libffi::high::Type<Self> {
+                // The struct is `#[repr(C)]`, and its fields are described
+                // in order.
+                unsafe { ::libffi::high::Type::structure(::std::vec![#(#types),*]) }
+            }
+            type RetType = Self;
+        }
+    })
+}
+
+// Checks that the struct is `#[repr(C)]` with its natural layout.
+fn check_repr(input: &DeriveInput) -> syn::Result<()> {
+    let mut is_c = false;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("repr") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C") {
+                is_c = true;
+                Ok(())
+            } else if meta.path.is_ident("packed") || meta.path.is_ident("align") {
+                Err(meta.error("#[derive(CType)] cannot describe packed or over-aligned structs"))
+            } else {
+                Ok(())
+            }
+        })?;
+    }
+
+    if is_c {
+        Ok(())
+    } else {
+        Err(Error::new(
+            input.ident.span(),
+            "#[derive(CType)] requires #[repr(C)]",
+        ))
+    }
+}
+
+// Builds an expression for the `middle::Type` of a field.
+fn describe(ty: &Type) -> TokenStream2 {
+    match ty {
+        Type::Array(array) => {
+            let element = describe(&array.elem);
+            let len = &array.len;
+            quote! { ::libffi::middle::Type::array(#element, #len) }
+        }
+        Type::Group(group) => describe(&group.elem),
+        Type::Paren(paren) => describe(&paren.elem),
+        _ => quote! { ::libffi::middle::Type::of::<#ty>() },
+    }
+}
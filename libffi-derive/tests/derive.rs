@@ -0,0 +1,94 @@
+use libffi::ffi_call;
+use libffi::high::{CType, Closure1, Closure2};
+use libffi::middle::Type;
+
+#[derive(Clone, Copy, Debug, PartialEq, CType)]
+#[repr(C)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, CType)]
+#[repr(C)]
+struct Segment {
+    from: Point,
+    to: Point,
+    label: *const u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, CType)]
+#[repr(C)]
+struct Grid {
+    tag: u8,
+    cells: [[u16; 3]; 2],
+    scale: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, CType)]
+#[repr(C)]
+struct Wrapped<T: CType>(T, u8);
+
+extern "C" fn flip(segment: Segment) -> Segment {
+    Segment {
+        from: segment.to,
+        to: segment.from,
+        label: segment.label,
+    }
+}
+
+extern "C" fn total(grid: Grid) -> f32 {
+    let sum: u16 = grid.cells.iter().flatten().sum();
+    f32::from(sum) * grid.scale + f32::from(grid.tag)
+}
+
+#[test]
+fn layouts() {
+    assert_eq!(
+        "{{i32, i32}, {i32, i32}, pointer}",
+        Type::of::<Segment>().to_string()
+    );
+    assert_eq!(
+        "{u8, {{u16, u16, u16}, {u16, u16, u16}}, f32}",
+        Type::of::<Grid>().to_string()
+    );
+    assert_eq!("{f64, u8}", Type::of::<Wrapped<f64>>().to_string());
+}
+
+#[test]
+fn call_with_derived_structs() {
+    let label = b"diagonal\0".as_ptr();
+    let segment = Segment {
+        from: Point { x: 1, y: 2 },
+        to: Point { x: 3, y: 4 },
+        label,
+    };
+    let flipped = ffi_call!(flip: extern "C" fn(Segment) -> Segment, segment);
+    assert_eq!(Point { x: 3, y: 4 }, flipped.from);
+    assert_eq!(Point { x: 1, y: 2 }, flipped.to);
+    assert_eq!(label, flipped.label);
+
+    let grid = Grid {
+        tag: 1,
+        cells: [[1, 2, 3], [4, 5, 6]],
+        scale: 0.5,
+    };
+    assert_eq!(11.5, ffi_call!(total: extern "C" fn(Grid) -> f32, grid));
+}
+
+#[test]
+fn closures_with_derived_structs() {
+    let shift = |p: Point, by: i32| Point {
+        x: p.x + by,
+        y: p.y - by,
+    };
+    let closure = Closure2::new(&shift);
+    assert_eq!(
+        Point { x: 12, y: -8 },
+        closure.code_ptr().call(Point { x: 2, y: 2 }, 10)
+    );
+
+    let swap = |w: Wrapped<f64>| Wrapped(-w.0, w.1 + 1);
+    let closure = Closure1::new(&swap);
+    assert_eq!(Wrapped(-1.5, 8), closure.code_ptr().call(Wrapped(1.5, 7)));
+}
@@ -0,0 +1,92 @@
+//! Benchmarks for creating and calling closures.
+//!
+//! Run with `cargo bench -p libffi`.
+
+use std::os::raw::c_void;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use libffi::high::{Closure1, ClosureOnce1};
+use libffi::low::ffi_cif;
+use libffi::middle::{Cif, Closure, ClosurePool, Type};
+
+unsafe extern "C" fn add(
+    _cif: &ffi_cif,
+    result: &mut u64,
+    args: *const *const c_void,
+    offset: &u64,
+) {
+    *result = *(*args as *const u64) + *offset;
+}
+
+fn create(c: &mut Criterion) {
+    let mut group = c.benchmark_group("create");
+
+    group.bench_function("borrowed", |b| {
+        let offset = 5u64;
+        let f = move |x: u64| x + offset;
+        b.iter(|| black_box(Closure1::new(&f)));
+    });
+
+    // Small environments are stored in the closure’s own allocation.
+    group.bench_function("once/inline", |b| {
+        b.iter(|| {
+            let offset = black_box(5u64);
+            black_box(ClosureOnce1::new(move |x: u64| x + offset))
+        });
+    });
+
+    group.bench_function("once/boxed", |b| {
+        b.iter(|| {
+            let table = black_box([5u64; 16]);
+            black_box(ClosureOnce1::new(move |x: u64| x + table[0]))
+        });
+    });
+
+    // Each closure is created with its own CIF, with a new allocation or
+    // one recycled by a pool.
+    let cif = Cif::new(vec![Type::u64()], Type::u64());
+    let offset = 5u64;
+    group.bench_function("middle", |b| {
+        b.iter(|| black_box(Closure::new(cif.clone(), add, &offset)));
+    });
+
+    group.bench_function("middle/pooled", |b| {
+        let pool = ClosurePool::new(1);
+        b.iter(|| black_box(pool.closure(cif.clone(), add, &offset)));
+    });
+
+    // Batches of closures that are alive at once, as for callbacks of
+    // requests in flight.
+    group.bench_function("middle/batch", |b| {
+        b.iter(|| {
+            let batch: Vec<_> = (0..100)
+                .map(|_| Closure::new(cif.clone(), add, &offset))
+                .collect();
+            black_box(batch)
+        });
+    });
+
+    group.bench_function("middle/batch/pooled", |b| {
+        let pool = ClosurePool::new(100);
+        b.iter(|| {
+            let batch: Vec<_> = (0..100)
+                .map(|_| pool.closure(cif.clone(), add, &offset))
+                .collect();
+            black_box(batch)
+        });
+    });
+
+    group.finish();
+}
+
+fn call(c: &mut Criterion) {
+    let offset = 5u64;
+    let f = move |x: u64| x + offset;
+    let closure = Closure1::new(&f);
+    let fun = closure.code_ptr();
+
+    c.bench_function("call", |b| b.iter(|| fun.call(black_box(6))));
+}
+
+criterion_group!(benches, create, call);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+/* C interface to the libffi crate, enabled by its `capi` feature.
+ *
+ * See the documentation of the crate's `capi` module for details. */
+
+#ifndef LIBFFI_RS_H
+#define LIBFFI_RS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct libffi_rs_type libffi_rs_type;
+typedef struct libffi_rs_cif libffi_rs_cif;
+typedef struct libffi_rs_closure libffi_rs_closure;
+
+typedef void (*libffi_rs_callback)(void *result, void **args, void *userdata);
+
+libffi_rs_type *libffi_rs_type_parse(const char *text);
+libffi_rs_type *libffi_rs_type_struct(const libffi_rs_type *const *fields, size_t nfields);
+void libffi_rs_type_layout(const libffi_rs_type *type, size_t *size, size_t *align);
+void libffi_rs_type_free(libffi_rs_type *type);
+
+libffi_rs_cif *libffi_rs_cif_new(const libffi_rs_type *const *args, size_t nargs,
+                                 const libffi_rs_type *result);
+libffi_rs_cif *libffi_rs_cif_parse(const char *signature);
+void libffi_rs_cif_free(libffi_rs_cif *cif);
+
+void libffi_rs_call(const libffi_rs_cif *cif, void (*fun)(void), void *result, void **args);
+
+libffi_rs_closure *libffi_rs_closure_new(const libffi_rs_cif *cif, libffi_rs_callback callback,
+                                         void *userdata);
+void *libffi_rs_closure_code(const libffi_rs_closure *closure);
+void libffi_rs_closure_free(libffi_rs_closure *closure);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* LIBFFI_RS_H */
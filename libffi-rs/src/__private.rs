@@ -0,0 +1,176 @@
+//! Support code for the expansions of this crate’s exported macros.
+//!
+//! This module is not part of the public API, but it is a stable
+//! contract for generated code. Macros such as [`ffi_call!`] and
+//! [`dynamic_extern!`] expand to code that calls into it, and so may
+//! companion crates, such as procedural macros, whose expansions are
+//! compiled against whichever version of this crate the downstream crate
+//! uses. To keep those expansions compiling:
+//!
+//!  - Expanded code refers only to items of a versioned submodule, such
+//!    as [`v1`], by their full `$crate::__private::v1::` path (or
+//!    `::libffi::__private::v1::` from another crate), and never to
+//!    anything else in this module.
+//!  - The items of a versioned submodule keep their names, signatures
+//!    and behavior for the rest of the crate’s major version. Items may
+//!    be added to it, but not removed or changed.
+//!  - An incompatible change adds a new submodule, `v2` and so on,
+//!    alongside the old one, which is kept until the next major version.
+//!
+//! The signatures of [`v1`] are pinned by the tests below.
+//!
+//! [`ffi_call!`]: crate::ffi_call
+//! [`dynamic_extern!`]: crate::dynamic_extern
+
+/// Version 1 of the contract for generated code.
+pub mod v1 {
+    use std::io::{self, Write};
+    use std::process;
+
+    pub use std::sync::OnceLock;
+
+    /// Runs `body`, aborting the process if it panics.
+    ///
+    /// Unwinding out of an `extern "C"` callback into C is undefined
+    /// behavior, so callbacks generated by macros run their bodies through
+    /// this function.
+    pub fn abort_on_panic<R>(msg: &str, body: impl FnOnce() -> R) -> R {
+        // Aborts when dropped (which will only happen due to an unwinding panic).
+        struct Bomb<'a>(&'a str);
+        impl Drop for Bomb<'_> {
+            fn drop(&mut self) {
+                let _ = writeln!(io::stderr(), "{}", self.0);
+                process::abort();
+            }
+        }
+
+        let bomb = Bomb(msg);
+        let result = body();
+        std::mem::forget(bomb);
+        result
+    }
+
+    /// Converts a value returned by libffi back to the declared result type.
+    ///
+    /// See [`CType::RetType`](crate::high::CType::RetType).
+    pub fn from_ret_type<R: crate::high::CType>(value: R::RetType) -> R {
+        std::convert::TryInto::try_into(value).ok().unwrap()
+    }
+
+    /// Gives an argument of [`ffi_call!`](crate::ffi_call) its declared
+    /// type.
+    pub fn typed<T>(value: T) -> T {
+        value
+    }
+
+    /// Tuples of argument values, which can be passed by reference.
+    pub trait ArgTuple {
+        /// Gets references to each of the values, in order.
+        fn args(&self) -> Vec<crate::middle::Arg>;
+    }
+
+    macro_rules! impl_arg_tuple {
+        ( $( $n:tt $T:ident ),* ) => {
+            impl<$( $T ),*> ArgTuple for ($( $T, )*) {
+                fn args(&self) -> Vec<crate::middle::Arg> {
+                    vec![$( crate::middle::Arg::val(&self.$n) ),*]
+                }
+            }
+        };
+    }
+
+    impl_arg_tuple!();
+    impl_arg_tuple!(0 A);
+    impl_arg_tuple!(0 A, 1 B);
+    impl_arg_tuple!(0 A, 1 B, 2 C);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D, 4 E);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H, 8 I);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H, 8 I, 9 J);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H, 8 I, 9 J, 10 K);
+    impl_arg_tuple!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H, 8 I, 9 J, 10 K, 11 L);
+
+    /// Creates the CIF for a function with the given argument types and
+    /// result type `R`.
+    pub fn cif_for<R: crate::high::CType>(args: Vec<crate::middle::Type>) -> crate::middle::Cif {
+        crate::middle::Cif::new(args, R::reify().into_middle())
+    }
+
+    /// Calls `fun` through `cif`, converting the result back to `R`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`](crate::middle::Cif::call).
+    pub unsafe fn call_cif<R: crate::high::CType>(
+        cif: &crate::middle::Cif,
+        fun: crate::middle::CodePtr,
+        args: &[crate::middle::Arg],
+    ) -> R {
+        from_ret_type(cif.call::<R::RetType>(fun, args))
+    }
+
+    /// The size of the signature table of `entries`, as
+    /// [`signature_table!`](crate::signature_table) declares it.
+    pub const fn signature_table_len(entries: &[(&str, &str)]) -> usize {
+        crate::plugin::encoded_len(entries)
+    }
+
+    /// Encodes the signature table of `entries`, whose size `N` is given
+    /// by [`signature_table_len`].
+    pub const fn signature_table<const N: usize>(entries: &[(&str, &str)]) -> [u8; N] {
+        crate::plugin::encode(entries)
+    }
+
+    /// Loads the library `name`, or returns the copy loaded by an earlier
+    /// call.
+    ///
+    /// Libraries loaded this way are never unloaded, so the functions bound
+    /// by [`dynamic_extern!`](crate::dynamic_extern) remain valid for the
+    /// rest of the program.
+    #[cfg(any(all(unix, feature = "libc"), windows))]
+    pub fn load_library(name: &str) -> crate::library::Result<crate::library::Library> {
+        use crate::library::Library;
+        use std::collections::HashMap;
+        use std::sync::{Mutex, OnceLock};
+
+        static LIBRARIES: OnceLock<Mutex<HashMap<String, Library>>> = OnceLock::new();
+
+        let mut libraries = LIBRARIES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(library) = libraries.get(name) {
+            return Ok(library.clone());
+        }
+
+        let library = unsafe { Library::open(name) }?;
+        libraries.insert(name.to_owned(), library.clone());
+        Ok(library)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::v1;
+    use crate::middle::{Arg, Cif, CodePtr, Type};
+
+    // Each line fixes the signature of an item of `v1`; if one fails to
+    // compile, the change must go in a new version instead.
+    #[test]
+    fn v1_signatures() {
+        let _: fn(&str, fn() -> u8) -> u8 = |msg, body| v1::abort_on_panic(msg, body);
+        let _: fn(u64) -> u8 = v1::from_ret_type::<u8>;
+        let _: fn(u8) -> u8 = v1::typed::<u8>;
+        let _: fn(&(u8, i32)) -> Vec<Arg> = <(u8, i32) as v1::ArgTuple>::args;
+        let _: fn(Vec<Type>) -> Cif = v1::cif_for::<u8>;
+        let _: unsafe fn(&Cif, CodePtr, &[Arg]) -> u8 = v1::call_cif::<u8>;
+        #[cfg(any(all(unix, feature = "libc"), windows))]
+        let _: fn(&str) -> crate::library::Result<crate::library::Library> = v1::load_library;
+        let _: v1::OnceLock<u8> = v1::OnceLock::new();
+        let _: fn(&[(&str, &str)]) -> usize = v1::signature_table_len;
+        let _: fn(&[(&str, &str)]) -> [u8; 20] = v1::signature_table::<20>;
+    }
+}
@@ -0,0 +1,390 @@
+//! A C interface to the middle layer, for embedding the crate in other
+//! languages.
+//!
+//! This module is enabled by `#[cfg(feature = "capi")]`.
+//!
+//! The functions here let a C host describe types, prepare CIFs, make
+//! calls and create closures through this crate, with its type
+//! descriptions and memory management, rather than through C libffi
+//! directly. They are declared in `include/libffi_rs.h`, and are
+//! exported unmangled, so building the crate as a C library exposes
+//! them:
+//!
+//! ```sh
+//! cargo rustc -p libffi --release --features capi --crate-type cdylib
+//! ```
+//!
+//! Types, CIFs and closures are opaque handles that the host frees
+//! with the matching `_free` function. Functions that create a handle
+//! return NULL on failure, including when a panic would otherwise
+//! cross into C, and never take ownership of the handles they are
+//! given: a CIF keeps its own copies of its types, and a closure of its
+//! CIF.
+//!
+//! # Examples
+//!
+//! ```c
+//! #include <libffi_rs.h>
+//!
+//! static void add(void *result, void **args, void *userdata) {
+//!     *(int64_t *)result = *(int64_t *)args[0] + *(int64_t *)userdata;
+//! }
+//!
+//! int64_t offset = 10;
+//! libffi_rs_cif *cif = libffi_rs_cif_parse("(i64) -> i64");
+//! libffi_rs_closure *closure = libffi_rs_closure_new(cif, add, &offset);
+//! int64_t (*fun)(int64_t) = (int64_t (*)(int64_t))libffi_rs_closure_code(closure);
+//! int64_t twelve = fun(2);
+//! libffi_rs_closure_free(closure);
+//! libffi_rs_cif_free(cif);
+//! ```
+
+#![allow(non_camel_case_types)]
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use crate::low;
+use crate::middle::{Cif, ClosureOwned, CodePtr, Type};
+
+/// The callback of a closure created with [`libffi_rs_closure_new`]:
+/// `void (*)(void *result, void **args, void *userdata)`.
+///
+/// It receives libffi’s result buffer and argument array, as a
+/// [`low::Callback`] does, and the closure’s userdata.
+pub type libffi_rs_callback =
+    unsafe extern "C" fn(result: *mut c_void, args: *mut *mut c_void, userdata: *mut c_void);
+
+/// A closure created with [`libffi_rs_closure_new`].
+pub struct libffi_rs_closure {
+    closure: ClosureOwned<Userdata>,
+}
+
+// The callback of a closure and the host’s userdata for it.
+struct Userdata {
+    callback: libffi_rs_callback,
+    userdata: *mut c_void,
+}
+
+// Runs `f`, turning a panic into `None` so that it does not unwind
+// into the host.
+fn guard<T>(f: impl FnOnce() -> Option<T>) -> Option<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).ok().flatten()
+}
+
+// Moves `value` to the heap for the host, or returns NULL.
+fn into_handle<T>(value: Option<T>) -> *mut T {
+    value.map_or(ptr::null_mut(), |value| Box::into_raw(Box::new(value)))
+}
+
+// Clones the `n` types at `types`.
+unsafe fn clone_types(types: *const *const Type, n: usize) -> Option<Vec<Type>> {
+    if n == 0 {
+        return Some(Vec::new());
+    }
+    if types.is_null() {
+        return None;
+    }
+    slice::from_raw_parts(types, n)
+        .iter()
+        .map(|&type_| type_.as_ref().cloned())
+        .collect()
+}
+
+// Parses a C string with `FromStr`.
+unsafe fn parse<T: std::str::FromStr>(text: *const c_char) -> Option<T> {
+    if text.is_null() {
+        return None;
+    }
+    CStr::from_ptr(text).to_str().ok()?.parse().ok()
+}
+
+/// Parses a type from its text form, such as `"{u8, f64}"`, as
+/// [`str::parse`] does for [`Type`].
+///
+/// Returns NULL if `text` is NULL or does not parse.
+///
+/// # Safety
+///
+/// `text` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_type_parse(text: *const c_char) -> *mut Type {
+    into_handle(guard(|| parse(text)))
+}
+
+/// Creates the type of a structure whose fields have the `nfields`
+/// types at `fields`, as [`Type::structure`] does.
+///
+/// Returns NULL if there are no fields, or any of them is NULL.
+///
+/// # Safety
+///
+/// `fields` must point to `nfields` pointers that are NULL or types
+/// created by this interface.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_type_struct(
+    fields: *const *const Type,
+    nfields: usize,
+) -> *mut Type {
+    into_handle(guard(|| {
+        let fields = clone_types(fields, nfields).filter(|fields| !fields.is_empty())?;
+        Some(Type::structure(fields))
+    }))
+}
+
+/// Stores the size and alignment of `type_` in `size` and `align`,
+/// either of which may be NULL.
+///
+/// # Safety
+///
+/// `type_` must be a type created by this interface, and `size` and
+/// `align` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_type_layout(
+    type_: *const Type,
+    size: *mut usize,
+    align: *mut usize,
+) {
+    let (type_size, type_align) = (*type_).layout();
+    if !size.is_null() {
+        *size = type_size;
+    }
+    if !align.is_null() {
+        *align = type_align;
+    }
+}
+
+/// Frees a type.
+///
+/// # Safety
+///
+/// `type_` must be NULL or a type created by this interface that has
+/// not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_type_free(type_: *mut Type) {
+    if !type_.is_null() {
+        drop(Box::from_raw(type_));
+    }
+}
+
+/// Prepares a CIF for a function with the `nargs` argument types at
+/// `args` and result type `result`, as [`Cif::try_new`] does.
+///
+/// Returns NULL if any type is NULL or libffi rejects the types.
+///
+/// # Safety
+///
+/// `args` must point to `nargs` pointers that are NULL or types
+/// created by this interface, and `result` must be NULL or such a
+/// type.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_cif_new(
+    args: *const *const Type,
+    nargs: usize,
+    result: *const Type,
+) -> *mut Cif {
+    into_handle(guard(|| {
+        let args = clone_types(args, nargs)?;
+        let result = result.as_ref()?.clone();
+        Cif::try_new(args, result).ok()
+    }))
+}
+
+/// Prepares a CIF from the text form of its signature, such as
+/// `"(u32, pointer) -> i32"`, as [`str::parse`] does for [`Cif`].
+///
+/// Returns NULL if `signature` is NULL or does not parse.
+///
+/// # Safety
+///
+/// `signature` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_cif_parse(signature: *const c_char) -> *mut Cif {
+    into_handle(guard(|| parse(signature)))
+}
+
+/// Frees a CIF.
+///
+/// # Safety
+///
+/// `cif` must be NULL or a CIF created by this interface that has not
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_cif_free(cif: *mut Cif) {
+    if !cif.is_null() {
+        drop(Box::from_raw(cif));
+    }
+}
+
+/// Calls `fun` through `cif` with the arguments at `args`, storing its
+/// result in `result`, as `ffi_call` does.
+///
+/// As with `ffi_call`, an integer result narrower than a machine word
+/// is widened, so `result` must have room for a word. Nothing is
+/// called if `fun` is NULL.
+///
+/// # Safety
+///
+/// `cif` must be a CIF created by this interface that describes `fun`,
+/// `args` must point to pointers to arguments of its types, and
+/// `result` must be writable for its result type.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_call(
+    cif: *const Cif,
+    fun: Option<unsafe extern "C" fn()>,
+    result: *mut c_void,
+    args: *mut *mut c_void,
+) {
+    let fun = match fun {
+        Some(fun) => fun,
+        None => return,
+    };
+    low::forward_call(
+        (*cif).as_raw_ptr(),
+        CodePtr(fun as *mut c_void),
+        args as *const *const c_void,
+        result,
+    );
+}
+
+unsafe extern "C" fn call_closure(
+    _cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    userdata: &Userdata,
+) {
+    crate::__private::v1::abort_on_panic("Cannot panic inside FFI callback", || {
+        (userdata.callback)(result, args as *mut *mut c_void, userdata.userdata);
+    });
+}
+
+/// Creates a closure that calls `callback` with `userdata` when called
+/// through `cif`, as [`ClosureOwned::new`] does.
+///
+/// Returns NULL if `cif` or `callback` is NULL, or libffi fails to
+/// create the closure.
+///
+/// # Safety
+///
+/// `cif` must be NULL or a CIF created by this interface, and
+/// `callback` must be safe to call with `userdata` for as long as the
+/// closure is.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_closure_new(
+    cif: *const Cif,
+    callback: Option<libffi_rs_callback>,
+    userdata: *mut c_void,
+) -> *mut libffi_rs_closure {
+    into_handle(guard(|| {
+        let userdata = Userdata {
+            callback: callback?,
+            userdata,
+        };
+        let closure = ClosureOwned::new(cif.as_ref()?.clone(), call_closure, userdata);
+        Some(libffi_rs_closure { closure })
+    }))
+}
+
+/// The code pointer of `closure`, to be cast to the function pointer
+/// type its CIF describes.
+///
+/// # Safety
+///
+/// `closure` must be a closure created by this interface that has not
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_closure_code(closure: *const libffi_rs_closure) -> *mut c_void {
+    *(*closure).closure.code_ptr() as *mut c_void
+}
+
+/// Frees a closure.
+///
+/// # Safety
+///
+/// `closure` must be NULL or a closure created by this interface that
+/// has not been freed, and that is not being called.
+#[no_mangle]
+pub unsafe extern "C" fn libffi_rs_closure_free(closure: *mut libffi_rs_closure) {
+    if !closure.is_null() {
+        drop(Box::from_raw(closure));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    extern "C" fn scale(x: f64, k: i32) -> f64 {
+        x * f64::from(k)
+    }
+
+    #[test]
+    fn types_and_calls() {
+        unsafe {
+            let double = libffi_rs_type_parse(b"f64\0".as_ptr() as *const c_char);
+            let int = libffi_rs_type_parse(b"i32\0".as_ptr() as *const c_char);
+            assert!(libffi_rs_type_parse(b"f65\0".as_ptr() as *const c_char).is_null());
+            assert!(libffi_rs_type_parse(ptr::null()).is_null());
+
+            let fields = [int as *const Type, double as *const Type];
+            let pair = libffi_rs_type_struct(fields.as_ptr(), 2);
+            assert!(libffi_rs_type_struct(fields.as_ptr(), 0).is_null());
+            let (mut size, mut align) = (0, 0);
+            libffi_rs_type_layout(pair, &mut size, &mut align);
+            assert_eq!((16, 8), (size, align));
+            libffi_rs_type_free(pair);
+
+            let cif = libffi_rs_cif_new(fields.as_ptr().add(1), 1, double);
+            assert!(!cif.is_null());
+            let args = [double as *const Type, int];
+            let missing = [double as *const Type, ptr::null()];
+            assert!(libffi_rs_cif_new(missing.as_ptr(), 2, double).is_null());
+            libffi_rs_cif_free(cif);
+            let cif = libffi_rs_cif_new(args.as_ptr(), 2, double);
+            libffi_rs_type_free(double);
+            libffi_rs_type_free(int);
+
+            let (mut x, mut k, mut result) = (1.5f64, 3i32, 0f64);
+            let mut values = [
+                &mut x as *mut f64 as *mut c_void,
+                &mut k as *mut i32 as *mut c_void,
+            ];
+            let fun: unsafe extern "C" fn() =
+                std::mem::transmute(scale as extern "C" fn(f64, i32) -> f64);
+            libffi_rs_call(
+                cif,
+                Some(fun),
+                &mut result as *mut f64 as *mut c_void,
+                values.as_mut_ptr(),
+            );
+            assert_eq!(4.5, result);
+            libffi_rs_cif_free(cif);
+        }
+    }
+
+    unsafe extern "C" fn add(result: *mut c_void, args: *mut *mut c_void, userdata: *mut c_void) {
+        *(result as *mut i64) = *(*args as *const i64) + *(userdata as *const i64);
+    }
+
+    #[test]
+    fn closures() {
+        unsafe {
+            let cif = libffi_rs_cif_parse(b"(i64) -> i64\0".as_ptr() as *const c_char);
+            assert!(libffi_rs_closure_new(cif, None, ptr::null_mut()).is_null());
+            let mut offset = 10i64;
+            let offset = &mut offset as *mut i64;
+            let closure = libffi_rs_closure_new(cif, Some(add), offset as *mut c_void);
+            libffi_rs_cif_free(cif);
+
+            let fun: extern "C" fn(i64) -> i64 =
+                std::mem::transmute(libffi_rs_closure_code(closure));
+            assert_eq!(12, fun(2));
+            *offset = -5;
+            assert_eq!(-3, fun(2));
+            libffi_rs_closure_free(closure);
+            libffi_rs_closure_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! The error type of the crate’s fallible constructors.
+
+use core::fmt;
+
+use crate::{low, raw};
+
+/// Why libffi could not prepare a CIF or a closure.
+///
+/// This is returned by the fallible constructors, such as
+/// [`Cif::try_new`](crate::middle::Cif::try_new) and
+/// [`Closure::try_new`](crate::middle::Closure::try_new); their
+/// infallible counterparts panic with it instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Error {
+    /// libffi rejected a type, for instance a `void` argument or a
+    /// struct with no fields.
+    BadTypedef,
+    /// libffi does not support the calling convention on this platform.
+    BadAbi,
+    /// libffi rejected the type of a variable argument, which must be
+    /// given after C’s default argument promotions.
+    BadArgType,
+    /// libffi could not allocate a closure’s executable memory, or the
+    /// allocator could not allocate a type description.
+    AllocationFailed,
+}
+
+impl Error {
+    // Converts the status returned by libffi.
+    pub(crate) fn check(status: raw::ffi_status) -> Result<(), Error> {
+        match status {
+            raw::ffi_status_FFI_OK => Ok(()),
+            raw::ffi_status_FFI_BAD_TYPEDEF => Err(Error::BadTypedef),
+            raw::ffi_status_FFI_BAD_ARGTYPE => Err(Error::BadArgType),
+            // As for `low`, an unrecognized status is an ABI error.
+            _ => Err(Error::BadAbi),
+        }
+    }
+}
+
+impl From<low::Error> for Error {
+    fn from(error: low::Error) -> Self {
+        match error {
+            low::Error::Typedef => Error::BadTypedef,
+            low::Error::Abi => Error::BadAbi,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::BadTypedef => "libffi rejected a type",
+            Error::BadAbi => "libffi does not support the calling convention",
+            Error::BadArgType => "libffi rejected the type of a variable argument",
+            Error::AllocationFailed => "could not allocate closure or type memory",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
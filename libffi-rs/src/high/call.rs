@@ -42,16 +42,25 @@ impl<'a> Arg<'a> {
     ///
     /// For a shorter alias of the same, see [`fn@arg`].
     pub fn new<T: super::CType>(arg: &'a T) -> Self {
+        arg.debug_check();
         Arg {
             type_: T::reify().into_middle(),
-            value: middle::Arg::new(arg),
+            value: middle::Arg::val(arg),
             _marker: PhantomData,
         }
     }
 }
 
+impl Arg<'_> {
+    // The argument’s type and its middle-layer representation.
+    #[cfg(any(feature = "libloading", feature = "windows-types"))]
+    pub(crate) fn parts(&self) -> (&middle::Type, &middle::Arg) {
+        (&self.type_, &self.value)
+    }
+}
+
 /// Constructs an [`Arg`] for passing to [`fn@call`].
-pub fn arg<T: super::CType>(arg: &T) -> Arg {
+pub fn arg<T: super::CType>(arg: &T) -> Arg<'_> {
     Arg::new(arg)
 }
 
@@ -74,6 +83,11 @@ pub fn arg<T: super::CType>(arg: &T) -> Arg {
 ///
 /// assert!((result - 5f32).abs() < 0.0001);
 /// ```
+///
+/// # Safety
+///
+/// There is no checking that `fun` actually takes arguments of the
+/// types given by `args` or returns a value of type `R`.
 pub unsafe fn call<R: super::CType>(fun: CodePtr, args: &[Arg]) -> R {
     let types = args.iter().map(|arg| arg.type_.clone());
     let cif = middle::Cif::new(types, R::reify().into_middle());
@@ -82,18 +96,33 @@ pub unsafe fn call<R: super::CType>(fun: CodePtr, args: &[Arg]) -> R {
     // If `R` is a small integer type, libffi implicitly extends it to
     // `ffi_arg` or `ffi_sarg`.  To account for this, use `R::RetType`
     // as return type for the low-level call, and convert the result back.
-    cif.call::<R::RetType>(fun, &values)
+    let result: R = cif
+        .call::<R::RetType>(fun, &values)
         .try_into()
         .ok()
-        .unwrap()
+        .unwrap();
+    result.debug_check();
+    result
 }
 
 /// Performs a dynamic call to a C function.
 ///
+/// A function that returns a function pointer can be called with result
+/// type [`ReturnedFn`](crate::middle::ReturnedFn) to decode it.
+///
 /// This macro provides sugar for [`high::arg`](crate::high::arg) and
 /// [`high::call`](fn@crate::high::call). For more control, see
 /// [`high::call`](fn@crate::high::call).
 ///
+/// Alternatively, the function can be annotated with its `extern "C"
+/// fn` pointer type, from which the argument and result types are
+/// taken. The arguments are converted to the declared types, the CIF is
+/// prepared on the first call at each use of the macro and cached for
+/// later ones, and since Rust checks the function against the pointer
+/// type, no `unsafe` block is needed. This form supports up to twelve
+/// arguments, and because of the cache, the types may not refer to
+/// generic parameters.
+///
 /// # Examples
 ///
 /// ```
@@ -106,6 +135,14 @@ pub unsafe fn call<R: super::CType>(fun: CodePtr, args: &[Arg]) -> R {
 /// let result = unsafe { ffi_call!{ hypot(3f32, 4f32) -> f32 } };
 ///
 /// assert!((result - 5f32).abs() < 0.0001);
+///
+/// extern "C" fn scale(n: i32, k: f64) -> u8 {
+///     (n as f64 * k) as u8
+/// }
+///
+/// let result = ffi_call!(scale: extern "C" fn(i32, f64) -> u8, 3, 1.5);
+///
+/// assert_eq!(4, result);
 /// ```
 #[macro_export]
 macro_rules! ffi_call {
@@ -130,4 +167,37 @@ macro_rules! ffi_call {
     =>
     { ffi_call!{ ($fun)($($arg),*) -> () } };
 
+    { ( $fun:expr ) : extern "C" fn ( $( $T:ty ),* ) -> $R:ty $( , $arg:expr )* $(,)? }
+    =>
+    {{
+        static CIF: $crate::__private::v1::OnceLock<$crate::middle::Cif> =
+            $crate::__private::v1::OnceLock::new();
+        let fun: extern "C" fn($( $T ),*) -> $R = $fun;
+        let cif = CIF.get_or_init(|| {
+            $crate::__private::v1::cif_for::<$R>(
+                ::std::vec![$( <$T as $crate::high::CType>::reify().into_middle() ),*])
+        });
+        let values = ($({
+            let value = $crate::__private::v1::typed::<$T>($arg);
+            $crate::high::CType::debug_check(&value);
+            value
+        }, )*);
+        let args = $crate::__private::v1::ArgTuple::args(&values);
+        // The function pointer’s type was checked above, so the call
+        // is as safe as calling it directly.
+        let result: $R = unsafe {
+            $crate::__private::v1::call_cif(cif, $crate::middle::CodePtr(fun as *mut _), &args)
+        };
+        $crate::high::CType::debug_check(&result);
+        result
+    }};
+
+    { ( $fun:expr ) : extern "C" fn ( $( $T:ty ),* ) $( , $arg:expr )* $(,)? }
+    =>
+    { ffi_call!{ ($fun): extern "C" fn($( $T ),*) -> () $( , $arg )* } };
+
+    { $fun:ident : extern "C" fn ( $( $T:ty ),* ) $( -> $R:ty )? $( , $arg:expr )* $(,)? }
+    =>
+    { ffi_call!{ ($fun): extern "C" fn($( $T ),*) $( -> $R )? $( , $arg )* } };
+
 }
@@ -0,0 +1,164 @@
+//! Typed wrappers for C functions that are called dynamically.
+
+/// Declares typed wrappers for C functions whose addresses are only
+/// known at run time.
+///
+/// Each declaration, written like a function signature, expands to a
+/// struct of the same name that holds a code pointer together with a
+/// CIF prepared for the signature, and has a `call` method with the
+/// declared argument and result types. Argument and result types must
+/// implement [`CType`](crate::high::CType).
+///
+/// Creating a wrapper with `new` is `unsafe`, since the signature is
+/// not checked against the function it points to; calling the wrapper
+/// afterwards is then safe. A wrapper can instead be created safely
+/// with `from_fn` from an `extern "C" fn` pointer of the declared type.
+///
+/// The generated struct has the following methods, with the visibility
+/// of the declaration:
+///
+/// - `unsafe fn new(fun: CodePtr) -> Self`
+/// - `fn from_fn(fun: extern "C" fn(..) -> R) -> Self`
+/// - `fn call(&self, ..) -> R`
+/// - `fn code_ptr(&self) -> CodePtr`
+/// - `fn cif(&self) -> &Cif`
+///
+/// # Examples
+///
+/// ```
+/// use libffi::ffi_fn;
+/// use libffi::middle::CodePtr;
+///
+/// ffi_fn! {
+///     fn add(x: i32, y: i32) -> i32;
+///     /// Scales a value.
+///     pub fn scale(x: f64, k: f64) -> f64;
+/// }
+///
+/// extern "C" fn add_impl(x: i32, y: i32) -> i32 {
+///     x + y
+/// }
+///
+/// extern "C" fn scale_impl(x: f64, k: f64) -> f64 {
+///     x * k
+/// }
+///
+/// // Usually the address would come from a library or a plugin.
+/// let add = unsafe { add::new(CodePtr(add_impl as *mut _)) };
+/// assert_eq!(5, add.call(2, 3));
+///
+/// let scale = scale::from_fn(scale_impl);
+/// assert_eq!(7.5, scale.call(2.5, 3.0));
+/// ```
+#[macro_export]
+macro_rules! ffi_fn {
+    ( $(
+        $( #[$attr:meta] )*
+        $vis:vis fn $name:ident ( $( $arg:ident : $T:ty ),* $(,)? ) $( -> $R:ty )?
+    );+ $(;)? ) => {
+        $(
+            $crate::__ffi_fn!(
+                [$( #[$attr] )*] [$vis] [$name] [$( ($arg : $T) )*] [$( $R )?]
+            );
+        )+
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ffi_fn {
+    ( [$( $attr:tt )*] [$vis:vis] [$name:ident] [$( ($arg:ident : $T:ty) )*] [] ) => {
+        $crate::__ffi_fn!([$( $attr )*] [$vis] [$name] [$( ($arg : $T) )*] [()]);
+    };
+
+    ( [$( $attr:tt )*] [$vis:vis] [$name:ident] [$( ($arg:ident : $T:ty) )*] [$R:ty] ) => {
+        $( $attr )*
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Debug)]
+        $vis struct $name {
+            fun: $crate::middle::CodePtr,
+            cif: $crate::middle::Cif,
+        }
+
+        #[allow(dead_code)]
+        impl $name {
+            /// Wraps the C function at `fun`.
+            ///
+            /// # Safety
+            ///
+            /// `fun` must point to a C function with the declared
+            /// signature, which remains valid while the wrapper is used.
+            $vis unsafe fn new(fun: $crate::middle::CodePtr) -> Self {
+                let cif = $crate::__private::v1::cif_for::<$R>(
+                    ::std::vec![$( <$T as $crate::high::CType>::reify().into_middle() ),*],
+                );
+                $name { fun, cif }
+            }
+
+            /// Wraps a function pointer of the declared signature.
+            $vis fn from_fn(fun: extern "C" fn($( $T ),*) -> $R) -> Self {
+                // Rust has checked the function against the signature.
+                unsafe { Self::new($crate::middle::CodePtr(fun as *mut _)) }
+            }
+
+            /// Calls the function.
+            #[allow(clippy::too_many_arguments)]
+            $vis fn call(&self, $( $arg: $T ),*) -> $R {
+                $( $crate::high::CType::debug_check(&$arg); )*
+                let args = [$( $crate::middle::Arg::val(&$arg) ),*];
+                // The signature was checked when the wrapper was created.
+                let result: $R =
+                    unsafe { $crate::__private::v1::call_cif(&self.cif, self.fun, &args) };
+                $crate::high::CType::debug_check(&result);
+                result
+            }
+
+            /// The code pointer of the function.
+            $vis fn code_ptr(&self) -> $crate::middle::CodePtr {
+                self.fun
+            }
+
+            /// The CIF prepared for the declared signature.
+            $vis fn cif(&self) -> &$crate::middle::Cif {
+                &self.cif
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::raw::c_void;
+
+    use crate::middle::CodePtr;
+
+    extern "C" fn narrow(x: u8, y: i16) -> u8 {
+        x.wrapping_add(y as u8)
+    }
+
+    extern "C" fn nothing() {}
+
+    extern "C" fn first(values: *const u32) -> u32 {
+        unsafe { *values }
+    }
+
+    ffi_fn! {
+        fn narrowing(x: u8, y: i16) -> u8;
+        fn unit();
+        fn deref(values: *const u32) -> u32
+    }
+
+    #[test]
+    fn typed_calls() {
+        let narrowing = narrowing::from_fn(narrow);
+        assert_eq!(4, narrowing.call(250, 10));
+        assert_eq!(2, narrowing.cif().clone().arg_layout(1).unwrap().0);
+
+        let unit = unsafe { unit::new(CodePtr(nothing as *mut c_void)) };
+        unit.clone().call();
+        assert_eq!(nothing as *mut c_void, unit.code_ptr().as_mut_ptr());
+
+        let values = [7u32, 8];
+        assert_eq!(7, deref::from_fn(first).call(values.as_ptr()));
+    }
+}
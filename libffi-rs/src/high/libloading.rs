@@ -0,0 +1,189 @@
+//! Calling functions loaded with the [`libloading`](::libloading)
+//! crate.
+//!
+//! A [`Function`] pairs a symbol loaded from a [`Library`] with the
+//! [CIF](Cif) describing it and the Rust type of its result. Binding the
+//! function is unsafe, since nothing checks that the CIF describes the
+//! symbol, but calling it is not: each call checks that its arguments
+//! have the types the CIF declares.
+//!
+//! This module is enabled by `#[cfg(feature = "libloading")]`.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(unix)] {
+//! use libffi::high::{arg, libloading::Function};
+//! use libffi::middle::{Cif, Type};
+//! use libloading::os::unix::Library;
+//!
+//! let library: libloading::Library = Library::this().into();
+//! let cif = Cif::new(vec![Type::i32()], Type::i32());
+//! let abs = unsafe { Function::<i32>::get(&library, b"abs", cif).unwrap() };
+//!
+//! assert_eq!(5, abs.call(&[arg(&-5i32)]));
+//! # }
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::sync::Arc;
+
+use ::libloading::{Library, Symbol};
+
+use super::{Arg, CType};
+use crate::middle::{BoundFn, Cif, CodePtr};
+
+/// A function loaded from a [`Library`], with its CIF and result type
+/// `R`.
+///
+/// The function borrows the library, so the library cannot be unloaded
+/// while it may be called.
+pub struct Function<'lib, R> {
+    bound: BoundFn,
+    _library: PhantomData<&'lib Library>,
+    _result: PhantomData<fn() -> R>,
+}
+
+impl<'lib, R: CType> Function<'lib, R> {
+    /// Binds the function `name` in `library` to `cif`.
+    ///
+    /// `name` may, but need not, end with a NUL byte.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from [`Library::get`] if the symbol cannot be
+    /// found.
+    ///
+    /// # Panics
+    ///
+    /// As for [`Function::new`].
+    ///
+    /// # Safety
+    ///
+    /// As for [`Function::new`].
+    pub unsafe fn get<C: Into<Arc<Cif>>>(
+        library: &'lib Library,
+        name: &[u8],
+        cif: C,
+    ) -> Result<Self, ::libloading::Error> {
+        let symbol: Symbol<'lib, *mut c_void> = library.get(name)?;
+        Ok(Function::new(symbol, cif))
+    }
+
+    /// Binds a loaded symbol to `cif`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result type of `cif` is not that of `R`.
+    ///
+    /// # Safety
+    ///
+    /// The symbol must be a function, and `cif` must describe it.
+    pub unsafe fn new<T, C: Into<Arc<Cif>>>(symbol: Symbol<'lib, T>, cif: C) -> Self {
+        let cif = cif.into();
+        let code = symbol
+            .try_as_raw_ptr()
+            .expect("Function::new: symbol is not a pointer");
+        assert!(
+            cif.has_result(&R::reify().into_middle()),
+            "Function::new: the CIF’s result type is not that of `{}`",
+            std::any::type_name::<R>()
+        );
+        Function {
+            bound: BoundFn::new(cif, CodePtr(code)),
+            _library: PhantomData,
+            _result: PhantomData,
+        }
+    }
+
+    /// Calls the function with the given arguments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the arguments do not have the types the CIF declares.
+    pub fn call(&self, args: &[Arg]) -> R {
+        let cif = self.bound.cif();
+        assert!(
+            cif.has_args(args.iter().map(|arg| arg.parts().0)),
+            "Function::call: the arguments do not match `{}`",
+            cif
+        );
+        let values = args
+            .iter()
+            .map(|arg| arg.parts().1.clone())
+            .collect::<Vec<_>>();
+        // The CIF describes the function, as promised when it was bound,
+        // and the arguments and result match the CIF.
+        unsafe { crate::__private::v1::from_ret_type::<R>(self.bound.call::<R::RetType>(&values)) }
+    }
+
+    /// Gets the underlying [`BoundFn`], which does not borrow the library.
+    pub fn bound(&self) -> &BoundFn {
+        &self.bound
+    }
+}
+
+impl<R> fmt::Debug for Function<'_, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("bound", &self.bound)
+            .finish()
+    }
+}
+
+impl<R> Clone for Function<'_, R> {
+    fn clone(&self) -> Self {
+        Function {
+            bound: self.bound.clone(),
+            _library: PhantomData,
+            _result: PhantomData,
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::high::arg;
+    use crate::middle::Type;
+
+    fn this() -> Library {
+        ::libloading::os::unix::Library::this().into()
+    }
+
+    #[test]
+    fn call_loaded_functions() {
+        let library = this();
+        let cif = Cif::new(vec![Type::pointer()], Type::usize());
+        let strlen = unsafe { Function::<usize>::get(&library, b"strlen\0", cif).unwrap() };
+        let text = b"loaded\0";
+        assert_eq!(6, strlen.call(&[arg(&text.as_ptr())]));
+
+        let cif = Cif::new(vec![Type::i64()], Type::i64());
+        let symbol = unsafe { library.get::<*mut c_void>(b"labs").unwrap() };
+        let labs = unsafe { Function::<i64>::new(symbol, cif) };
+        assert_eq!(9, labs.clone().call(&[arg(&-9i64)]));
+
+        let cif = Cif::new(vec![], Type::void());
+        assert!(unsafe { Function::<()>::get(&library, b"no_such_function", cif) }.is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "the arguments do not match `(i32) -> i32`")]
+    fn wrong_arguments() {
+        let library = this();
+        let cif = Cif::new(vec![Type::i32()], Type::i32());
+        let abs = unsafe { Function::<i32>::get(&library, b"abs", cif).unwrap() };
+        abs.call(&[arg(&-1i64)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "the CIF’s result type is not that of `f64`")]
+    fn wrong_result() {
+        let library = this();
+        let cif = Cif::new(vec![Type::i32()], Type::i32());
+        let _ = unsafe { Function::<f64>::get(&library, b"abs", cif) };
+    }
+}
@@ -0,0 +1,176 @@
+//! Closures that call a method on a receiver.
+
+/// Creates a closure that calls a `&mut self` method on a receiver.
+///
+/// C APIs frequently register a callback together with a `void*`
+/// argument identifying the object it acts on. This macro produces a
+/// typed mutable closure (a [`ClosureMutN`](crate::high::ClosureMut1)
+/// of the appropriate arity) whose userdata is the receiver itself,
+/// and whose code pointer calls the given method on it. The argument
+/// and result types are given after the method name, as in a function
+/// signature, and must implement [`CType`](crate::high::CType).
+///
+/// The first argument is a `&mut` reference to the receiver, which the
+/// closure borrows for its whole lifetime. Rust therefore ensures that
+/// the receiver cannot be used elsewhere while the closure exists.
+/// However, C code must not invoke the closure re-entrantly (from
+/// inside the method), since the method would then observe two
+/// aliasing `&mut self` references.
+///
+/// If the method panics, the process is aborted rather than unwinding
+/// into C.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::method_closure;
+///
+/// struct Counter {
+///     total: u64,
+/// }
+///
+/// impl Counter {
+///     fn add(&mut self, x: u32, y: u32) -> u64 {
+///         self.total += u64::from(x + y);
+///         self.total
+///     }
+/// }
+///
+/// let mut counter = Counter { total: 0 };
+///
+/// {
+///     let closure = method_closure!(&mut counter, Counter::add(u32, u32) -> u64);
+///     let add = closure.code_ptr();
+///
+///     assert_eq!(3, add.call(1, 2));
+///     assert_eq!(10, add.call(3, 4));
+/// }
+///
+/// assert_eq!(10, counter.total);
+/// ```
+///
+/// Receivers of generic type are written with angle brackets:
+///
+/// ```
+/// # use libffi::method_closure;
+/// struct Cell<T>(T);
+///
+/// impl<T: Copy> Cell<T> {
+///     fn set(&mut self, value: T) {
+///         self.0 = value;
+///     }
+/// }
+///
+/// let mut cell = Cell(0i32);
+/// method_closure!(&mut cell, <Cell<i32>>::set(i32)).code_ptr().call(7);
+/// assert_eq!(7, cell.0);
+/// ```
+#[macro_export]
+macro_rules! method_closure {
+    ( $recv:expr, < $S:ty > :: $method:ident ( $( $A:ty ),* $(,)? ) -> $R:ty ) => {
+        $crate::__method_closure!(
+            @munch [$recv] [$S] [$method] [$R]
+            [ClosureMut0 Cif0] []
+            [(ClosureMut1 Cif1 a1) (ClosureMut2 Cif2 a2) (ClosureMut3 Cif3 a3)
+             (ClosureMut4 Cif4 a4) (ClosureMut5 Cif5 a5) (ClosureMut6 Cif6 a6)
+             (ClosureMut7 Cif7 a7) (ClosureMut8 Cif8 a8) (ClosureMut9 Cif9 a9)
+             (ClosureMut10 Cif10 a10) (ClosureMut11 Cif11 a11) (ClosureMut12 Cif12 a12)]
+            $( $A, )*
+        )
+    };
+
+    ( $recv:expr, < $S:ty > :: $method:ident ( $( $A:ty ),* $(,)? ) ) => {
+        $crate::method_closure!($recv, <$S>::$method($( $A ),*) -> ())
+    };
+
+    ( $recv:expr, $S:ident :: $method:ident ( $( $A:ty ),* $(,)? ) $( -> $R:ty )? ) => {
+        $crate::method_closure!($recv, <$S>::$method($( $A ),*) $( -> $R )?)
+    };
+}
+
+// Pairs each argument type with a fresh parameter name, while tracking
+// the closure and CIF types for the arity seen so far.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __method_closure {
+    (
+        @munch [$recv:expr] [$S:ty] [$method:ident] [$R:ty]
+        [$closure:ident $cif:ident] [$( ($n:ident : $T:ty) )*]
+        [($next_closure:ident $next_cif:ident $next_n:ident) $( $more:tt )*]
+        $A:ty, $( $rest:ty, )*
+    ) => {
+        $crate::__method_closure!(
+            @munch [$recv] [$S] [$method] [$R]
+            [$next_closure $next_cif] [$( ($n : $T) )* ($next_n : $A)]
+            [$( $more )*]
+            $( $rest, )*
+        )
+    };
+
+    (
+        @munch [$recv:expr] [$S:ty] [$method:ident] [$R:ty]
+        [$closure:ident $cif:ident] [$( ($n:ident : $T:ty) )*]
+        [$( $more:tt )*]
+    ) => {{
+        #[allow(clippy::too_many_arguments)]
+        extern "C" fn callback(
+            _cif: &$crate::low::ffi_cif,
+            result: &mut <$R as $crate::high::CType>::RetType,
+            &($( &$n, )*): &($( &$T, )*),
+            receiver: &mut $S,
+        ) {
+            $crate::__private::v1::abort_on_panic("Cannot panic inside FFI callback", || {
+                let value: $R = <$S>::$method(receiver, $( $n ),*);
+                unsafe { ::std::ptr::write(result, value.into()) }
+            })
+        }
+
+        $crate::high::$closure::from_parts(
+            <$crate::high::$cif<$( $T, )* $R>>::reify(),
+            callback,
+            $recv,
+        )
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    struct Accumulator {
+        values: Vec<f64>,
+    }
+
+    impl Accumulator {
+        fn push(&mut self, x: f64) {
+            self.values.push(x);
+        }
+
+        fn sum(&mut self) -> f64 {
+            self.values.iter().sum()
+        }
+
+        fn scaled(&mut self, a: f64, b: i32, c: u8) -> f64 {
+            self.sum() * a + f64::from(b) + f64::from(c)
+        }
+    }
+
+    #[test]
+    fn calls_methods() {
+        let mut acc = Accumulator { values: vec![] };
+
+        {
+            let push = method_closure!(&mut acc, Accumulator::push(f64));
+            push.code_ptr().call(1.5);
+            push.code_ptr().call(2.5);
+        }
+
+        assert_eq!(
+            4.0,
+            method_closure!(&mut acc, Accumulator::sum() -> f64)
+                .code_ptr()
+                .call()
+        );
+
+        let scaled = method_closure!(&mut acc, Accumulator::scaled(f64, i32, u8) -> f64);
+        assert_eq!(14.0, scaled.code_ptr().call(2.0, 3, 3));
+    }
+}
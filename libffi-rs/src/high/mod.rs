@@ -24,7 +24,10 @@
 //! [`Closure2::new_with_cif`].
 //!
 //! See the [`mod@call`] submodule for a simple interface
-//! to dynamic calls to C functions.
+//! to dynamic calls to C functions, and [`ffi_fn!`](crate::ffi_fn) for
+//! typed wrappers of C functions called repeatedly.
+//! See the [`variadic`] submodule for closures that C calls with
+//! variable arguments.
 //!
 //! # Examples
 //!
@@ -67,34 +70,52 @@
 //! ```
 //!
 //! Invoking the closure a second time will panic.
+//!
+//! # Allocation
+//!
+//! Creating a closure allocates its CIF, type descriptions and
+//! trampoline, but calling an existing closure performs no heap
+//! allocation, whichever features are enabled, so closures may be
+//! called from real-time threads such as audio callbacks. See
+//! [`rt`](crate::rt) for the full real-time-safe subset.
 
 pub use crate::middle::{ffi_abi_FFI_DEFAULT_ABI, FfiAbi};
 
 pub mod types;
 pub use types::{CType, Type};
 
+/// Derives [`CType`] for a `#[repr(C)]` struct, describing it as a
+/// structure of its fields.
+///
+/// This item is enabled by `#[cfg(feature = "derive")]`; see
+/// [the `libffi-derive` documentation](https://docs.rs/libffi-derive)
+/// for details.
+#[cfg(feature = "derive")]
+pub use libffi_derive::CType;
+
 pub mod call;
 pub use call::*;
 
-macro_rules! abort_on_panic {
-    ($msg:literal, $body:expr) => {{
-        // Aborts when dropped (which will only happen due to an unwinding panic).
-        struct Bomb;
-        impl Drop for Bomb {
-            fn drop(&mut self) {
-                // We do our best to ignore errors that occur during printing.
-                // If this panics anyway, that'll still just be a double-panic which leads to abort.
-                let _ = writeln!(std::io::stderr(), $msg);
-                std::process::abort();
-            }
-        }
+mod ffi_fn;
+
+#[cfg(feature = "libloading")]
+pub mod libloading;
 
-        let b = Bomb;
-        // If this panics, `b` will be dropped, triggering the bomb.
-        $body;
-        // Defuse the bomb.
-        std::mem::forget(b);
-    }};
+mod method;
+
+#[cfg(feature = "high-only")]
+pub mod scalar;
+
+pub mod variadic;
+
+#[cfg(feature = "windows-types")]
+pub mod windows;
+
+// Runs the callback body `$body`, aborting with `$msg` if it panics.
+macro_rules! abort_on_panic {
+    ($msg:literal, $body:expr) => {
+        $crate::__private::v1::abort_on_panic($msg, || $body)
+    };
 }
 
 macro_rules! define_closure_mod {
@@ -197,6 +218,37 @@ macro_rules! define_closure_mod {
                 {
                     Self::new_with_cif($cif::reify(), callback)
                 }
+
+                /// Constructs a typed closure callable from C from a
+                /// Rust closure that borrows its arguments.
+                ///
+                /// The Rust closure receives references into libffi’s
+                /// argument storage rather than copies, which avoids
+                /// copying large struct arguments. The references are
+                /// valid only for the duration of the call.
+                pub fn new_borrowed<Callback>(callback: &'a Callback) -> Self
+                    where Callback: Fn($( &$T, )*) -> R + 'a
+                {
+                    Self::from_parts($cif::reify(),
+                                     Self::borrowed_callback,
+                                     callback)
+                }
+
+                #[allow(non_snake_case)]
+                extern "C" fn borrowed_callback<Callback>
+                    (_cif:     &low::ffi_cif,
+                     result:   &mut R::RetType,
+                     &($( $T, )*):
+                               &($( &$T, )*),
+                     userdata: &Callback)
+                  where Callback: Fn($( &$T, )*) -> R + 'a
+                {
+                    abort_on_panic!("Cannot panic inside FFI callback", {
+                        unsafe {
+                            ptr::write(result, userdata($( $T, )*).into());
+                        }
+                    });
+                }
             }
 
             impl<'a, $( $T, )* R: CType> $closure<'a, $( $T, )* R> {
@@ -218,6 +270,22 @@ macro_rules! define_closure_mod {
                     }
                 }
 
+                /// Returns whether `addr` lies within the closure’s
+                /// trampoline; see
+                /// [`middle::Closure::contains_address`].
+                pub fn contains_address(&self, addr: *const std::os::raw::c_void) -> bool {
+                    self.untyped.contains_address(addr)
+                }
+
+                /// Gets the histogram of the latencies of this closure’s
+                /// calls.
+                #[cfg(feature = "stats")]
+                pub fn latency_histogram(&self)
+                    -> std::sync::Arc<middle::stats::LatencyHistogram>
+                {
+                    self.untyped.latency_histogram()
+                }
+
                 /// Constructs a typed closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function, a callback for the function to call, and
@@ -295,6 +363,36 @@ macro_rules! define_closure_mod {
                 {
                     Self::new_with_cif($cif::reify(), callback)
                 }
+
+                /// Constructs a typed closure callable from C from a
+                /// Rust closure that borrows its arguments.
+                ///
+                /// As for `new_borrowed` on the immutable closure of the
+                /// same arity, the references are valid only for the
+                /// duration of the call.
+                pub fn new_borrowed<Callback>(callback: &'a mut Callback) -> Self
+                    where Callback: FnMut($( &$T, )*) -> R + 'a
+                {
+                    Self::from_parts($cif::reify(),
+                                     Self::borrowed_callback,
+                                     callback)
+                }
+
+                #[allow(non_snake_case)]
+                extern "C" fn borrowed_callback<Callback>
+                    (_cif:     &low::ffi_cif,
+                     result:   &mut R::RetType,
+                     &($( $T, )*):
+                               &($( &$T, )*),
+                     userdata: &mut Callback)
+                  where Callback: FnMut($( &$T, )*) -> R + 'a
+                {
+                    abort_on_panic!("Cannot panic inside FFI callback", {
+                        unsafe {
+                            ptr::write(result, userdata($( $T, )*).into());
+                        }
+                    });
+                }
             }
 
             impl<'a, $( $T, )* R: CType> $closure_mut<'a, $( $T, )* R> {
@@ -306,6 +404,22 @@ macro_rules! define_closure_mod {
                     }
                 }
 
+                /// Returns whether `addr` lies within the closure’s
+                /// trampoline; see
+                /// [`middle::Closure::contains_address`].
+                pub fn contains_address(&self, addr: *const std::os::raw::c_void) -> bool {
+                    self.untyped.contains_address(addr)
+                }
+
+                /// Gets the histogram of the latencies of this closure’s
+                /// calls.
+                #[cfg(feature = "stats")]
+                pub fn latency_histogram(&self)
+                    -> std::sync::Arc<middle::stats::LatencyHistogram>
+                {
+                    self.untyped.latency_histogram()
+                }
+
                 /// Constructs a typed closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function, a callback for the function to call, and
@@ -427,6 +541,22 @@ macro_rules! define_closure_mod {
                     }
                 }
 
+                /// Returns whether `addr` lies within the closure’s
+                /// trampoline; see
+                /// [`middle::Closure::contains_address`].
+                pub fn contains_address(&self, addr: *const std::os::raw::c_void) -> bool {
+                    self.untyped.contains_address(addr)
+                }
+
+                /// Gets the histogram of the latencies of this closure’s
+                /// calls.
+                #[cfg(feature = "stats")]
+                pub fn latency_histogram(&self)
+                    -> std::sync::Arc<middle::stats::LatencyHistogram>
+                {
+                    self.untyped.latency_histogram()
+                }
+
                 /// Constructs a one-shot closure callable from C from a CIF
                 /// describing the calling convention for the resulting
                 /// function, a callback for the function to call, and
@@ -555,6 +685,43 @@ mod test {
         assert_eq!(12, closure.code_ptr().call(5, 6));
     }
 
+    #[test]
+    fn const_and_mut_pointers() {
+        let read = |p: &u64| *p + 1;
+        let read_closure = Closure1::new(&read);
+
+        let write = |p: *mut u64| unsafe { *p += 1 };
+        let write_closure = Closure1::new(&write);
+
+        let mut x = 5u64;
+        assert_eq!(6, read_closure.code_ptr().call(&x));
+
+        write_closure.code_ptr().call(&mut x);
+        assert_eq!(6, x);
+    }
+
+    #[test]
+    fn call_returning_fn() {
+        use crate::ffi_call;
+        use crate::middle::{self, ReturnedFn};
+
+        extern "C" fn triple(x: u32) -> u32 {
+            x * 3
+        }
+
+        extern "C" fn get_triple() -> extern "C" fn(u32) -> u32 {
+            triple
+        }
+
+        let f = unsafe {
+            ffi_call! { get_triple() -> ReturnedFn }
+        };
+        let cif = middle::Cif::new(vec![middle::Type::u32()], middle::Type::u32());
+        let n: u32 = unsafe { f.call(&cif, &[middle::arg(&4u32)]) };
+
+        assert_eq!(12, n);
+    }
+
     #[test]
     fn new_mut() {
         let mut x: u64 = 0;
@@ -570,4 +737,101 @@ mod test {
         assert_eq!(6, counter.call(1));
         assert_eq!(8, counter.call(2));
     }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[repr(C)]
+    struct Span {
+        start: u8,
+        len: u64,
+        weight: f32,
+    }
+
+    unsafe impl CType for Span {
+        fn reify() -> Type<Self> {
+            unsafe {
+                Type::structure(vec![
+                    u8::reify().into_middle(),
+                    u64::reify().into_middle(),
+                    f32::reify().into_middle(),
+                ])
+            }
+        }
+        type RetType = Self;
+    }
+
+    #[test]
+    fn struct_arguments_by_value() {
+        let shift = |s: Span, by: u8| Span {
+            start: s.start + by,
+            ..s
+        };
+        let closure = Closure2::new(&shift);
+        let span = Span {
+            start: 1,
+            len: 10,
+            weight: 0.5,
+        };
+        assert_eq!(
+            Span {
+                start: 4,
+                len: 10,
+                weight: 0.5
+            },
+            closure.code_ptr().call(span, 3)
+        );
+
+        let mut total = 0;
+        let mut sum = |s: Span| total += s.len;
+        ClosureMut1::new(&mut sum).code_ptr().call(span);
+        assert_eq!(10, total);
+    }
+
+    #[test]
+    #[should_panic(expected = "fields do not match")]
+    fn mismatched_struct_fields() {
+        let _ = unsafe { Type::<Span>::structure(vec![u8::reify().into_middle()]) };
+    }
+
+    #[test]
+    fn borrowed_struct_arguments() {
+        let span = Span {
+            start: 1,
+            len: 10,
+            weight: 0.5,
+        };
+
+        let len = |s: &Span, k: &u64| s.len * *k;
+        let closure = Closure2::new_borrowed(&len);
+        assert_eq!(30, closure.code_ptr().call(span, 3));
+
+        let mut seen = vec![];
+        let mut record = |s: &Span| seen.push(s.start);
+        ClosureMut1::new_borrowed(&mut record).code_ptr().call(span);
+        assert_eq!(vec![1], seen);
+    }
+
+    #[test]
+    fn typed_ffi_call() {
+        use crate::ffi_call;
+
+        extern "C" fn sum(a: u8, b: i64, c: f32) -> i64 {
+            a as i64 + b + c as i64
+        }
+
+        extern "C" fn store(p: *mut u32, n: u32) {
+            unsafe { *p = n }
+        }
+
+        for i in 0..3 {
+            assert_eq!(
+                13 + i,
+                ffi_call!(sum: extern "C" fn(u8, i64, f32) -> i64, 1, 10 + i, 2.5)
+            );
+        }
+
+        let mut x = 0u32;
+        let p: *mut u32 = &mut x;
+        ffi_call!((store): extern "C" fn(*mut u32, u32), p, 7);
+        assert_eq!(7, x);
+    }
 }
@@ -0,0 +1,224 @@
+//! Typed closures over scalar types, without heap-allocated type
+//! descriptions.
+//!
+//! The closures of the parent module describe their signatures with
+//! [`middle::Type`](crate::middle::Type)s, which allocate, and keep
+//! their CIFs in separately allocated [`middle::Cif`](crate::middle::Cif)s.
+//! For callbacks that only take and return scalars, that machinery is
+//! unnecessary: the <code>ScalarClosure<em>N</em></code> types here
+//! point their CIFs directly at libffi’s static `ffi_type` objects, and
+//! store the CIF in the same allocation as the closure itself, so that
+//! creating one performs no allocation besides libffi’s. Programs that
+//! use only these closures do not link the dynamic type machinery at
+//! all.
+//!
+//! This module is enabled by the `high-only` feature. Scalar closures
+//! are counted by the `metrics` feature, but are not instrumented by the
+//! `stats` feature and are not recorded by the `trampoline-registry`
+//! feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::high::scalar::ScalarClosure2;
+//!
+//! let offset = 10;
+//! let add = |x: u32, y: u32| x + y + offset;
+//! let closure = ScalarClosure2::new(&add);
+//!
+//! assert_eq!(15, closure.code_ptr().call(2, 3));
+//! ```
+
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::{mem, ptr};
+
+use super::*;
+use crate::{low, raw};
+
+/// Scalar types, which libffi describes with a static `ffi_type`.
+///
+/// # Safety
+///
+/// [`Scalar::raw_type`] must return a static `ffi_type` that describes
+/// `Self`.
+pub unsafe trait Scalar: CType + Copy {
+    /// Gets libffi’s static description of the type.
+    fn raw_type() -> *mut low::ffi_type;
+}
+
+macro_rules! impl_scalar {
+    ( $( $type_:ty => $raw:ident ),* $(,)? ) => {
+        $(
+            unsafe impl Scalar for $type_ {
+                fn raw_type() -> *mut low::ffi_type {
+                    // The statics are only read, by libffi.
+                    #[allow(unused_unsafe)]
+                    let raw = unsafe { ptr::addr_of!(low::types::$raw) };
+                    raw as *mut low::ffi_type
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar! {
+    u8 => uint8,
+    i8 => sint8,
+    u16 => uint16,
+    i16 => sint16,
+    u32 => uint32,
+    i32 => sint32,
+    u64 => uint64,
+    i64 => sint64,
+    f32 => float,
+    f64 => double,
+    () => void,
+}
+
+#[cfg(target_pointer_width = "32")]
+impl_scalar!(usize => uint32, isize => sint32);
+#[cfg(target_pointer_width = "64")]
+impl_scalar!(usize => uint64, isize => sint64);
+
+unsafe impl<T> Scalar for *const T {
+    fn raw_type() -> *mut low::ffi_type {
+        <*mut T>::raw_type()
+    }
+}
+
+unsafe impl<T> Scalar for *mut T {
+    fn raw_type() -> *mut low::ffi_type {
+        #[allow(unused_unsafe)]
+        let raw = unsafe { ptr::addr_of!(low::types::pointer) };
+        raw as *mut low::ffi_type
+    }
+}
+
+// The CIF of a scalar closure, stored after the closure in the same
+// allocation.
+#[repr(C)]
+struct RawCif<const N: usize> {
+    cif: low::ffi_cif,
+    args: [*mut low::ffi_type; N],
+}
+
+macro_rules! define_scalar_closure {
+    ( $closure:ident $fnptr:ident $n:literal; $( $i:tt $T:ident )* ) => {
+        /// An immutable, typed closure over scalar argument and result
+        /// types.
+        pub struct $closure<'a, $( $T, )* R> {
+            alloc: *mut low::ffi_closure,
+            code: low::CodePtr,
+            _marker: PhantomData<(&'a (), fn($( $T, )*) -> R)>,
+        }
+
+        impl<'a, $( $T: Scalar, )* R: Scalar> $closure<'a, $( $T, )* R> {
+            /// Constructs a typed closure callable from C from a Rust
+            /// closure.
+            pub fn new<Callback>(callback: &'a Callback) -> Self
+            where
+                Callback: Fn($( $T, )*) -> R + 'a,
+            {
+                let (alloc, code) = low::closure_alloc_extra(mem::size_of::<RawCif<$n>>());
+                assert!(!alloc.is_null(), "closure_alloc: returned null");
+
+                unsafe {
+                    let storage = alloc.add(1) as *mut RawCif<$n>;
+                    ptr::write(
+                        ptr::addr_of_mut!((*storage).args),
+                        [$( $T::raw_type(), )*],
+                    );
+                    low::prep_cif(
+                        ptr::addr_of_mut!((*storage).cif),
+                        low::ffi_abi_FFI_DEFAULT_ABI,
+                        $n,
+                        R::raw_type(),
+                        ptr::addr_of_mut!((*storage).args) as *mut *mut low::ffi_type,
+                    )
+                    .unwrap();
+                    let status = raw::ffi_prep_closure_loc(
+                        alloc,
+                        ptr::addr_of_mut!((*storage).cif),
+                        Some(Self::call::<Callback>),
+                        callback as *const Callback as *mut c_void,
+                        code.as_mut_ptr(),
+                    );
+                    assert_eq!(raw::ffi_status_FFI_OK, status, "ffi_prep_closure_loc failed");
+                }
+                #[cfg(feature = "metrics")]
+                crate::middle::metrics::closure_created();
+
+                $closure { alloc, code, _marker: PhantomData }
+            }
+
+            #[allow(unused_variables)]
+            unsafe extern "C" fn call<Callback>(
+                _cif: *mut low::ffi_cif,
+                result: *mut c_void,
+                args: *mut *mut c_void,
+                userdata: *mut c_void,
+            ) where
+                Callback: Fn($( $T, )*) -> R + 'a,
+            {
+                crate::__private::v1::abort_on_panic("Cannot panic inside FFI callback", || {
+                    let callback = &*(userdata as *const Callback);
+                    let value = callback($( *(*args.add($i) as *const $T), )*);
+                    ptr::write(result as *mut R::RetType, value.into());
+                });
+            }
+
+            /// Gets the C code pointer that is used to invoke the
+            /// closure.
+            pub fn code_ptr(&self) -> &$fnptr<'a, $( $T, )* R> {
+                // Safety: `FnPtrN` is a transparent wrapper for the
+                // `fn` pointer of this signature, which the closure
+                // implements, and borrows the closure.
+                unsafe { self.code.as_any_ref_() }
+            }
+        }
+
+        impl<'a, $( $T, )* R> Drop for $closure<'a, $( $T, )* R> {
+            fn drop(&mut self) {
+                #[cfg(feature = "metrics")]
+                crate::middle::metrics::closure_dropped();
+                unsafe { low::closure_free(self.alloc) }
+            }
+        }
+    };
+}
+
+define_scalar_closure!(ScalarClosure0 FnPtr0 0;);
+define_scalar_closure!(ScalarClosure1 FnPtr1 1; 0 A);
+define_scalar_closure!(ScalarClosure2 FnPtr2 2; 0 A 1 B);
+define_scalar_closure!(ScalarClosure3 FnPtr3 3; 0 A 1 B 2 C);
+define_scalar_closure!(ScalarClosure4 FnPtr4 4; 0 A 1 B 2 C 3 D);
+define_scalar_closure!(ScalarClosure5 FnPtr5 5; 0 A 1 B 2 C 3 D 4 E);
+define_scalar_closure!(ScalarClosure6 FnPtr6 6; 0 A 1 B 2 C 3 D 4 E 5 F);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mixed_scalars() {
+        let f = |a: u8, b: i16, c: f32, d: f64, e: *const u64| -> i64 {
+            let e = unsafe { *e } as i64;
+            a as i64 + b as i64 + c as i64 + d as i64 + e
+        };
+        let closure = ScalarClosure5::new(&f);
+        let x = 100u64;
+        assert_eq!(
+            1 - 2 + 3 + 4 + 100,
+            closure.code_ptr().call(1, -2, 3.5, 4.5, &x)
+        );
+
+        let negate = |x: i8| -x;
+        let closure = ScalarClosure1::new(&negate);
+        assert_eq!(-5, closure.code_ptr().call(5));
+
+        let constant = || 0.25f32;
+        let closure = ScalarClosure0::new(&constant);
+        assert_eq!(0.25, closure.code_ptr().call());
+    }
+}
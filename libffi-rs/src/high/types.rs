@@ -1,6 +1,133 @@
 //! Representations of C types for the high layer.
+//!
+//! # Pointer parameters
+//!
+//! Pointer types in typed signatures record whether the pointee may be
+//! modified:
+//!
+//!   - `*const T` and `&T` correspond to a C `const T*`;
+//!   - `*mut T` corresponds to a C `T*`.
+//!
+//! Because Rust never coerces a shared reference to a mutable pointer,
+//! passing `&T` where a signature declares `*mut T` is a compile
+//! error:
+//!
+//! ```compile_fail
+//! use libffi::high::Closure1;
+//!
+//! let bump = |p: *mut u32| unsafe { *p += 1 };
+//! let closure = Closure1::new(&bump);
+//!
+//! let x = 0u32;
+//! closure.code_ptr().call(&x); // error: expected `*mut u32`, found `&u32`
+//! ```
+//!
+//! Passing `&mut x` coerces to `*mut u32` as expected. If the C
+//! function is known not to modify its argument despite a non-`const`
+//! declaration, the check can be overridden explicitly with a cast such
+//! as `&x as *const u32 as *mut u32`.
+//!
+//! A `&T` parameter must never receive NULL from C; use `*const T` or
+//! `Option<&T>` if the pointer may be null. `Option<&T>` and
+//! `Option<NonNull<T>>` are laid out as nullable pointers, so `None` is
+//! passed as NULL, and a NULL result is returned as `None`:
+//!
+//! ```
+//! use libffi::high::Closure1;
+//!
+//! let first = |p: Option<&u32>| p.copied().unwrap_or(0);
+//! let closure = Closure1::new(&first);
+//!
+//! assert_eq!(5, closure.code_ptr().call(Some(&5)));
+//! assert_eq!(0, closure.code_ptr().call(None));
+//! ```
+//!
+//! # Struct parameters
+//!
+//! A `#[repr(C)]` struct can be passed and returned by value once it
+//! implements [`CType`], using [`Type::structure`] to describe its
+//! fields. Typed closures then receive their struct arguments as Rust
+//! values, copied out of libffi’s argument storage before the closure
+//! is called:
+//!
+//! ```
+//! use libffi::high::{CType, Closure2, Type};
+//!
+//! #[derive(Clone, Copy, Debug, PartialEq)]
+//! #[repr(C)]
+//! struct Point {
+//!     x: f64,
+//!     y: f64,
+//! }
+//!
+//! unsafe impl CType for Point {
+//!     fn reify() -> Type<Self> {
+//!         unsafe { Type::structure(vec![f64::reify().into_middle(), f64::reify().into_middle()]) }
+//!     }
+//!     type RetType = Self;
+//! }
+//!
+//! let scale = |p: Point, k: f64| Point { x: p.x * k, y: p.y * k };
+//! let closure = Closure2::new(&scale);
+//!
+//! let p = closure.code_ptr().call(Point { x: 1.0, y: 2.0 }, 3.0);
+//! assert_eq!(Point { x: 3.0, y: 6.0 }, p);
+//! ```
+//!
+//! Declaring the struct with [`struct_ctype!`](crate::struct_ctype)
+//! makes it `#[repr(C)]` and writes this implementation, describing its
+//! fields in order; with the `derive` feature, `#[derive(CType)]` does
+//! the same and also accepts array fields:
+//!
+//! ```
+//! use libffi::high::{Closure1, ClosureMut1};
+//!
+//! libffi::struct_ctype! {
+//!     #[derive(Clone, Copy, Debug, PartialEq)]
+//!     pub struct Range {
+//!         pub start: u32,
+//!         pub end: u32,
+//!     }
+//! }
+//!
+//! let len = |r: Range| r.end - r.start;
+//! let closure = Closure1::new(&len);
+//! assert_eq!(5, closure.code_ptr().call(Range { start: 2, end: 7 }));
+//!
+//! let mut next = 0;
+//! let mut advance = |n: u32| {
+//!     next += n;
+//!     Range { start: next - n, end: next }
+//! };
+//! let closure = ClosureMut1::new(&mut advance);
+//! assert_eq!(Range { start: 0, end: 3 }, closure.code_ptr().call(3));
+//! assert_eq!(Range { start: 3, end: 7 }, closure.code_ptr().call(4));
+//! ```
+//!
+//! # Transparent newtypes
+//!
+//! A `#[repr(transparent)]` wrapper around a [`CType`] is passed and
+//! returned exactly like the type it wraps. Declaring it with
+//! [`transparent_ctype!`](crate::transparent_ctype) implements
+//! [`CType`] for it, including the decoding of extended return
+//! values:
+//!
+//! ```
+//! use libffi::high::Closure1;
+//!
+//! libffi::transparent_ctype! {
+//!     #[derive(Clone, Copy, Debug, PartialEq)]
+//!     pub struct Handle(pub u16);
+//! }
+//!
+//! let next = |h: Handle| Handle(h.0 + 1);
+//! let closure = Closure1::new(&next);
+//!
+//! assert_eq!(Handle(8), closure.code_ptr().call(Handle(7)));
+//! ```
 
 use std::marker::PhantomData;
+use std::ptr::NonNull;
 
 use super::super::low;
 use super::super::middle;
@@ -29,6 +156,49 @@ impl<T> Type<T> {
     pub fn into_middle(self) -> middle::Type {
         self.untyped
     }
+
+    /// Describes `T` as a structure whose fields have the given types.
+    ///
+    /// This is the building block for implementing [`CType`] for
+    /// `#[repr(C)]` structs; see [the module documentation](self) for
+    /// an example.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be a `#[repr(C)]` struct whose fields are described by
+    /// `fields`, in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size or alignment of the described structure
+    /// differs from that of `T`, which catches most mistakes in
+    /// `fields`.
+    pub unsafe fn structure<I>(fields: I) -> Self
+    where
+        I: IntoIterator<Item = middle::Type>,
+        I::IntoIter: ExactSizeIterator<Item = middle::Type>,
+    {
+        let untyped = middle::Type::structure(fields);
+        assert_eq!(
+            (std::mem::size_of::<T>(), std::mem::align_of::<T>()),
+            untyped.layout(),
+            "Type::structure: fields do not match the layout of {}",
+            std::any::type_name::<T>()
+        );
+        Type::make(untyped)
+    }
+
+    /// Describes `T` as the type it wraps.
+    ///
+    /// This is used by [`transparent_ctype!`](crate::transparent_ctype),
+    /// which should be preferred to calling it directly.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be a `#[repr(transparent)]` wrapper around `U`.
+    pub unsafe fn transparent<U>(inner: Type<U>) -> Self {
+        Type::make(inner.untyped)
+    }
 }
 
 /// Types that we can automatically marshall to/from C.
@@ -38,6 +208,11 @@ impl<T> Type<T> {
 /// This trait is unsafe to implement because if the libffi type
 /// associated with a Rust type doesn’t match then we get
 /// undefined behavior.
+///
+/// # Safety
+///
+/// The [`Type`] returned by [`CType::reify`] must describe the size,
+/// alignment, and calling-convention class of `Self` exactly.
 pub unsafe trait CType: Copy {
     /// Creates or retrieves a `Type<T>` for any type `T: CType`.
     ///
@@ -48,6 +223,166 @@ pub unsafe trait CType: Copy {
     /// return values to `ffi_arg` or `ffi_sarg`.  Track the possibly
     /// extended variant of `T` as an associated type here.
     type RetType: std::convert::From<Self> + std::convert::TryInto<Self>;
+
+    /// Checks a value passed to or returned from C for obvious
+    /// mistakes, panicking if it finds one.
+    ///
+    /// With the `pointer-checks` feature, in builds with debug
+    /// assertions, the pointer types check that they are aligned for
+    /// their pointee, and `&T` checks that it is not NULL, as it may be
+    /// if it was made from a null pointer by mistake. The dynamic
+    /// calls of [`call`](fn@crate::high::call) and
+    /// [`ffi_call!`](crate::ffi_call) check their arguments and results
+    /// this way. Otherwise, and for other types, this does nothing.
+    fn debug_check(&self) {}
+}
+
+// Checks that `ptr` is aligned for `T` and, unless `nullable`, is not
+// NULL; see `CType::debug_check`.
+#[inline(always)]
+fn check_pointer<T>(ptr: *const T, nullable: bool) {
+    #[cfg(all(feature = "pointer-checks", debug_assertions))]
+    {
+        if ptr.is_null() {
+            assert!(
+                nullable,
+                "pointer-checks: NULL passed as a `&{}`",
+                std::any::type_name::<T>()
+            );
+        } else {
+            assert!(
+                ptr as usize % std::mem::align_of::<T>() == 0,
+                "pointer-checks: {:p} is not aligned for `{}`",
+                ptr,
+                std::any::type_name::<T>()
+            );
+        }
+    }
+    #[cfg(not(all(feature = "pointer-checks", debug_assertions)))]
+    let _ = (ptr, nullable);
+}
+
+/// The [`CType::RetType`] of a transparent newtype `N` around `T`.
+///
+/// It holds the possibly extended return value of `T`, so that a
+/// result of type `N` is decoded the same way as a result of type `T`.
+/// Implementations for a newtype are generated by
+/// [`transparent_ctype!`](crate::transparent_ctype).
+#[repr(transparent)]
+pub struct NewtypeRet<T: CType, N>(T::RetType, PhantomData<N>);
+
+impl<T: CType, N> NewtypeRet<T, N> {
+    /// Extends the wrapped value of a newtype.
+    pub fn new(value: T) -> Self {
+        NewtypeRet(value.into(), PhantomData)
+    }
+
+    /// Recovers the wrapped value of a newtype.
+    pub fn get(self) -> T {
+        crate::__private::v1::from_ret_type::<T>(self.0)
+    }
+}
+
+/// Declares `#[repr(transparent)]` newtypes that implement [`CType`].
+///
+/// Each struct must be a tuple struct with a single field whose type
+/// implements [`CType`]. The struct is made `#[repr(transparent)]`, and
+/// is passed and returned like its field; since [`CType`] requires
+/// [`Copy`], the struct must also derive [`Clone`] and [`Copy`]. See
+/// [the module documentation](crate::high::types#transparent-newtypes)
+/// for an example.
+#[macro_export]
+macro_rules! transparent_ctype {
+    ($(
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident ( $(#[$fattr:meta])* $fvis:vis $inner:ty );
+    )*) => {$(
+        $(#[$attr])*
+        #[repr(transparent)]
+        $vis struct $name($(#[$fattr])* $fvis $inner);
+
+        unsafe impl $crate::high::CType for $name {
+            fn reify() -> $crate::high::Type<Self> {
+                unsafe {
+                    $crate::high::Type::transparent(<$inner as $crate::high::CType>::reify())
+                }
+            }
+            type RetType = $crate::high::types::NewtypeRet<$inner, $name>;
+        }
+
+        impl ::std::convert::From<$name> for $crate::high::types::NewtypeRet<$inner, $name> {
+            fn from(value: $name) -> Self {
+                Self::new(value.0)
+            }
+        }
+
+        impl ::std::convert::From<$crate::high::types::NewtypeRet<$inner, $name>> for $name {
+            fn from(value: $crate::high::types::NewtypeRet<$inner, $name>) -> Self {
+                $name(value.get())
+            }
+        }
+    )*};
+}
+
+/// Declares `#[repr(C)]` structs that implement [`CType`].
+///
+/// Each struct must have at least one field, and the types of its
+/// fields must implement [`CType`]; they are described to libffi as a
+/// structure of those types, in order, so the struct is passed and
+/// returned by value as C does. Both structs with named fields and
+/// tuple structs are accepted. Since [`CType`] requires [`Copy`], the
+/// struct must also derive [`Clone`] and [`Copy`]. See [the module
+/// documentation](crate::high::types#struct-parameters) for an
+/// example.
+///
+/// Array fields have no [`CType`] implementation, since C does not pass
+/// arrays by value; a struct with them can use `#[derive(CType)]` from
+/// the `derive` feature instead.
+#[macro_export]
+macro_rules! struct_ctype {
+    (@impl $name:ident, $($fty:ty),+) => {
+        unsafe impl $crate::high::CType for $name {
+            fn reify() -> $crate::high::Type<Self> {
+                // The struct is `#[repr(C)]`, and its fields are described
+                // in order.
+                unsafe {
+                    $crate::high::Type::structure(::std::vec![
+                        $(<$fty as $crate::high::CType>::reify().into_middle()),+
+                    ])
+                }
+            }
+            type RetType = Self;
+        }
+    };
+    () => {};
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$fattr:meta])* $fvis:vis $field:ident : $fty:ty),+ $(,)?
+        }
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        #[repr(C)]
+        $vis struct $name {
+            $($(#[$fattr])* $fvis $field: $fty),+
+        }
+
+        $crate::struct_ctype!(@impl $name, $($fty),+);
+        $crate::struct_ctype!($($rest)*);
+    };
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident ( $($(#[$fattr:meta])* $fvis:vis $fty:ty),+ $(,)? );
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        #[repr(C)]
+        $vis struct $name($($(#[$fattr])* $fvis $fty),+);
+
+        $crate::struct_ctype!(@impl $name, $($fty),+);
+        $crate::struct_ctype!($($rest)*);
+    };
 }
 
 macro_rules! impl_ffi_type {
@@ -125,11 +460,29 @@ impl_ffi_type!(c_c32, c32);
 #[cfg(feature = "complex")]
 impl_ffi_type!(c_c64, c64);
 
+macro_rules! impl_libc_type {
+    ($($name:ident),*) => {$(
+        #[cfg(all(unix, feature = "libc-types"))]
+        unsafe impl CType for libc::$name {
+            fn reify() -> Type<Self> {
+                Type::make(middle::Type::$name())
+            }
+            type RetType = Self;
+        }
+    )*};
+}
+
+impl_libc_type!(timespec, timeval, stat, sockaddr_storage);
+
 unsafe impl<T> CType for *const T {
     fn reify() -> Type<Self> {
         Type::make(middle::Type::pointer())
     }
     type RetType = *const T;
+
+    fn debug_check(&self) {
+        check_pointer(*self, true);
+    }
 }
 
 unsafe impl<T> CType for *mut T {
@@ -137,4 +490,228 @@ unsafe impl<T> CType for *mut T {
         Type::make(middle::Type::pointer())
     }
     type RetType = *mut T;
+
+    fn debug_check(&self) {
+        check_pointer(*self, true);
+    }
+}
+
+unsafe impl CType for middle::ReturnedFn {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::pointer())
+    }
+    type RetType = Self;
+}
+
+unsafe impl<'a, T> CType for &'a T {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::pointer())
+    }
+    type RetType = &'a T;
+
+    fn debug_check(&self) {
+        // Read the address as an integer, since the compiler may assume
+        // that a reference is not NULL.
+        let addr = unsafe { std::ptr::read(self as *const &T as *const usize) };
+        check_pointer(addr as *const T, false);
+    }
+}
+
+// `Option<&T>` and `Option<NonNull<T>>` are guaranteed to have the
+// same layout as a pointer, with `None` represented as NULL.
+unsafe impl<'a, T> CType for Option<&'a T> {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::pointer())
+    }
+    type RetType = Option<&'a T>;
+
+    fn debug_check(&self) {
+        if let Some(r) = *self {
+            check_pointer(r, false);
+        }
+    }
+}
+
+unsafe impl<T> CType for Option<NonNull<T>> {
+    fn reify() -> Type<Self> {
+        Type::make(middle::Type::pointer())
+    }
+    type RetType = Option<NonNull<T>>;
+
+    fn debug_check(&self) {
+        if let Some(p) = *self {
+            check_pointer(p.as_ptr(), false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::*;
+    use crate::ffi_call;
+    use std::ptr::NonNull;
+
+    crate::transparent_ctype! {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Level(i8);
+
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Cursor(*const u32);
+    }
+
+    crate::struct_ctype! {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Span {
+            start: *const u8,
+            len: usize,
+        }
+
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        struct Token(u8, Span, f32);
+    }
+
+    extern "C" fn skip(token: Token) -> Token {
+        let span = Span {
+            start: unsafe { token.1.start.add(1) },
+            len: token.1.len - 1,
+        };
+        Token(token.0 + 1, span, token.2 * 2.0)
+    }
+
+    extern "C" fn lower(level: Level) -> Level {
+        Level(level.0 - 10)
+    }
+
+    #[test]
+    fn transparent_newtypes() {
+        assert_eq!((1, 1), Level::reify().into_middle().layout());
+
+        let advance = |c: Cursor| Cursor(unsafe { c.0.add(1) });
+        let closure = Closure1::new(&advance);
+        let values = [1u32, 2, 3];
+        let next = closure.code_ptr().call(Cursor(values.as_ptr()));
+        assert_eq!(2, unsafe { *next.0 });
+
+        let lowered: Level = ffi_call!(lower: extern "C" fn(Level) -> Level, Level(3));
+        assert_eq!(Level(-7), lowered);
+
+        let negate = |l: Level| Level(-l.0);
+        let closure = Closure1::new(&negate);
+        assert_eq!(Level(-100), closure.code_ptr().call(Level(100)));
+    }
+
+    #[cfg(all(unix, feature = "libc-types"))]
+    #[test]
+    fn libc_structures() {
+        let add_second = |t: libc::timeval| libc::timeval {
+            tv_sec: t.tv_sec + 1,
+            ..t
+        };
+        let closure = Closure1::new(&add_second);
+        let t = closure.code_ptr().call(libc::timeval {
+            tv_sec: 41,
+            tv_usec: 250,
+        });
+        assert_eq!((42, 250), (t.tv_sec, t.tv_usec));
+    }
+
+    #[test]
+    fn struct_declarations() {
+        assert_eq!(
+            (std::mem::size_of::<Token>(), std::mem::align_of::<Token>()),
+            Token::reify().into_middle().layout()
+        );
+
+        let text = b"token";
+        let span = Span {
+            start: text.as_ptr(),
+            len: text.len(),
+        };
+        let skipped: Token = ffi_call!(skip: extern "C" fn(Token) -> Token, Token(1, span, 1.5));
+        assert_eq!(
+            Token(
+                2,
+                Span {
+                    start: unsafe { text.as_ptr().add(1) },
+                    len: 4
+                },
+                3.0
+            ),
+            skipped
+        );
+
+        let first = |t: Token, n: u8| unsafe { *t.1.start } + n;
+        let closure = Closure2::new(&first);
+        assert_eq!(b'o' + 1, closure.code_ptr().call(skipped, 1));
+    }
+
+    extern "C" fn read(p: *const u32) -> u32 {
+        unsafe { p.read_unaligned() }
+    }
+
+    extern "C" fn null() -> *const u16 {
+        std::ptr::null()
+    }
+
+    #[test]
+    #[cfg(all(feature = "pointer-checks", debug_assertions))]
+    #[should_panic(expected = "is not aligned for `u32`")]
+    fn unaligned_argument() {
+        let bytes = [0u8; 8];
+        let p = bytes.as_ptr().wrapping_add(1 + bytes.as_ptr() as usize % 4) as *const u32;
+        ffi_call!(read: extern "C" fn(*const u32) -> u32, p);
+    }
+
+    #[test]
+    #[cfg(all(feature = "pointer-checks", debug_assertions))]
+    #[should_panic(expected = "is not aligned for `u64`")]
+    fn misaligned_result() {
+        extern "C" fn misaligned() -> *const u64 {
+            12 as *const u64
+        }
+
+        unsafe { ffi_call!(misaligned() -> *const u64) };
+    }
+
+    #[test]
+    #[cfg(all(feature = "pointer-checks", debug_assertions))]
+    #[should_panic(expected = "NULL passed as a `&u16`")]
+    fn null_reference() {
+        // Making a null reference to pass is itself undefined behavior,
+        // so this checks the address as `<&T>::debug_check` would.
+        super::check_pointer::<u16>(std::ptr::null(), false);
+    }
+
+    #[test]
+    fn checked_pointers() {
+        let x = 5u32;
+        assert_eq!(5, ffi_call!(read: extern "C" fn(*const u32) -> u32, &x));
+        let null: *const u16 = unsafe { ffi_call!(null() -> *const u16) };
+        assert!(null.is_null());
+    }
+
+    extern "C" fn non_null(p: *mut u8) -> *mut u8 {
+        p
+    }
+
+    #[test]
+    fn nullable_pointers() {
+        let deref = |p: Option<&i64>| p.map_or(-1, |x| *x);
+        let closure = Closure1::new(&deref);
+        assert_eq!(42, closure.code_ptr().call(Some(&42)));
+        assert_eq!(-1, closure.code_ptr().call(None));
+
+        let mut byte = 7u8;
+        let p = NonNull::new(&mut byte as *mut u8);
+        let back = unsafe { ffi_call!(non_null(p) -> Option<NonNull<u8>>) };
+        assert_eq!(p, back);
+        let none: Option<NonNull<u8>> = None;
+        let back = unsafe { ffi_call!(non_null(none) -> Option<NonNull<u8>>) };
+        assert_eq!(None, back);
+
+        let pass = |p: Option<&'static u16>| p;
+        let closure = Closure1::new(&pass);
+        assert_eq!(None, closure.code_ptr().call(None));
+        assert_eq!(Some(&3), closure.code_ptr().call(Some(&3)));
+    }
 }
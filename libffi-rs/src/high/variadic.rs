@@ -0,0 +1,246 @@
+//! Typed closures that C calls with variable arguments.
+//!
+//! Some C APIs take callbacks of variadic type, such as the logging hook
+//! `void (*)(int level, const char *format, ...)`. The
+//! <code>VariadicClosure<em>N</em></code> and
+//! <code>VariadicClosureMut<em>N</em></code> types here create such
+//! callbacks from Rust closures: the *`N`* fixed arguments are passed
+//! to the Rust closure as typed values, as for
+//! [`Closure1`](super::Closure1) and its siblings, and the variable
+//! arguments that follow as a slice of raw pointers to them.
+//!
+//! A C function cannot find out how many variable arguments it was
+//! passed, and neither can a closure, so its CIF, prepared with
+//! `ffi_prep_cif_var`, fixes the types of the variable arguments when
+//! the closure is created. The closure must then only be called with
+//! variable arguments of those types, as the format string of a logging
+//! hook typically guarantees. As for
+//! [`middle::Cif::new_variadic`](crate::middle::Cif::new_variadic), the
+//! types are those after C’s default argument promotions, such as `f64`
+//! rather than `f32`.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::os::raw::c_void;
+//!
+//! use libffi::high::variadic::VariadicClosure1;
+//! use libffi::middle::Type;
+//!
+//! let sum = |count: i32, rest: &[*const c_void]| {
+//!     assert_eq!(count as usize, rest.len());
+//!     rest.iter().map(|&arg| unsafe { *(arg as *const f64) }).sum::<f64>()
+//! };
+//! let closure = VariadicClosure1::new(vec![Type::f64(), Type::f64()], &sum);
+//! let fun = closure.code_ptr();
+//!
+//! assert_eq!(3.5, unsafe { fun(2, 1.25f64, 2.25f64) });
+//! ```
+
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::{ptr, slice};
+
+use super::CType;
+use crate::{low, middle};
+
+// Builds the variadic CIF of a closure with the given fixed and
+// variable argument types.
+fn variadic_cif<R: CType>(
+    fixed: Vec<middle::Type>,
+    variadic: impl IntoIterator<Item = middle::Type>,
+) -> middle::Cif {
+    let nfixed = fixed.len();
+    let mut args = fixed;
+    args.extend(variadic);
+    middle::Cif::new_variadic(args, nfixed, R::reify().into_middle())
+}
+
+// Gets the variable arguments of a call from the argument array.
+unsafe fn rest<'a>(
+    cif: &low::ffi_cif,
+    args: *const *const c_void,
+    nfixed: usize,
+) -> &'a [*const c_void] {
+    slice::from_raw_parts(args.add(nfixed), cif.nargs as usize - nfixed)
+}
+
+macro_rules! define_variadic_closure {
+    ( $closure:ident $closure_mut:ident $n:literal; $( $i:tt $T:ident )* ) => {
+        /// An immutable, typed closure with the given fixed argument and
+        /// result types, which C calls with variable arguments.
+        pub struct $closure<'a, $( $T, )* R> {
+            untyped: middle::Closure<'a>,
+            _marker: PhantomData<fn($( $T, )*) -> R>,
+        }
+
+        impl<'a, $( $T: CType, )* R: CType> $closure<'a, $( $T, )* R> {
+            /// Constructs a closure callable from C from a Rust closure,
+            /// which C calls with variable arguments of the types
+            /// `variadic`.
+            pub fn new<Callback, I>(variadic: I, callback: &'a Callback) -> Self
+            where
+                Callback: Fn($( $T, )* &[*const c_void]) -> R + 'a,
+                I: IntoIterator<Item = middle::Type>,
+            {
+                let cif = variadic_cif::<R>(vec![$( $T::reify().into_middle(), )*], variadic);
+                $closure {
+                    untyped: middle::Closure::new(cif, Self::call::<Callback>, callback),
+                    _marker: PhantomData,
+                }
+            }
+
+            unsafe extern "C" fn call<Callback>(
+                cif: &low::ffi_cif,
+                result: &mut R::RetType,
+                args: *const *const c_void,
+                callback: &Callback,
+            ) where
+                Callback: Fn($( $T, )* &[*const c_void]) -> R + 'a,
+            {
+                crate::__private::v1::abort_on_panic("Cannot panic inside FFI callback", || {
+                    let rest = rest(cif, args, $n);
+                    let value = callback($( ptr::read(*args.add($i) as *const $T), )* rest);
+                    ptr::write(result, value.into());
+                });
+            }
+
+            /// Gets the C code pointer that is used to invoke the
+            /// closure.
+            ///
+            /// Calling it is unsafe, since the variable arguments must
+            /// have the types the closure was created with.
+            pub fn code_ptr(&self) -> &unsafe extern "C" fn($( $T, )* ...) -> R {
+                // Safety: the closure implements this signature, given
+                // the right variable arguments.
+                unsafe { self.untyped.instantiate_code_ptr() }
+            }
+
+            /// Returns whether `addr` lies within the closure’s
+            /// trampoline; see
+            /// [`middle::Closure::contains_address`].
+            pub fn contains_address(&self, addr: *const c_void) -> bool {
+                self.untyped.contains_address(addr)
+            }
+        }
+
+        /// A mutable, typed closure with the given fixed argument and
+        /// result types, which C calls with variable arguments.
+        pub struct $closure_mut<'a, $( $T, )* R> {
+            untyped: middle::Closure<'a>,
+            _marker: PhantomData<fn($( $T, )*) -> R>,
+        }
+
+        impl<'a, $( $T: CType, )* R: CType> $closure_mut<'a, $( $T, )* R> {
+            /// Constructs a closure callable from C from a mutable Rust
+            /// closure, which C calls with variable arguments of the
+            /// types `variadic`.
+            pub fn new<Callback, I>(variadic: I, callback: &'a mut Callback) -> Self
+            where
+                Callback: FnMut($( $T, )* &[*const c_void]) -> R + 'a,
+                I: IntoIterator<Item = middle::Type>,
+            {
+                let cif = variadic_cif::<R>(vec![$( $T::reify().into_middle(), )*], variadic);
+                $closure_mut {
+                    untyped: middle::Closure::new_mut(cif, Self::call::<Callback>, callback),
+                    _marker: PhantomData,
+                }
+            }
+
+            unsafe extern "C" fn call<Callback>(
+                cif: &low::ffi_cif,
+                result: &mut R::RetType,
+                args: *const *const c_void,
+                callback: &mut Callback,
+            ) where
+                Callback: FnMut($( $T, )* &[*const c_void]) -> R + 'a,
+            {
+                crate::__private::v1::abort_on_panic("Cannot panic inside FFI callback", || {
+                    let rest = rest(cif, args, $n);
+                    let value = callback($( ptr::read(*args.add($i) as *const $T), )* rest);
+                    ptr::write(result, value.into());
+                });
+            }
+
+            /// Gets the C code pointer that is used to invoke the
+            /// closure.
+            ///
+            /// Calling it is unsafe, since the variable arguments must
+            /// have the types the closure was created with.
+            pub fn code_ptr(&self) -> &unsafe extern "C" fn($( $T, )* ...) -> R {
+                unsafe { self.untyped.instantiate_code_ptr() }
+            }
+
+            /// Returns whether `addr` lies within the closure’s
+            /// trampoline; see
+            /// [`middle::Closure::contains_address`].
+            pub fn contains_address(&self, addr: *const c_void) -> bool {
+                self.untyped.contains_address(addr)
+            }
+        }
+    };
+}
+
+define_variadic_closure!(VariadicClosure1 VariadicClosureMut1 1; 0 A);
+define_variadic_closure!(VariadicClosure2 VariadicClosureMut2 2; 0 A 1 B);
+define_variadic_closure!(VariadicClosure3 VariadicClosureMut3 3; 0 A 1 B 2 C);
+define_variadic_closure!(VariadicClosure4 VariadicClosureMut4 4; 0 A 1 B 2 C 3 D);
+define_variadic_closure!(VariadicClosure5 VariadicClosureMut5 5; 0 A 1 B 2 C 3 D 4 E);
+define_variadic_closure!(VariadicClosure6 VariadicClosureMut6 6; 0 A 1 B 2 C 3 D 4 E 5 F);
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CStr;
+    use std::os::raw::{c_char, c_int};
+
+    use super::*;
+    use crate::middle::Type;
+
+    #[test]
+    fn mixed_variable_arguments() {
+        let describe = |level: u8, format: *const c_char, rest: &[*const c_void]| {
+            let format = unsafe { CStr::from_ptr(format) }.to_str().unwrap();
+            let n = unsafe { *(rest[0] as *const c_int) };
+            let x = unsafe { *(rest[1] as *const f64) };
+            let s = unsafe { CStr::from_ptr(*(rest[2] as *const *const c_char)) };
+            i64::from(level) * 1000
+                + i64::from(n)
+                + x as i64
+                + format.len() as i64
+                + s.to_bytes().len() as i64
+        };
+        let closure =
+            VariadicClosure2::new(vec![Type::i32(), Type::f64(), Type::pointer()], &describe);
+        let fun = closure.code_ptr();
+        let result = unsafe {
+            fun(
+                2,
+                b"%d %f %s\0".as_ptr() as *const c_char,
+                -5 as c_int,
+                10.5f64,
+                b"abc\0".as_ptr() as *const c_char,
+            )
+        };
+        assert_eq!(2000 - 5 + 10 + 8 + 3, result);
+        assert!(closure.contains_address(*fun as *const c_void));
+    }
+
+    #[test]
+    fn mutable_and_empty() {
+        let mut seen = vec![];
+        let mut record = |tag: u32, rest: &[*const c_void]| {
+            seen.push((tag, rest.len()));
+        };
+        {
+            let closure = VariadicClosureMut1::new(vec![], &mut record);
+            let fun = closure.code_ptr();
+            unsafe { fun(7) };
+        }
+        {
+            let closure = VariadicClosureMut1::new(vec![Type::u64()], &mut record);
+            let fun = closure.code_ptr();
+            unsafe { fun(8, 1u64) };
+        }
+        assert_eq!(vec![(7, 0), (8, 1)], seen);
+    }
+}
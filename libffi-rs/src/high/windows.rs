@@ -0,0 +1,305 @@
+//! Types of the Win32 API and its return conventions.
+//!
+//! This module is enabled by `#[cfg(feature = "windows-types")]`.
+//!
+//! Win32 functions mostly return one of two things: a [`BOOL`] that is
+//! `FALSE` on failure, with the reason left for `GetLastError`, or an
+//! [`HRESULT`] whose sign tells success from failure. The types here
+//! are [`CType`]s laid out as their C definitions, so they can appear
+//! in typed calls and closures, and the [`FromFfiReturn`] trait turns
+//! such a result into a [`Result`]. [`call_checked`] makes a dynamic
+//! call and converts its result immediately, before anything else can
+//! overwrite the thread’s last error, and [`call_checked_reported`]
+//! also captures a [`CallReport`] of a call that failed.
+//!
+//! The types are available on every target, so that code binding the
+//! Win32 API dynamically can be built and tested elsewhere; the last
+//! error is read with [`io::Error::last_os_error`], which is
+//! `GetLastError` on Windows and `errno` on other targets.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::high::call::arg;
+//! use libffi::high::windows::{call_checked, BOOL, HRESULT};
+//! use libffi::middle::CodePtr;
+//!
+//! extern "C" fn check(x: u32) -> BOOL {
+//!     BOOL::from(x % 2 == 0)
+//! }
+//!
+//! extern "C" fn query(x: u32) -> HRESULT {
+//!     if x == 0 { HRESULT::E_INVALIDARG } else { HRESULT::S_OK }
+//! }
+//!
+//! unsafe {
+//!     assert!(call_checked::<BOOL>(CodePtr(check as *mut _), &[arg(&4u32)]).is_ok());
+//!     assert!(call_checked::<BOOL>(CodePtr(check as *mut _), &[arg(&5u32)]).is_err());
+//!
+//!     let ok = call_checked::<HRESULT>(CodePtr(query as *mut _), &[arg(&1u32)]);
+//!     assert_eq!(Ok(HRESULT::S_OK), ok);
+//!     let err = call_checked::<HRESULT>(CodePtr(query as *mut _), &[arg(&0u32)]);
+//!     assert_eq!(Err(HRESULT::E_INVALIDARG), err);
+//! }
+//! ```
+
+use std::os::raw::c_void;
+use std::{error, fmt, io, ptr};
+
+use super::call::{call, Arg, CodePtr};
+use super::CType;
+use crate::middle::{self, CallReport};
+
+crate::transparent_ctype! {
+    /// The Win32 `BOOL`, a 32-bit integer that is nonzero for true.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct BOOL(pub i32);
+
+    /// The Win32 `HRESULT`, a 32-bit status code that is negative for
+    /// failures.
+    #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct HRESULT(pub i32);
+
+    /// The Win32 `HANDLE`, an opaque pointer to a kernel object.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct HANDLE(pub *mut c_void);
+}
+
+impl BOOL {
+    /// `FALSE`.
+    pub const FALSE: BOOL = BOOL(0);
+    /// `TRUE`.
+    pub const TRUE: BOOL = BOOL(1);
+
+    /// Whether the value is true, that is, nonzero.
+    pub fn as_bool(self) -> bool {
+        self.0 != 0
+    }
+}
+
+impl From<bool> for BOOL {
+    fn from(value: bool) -> Self {
+        BOOL(value as i32)
+    }
+}
+
+impl From<BOOL> for bool {
+    fn from(value: BOOL) -> Self {
+        value.as_bool()
+    }
+}
+
+impl HRESULT {
+    /// `S_OK`, success.
+    pub const S_OK: HRESULT = HRESULT(0);
+    /// `S_FALSE`, success with a negative answer.
+    pub const S_FALSE: HRESULT = HRESULT(1);
+    /// `E_FAIL`, an unspecified failure.
+    pub const E_FAIL: HRESULT = HRESULT(0x8000_4005_u32 as i32);
+    /// `E_INVALIDARG`, an invalid argument.
+    pub const E_INVALIDARG: HRESULT = HRESULT(0x8007_0057_u32 as i32);
+
+    /// Whether the code reports success, as the `SUCCEEDED` macro
+    /// tests.
+    pub fn is_ok(self) -> bool {
+        self.0 >= 0
+    }
+
+    /// Whether the code reports failure, as the `FAILED` macro tests.
+    pub fn is_err(self) -> bool {
+        !self.is_ok()
+    }
+}
+
+impl fmt::Debug for HRESULT {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HRESULT({:#010x})", self.0 as u32)
+    }
+}
+
+impl fmt::Display for HRESULT {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HRESULT {:#010x}", self.0 as u32)
+    }
+}
+
+impl error::Error for HRESULT {}
+
+impl HANDLE {
+    /// `INVALID_HANDLE_VALUE`, which functions such as `CreateFileW`
+    /// return on failure.
+    pub const INVALID: HANDLE = HANDLE(-1isize as *mut c_void);
+
+    /// The null handle, which other functions return on failure.
+    pub fn null() -> Self {
+        HANDLE(ptr::null_mut())
+    }
+
+    /// Whether the handle is neither null nor `INVALID_HANDLE_VALUE`.
+    pub fn is_valid(self) -> bool {
+        !self.0.is_null() && self != HANDLE::INVALID
+    }
+}
+
+/// Return types with a convention for reporting failure, which a
+/// result is converted by as soon as it is returned.
+pub trait FromFfiReturn: CType {
+    /// What the result is converted into.
+    type Output;
+
+    /// Converts a result that was just returned.
+    ///
+    /// This may read state the function left for its caller, such as
+    /// the thread’s last error, so it must run before anything else
+    /// that could change that state.
+    fn from_ffi_return(ret: Self) -> Self::Output;
+}
+
+/// `FALSE` captures the thread’s last error.
+impl FromFfiReturn for BOOL {
+    type Output = Result<(), io::Error>;
+
+    fn from_ffi_return(ret: Self) -> Self::Output {
+        if ret.as_bool() {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// Failure codes are errors; success codes, including `S_FALSE`, are
+/// kept.
+impl FromFfiReturn for HRESULT {
+    type Output = Result<HRESULT, HRESULT>;
+
+    fn from_ffi_return(ret: Self) -> Self::Output {
+        if ret.is_ok() {
+            Ok(ret)
+        } else {
+            Err(ret)
+        }
+    }
+}
+
+/// Performs a dynamic call to a C function, as [`call`] does, and
+/// converts its result with [`FromFfiReturn`].
+///
+/// # Safety
+///
+/// There is no checking that `fun` actually takes arguments of the
+/// types given by `args` or returns a value of type `R`.
+pub unsafe fn call_checked<R: FromFfiReturn>(fun: CodePtr, args: &[Arg]) -> R::Output {
+    R::from_ffi_return(call::<R>(fun, args))
+}
+
+/// Performs a dynamic call to a C function, as [`call_checked`] does,
+/// and if the converted result is an error, pairs it with a
+/// [`CallReport`] of the call for crash logs.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::high::call::arg;
+/// use libffi::high::windows::{call_checked_reported, HRESULT};
+/// use libffi::middle::{CodePtr, Value};
+///
+/// extern "C" fn query(x: u32) -> HRESULT {
+///     if x == 0 { HRESULT::E_INVALIDARG } else { HRESULT::S_OK }
+/// }
+///
+/// let fun = CodePtr(query as *mut _);
+/// let result = unsafe { call_checked_reported::<HRESULT, _, _>(fun, &[arg(&0u32)]) };
+/// let (error, report) = result.unwrap_err();
+/// assert_eq!(HRESULT::E_INVALIDARG, error);
+/// assert_eq!("(u32) -> i32", report.signature);
+/// assert_eq!(vec![Value::U32(0)], report.args);
+/// ```
+///
+/// # Safety
+///
+/// There is no checking that `fun` actually takes arguments of the
+/// types given by `args` or returns a value of type `R`.
+pub unsafe fn call_checked_reported<R, T, E>(
+    fun: CodePtr,
+    args: &[Arg],
+) -> Result<T, (E, CallReport)>
+where
+    R: FromFfiReturn<Output = Result<T, E>>,
+    E: fmt::Display,
+{
+    call_checked::<R>(fun, args).map_err(|error| {
+        let types = args.iter().map(|arg| arg.parts().0.clone());
+        let cif = middle::Cif::new(types, R::reify().into_middle());
+        let values = args
+            .iter()
+            .map(|arg| arg.parts().1.clone())
+            .collect::<Vec<_>>();
+        let report = CallReport::capture(&cif, fun, &values, error.to_string());
+        (error, report)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::high::arg;
+
+    extern "C" fn touch(path: *const u8) -> BOOL {
+        let path = unsafe { std::ffi::CStr::from_ptr(path as *const _) };
+        BOOL::from(std::fs::metadata(path.to_str().unwrap()).is_ok())
+    }
+
+    #[test]
+    fn last_error_on_false() {
+        let missing = b"/nonexistent/libffi-windows-types\0";
+        let result =
+            unsafe { call_checked::<BOOL>(CodePtr(touch as *mut _), &[arg(&missing.as_ptr())]) };
+        assert_eq!(io::ErrorKind::NotFound, result.unwrap_err().kind());
+
+        let found = concat!(env!("CARGO_MANIFEST_DIR"), "\0");
+        let result =
+            unsafe { call_checked::<BOOL>(CodePtr(touch as *mut _), &[arg(&found.as_ptr())]) };
+        assert!(result.is_ok());
+    }
+
+    extern "C" fn status(code: i32) -> HRESULT {
+        HRESULT(code)
+    }
+
+    #[test]
+    fn hresults() {
+        let fun = status as *mut _;
+        let result = unsafe { call_checked::<HRESULT>(CodePtr(fun), &[arg(&1i32)]) };
+        assert_eq!(Ok(HRESULT::S_FALSE), result);
+        let result = unsafe { call_checked::<HRESULT>(CodePtr(fun), &[arg(&HRESULT::E_FAIL.0)]) };
+        assert_eq!("HRESULT 0x80004005", result.unwrap_err().to_string());
+    }
+
+    #[test]
+    fn reported_failures() {
+        let fun = CodePtr(status as *mut _);
+        let result = unsafe { call_checked_reported::<HRESULT, _, _>(fun, &[arg(&1i32)]) };
+        assert_eq!(Ok(HRESULT::S_FALSE), result);
+
+        let code = HRESULT::E_FAIL.0;
+        let result = unsafe { call_checked_reported::<HRESULT, _, _>(fun, &[arg(&code)]) };
+        let (error, report) = result.unwrap_err();
+        assert_eq!(HRESULT::E_FAIL, error);
+        assert_eq!("HRESULT 0x80004005", report.reason);
+        assert_eq!(status as *const () as u64, report.function);
+        assert_eq!(vec![middle::Value::I32(code)], report.args);
+    }
+
+    #[test]
+    fn handles() {
+        assert_eq!(
+            (std::mem::size_of::<usize>(), std::mem::align_of::<usize>()),
+            HANDLE::reify().into_middle().layout()
+        );
+        assert!(!HANDLE::null().is_valid());
+        assert!(!HANDLE::INVALID.is_valid());
+        let x = 0u8;
+        assert!(HANDLE(&x as *const u8 as *mut c_void).is_valid());
+    }
+}
@@ -38,7 +38,65 @@
 //! See [the `libffi-sys` documentation] for more information about how it
 //! finds C libffi.
 //!
-//! This crate supports Rust version 1.48 and later.
+//! Enabling the `stats` feature makes closures record the latencies of
+//! their calls; see [`middle::stats`] for details.
+//! Enabling the `trampoline-registry` feature lets code addresses be
+//! mapped back to live closures; see `middle::trampolines`.
+//! Enabling the `high-only` feature adds closures over scalar types that
+//! need no heap-allocated type descriptions; see `high::scalar`.
+//! Enabling the `min-size` feature makes the crate smaller, for
+//! embedded use: internal errors abort the process with a fixed message
+//! instead of panicking with a formatted one, and the `Debug` output of
+//! types, CIFs and closures shows only their names.
+//! Enabling the `metrics` feature reports gauges of live closures and
+//! CIFs through the `metrics` crate; see `middle::metrics`.
+//! Enabling the `pointer-checks` feature makes dynamic calls in the
+//! `high` layer check, in debug builds, that pointer arguments and
+//! results are aligned and that references are not NULL; see
+//! `high::CType::debug_check`.
+//! Enabling the `derive` feature provides `#[derive(CType)]` for
+//! `#[repr(C)]` structs; see `high::CType`.
+//! Enabling the `libloading` feature adds `high::libloading`, for
+//! calling functions loaded with the `libloading` crate.
+//! Enabling the `deferred-reclaim` feature makes dropping a closure
+//! hand its executable memory to a background thread to free, so that
+//! drops take no locks; see `middle::reclaim`. Enabling
+//! `safe-reclaim` as well makes closures count their calls in progress,
+//! so that a closure dropped while C is still calling it waits for
+//! those calls, on a best-effort basis.
+//! Enabling the `serde` feature implements `Serialize` and `Deserialize`
+//! for [`middle::Value`].
+//! Enabling the `libc-types` feature adds, on Unix, types of common C
+//! library structures such as `timespec`, with [`high::CType`]
+//! implementations for their `libc` definitions.
+//! Enabling the `windows-types` feature adds the Win32 `BOOL`,
+//! `HRESULT` and `HANDLE` types and the conversion of their failure
+//! results into errors; see `high::windows`.
+//! Enabling the `capi` feature exports a C interface to the middle
+//! layer, for building the crate as a C library; see `capi`.
+//!
+//! The `libc` feature, enabled by default, uses the C library on Unix
+//! for [`library`] and, on Linux, `middle::syscall`. Disabling it drops
+//! the `libc` dependency, for platforms it does not support; those
+//! modules are then unavailable.
+//!
+//! The `std` feature, also enabled by default, may be disabled to build
+//! the crate with only `core` and `alloc`, for targets that have libffi
+//! but not the standard library. The [`mod@raw`] and [`mod@low`] layers
+//! remain, as do the types, CIFs and calls of the [`mod@middle`] layer;
+//! closures, the [`mod@high`] layer and the modules that need threads,
+//! locks or the operating system are then unavailable. This needs
+//! `libffi-sys` to be built without its own `std` feature as well,
+//! which disabling this one does.
+//!
+//! On WebAssembly, only `wasm32-unknown-emscripten` is supported, with
+//! the `system` feature and a libffi built with Emscripten, since the
+//! bundled libffi has no WebAssembly port. Closures there are entries
+//! in the function table rather than executable memory. Where libffi
+//! cannot create a closure, the fallible constructors, such as
+//! [`middle::Closure::try_new`], return an [`Error`] instead.
+//!
+//! This crate supports Rust version 1.70 and later.
 //!
 //! # Organization
 //!
@@ -63,6 +121,17 @@
 //! layers (and it will be considered a bug to the extent that it
 //! isn’t).
 //!
+//! The [`prelude`] module re-exports the most commonly used items from
+//! all of the layers, for code that would rather not depend on where
+//! each item lives.
+//!
+//! The [`library`] module loads shared libraries at run time and binds
+//! their functions to CIFs; [`dynamic_extern!`] builds on it to declare
+//! such functions much like an `extern` block, and the [`plugin`]
+//! module checks the functions of a plugin against a table of their
+//! signatures that it embeds before binding them. The [`restricted`]
+//! module binds only functions and signatures a host has approved.
+//!
 //! # Examples
 //!
 //! In this example, we convert a Rust lambda containing a free variable
@@ -70,6 +139,7 @@
 //! `extern "C" fn(u64, u64) -> u64`.
 //!
 //! ```
+//! # #[cfg(feature = "std")] {
 //! use libffi::high::Closure2;
 //!
 //! let x = 5u64;
@@ -79,6 +149,7 @@
 //! let fun     = closure.code_ptr();
 //!
 //! assert_eq!(18, fun.call(6, 7));
+//! # }
 //! ```
 //!
 //! [the `libffi-sys` crate]: https://crates.io/crates/libffi-sys/
@@ -87,6 +158,11 @@
 //!
 
 #![deny(missing_docs)]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+// Without `std`, the crate uses only `core` and `alloc`; the modules
+// that need more are gated on the feature.
+extern crate alloc;
 
 /// Raw definitions imported from the C library (via bindgen).
 ///
@@ -96,6 +172,35 @@ pub mod raw {
     pub use libffi_sys::*;
 }
 
+#[macro_use]
+mod sys;
+
+mod error;
+pub use error::Error;
+
+#[cfg(all(feature = "capi", feature = "std"))]
+pub mod capi;
+
+#[cfg(feature = "std")]
 pub mod high;
+#[cfg(all(any(all(unix, feature = "libc"), windows), feature = "std"))]
+pub mod library;
 pub mod low;
 pub mod middle;
+#[cfg(feature = "std")]
+pub mod plugin;
+#[cfg(feature = "std")]
+pub mod prelude;
+#[cfg(all(any(all(unix, feature = "libc"), windows), feature = "std"))]
+pub mod restricted;
+#[cfg(feature = "std")]
+pub mod rt;
+
+#[cfg(feature = "std")]
+pub mod self_test;
+#[cfg(feature = "std")]
+pub use self_test::{self_test, SelfTestError};
+
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub mod __private;
@@ -0,0 +1,1059 @@
+//! Loading shared libraries and binding their functions to CIFs.
+//!
+//! A [`Library`] is a handle to a dynamically loaded shared library.
+//! Its functions can be looked up by name as [`CodePtr`]s, or bound
+//! together with a [CIF](Cif) as [`BoundFn`]s ready to be called.
+//!
+//! For a declarative interface similar to an `extern` block, see
+//! [`dynamic_extern!`](crate::dynamic_extern).
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(target_os = "linux")] {
+//! use libffi::library::Library;
+//! use libffi::middle::*;
+//!
+//! let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+//! let cos = libm
+//!     .bind("cos", Cif::new(vec![Type::f64()], Type::f64()))
+//!     .unwrap();
+//!
+//! let x: f64 = unsafe { cos.call(&[arg(&0f64)]) };
+//! assert_eq!(1.0, x);
+//! # }
+//! ```
+
+use std::error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::middle::{BoundFn, Cif, CodePtr, Owner};
+
+pub use crate::middle::LibraryClosed;
+
+/// Errors that occur while loading libraries and looking up symbols.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The library could not be loaded.
+    Open {
+        /// The name or path of the library.
+        library: String,
+        /// The reason given by the operating system.
+        message: String,
+    },
+    /// The symbol could not be found in the library.
+    Symbol {
+        /// The name of the symbol.
+        symbol: String,
+        /// The reason given by the operating system.
+        message: String,
+    },
+    /// The library has been [closed](Library::close_when_unused).
+    Closed,
+}
+
+impl From<LibraryClosed> for Error {
+    fn from(_: LibraryClosed) -> Self {
+        Error::Closed
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Open { library, message } => {
+                write!(f, "could not load library `{}`: {}", library, message)
+            }
+            Error::Symbol { symbol, message } => {
+                write!(f, "could not find symbol `{}`: {}", symbol, message)
+            }
+            Error::Closed => LibraryClosed.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The [`std::result::Result`] type specialized for library [`Error`]s.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A dynamically loaded shared library.
+///
+/// Cloning a `Library` produces another handle to the same loaded
+/// library. The library is unloaded once every handle, and every
+/// [`BoundFn`] bound from it, has been dropped, so a `BoundFn` can
+/// never call into an unloaded library. Raw code pointers obtained
+/// with [`symbol`](Library::symbol) are not tracked, however, and must
+/// not be used once the library is unloaded.
+///
+/// To unload a library while `BoundFn`s may still be around, use
+/// [`close_when_unused`](Library::close_when_unused).
+#[derive(Clone, Debug)]
+pub struct Library {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    handle: sys::Handle,
+    closed: AtomicBool,
+}
+
+// Library handles may be used and released from any thread.
+unsafe impl Send for Inner {}
+unsafe impl Sync for Inner {}
+
+impl Owner for Inner {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        unsafe { sys::close(self.handle) }
+    }
+}
+
+impl Library {
+    /// Loads the shared library with the given file name or path.
+    ///
+    /// The name is resolved using the platform’s usual search rules.
+    /// To control how the library is found and loaded, use
+    /// [`OpenOptions`] instead.
+    ///
+    /// # Safety
+    ///
+    /// Loading a library runs its initialization routines, which may
+    /// do anything at all.
+    pub unsafe fn open<P: AsRef<OsStr>>(name: P) -> Result<Self> {
+        OpenOptions::new().open(name)
+    }
+
+    /// Gets a handle for resolving symbols from the running process.
+    ///
+    /// Symbols are looked up in the program itself and in the libraries
+    /// it was linked against (on Unix, any library loaded into the
+    /// global namespace as well), so statically linked and interposed
+    /// functions can be bound the same way as those of a loaded library.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(unix)] {
+    /// use libffi::library::Library;
+    /// use libffi::middle::*;
+    ///
+    /// let this = Library::this_process().unwrap();
+    /// let abs = this
+    ///     .bind("abs", Cif::new(vec![Type::c_int()], Type::c_int()))
+    ///     .unwrap();
+    ///
+    /// let n: std::os::raw::c_int = unsafe { abs.call(&[arg(&-4)]) };
+    /// assert_eq!(4, n);
+    /// # }
+    /// ```
+    pub fn this_process() -> Result<Self> {
+        unsafe { sys::this_process() }
+            .map(Library::from_handle)
+            .map_err(|message| Error::Open {
+                library: "<this process>".to_owned(),
+                message,
+            })
+    }
+
+    fn from_handle(handle: sys::Handle) -> Self {
+        Library {
+            inner: Arc::new(Inner {
+                handle,
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    // Gets the handle, unless the library has been closed.
+    fn handle(&self) -> Result<sys::Handle> {
+        if self.is_closed() {
+            Err(Error::Closed)
+        } else {
+            Ok(self.inner.handle)
+        }
+    }
+
+    /// Returns whether the library has been
+    /// [closed](Library::close_when_unused).
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Closes the library, unloading it once it is no longer in use.
+    ///
+    /// Every handle to the library is poisoned: looking up symbols
+    /// through any clone of this `Library` fails with [`Error::Closed`],
+    /// and calling any [`BoundFn`] bound from it fails with
+    /// [`LibraryClosed`]. The library itself is unloaded once the last
+    /// of them is dropped, so calls already in progress complete
+    /// normally.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(target_os = "linux")] {
+    /// use libffi::library::{Library, LibraryClosed};
+    /// use libffi::middle::*;
+    ///
+    /// let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+    /// let cos = libm
+    ///     .bind("cos", Cif::new(vec![Type::f64()], Type::f64()))
+    ///     .unwrap();
+    ///
+    /// libm.close_when_unused();
+    /// let result = unsafe { cos.try_call::<f64>(&[arg(&0f64)]) };
+    /// assert_eq!(Err(LibraryClosed), result);
+    /// # }
+    /// ```
+    pub fn close_when_unused(self) {
+        self.inner.closed.store(true, Ordering::Release);
+    }
+
+    /// Looks up the address of the symbol `name`.
+    ///
+    /// The address is only valid while the library remains loaded;
+    /// prefer [`bind`](Library::bind), which keeps it loaded.
+    pub fn symbol(&self, name: &str) -> Result<CodePtr> {
+        unsafe { sys::symbol(self.handle()?, name) }
+            .map(CodePtr)
+            .map_err(|message| Error::Symbol {
+                symbol: name.to_owned(),
+                message,
+            })
+    }
+
+    /// Looks up the function `name` and pairs it with `cif`.
+    ///
+    /// The resulting [`BoundFn`] keeps the library loaded.
+    pub fn bind<C: Into<Arc<Cif>>>(&self, name: &str, cif: C) -> Result<BoundFn> {
+        self.symbol(name).map(|code| self.bound(cif, code))
+    }
+
+    fn bound<C: Into<Arc<Cif>>>(&self, cif: C, code: CodePtr) -> BoundFn {
+        BoundFn::with_owner(cif, code, self.inner.clone())
+    }
+
+    /// Looks up the address of version `version` of the symbol `name`.
+    ///
+    /// Libraries such as glibc keep several versions of some symbols for
+    /// backward compatibility; [`symbol`](Library::symbol) finds the
+    /// default version, whereas this finds the requested one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[cfg(all(target_os = "linux", target_env = "gnu", target_arch = "x86_64"))] {
+    /// use libffi::library::Library;
+    ///
+    /// let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+    /// assert!(libm.symbol_version("pow", "GLIBC_2.2.5").is_ok());
+    /// # }
+    /// ```
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    pub fn symbol_version(&self, name: &str, version: &str) -> Result<CodePtr> {
+        unsafe { sys::symbol_version(self.handle()?, name, version) }
+            .map(CodePtr)
+            .map_err(|message| Error::Symbol {
+                symbol: format!("{}@{}", name, version),
+                message,
+            })
+    }
+
+    /// Looks up version `version` of the function `name` and pairs it
+    /// with `cif`.
+    ///
+    /// The resulting [`BoundFn`] keeps the library loaded.
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    pub fn bind_version<C: Into<Arc<Cif>>>(
+        &self,
+        name: &str,
+        version: &str,
+        cif: C,
+    ) -> Result<BoundFn> {
+        self.symbol_version(name, version)
+            .map(|code| self.bound(cif, code))
+    }
+
+    /// Lists the versions of the symbol `name` that this library
+    /// defines.
+    ///
+    /// The result is empty if the library does not define `name`, does
+    /// not use symbol versioning, or has been closed.
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    pub fn symbol_versions(&self, name: &str) -> Vec<String> {
+        match self.handle() {
+            Ok(handle) => unsafe { sys::symbol_versions(handle, name) },
+            Err(_) => vec![],
+        }
+    }
+}
+
+/// When the functions a library uses from other libraries are resolved.
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    /// Resolve all of them while loading the library (`RTLD_NOW`), so
+    /// that missing symbols are reported by [`OpenOptions::open`].
+    Now,
+    /// Resolve each of them when it is first called (`RTLD_LAZY`).
+    Lazy,
+}
+
+/// Whether a library’s symbols are available to libraries loaded after
+/// it.
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Only through its own handle (`RTLD_LOCAL`).
+    Local,
+    /// Also for resolving the symbols of later libraries (`RTLD_GLOBAL`).
+    Global,
+}
+
+/// Directories searched for a library and its dependencies
+/// (`LOAD_LIBRARY_SEARCH_*`).
+///
+/// Directories are combined with `|`. Restricting the search to known
+/// directories avoids picking up libraries planted in the current
+/// directory or on the `PATH`.
+#[cfg(windows)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SearchPath(u32);
+
+#[cfg(windows)]
+impl SearchPath {
+    /// The directory of the library being loaded, which must be given
+    /// by absolute path (`LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR`).
+    pub const DLL_LOAD_DIR: SearchPath = SearchPath(0x0000_0100);
+    /// The application’s directory (`LOAD_LIBRARY_SEARCH_APPLICATION_DIR`).
+    pub const APPLICATION_DIR: SearchPath = SearchPath(0x0000_0200);
+    /// Directories added with `AddDllDirectory` or `SetDllDirectory`
+    /// (`LOAD_LIBRARY_SEARCH_USER_DIRS`).
+    pub const USER_DIRS: SearchPath = SearchPath(0x0000_0400);
+    /// The system directory (`LOAD_LIBRARY_SEARCH_SYSTEM32`).
+    pub const SYSTEM32: SearchPath = SearchPath(0x0000_0800);
+    /// The application directory, user directories, and system
+    /// directory (`LOAD_LIBRARY_SEARCH_DEFAULT_DIRS`).
+    pub const DEFAULT_DIRS: SearchPath = SearchPath(0x0000_1000);
+
+    /// Gets the `LOAD_LIBRARY_SEARCH_*` flags.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+#[cfg(windows)]
+impl std::ops::BitOr for SearchPath {
+    type Output = SearchPath;
+
+    fn bitor(self, rhs: SearchPath) -> SearchPath {
+        SearchPath(self.0 | rhs.0)
+    }
+}
+
+/// Options for loading a [`Library`].
+///
+/// The defaults are those used by [`Library::open`]: on Unix, symbols
+/// are bound immediately and kept local to the library; on Windows,
+/// the standard search order is used.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(target_os = "linux")] {
+/// use libffi::library::{Binding, OpenOptions, Scope};
+///
+/// let libm = unsafe {
+///     OpenOptions::new()
+///         .binding(Binding::Lazy)
+///         .scope(Scope::Global)
+///         .open("libm.so.6")
+/// }
+/// .unwrap();
+/// assert!(libm.symbol("cos").is_ok());
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    #[cfg(unix)]
+    binding: Binding,
+    #[cfg(unix)]
+    scope: Scope,
+    #[cfg(unix)]
+    no_load: bool,
+    #[cfg(unix)]
+    no_delete: bool,
+    #[cfg(windows)]
+    search_path: Option<SearchPath>,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        OpenOptions::new()
+    }
+}
+
+impl OpenOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        OpenOptions {
+            #[cfg(unix)]
+            binding: Binding::Now,
+            #[cfg(unix)]
+            scope: Scope::Local,
+            #[cfg(unix)]
+            no_load: false,
+            #[cfg(unix)]
+            no_delete: false,
+            #[cfg(windows)]
+            search_path: None,
+        }
+    }
+
+    /// Sets when the library’s undefined symbols are resolved.
+    #[cfg(unix)]
+    pub fn binding(mut self, binding: Binding) -> Self {
+        self.binding = binding;
+        self
+    }
+
+    /// Sets whether the library’s symbols are available to libraries
+    /// loaded later.
+    #[cfg(unix)]
+    pub fn scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Only succeed if the library is already loaded (`RTLD_NOLOAD`).
+    #[cfg(unix)]
+    pub fn no_load(mut self, no_load: bool) -> Self {
+        self.no_load = no_load;
+        self
+    }
+
+    /// Never unload the library, even once every handle to it is
+    /// closed (`RTLD_NODELETE`).
+    #[cfg(unix)]
+    pub fn no_delete(mut self, no_delete: bool) -> Self {
+        self.no_delete = no_delete;
+        self
+    }
+
+    /// Restricts the directories searched for the library and its
+    /// dependencies.
+    #[cfg(windows)]
+    pub fn search_path(mut self, search_path: SearchPath) -> Self {
+        self.search_path = Some(search_path);
+        self
+    }
+
+    #[cfg(unix)]
+    fn flags(&self) -> sys::Flags {
+        let mut flags = match self.binding {
+            Binding::Now => libc::RTLD_NOW,
+            Binding::Lazy => libc::RTLD_LAZY,
+        };
+        flags |= match self.scope {
+            Scope::Local => libc::RTLD_LOCAL,
+            Scope::Global => libc::RTLD_GLOBAL,
+        };
+        if self.no_load {
+            flags |= libc::RTLD_NOLOAD;
+        }
+        if self.no_delete {
+            flags |= libc::RTLD_NODELETE;
+        }
+        flags
+    }
+
+    #[cfg(windows)]
+    fn flags(&self) -> sys::Flags {
+        self.search_path.map_or(0, SearchPath::bits)
+    }
+
+    /// Loads the shared library with the given file name or path using
+    /// these options.
+    ///
+    /// # Safety
+    ///
+    /// Loading a library runs its initialization routines, which may
+    /// do anything at all.
+    pub unsafe fn open<P: AsRef<OsStr>>(&self, name: P) -> Result<Library> {
+        let name = name.as_ref();
+        sys::open(name, self.flags())
+            .map(Library::from_handle)
+            .map_err(|message| Error::Open {
+                library: name.to_string_lossy().into_owned(),
+                message,
+            })
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{CStr, CString, OsStr};
+    use std::os::raw::{c_char, c_void};
+    use std::os::unix::ffi::OsStrExt;
+
+    pub type Handle = *mut c_void;
+    pub type Flags = std::os::raw::c_int;
+
+    // Returns and clears the description of the last `dl*` error on
+    // this thread.
+    unsafe fn last_error() -> String {
+        let message: *const c_char = libc::dlerror();
+        if message.is_null() {
+            "unknown error".to_owned()
+        } else {
+            CStr::from_ptr(message).to_string_lossy().into_owned()
+        }
+    }
+
+    pub unsafe fn open(name: &OsStr, flags: Flags) -> Result<Handle, String> {
+        let name = CString::new(name.as_bytes()).map_err(|e| e.to_string())?;
+        let handle = libc::dlopen(name.as_ptr(), flags);
+        if handle.is_null() {
+            Err(last_error())
+        } else {
+            Ok(handle)
+        }
+    }
+
+    pub unsafe fn this_process() -> Result<Handle, String> {
+        // Unlike the `RTLD_DEFAULT` pseudo-handle, this is a real handle,
+        // which can be closed like any other.
+        let handle = libc::dlopen(std::ptr::null(), libc::RTLD_NOW);
+        if handle.is_null() {
+            Err(last_error())
+        } else {
+            Ok(handle)
+        }
+    }
+
+    pub unsafe fn symbol(handle: Handle, name: &str) -> Result<*mut c_void, String> {
+        let name = CString::new(name).map_err(|e| e.to_string())?;
+        // A symbol’s address may legitimately be NULL, so the only way
+        // to detect failure is to check `dlerror`.
+        libc::dlerror();
+        let symbol = libc::dlsym(handle, name.as_ptr());
+        if symbol.is_null() && !libc::dlerror().is_null() {
+            Err(format!("{} not found", name.to_string_lossy()))
+        } else {
+            Ok(symbol)
+        }
+    }
+
+    pub unsafe fn close(handle: Handle) {
+        libc::dlclose(handle);
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    pub unsafe fn symbol_version(
+        handle: Handle,
+        name: &str,
+        version: &str,
+    ) -> Result<*mut c_void, String> {
+        let name = CString::new(name).map_err(|e| e.to_string())?;
+        let version = CString::new(version).map_err(|e| e.to_string())?;
+        libc::dlerror();
+        let symbol = libc::dlvsym(handle, name.as_ptr(), version.as_ptr());
+        if symbol.is_null() && !libc::dlerror().is_null() {
+            Err(format!(
+                "{}@{} not found",
+                name.to_string_lossy(),
+                version.to_string_lossy()
+            ))
+        } else {
+            Ok(symbol)
+        }
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    pub unsafe fn symbol_versions(handle: Handle, name: &str) -> Vec<String> {
+        let name = match CString::new(name) {
+            Ok(name) => name,
+            Err(_) => return vec![],
+        };
+
+        // Each version the library defines is a candidate; a version of
+        // `name` exists if `dlvsym` resolves it to this very library
+        // rather than to one of its dependencies.
+        let link_map = match elf::link_map(handle) {
+            Some(link_map) => link_map,
+            None => return vec![],
+        };
+        let library_name = CStr::from_ptr(link_map.l_name);
+
+        elf::defined_versions(link_map)
+            .into_iter()
+            .filter(|version| {
+                let symbol = libc::dlvsym(handle, name.as_ptr(), version.as_ptr());
+                let mut info: libc::Dl_info = std::mem::zeroed();
+                !symbol.is_null()
+                    && libc::dladdr(symbol, &mut info) != 0
+                    && !info.dli_fname.is_null()
+                    && CStr::from_ptr(info.dli_fname) == library_name
+            })
+            .map(|version| version.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    // Just enough of the ELF dynamic section to read a loaded library’s
+    // version definitions. These layouts are fixed by the ELF and
+    // `<link.h>` ABIs.
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    mod elf {
+        use std::ffi::CStr;
+        use std::os::raw::{c_char, c_void};
+
+        const DT_NULL: isize = 0;
+        const DT_STRTAB: isize = 5;
+        const DT_VERDEF: isize = 0x6fff_fffc;
+        const DT_VERDEFNUM: isize = 0x6fff_fffd;
+        const VER_FLG_BASE: u16 = 1;
+
+        #[repr(C)]
+        pub struct LinkMap {
+            pub l_addr: usize,
+            pub l_name: *const c_char,
+            pub l_ld: *const Dyn,
+            pub l_next: *const LinkMap,
+            pub l_prev: *const LinkMap,
+        }
+
+        #[repr(C)]
+        pub struct Dyn {
+            pub d_tag: isize,
+            pub d_val: usize,
+        }
+
+        #[repr(C)]
+        struct Verdef {
+            vd_version: u16,
+            vd_flags: u16,
+            vd_ndx: u16,
+            vd_cnt: u16,
+            vd_hash: u32,
+            vd_aux: u32,
+            vd_next: u32,
+        }
+
+        #[repr(C)]
+        struct Verdaux {
+            vda_name: u32,
+            vda_next: u32,
+        }
+
+        pub unsafe fn link_map<'a>(handle: *mut c_void) -> Option<&'a LinkMap> {
+            let mut link_map: *const LinkMap = std::ptr::null();
+            let status = libc::dlinfo(
+                handle,
+                libc::RTLD_DI_LINKMAP,
+                &mut link_map as *mut *const LinkMap as *mut c_void,
+            );
+            if status != 0 || link_map.is_null() || (*link_map).l_ld.is_null() {
+                None
+            } else {
+                Some(&*link_map)
+            }
+        }
+
+        pub unsafe fn defined_versions(link_map: &LinkMap) -> Vec<&CStr> {
+            let mut strtab = 0;
+            let mut verdef = 0;
+            let mut verdefnum = 0;
+
+            let mut entry = link_map.l_ld;
+            while (*entry).d_tag != DT_NULL {
+                match (*entry).d_tag {
+                    DT_STRTAB => strtab = (*entry).d_val,
+                    DT_VERDEF => verdef = (*entry).d_val,
+                    DT_VERDEFNUM => verdefnum = (*entry).d_val,
+                    _ => {}
+                }
+                entry = entry.add(1);
+            }
+
+            if strtab == 0 || verdef == 0 {
+                return vec![];
+            }
+
+            // The loader relocates some dynamic entries in place and
+            // leaves others as offsets from the load address.
+            let rebase = |addr: usize| {
+                if addr < link_map.l_addr {
+                    addr + link_map.l_addr
+                } else {
+                    addr
+                }
+            };
+            let strtab = rebase(strtab) as *const c_char;
+            let mut def = rebase(verdef) as *const Verdef;
+
+            let mut versions = Vec::with_capacity(verdefnum);
+            for _ in 0..verdefnum {
+                if (*def).vd_flags & VER_FLG_BASE == 0 && (*def).vd_cnt > 0 {
+                    let aux = (def as *const u8).add((*def).vd_aux as usize) as *const Verdaux;
+                    versions.push(CStr::from_ptr(strtab.add((*aux).vda_name as usize)));
+                }
+                if (*def).vd_next == 0 {
+                    break;
+                }
+                def = (def as *const u8).add((*def).vd_next as usize) as *const Verdef;
+            }
+            versions
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::os::raw::{c_char, c_void};
+    use std::os::windows::ffi::OsStrExt;
+
+    pub type Handle = *mut c_void;
+    pub type Flags = u32;
+
+    extern "system" {
+        fn LoadLibraryExW(name: *const u16, file: Handle, flags: u32) -> Handle;
+        fn GetModuleHandleExW(flags: u32, name: *const u16, module: *mut Handle) -> i32;
+        fn GetProcAddress(module: Handle, name: *const c_char) -> *mut c_void;
+        fn FreeLibrary(module: Handle) -> i32;
+    }
+
+    pub unsafe fn open(name: &OsStr, flags: Flags) -> Result<Handle, String> {
+        let name: Vec<u16> = name.encode_wide().chain(Some(0)).collect();
+        let handle = LoadLibraryExW(name.as_ptr(), std::ptr::null_mut(), flags);
+        if handle.is_null() {
+            Err(io::Error::last_os_error().to_string())
+        } else {
+            Ok(handle)
+        }
+    }
+
+    pub unsafe fn this_process() -> Result<Handle, String> {
+        // With no flags, this takes a reference to the executable’s
+        // module, which is released by `FreeLibrary` on close.
+        let mut handle = std::ptr::null_mut();
+        if GetModuleHandleExW(0, std::ptr::null(), &mut handle) == 0 {
+            Err(io::Error::last_os_error().to_string())
+        } else {
+            Ok(handle)
+        }
+    }
+
+    pub unsafe fn symbol(handle: Handle, name: &str) -> Result<*mut c_void, String> {
+        let name = CString::new(name).map_err(|e| e.to_string())?;
+        let symbol = GetProcAddress(handle, name.as_ptr());
+        if symbol.is_null() {
+            Err(io::Error::last_os_error().to_string())
+        } else {
+            Ok(symbol)
+        }
+    }
+
+    pub unsafe fn close(handle: Handle) {
+        FreeLibrary(handle);
+    }
+}
+
+/// Declares functions that are loaded from a shared library on first
+/// use.
+///
+/// This provides an experience similar to an `extern "C"` block, except
+/// that the library is loaded and each function looked up at run time,
+/// the first time the function is called. The CIF for each function is
+/// prepared once and cached. Argument and result types must implement
+/// [`CType`](crate::high::CType).
+///
+/// As with an `extern` block, the declarations are not checked against
+/// the library, so the generated functions are `unsafe` to call. Prefix
+/// a declaration with `safe` to assert that calling the function is
+/// safe for all arguments, which generates a safe function instead.
+///
+/// # Panics
+///
+/// The generated functions panic if the library cannot be loaded or
+/// does not contain the function.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(target_os = "linux")] {
+/// use libffi::dynamic_extern;
+///
+/// dynamic_extern! {
+///     lib "libm.so.6";
+///     safe fn cos(f64) -> f64;
+///     pub fn ldexp(f64, i32) -> f64;
+/// }
+///
+/// assert_eq!(1.0, cos(0.0));
+/// assert_eq!(12.0, unsafe { ldexp(3.0, 2) });
+/// # }
+/// ```
+#[macro_export]
+macro_rules! dynamic_extern {
+    ( lib $lib:literal; $( $decls:tt )* ) => {
+        $crate::__dynamic_extern!(@decl $lib; $( $decls )*);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __dynamic_extern {
+    (@decl $lib:literal; ) => {};
+
+    (@decl $lib:literal;
+        $( #[$attr:meta] )* $vis:vis safe fn $name:ident ( $( $A:ty ),* $(,)? ) $( -> $R:ty )? ;
+        $( $rest:tt )*
+    ) => {
+        $crate::__dynamic_extern!(
+            @munch [$lib] [$( #[$attr] )*] [$vis] [] [$name] [$( $R )?] []
+            [a1 a2 a3 a4 a5 a6 a7 a8 a9 a10 a11 a12] $( $A, )*
+        );
+        $crate::__dynamic_extern!(@decl $lib; $( $rest )*);
+    };
+
+    (@decl $lib:literal;
+        $( #[$attr:meta] )* $vis:vis fn $name:ident ( $( $A:ty ),* $(,)? ) $( -> $R:ty )? ;
+        $( $rest:tt )*
+    ) => {
+        $crate::__dynamic_extern!(
+            @munch [$lib] [$( #[$attr] )*] [$vis] [unsafe] [$name] [$( $R )?] []
+            [a1 a2 a3 a4 a5 a6 a7 a8 a9 a10 a11 a12] $( $A, )*
+        );
+        $crate::__dynamic_extern!(@decl $lib; $( $rest )*);
+    };
+
+    // Pairs each argument type with a parameter name.
+    (@munch [$lib:literal] [$( $attr:tt )*] [$vis:vis] [$( $unsafety:ident )?] [$name:ident] [$( $R:ty )?]
+        [$( ($n:ident : $T:ty) )*] [$next:ident $( $names:ident )*] $A:ty, $( $rest:ty, )*
+    ) => {
+        $crate::__dynamic_extern!(
+            @munch [$lib] [$( $attr )*] [$vis] [$( $unsafety )?] [$name] [$( $R )?]
+            [$( ($n : $T) )* ($next : $A)] [$( $names )*] $( $rest, )*
+        );
+    };
+
+    (@munch [$lib:literal] [$( $attr:tt )*] [$vis:vis] [$( $unsafety:ident )?] [$name:ident] []
+        [$( ($n:ident : $T:ty) )*] [$( $names:ident )*]
+    ) => {
+        $crate::__dynamic_extern!(
+            @munch [$lib] [$( $attr )*] [$vis] [$( $unsafety )?] [$name] [()]
+            [$( ($n : $T) )*] [$( $names )*]
+        );
+    };
+
+    (@munch [$lib:literal] [$( $attr:tt )*] [$vis:vis] [$( $unsafety:ident )?] [$name:ident] [$R:ty]
+        [$( ($n:ident : $T:ty) )*] [$( $names:ident )*]
+    ) => {
+        $( $attr )*
+        #[allow(clippy::too_many_arguments)]
+        $vis $( $unsafety )? fn $name( $( $n: $T ),* ) -> $R {
+            static BOUND: $crate::__private::v1::OnceLock<$crate::middle::BoundFn> =
+                $crate::__private::v1::OnceLock::new();
+
+            let bound = BOUND.get_or_init(|| {
+                let cif = $crate::middle::Cif::new(
+                    ::std::vec![$( <$T as $crate::high::CType>::reify().into_middle() ),*],
+                    <$R as $crate::high::CType>::reify().into_middle(),
+                );
+                $crate::__private::v1::load_library($lib)
+                    .and_then(|library| library.bind(::std::stringify!($name), cif))
+                    .unwrap_or_else(|error| ::std::panic!("{}", error))
+            });
+
+            #[allow(unused_unsafe)]
+            let result: <$R as $crate::high::CType>::RetType = unsafe {
+                bound.call(&[$( $crate::middle::arg(&$n) ),*])
+            };
+            $crate::__private::v1::from_ret_type(result)
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::{arg, Type, Value};
+    use std::os::raw::c_void;
+
+    #[test]
+    fn missing_library() {
+        let error = unsafe { Library::open("libffi-rs-does-not-exist.so") }.unwrap_err();
+        match error {
+            Error::Open { library, .. } => assert_eq!("libffi-rs-does-not-exist.so", library),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn bind_and_call() {
+        let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+
+        let hypot = libm
+            .bind(
+                "hypot",
+                Cif::new(vec![Type::f64(), Type::f64()], Type::f64()),
+            )
+            .unwrap();
+        let n: f64 = unsafe { hypot.call(&[arg(&3f64), arg(&4f64)]) };
+        assert_eq!(5.0, n);
+
+        assert!(matches!(
+            libm.symbol("no_such_function"),
+            Err(Error::Symbol { .. })
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn bound_fns_keep_library_alive() {
+        let cif = Arc::new(Cif::new(vec![Type::f64()], Type::f64()));
+        let (sqrt, cbrt) = {
+            let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+            let sqrt = libm.bind("sqrt", cif.clone()).unwrap();
+            (sqrt, libm.bind("cbrt", cif).unwrap())
+        };
+
+        assert_eq!(3.0, unsafe { sqrt.call::<f64>(&[arg(&9f64)]) });
+        assert_eq!(2.0, unsafe { cbrt.call::<f64>(&[arg(&8f64)]) });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn close_poisons_handles() {
+        let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+        let clone = libm.clone();
+        let trunc = libm
+            .bind("trunc", Cif::new(vec![Type::f64()], Type::f64()))
+            .unwrap();
+        let copy = trunc.clone();
+
+        assert_eq!(Ok(1.0), unsafe { trunc.try_call::<f64>(&[arg(&1.5f64)]) });
+        assert!(!copy.is_closed());
+
+        libm.close_when_unused();
+
+        assert!(clone.is_closed());
+        assert!(copy.is_closed());
+        assert_eq!(Err(LibraryClosed), unsafe {
+            trunc.try_call::<f64>(&[arg(&1.5f64)])
+        });
+        let report = unsafe { trunc.try_call_reported::<f64>(&[arg(&1.5f64)]) }.unwrap_err();
+        assert_eq!("(f64) -> f64", report.signature);
+        assert_eq!(vec![Value::F64(1.5)], report.args);
+        assert!(matches!(clone.symbol("trunc"), Err(Error::Closed)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[should_panic(expected = "library has been closed")]
+    fn call_after_close_panics() {
+        let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+        let round = libm
+            .bind("round", Cif::new(vec![Type::f64()], Type::f64()))
+            .unwrap();
+        libm.close_when_unused();
+        unsafe { round.call::<f64>(&[arg(&1.5f64)]) };
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn open_options() {
+        let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+        let again = unsafe { OpenOptions::new().no_load(true).open("libm.so.6") }.unwrap();
+        assert_eq!(
+            libm.symbol("cos").unwrap().as_ptr(),
+            again.symbol("cos").unwrap().as_ptr()
+        );
+
+        let error = unsafe {
+            OpenOptions::new()
+                .binding(Binding::Lazy)
+                .no_load(true)
+                .open("libffi-rs-does-not-exist.so")
+        };
+        assert!(matches!(error, Err(Error::Open { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn this_process() {
+        let this = Library::this_process().unwrap();
+        let code = this.symbol("strlen").unwrap();
+        assert_eq!(libc::strlen as *mut c_void, code.as_mut_ptr());
+
+        let strlen = this
+            .bind("strlen", Cif::new(vec![Type::pointer()], Type::usize()))
+            .unwrap();
+        let s = b"hello\0".as_ptr();
+        assert_eq!(5, unsafe { strlen.call::<usize>(&[arg(&s)]) });
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    #[test]
+    fn symbol_versions() {
+        let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+
+        let versions = libm.symbol_versions("hypot");
+        assert!(!versions.is_empty());
+        for version in &versions {
+            assert!(version.starts_with("GLIBC_"));
+            assert!(libm.symbol_version("hypot", version).is_ok());
+        }
+
+        assert!(libm.symbol_versions("no_such_function").is_empty());
+        assert!(matches!(
+            libm.symbol_version("hypot", "GLIBC_0.0"),
+            Err(Error::Symbol { .. })
+        ));
+    }
+
+    #[cfg(all(target_os = "linux", target_env = "gnu", target_arch = "x86_64"))]
+    #[test]
+    fn bind_old_version() {
+        let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+
+        let versions = libm.symbol_versions("pow");
+        assert!(versions.iter().any(|v| v == "GLIBC_2.2.5"));
+
+        let cif = Arc::new(Cif::new(vec![Type::f64(), Type::f64()], Type::f64()));
+        for version in &versions {
+            let pow = libm.bind_version("pow", version, cif.clone()).unwrap();
+            let n: f64 = unsafe { pow.call(&[arg(&2f64), arg(&10f64)]) };
+            assert_eq!(1024.0, n);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    mod libm {
+        dynamic_extern! {
+            lib "libm.so.6";
+            pub(super) safe fn floor(f64) -> f64;
+            pub(super) fn fmaf(f32, f32, f32) -> f32;
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dynamic_extern() {
+        assert_eq!(2.0, libm::floor(2.5));
+        assert_eq!(7.0, unsafe { libm::fmaf(2.0, 3.0, 1.0) });
+    }
+}
@@ -7,8 +7,8 @@
 //! avoided drastic renaming in favor of hewing close to the libffi API.
 //! See [`middle`](crate::middle) for an easier-to-use approach.
 
-use std::mem;
-use std::os::raw::{c_uint, c_void};
+use core::ffi::{c_uint, c_void};
+use core::mem;
 
 use crate::raw;
 
@@ -22,7 +22,7 @@ pub enum Error {
 }
 
 /// The [`std::result::Result`] type specialized for libffi [`Error`]s.
-pub type Result<T> = ::std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 // Converts the raw status type to a `Result`.
 fn status_to_result<R>(status: raw::ffi_status, good: R) -> Result<R> {
@@ -191,7 +191,7 @@ pub mod types {
 /// ```
 pub mod type_tag {
     use crate::raw;
-    use std::os::raw::c_ushort;
+    use core::ffi::c_ushort;
 
     /// Indicates a structure type.
     pub const STRUCT: c_ushort = raw::ffi_type_enum_STRUCT as c_ushort;
@@ -276,7 +276,7 @@ pub unsafe fn prep_cif(
 /// - `abi` — the calling convention to use
 /// - `nfixedargs` — the number of fixed arguments
 /// - `ntotalargs` — the total number of arguments, including fixed and
-///    var args
+///   var args
 /// - `rtype` — the result type
 /// - `atypes` — the argument types (length must be at least `nargs`)
 ///
@@ -308,7 +308,7 @@ pub unsafe fn prep_cif_var(
 /// # Arguments
 ///
 /// * `cif` — describes the argument and result types and the calling
-///           convention
+///   convention
 /// * `fun` — the function to call
 /// * `args` — the arguments to pass to `fun`
 ///
@@ -316,6 +316,14 @@ pub unsafe fn prep_cif_var(
 ///
 /// The result of calling `fun` with `args`.
 ///
+/// # Safety
+///
+/// There is no checking that `cif` describes the actual calling
+/// convention and types of `fun`, nor that `args` points to enough
+/// correctly typed argument pointers. Type `R` must be large enough to
+/// hold the result described by `cif`; an integer result that libffi
+/// widens to [`ffi_arg`] may be received as its own type.
+///
 /// # Examples
 ///
 /// ```
@@ -340,14 +348,137 @@ pub unsafe fn prep_cif_var(
 /// assert_eq!(9, result);
 /// ```
 pub unsafe fn call<R>(cif: *mut ffi_cif, fun: CodePtr, args: *mut *mut c_void) -> R {
-    let mut result = mem::MaybeUninit::<R>::uninit();
+    // libffi writes integer results smaller than `ffi_arg` as a whole
+    // `ffi_arg`, so the result is received into a slot at least that
+    // large and narrowed afterwards.
+    let mut result = mem::MaybeUninit::<ResultSlot<R>>::uninit();
     raw::ffi_call(
         cif,
         Some(*fun.as_safe_fun()),
         result.as_mut_ptr() as *mut c_void,
         args,
     );
-    result.assume_init()
+
+    let word = mem::size_of::<ffi_arg>();
+    let size = mem::size_of::<R>();
+    let offset = if size < word && cfg!(target_endian = "big") && is_widened(&*(*cif).rtype) {
+        // The value is in the low-order, and so last, bytes.
+        word - size
+    } else {
+        0
+    };
+    (result.as_ptr() as *const u8)
+        .add(offset)
+        .cast::<R>()
+        .read_unaligned()
+}
+
+// Space for a result of type `R`, and for an `ffi_arg` that libffi may
+// write in its place.
+#[repr(C)]
+union ResultSlot<R> {
+    _value: mem::ManuallyDrop<R>,
+    _word: ffi_arg,
+}
+
+// Whether libffi widens results of type `ty` to `ffi_arg` or
+// `ffi_sarg`.
+pub(crate) fn is_widened(ty: &ffi_type) -> bool {
+    matches!(
+        u32::from(ty.type_),
+        raw::FFI_TYPE_INT
+            | raw::FFI_TYPE_UINT8
+            | raw::FFI_TYPE_SINT8
+            | raw::FFI_TYPE_UINT16
+            | raw::FFI_TYPE_SINT16
+            | raw::FFI_TYPE_UINT32
+            | raw::FFI_TYPE_SINT32
+    )
+}
+
+/// Calls a C function with the arguments a closure was called with.
+///
+/// A closure’s callback receives its arguments as libffi has already
+/// marshaled them, an array of pointers to their values, and a buffer
+/// for its result. When the callback only needs to pass the call on to
+/// a function of the same signature, such as the function a wrapper
+/// replaced, this hands the argument array and the result buffer
+/// straight to [`ffi_call`](raw::ffi_call) without copying anything.
+///
+/// # Arguments
+///
+/// * `cif` — the CIF the callback was called with, which describes
+///   `fun`
+/// * `fun` — the function to call
+/// * `args` — the arguments the callback was given
+/// * `result` — the callback’s result buffer, which receives the result
+///   of `fun`
+///
+/// # Safety
+///
+/// `fun` must have the argument and result types and the calling
+/// convention of `cif`, and `args` and `result` must be the arguments
+/// and the result buffer of a call through `cif`. libffi widens
+/// integer results smaller than [`ffi_arg`] to its size, which a
+/// closure’s result buffer accommodates, but a smaller buffer does not.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::low::*;
+///
+/// use std::mem;
+/// use std::os::raw::c_void;
+///
+/// extern "C" fn sub(x: i32, y: i32) -> i32 {
+///     x - y
+/// }
+///
+/// unsafe extern "C" fn callback(cif: &ffi_cif,
+///                               result: &mut ffi_arg,
+///                               args: *const *const c_void,
+///                               calls: &mut u32)
+/// {
+///     *calls += 1;
+///     forward_call(cif, CodePtr(sub as *mut _), args, result as *mut _ as *mut c_void);
+/// }
+///
+/// unsafe {
+///     let mut cif: ffi_cif = Default::default();
+///     let mut args = [&mut types::sint32 as *mut _, &mut types::sint32 as *mut _];
+///     let mut calls = 0;
+///
+///     prep_cif(&mut cif, ffi_abi_FFI_DEFAULT_ABI, 2, &mut types::sint32,
+///              args.as_mut_ptr()).unwrap();
+///
+///     let (closure, code) = closure_alloc();
+///     let counted: extern "C" fn(i32, i32) -> i32 = mem::transmute(code);
+///
+///     prep_closure_mut(closure,
+///                      &mut cif,
+///                      callback,
+///                      &mut calls,
+///                      CodePtr(counted as *mut _)).unwrap();
+///
+///     assert_eq!(-2, counted(5, 7));
+///     assert_eq!(1, counted(8, 7));
+///     assert_eq!(2, calls);
+///
+///     closure_free(closure);
+/// }
+/// ```
+pub unsafe fn forward_call(
+    cif: *const ffi_cif,
+    fun: CodePtr,
+    args: *const *const c_void,
+    result: *mut c_void,
+) {
+    raw::ffi_call(
+        cif as *mut _,
+        Some(*fun.as_safe_fun()),
+        result,
+        args as *mut *mut c_void,
+    );
 }
 
 /// Allocates a closure.
@@ -379,11 +510,38 @@ pub fn closure_alloc() -> (*mut ffi_closure, CodePtr) {
     }
 }
 
+/// Allocates a closure followed by `extra` writable bytes.
+///
+/// As [`closure_alloc`], except that the extra bytes start at
+/// `closure.add(1)` and, like the closure, remain valid until it is
+/// freed with [`closure_free`]. They are aligned for [`ffi_closure`],
+/// and can hold data the closure refers to without a separate
+/// allocation.
+pub fn closure_alloc_extra(extra: usize) -> (*mut ffi_closure, CodePtr) {
+    unsafe {
+        let mut code_pointer = mem::MaybeUninit::<*mut c_void>::uninit();
+        let closure = raw::ffi_closure_alloc(
+            mem::size_of::<ffi_closure>() + extra,
+            code_pointer.as_mut_ptr(),
+        );
+        (
+            closure as *mut ffi_closure,
+            CodePtr::from_ptr(code_pointer.assume_init()),
+        )
+    }
+}
+
 /// Frees a closure.
 ///
-/// Closures allocated with [`closure_alloc`] must be deallocated with
+/// Closures allocated with [`closure_alloc`] or [`closure_alloc_extra`] must be deallocated with
 /// [`closure_free`].
 ///
+/// # Safety
+///
+/// `closure` must have been returned by [`closure_alloc`] and must not
+/// have been freed already. Its code pointer must not be used after
+/// this call.
+///
 /// # Examples
 ///
 /// ```
@@ -451,7 +609,7 @@ pub type RawCallback = unsafe extern "C" fn(
 /// - `cif` — the calling convention and types for calling the closure
 /// - `callback` — the function that the closure will invoke
 /// - `userdata` — the closed-over value, stored in the closure and
-///    passed to the callback upon invocation
+///   passed to the callback upon invocation
 /// - `code` — the closure’s code pointer, *i.e.*, the second component
 ///   returned by [`closure_alloc`].
 ///
@@ -543,7 +701,7 @@ pub unsafe fn prep_closure<U, R>(
 /// - `cif` — the calling convention and types for calling the closure
 /// - `callback` — the function that the closure will invoke
 /// - `userdata` — the closed-over value, stored in the closure and
-///    passed to the callback upon invocation
+///   passed to the callback upon invocation
 /// - `code` — the closure’s code pointer, *i.e.*, the second component
 ///   returned by [`closure_alloc`].
 ///
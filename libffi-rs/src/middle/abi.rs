@@ -0,0 +1,238 @@
+use core::fmt;
+
+use super::FfiAbi;
+use crate::{low, raw};
+
+/// The calling conventions libffi supports on the target.
+///
+/// libffi identifies calling conventions by numbers whose meaning
+/// depends on the target, given by the `ffi_abi_FFI_*` constants of
+/// [`raw`](crate::raw). This enum names the conventions of the current
+/// target, so that a convention it does not support is a compile-time
+/// error rather than a CIF that libffi rejects. An `Abi` can be passed
+/// to [`Builder::abi`](super::Builder::abi) and
+/// [`Cif::with_abi`](super::Cif::with_abi), and converts into an
+/// [`FfiAbi`] for [`Cif::set_abi`](super::Cif::set_abi).
+///
+/// The variants other than [`Abi::Default`] depend on the target:
+///
+/// - x86-64 Unix: `Unix64` (the default), `Win64`, and `Gnuw64`;
+/// - x86-64 Windows: `Win64`, and `Gnuw64` (the default);
+/// - 32-bit x86: `SysV`, `Stdcall`, `Thiscall`, `Fastcall`, `MsCdecl`,
+///   `Pascal`, and `Register`;
+/// - 32-bit ARM: `SysV` (the default) and `Vfp`;
+/// - AArch64: `SysV` (the default).
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::{Abi, Cif, Type};
+///
+/// let cif = Cif::with_abi(vec![Type::i32()], Type::i32(), Abi::Default);
+/// assert_eq!(Some(Abi::default_abi()), Abi::from_raw(cif.abi()));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Abi {
+    /// The target’s default calling convention, that of `extern "C"`.
+    #[default]
+    Default,
+    /// The System V AMD64 calling convention.
+    #[cfg(all(target_arch = "x86_64", unix))]
+    Unix64,
+    /// The Microsoft x64 calling convention, as `extern "win64"`.
+    #[cfg(target_arch = "x86_64")]
+    Win64,
+    /// The Microsoft x64 calling convention as GCC implements it.
+    #[cfg(target_arch = "x86_64")]
+    Gnuw64,
+    /// The System V calling convention, with the caller cleaning up
+    /// the stack.
+    #[cfg(any(target_arch = "x86", target_arch = "arm", target_arch = "aarch64"))]
+    SysV,
+    /// `__stdcall`, as `extern "stdcall"`, which most of the Win32 API
+    /// uses.
+    #[cfg(target_arch = "x86")]
+    Stdcall,
+    /// `__thiscall`, as `extern "thiscall"`, which MSVC uses for C++
+    /// member functions.
+    #[cfg(target_arch = "x86")]
+    Thiscall,
+    /// `__fastcall`, as `extern "fastcall"`.
+    #[cfg(target_arch = "x86")]
+    Fastcall,
+    /// `__cdecl` as MSVC implements it.
+    #[cfg(target_arch = "x86")]
+    MsCdecl,
+    /// The Pascal calling convention.
+    #[cfg(target_arch = "x86")]
+    Pascal,
+    /// Borland’s `__fastcall` or Delphi’s `register` convention.
+    #[cfg(target_arch = "x86")]
+    Register,
+    /// The ARM hard-float calling convention, passing floating-point
+    /// values in VFP registers.
+    #[cfg(target_arch = "arm")]
+    Vfp,
+}
+
+impl Abi {
+    /// The named calling convention that is the target’s default.
+    pub fn default_abi() -> Abi {
+        Abi::from_raw(low::ffi_abi_FFI_DEFAULT_ABI).unwrap_or(Abi::Default)
+    }
+
+    /// libffi’s number for the calling convention.
+    pub fn as_raw(self) -> FfiAbi {
+        match self {
+            Abi::Default => low::ffi_abi_FFI_DEFAULT_ABI,
+            #[cfg(all(target_arch = "x86_64", unix))]
+            Abi::Unix64 => raw::ffi_abi_FFI_UNIX64,
+            #[cfg(target_arch = "x86_64")]
+            Abi::Win64 => raw::ffi_abi_FFI_WIN64,
+            #[cfg(target_arch = "x86_64")]
+            Abi::Gnuw64 => raw::ffi_abi_FFI_GNUW64,
+            #[cfg(any(target_arch = "x86", target_arch = "arm", target_arch = "aarch64"))]
+            Abi::SysV => raw::ffi_abi_FFI_SYSV,
+            #[cfg(target_arch = "x86")]
+            Abi::Stdcall => raw::ffi_abi_FFI_STDCALL,
+            #[cfg(target_arch = "x86")]
+            Abi::Thiscall => raw::ffi_abi_FFI_THISCALL,
+            #[cfg(target_arch = "x86")]
+            Abi::Fastcall => raw::ffi_abi_FFI_FASTCALL,
+            #[cfg(target_arch = "x86")]
+            Abi::MsCdecl => raw::ffi_abi_FFI_MS_CDECL,
+            #[cfg(target_arch = "x86")]
+            Abi::Pascal => raw::ffi_abi_FFI_PASCAL,
+            #[cfg(target_arch = "x86")]
+            Abi::Register => raw::ffi_abi_FFI_REGISTER,
+            #[cfg(target_arch = "arm")]
+            Abi::Vfp => raw::ffi_abi_FFI_VFP,
+        }
+    }
+
+    /// The named calling convention with libffi’s number `abi`, or
+    /// `None` if there is none on the target.
+    pub fn from_raw(abi: FfiAbi) -> Option<Abi> {
+        Abi::named()
+            .iter()
+            .copied()
+            .find(|named| named.as_raw() == abi)
+    }
+
+    // The variants other than `Default`.
+    fn named() -> &'static [Abi] {
+        &[
+            #[cfg(all(target_arch = "x86_64", unix))]
+            Abi::Unix64,
+            #[cfg(target_arch = "x86_64")]
+            Abi::Win64,
+            #[cfg(target_arch = "x86_64")]
+            Abi::Gnuw64,
+            #[cfg(any(target_arch = "x86", target_arch = "arm", target_arch = "aarch64"))]
+            Abi::SysV,
+            #[cfg(target_arch = "x86")]
+            Abi::Stdcall,
+            #[cfg(target_arch = "x86")]
+            Abi::Thiscall,
+            #[cfg(target_arch = "x86")]
+            Abi::Fastcall,
+            #[cfg(target_arch = "x86")]
+            Abi::MsCdecl,
+            #[cfg(target_arch = "x86")]
+            Abi::Pascal,
+            #[cfg(target_arch = "x86")]
+            Abi::Register,
+            #[cfg(target_arch = "arm")]
+            Abi::Vfp,
+        ]
+    }
+}
+
+impl From<Abi> for FfiAbi {
+    fn from(abi: Abi) -> Self {
+        abi.as_raw()
+    }
+}
+
+impl fmt::Display for Abi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use crate::middle::{arg, Builder, Cif, CodePtr, Type};
+
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+        x + y
+    }
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(low::ffi_abi_FFI_DEFAULT_ABI, Abi::Default.as_raw());
+        assert_eq!(Abi::default_abi().as_raw(), Abi::default().as_raw());
+        for &abi in Abi::named() {
+            assert_eq!(Some(abi), Abi::from_raw(abi.into()));
+        }
+        assert_eq!(None, Abi::from_raw(raw::ffi_abi_FFI_LAST_ABI));
+    }
+
+    #[test]
+    fn builder_and_cif() {
+        let cif = Builder::new()
+            .args(vec![Type::i32(), Type::i32()])
+            .res(Type::i32())
+            .abi(Abi::default_abi())
+            .into_cif();
+        let n: i32 = unsafe { cif.call(CodePtr(add as *mut _), &[arg(&2), arg(&3)]) };
+        assert_eq!(5, n);
+
+        let mut cif = Cif::new(vec![], Type::void());
+        cif.set_abi(low::ffi_abi_FFI_DEFAULT_ABI);
+        assert_eq!(Some(Abi::default_abi()), Abi::from_raw(cif.abi()));
+    }
+
+    #[cfg(all(target_arch = "x86_64", unix))]
+    #[test]
+    fn win64_from_unix() {
+        extern "win64" fn sub(x: i32, y: i32) -> i32 {
+            x - y
+        }
+
+        let cif = Builder::new()
+            .args(vec![Type::i32(), Type::i32()])
+            .res(Type::i32())
+            .abi(Abi::Win64)
+            .into_cif();
+        assert_eq!("Win64", Abi::from_raw(cif.abi()).unwrap().to_string());
+        let n: i32 = unsafe { cif.call(CodePtr(sub as *mut _), &[arg(&7), arg(&3)]) };
+        assert_eq!(4, n);
+
+        let cif = Cif::with_abi(vec![Type::i32(), Type::i32()], Type::i32(), Abi::Win64);
+        let n: i32 = unsafe { cif.call(CodePtr(sub as *mut _), &[arg(&9), arg(&3)]) };
+        assert_eq!(6, n);
+    }
+
+    // Win64 returns a 12-byte struct through memory, where Unix64 uses
+    // registers, so the CIF must be prepared again for the new ABI.
+    #[cfg(all(target_arch = "x86_64", unix))]
+    #[test]
+    fn set_abi_prepares_again() {
+        #[repr(C)]
+        #[derive(Debug, PartialEq)]
+        struct Triple(i32, i32, i32);
+
+        extern "win64" fn triple(x: i32) -> Triple {
+            Triple(x, x * 2, x * 3)
+        }
+
+        let mut cif = Cif::new(vec![Type::i32()], Type::structure(vec![Type::i32(); 3]));
+        cif.set_abi(Abi::Win64.as_raw());
+        assert_eq!(Some(Abi::Win64), Abi::from_raw(cif.abi()));
+        let result: Triple = unsafe { cif.call(CodePtr(triple as *mut _), &[arg(&5)]) };
+        assert_eq!(Triple(5, 10, 15), result);
+    }
+}
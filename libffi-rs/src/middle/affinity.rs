@@ -0,0 +1,196 @@
+//! Checking that closures are called on the thread that created them.
+//!
+//! Many C libraries, GUI toolkits especially, require that a callback
+//! only run on the thread that registered it, and calling it from
+//! another thread corrupts their state in ways that are hard to trace
+//! back. A closure built by a [`Builder`](super::Builder) configured
+//! with [`thread_affinity`](super::Builder::thread_affinity) records the
+//! thread that creates it, and checks on every call that it is called
+//! on that thread. A call from any other thread does not run the
+//! callback, but fails with [`WrongThread`] according to the given
+//! [`ErrorPolicy`]: it aborts the process,
+//! as an assertion would, or returns an error code and stores the error
+//! for [`take_callback_error`](super::bridge::take_callback_error).
+//!
+//! # Examples
+//!
+//! ```
+//! use std::os::raw::c_void;
+//!
+//! use libffi::low;
+//! use libffi::middle::bridge::{take_callback_error, ErrorPolicy};
+//! use libffi::middle::{Builder, Type};
+//!
+//! unsafe extern "C" fn redraw(
+//!     _cif: &low::ffi_cif,
+//!     result: &mut i32,
+//!     _args: *const *const c_void,
+//!     _window: &(),
+//! ) {
+//!     *result = 0;
+//! }
+//!
+//! let closure = Builder::new()
+//!     .res(Type::i32())
+//!     .thread_affinity(ErrorPolicy::ReturnCode(-1i32))
+//!     .into_closure(redraw, &());
+//! let fun: extern "C" fn() -> i32 = *unsafe { closure.instantiate_code_ptr() };
+//!
+//! assert_eq!(0, fun());
+//! let (result, error) = std::thread::spawn(move || (fun(), take_callback_error()))
+//!     .join()
+//!     .unwrap();
+//! assert_eq!(-1, result);
+//! assert!(error.unwrap().to_string().contains("was called on thread"));
+//! ```
+
+use std::error;
+use std::fmt;
+use std::os::raw::c_void;
+use std::thread::{self, ThreadId};
+
+use super::bridge::{self, ErrorPolicy};
+use crate::low;
+
+/// The error reported for calls made to a closure on a thread other
+/// than the one that created it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WrongThread {
+    /// The thread that created the closure.
+    pub expected: ThreadId,
+    /// The thread that called it.
+    pub actual: ThreadId,
+}
+
+impl fmt::Display for WrongThread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a closure created on thread {:?} was called on thread {:?}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl error::Error for WrongThread {}
+
+/// The userdata of a closure’s checking trampoline: the closure’s real
+/// callback and userdata, the thread that created it, and what to do
+/// on calls from other threads.
+#[derive(Debug)]
+pub(crate) struct Pinned {
+    callback: low::RawCallback,
+    userdata: *mut c_void,
+    thread: ThreadId,
+    policy: ErrorPolicy<bridge::Erased>,
+}
+
+impl Pinned {
+    pub(crate) fn new(
+        callback: low::RawCallback,
+        userdata: *mut c_void,
+        policy: ErrorPolicy<bridge::Erased>,
+    ) -> Box<Self> {
+        Box::new(Pinned {
+            callback,
+            userdata,
+            thread: thread::current().id(),
+            policy,
+        })
+    }
+}
+
+/// Calls the real callback if called on the closure’s thread, and fails
+/// the call otherwise.
+pub(crate) unsafe extern "C" fn pinned(
+    cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    pinned: &Pinned,
+) {
+    let actual = thread::current().id();
+    if actual == pinned.thread {
+        (pinned.callback)(
+            cif as *const _ as *mut _,
+            result,
+            args as *mut *mut c_void,
+            pinned.userdata,
+        );
+    } else {
+        let error = WrongThread {
+            expected: pinned.thread,
+            actual,
+        };
+        pinned.policy.fail(result, error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::middle::bridge::take_callback_error;
+    use crate::middle::{Builder, Type};
+
+    unsafe extern "C" fn add(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        args: *const *const c_void,
+        offset: &u64,
+    ) {
+        *result = **(args as *const &u64) + offset;
+    }
+
+    unsafe extern "C" fn take(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        _args: *const *const c_void,
+        userdata: &mut Option<u64>,
+    ) {
+        *result = userdata.unwrap_or(0);
+    }
+
+    #[test]
+    fn calls_on_other_threads_fail() {
+        let builder = Builder::new()
+            .arg(Type::u64())
+            .res(Type::u64())
+            .thread_affinity(ErrorPolicy::ReturnCode(u64::MAX));
+        let closure = builder.clone().into_closure(add, &5);
+        let fun: extern "C" fn(u64) -> u64 = *unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(6, fun(1));
+        assert!(take_callback_error().is_none());
+
+        let (result, error) = thread::spawn(move || (fun(1), take_callback_error()))
+            .join()
+            .unwrap();
+        assert_eq!(u64::MAX, result);
+        assert!(error.unwrap().to_string().contains("was called on thread"));
+
+        let once = builder.into_closure_once(take, 7u64);
+        let fun: extern "C" fn(u64) -> u64 = *unsafe { once.instantiate_code_ptr() };
+        assert_eq!(7, fun(0));
+        let result = thread::spawn(move || fun(0)).join().unwrap();
+        assert_eq!(u64::MAX, result);
+    }
+
+    #[test]
+    #[should_panic(expected = "larger than the CIF’s result type")]
+    fn oversized_error_codes_are_rejected() {
+        let _ = Builder::new()
+            .res(Type::u16())
+            .thread_affinity(ErrorPolicy::ReturnCode([0u64; 2]))
+            .into_closure_once(take, 1u64);
+    }
+
+    #[test]
+    fn unpinned_closures_run_anywhere() {
+        let closure = Builder::new()
+            .arg(Type::u64())
+            .res(Type::u64())
+            .into_closure_owned(add, 2u64);
+        let fun: extern "C" fn(u64) -> u64 = *unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(5, thread::spawn(move || fun(3)).join().unwrap());
+    }
+}
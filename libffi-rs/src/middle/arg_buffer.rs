@@ -0,0 +1,288 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::{fmt, mem, ptr, slice};
+
+use super::util::{self, Chunk};
+use super::{Arg, BytesError, Cif, CodePtr, TypedBuffer};
+
+/// Reusable storage for the arguments of calls through a [`Cif`].
+///
+/// Calling through [`Cif::call`] takes a slice of [`Arg`]s, each
+/// pointing at an argument value, which callers usually build afresh,
+/// often on the heap, for every call. An `ArgBuffer` owns a CIF
+/// together with storage for one value of each of its argument types
+/// and the array of pointers to them, both allocated once, when the
+/// buffer is created. A call then only writes the argument values into
+/// their slots with [`set`](ArgBuffer::set) and calls through the
+/// buffer, which neither allocates nor builds an argument array. This
+/// suits hot call paths such as the FFI dispatch of an interpreter,
+/// which can keep a buffer for each function it calls.
+///
+/// Slots keep their values between calls, so arguments that do not
+/// change need only be set once.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::{ArgBuffer, Cif, CodePtr, Type};
+///
+/// extern "C" fn scale(x: i32, k: f64) -> f64 {
+///     f64::from(x) * k
+/// }
+///
+/// let mut args = ArgBuffer::new(Cif::new(vec![Type::i32(), Type::f64()], Type::f64()));
+/// args.set(1, 0.5f64).unwrap();
+///
+/// let mut total = 0.0;
+/// for x in 0..4 {
+///     args.set(0, x).unwrap();
+///     total += unsafe { args.call::<f64>(CodePtr(scale as *mut _)) };
+/// }
+/// assert_eq!(3.0, total);
+///
+/// assert!(args.set(0, 1u64).is_err());
+/// ```
+pub struct ArgBuffer {
+    cif: Cif,
+    // The offset and size of each argument’s slot in `data`.
+    slots: Box<[(usize, usize)]>,
+    data: Box<[Chunk]>,
+    // Points into `data`, whose contents do not move with the buffer.
+    args: Box<[Arg]>,
+}
+
+// The argument pointers only refer to the buffer’s own storage.
+unsafe impl Send for ArgBuffer {}
+
+impl ArgBuffer {
+    /// Creates a buffer for the arguments of calls through `cif`, with
+    /// every argument zeroed.
+    pub fn new(cif: Cif) -> Self {
+        let mut slots = Vec::with_capacity(cif.cif.nargs as usize);
+        let mut end = 0;
+        while let Some((size, alignment)) = cif.arg_layout(slots.len()) {
+            let alignment = alignment.max(1);
+            let offset = (end + alignment - 1) / alignment * alignment;
+            slots.push((offset, size));
+            end = offset + size;
+        }
+
+        let mut data = util::chunks(end);
+        let base = data.as_mut_ptr() as *mut u8;
+        let args = slots
+            .iter()
+            .map(|&(offset, _)| Arg(unsafe { base.add(offset) } as *mut c_void))
+            .collect();
+
+        ArgBuffer {
+            cif,
+            slots: slots.into_boxed_slice(),
+            data,
+            args,
+        }
+    }
+
+    /// The CIF the buffer is bound to.
+    pub fn cif(&self) -> &Cif {
+        &self.cif
+    }
+
+    /// Consumes the buffer, returning its CIF.
+    pub fn into_cif(self) -> Cif {
+        self.cif
+    }
+
+    /// The number of arguments.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns whether the CIF takes no arguments.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// The argument pointers, for passing to [`Cif::call`] or other
+    /// functions taking a slice of [`Arg`]s.
+    pub fn args(&self) -> &[Arg] {
+        &self.args
+    }
+
+    /// Sets argument `index` to `value`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the size of `T` differs from the size of the argument’s
+    /// type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    pub fn set<T: Copy>(&mut self, index: usize, value: T) -> Result<(), BytesError> {
+        let slot = self.slot_mut(index, mem::size_of::<T>())?;
+        unsafe { ptr::write_unaligned(slot.as_mut_ptr() as *mut T, value) };
+        Ok(())
+    }
+
+    /// Sets argument `index` by copying its representation from
+    /// `bytes`, which need not be aligned.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the length of `bytes` differs from the size of the
+    /// argument’s type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    pub fn set_bytes(&mut self, index: usize, bytes: &[u8]) -> Result<(), BytesError> {
+        self.slot_mut(index, bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// The bytes of argument `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    pub fn bytes(&self, index: usize) -> &[u8] {
+        let (offset, size) = self.slots[index];
+        unsafe { slice::from_raw_parts((self.data.as_ptr() as *const u8).add(offset), size) }
+    }
+
+    /// The bytes of argument `index`, for modifying it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    pub fn bytes_mut(&mut self, index: usize) -> &mut [u8] {
+        let (offset, size) = self.slots[index];
+        unsafe { slice::from_raw_parts_mut((self.data.as_mut_ptr() as *mut u8).add(offset), size) }
+    }
+
+    // Gets the slot of argument `index` for a value of `size` bytes.
+    fn slot_mut(&mut self, index: usize, size: usize) -> Result<&mut [u8], BytesError> {
+        let expected = self.slots[index].1;
+        if size != expected {
+            return Err(BytesError::Size {
+                expected,
+                actual: size,
+            });
+        }
+        Ok(self.bytes_mut(index))
+    }
+
+    /// Calls a function with the arguments in the buffer, as for
+    /// [`Cif::call`].
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`]: the function must have the signature the
+    /// CIF describes, `R` must be its result type, and any pointer
+    /// arguments must be valid for the function to use.
+    pub unsafe fn call<R>(&self, fun: CodePtr) -> R {
+        self.cif.call(fun, &self.args)
+    }
+
+    /// Calls a function with the arguments in the buffer, storing its
+    /// result in `result`, as for [`Cif::call_into`].
+    ///
+    /// # Safety
+    ///
+    /// As for [`ArgBuffer::call`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of `result` differs from the size of the
+    /// CIF’s result type.
+    pub unsafe fn call_into(&self, fun: CodePtr, result: &mut TypedBuffer) {
+        self.cif.call_into(fun, &self.args, result)
+    }
+}
+
+impl Clone for ArgBuffer {
+    fn clone(&self) -> Self {
+        let mut copy = ArgBuffer::new(self.cif.clone());
+        copy.data.copy_from_slice(&self.data);
+        copy
+    }
+}
+
+impl fmt::Debug for ArgBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<&[u8]> = (0..self.len()).map(|index| self.bytes(index)).collect();
+        f.debug_struct("ArgBuffer")
+            .field("cif", &self.cif)
+            .field("args", &args)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Pair {
+        tag: u8,
+        value: f64,
+    }
+
+    extern "C" fn weigh(a: u8, pair: Pair, scale: *const f64) -> f64 {
+        (f64::from(a) + f64::from(pair.tag) + pair.value) * unsafe { *scale }
+    }
+
+    #[test]
+    fn reused_across_calls() {
+        let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+        let cif = Cif::new(vec![Type::u8(), pair, Type::pointer()], Type::f64());
+        let mut args = ArgBuffer::new(cif);
+        assert_eq!(3, args.len());
+        assert_eq!(16, args.bytes(1).len());
+
+        let scale = 2.0f64;
+        args.set(2, &scale as *const f64).unwrap();
+        args.set(1, Pair { tag: 1, value: 0.5 }).unwrap();
+        let fun = CodePtr(weigh as *mut _);
+        for a in 0..3u8 {
+            args.set(0, a).unwrap();
+            let expected = (f64::from(a) + 1.5) * 2.0;
+            assert_eq!(expected, unsafe { args.call::<f64>(fun) });
+        }
+
+        let copy = args.clone();
+        drop(args);
+        let mut result = TypedBuffer::new(Type::f64());
+        unsafe { copy.call_into(fun, &mut result) };
+        assert_eq!(&7.0f64.to_ne_bytes()[..], result.as_bytes());
+        assert_eq!(&[2][..], copy.bytes(0));
+    }
+
+    #[test]
+    fn checks_sizes() {
+        let mut args = ArgBuffer::new(Cif::new(vec![Type::u16()], Type::void()));
+        assert_eq!(
+            Err(BytesError::Size {
+                expected: 2,
+                actual: 4,
+            }),
+            args.set(0, 1u32)
+        );
+        assert!(args.set_bytes(0, &[1]).is_err());
+        args.set_bytes(0, &7u16.to_ne_bytes()).unwrap();
+        assert_eq!(&7u16.to_ne_bytes()[..], args.bytes(0));
+        assert!(format!("{:?}", args).starts_with("ArgBuffer"));
+
+        assert!(ArgBuffer::new(Cif::new(vec![], Type::void())).is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_range() {
+        let mut args = ArgBuffer::new(Cif::new(vec![Type::u16()], Type::void()));
+        let _ = args.set(1, 0u16);
+    }
+}
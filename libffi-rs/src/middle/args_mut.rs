@@ -0,0 +1,290 @@
+use core::ffi::c_void;
+use core::marker::PhantomData;
+use core::{fmt, mem, ptr};
+
+use super::BytesError;
+use crate::low;
+
+/// A view of the arguments of a call in progress, through which they
+/// can be read and replaced.
+///
+/// A closure’s callback receives its arguments as an array of pointers
+/// to their values, and an [`Interceptor`](super::intercept::Interceptor)
+/// passes that same array on to the function it wraps, so a pre-call
+/// hook that replaces an argument changes what the function receives,
+/// for instance to redirect a path. An `ArgsMut` gives access to the
+/// array with the bounds and sizes checked against the CIF: arguments
+/// are read and written by value, as with [`ArgBuffer`](super::ArgBuffer),
+/// and any number of views of the same call may coexist.
+///
+/// A replaced pointer argument must remain valid for as long as the
+/// function may use it, which is usually longer than the hook runs; a
+/// hook can keep the data it points to in its own state.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "std")] {
+/// use libffi::middle::intercept::intercept;
+/// use libffi::middle::{Cif, CodePtr, Type};
+///
+/// extern "C" fn half(x: i32) -> i32 {
+///     x / 2
+/// }
+///
+/// let interceptor = unsafe {
+///     intercept(
+///         CodePtr(half as *mut _),
+///         Cif::new(vec![Type::i32()], Type::i32()),
+///         |call| {
+///             let args = call.args_mut();
+///             let x: i32 = unsafe { args.get(0) }.unwrap();
+///             args.set(0, x.max(0)).unwrap();
+///         },
+///         |_| {},
+///     )
+/// };
+/// let half: &extern "C" fn(i32) -> i32 = unsafe { interceptor.instantiate_code_ptr() };
+///
+/// assert_eq!(4, half(8));
+/// assert_eq!(0, half(-8));
+/// # }
+/// ```
+pub struct ArgsMut<'a> {
+    cif: &'a low::ffi_cif,
+    args: *const *const c_void,
+    _marker: PhantomData<&'a c_void>,
+}
+
+impl<'a> ArgsMut<'a> {
+    /// Creates a view of the arguments `args` of a call through `cif`,
+    /// as a closure’s callback receives them.
+    ///
+    /// # Safety
+    ///
+    /// `args` must point to an array of pointers to the arguments,
+    /// whose types are those of `cif`, and which are writable and
+    /// remain valid for `'a`.
+    pub unsafe fn from_raw(cif: &'a low::ffi_cif, args: *const *const c_void) -> Self {
+        ArgsMut {
+            cif,
+            args,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The number of arguments.
+    pub fn len(&self) -> usize {
+        self.cif.nargs as usize
+    }
+
+    /// Whether there are no arguments.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The size in bytes of the type of argument `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    pub fn size(&self, index: usize) -> usize {
+        self.slot(index).1
+    }
+
+    /// Reads argument `index` as a `T`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the size of `T` differs from the size of the argument’s
+    /// type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    ///
+    /// # Safety
+    ///
+    /// The argument’s value must be a valid `T`.
+    pub unsafe fn get<T: Copy>(&self, index: usize) -> Result<T, BytesError> {
+        let arg = self.checked(index, mem::size_of::<T>())?;
+        Ok(ptr::read_unaligned(arg as *const T))
+    }
+
+    /// Replaces argument `index` with `value`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the size of `T` differs from the size of the argument’s
+    /// type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    pub fn set<T: Copy>(&self, index: usize, value: T) -> Result<(), BytesError> {
+        let arg = self.checked(index, mem::size_of::<T>())?;
+        unsafe { ptr::write_unaligned(arg as *mut T, value) };
+        Ok(())
+    }
+
+    /// Copies the representation of argument `index` into `bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the length of `bytes` differs from the size of the
+    /// argument’s type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    pub fn read_bytes(&self, index: usize, bytes: &mut [u8]) -> Result<(), BytesError> {
+        let arg = self.checked(index, bytes.len())?;
+        unsafe { ptr::copy_nonoverlapping(arg as *const u8, bytes.as_mut_ptr(), bytes.len()) };
+        Ok(())
+    }
+
+    /// Replaces argument `index` by copying its representation from
+    /// `bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the length of `bytes` differs from the size of the
+    /// argument’s type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument `index`.
+    pub fn set_bytes(&self, index: usize, bytes: &[u8]) -> Result<(), BytesError> {
+        let arg = self.checked(index, bytes.len())?;
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), arg as *mut u8, bytes.len()) };
+        Ok(())
+    }
+
+    // The pointer to and size of argument `index`.
+    fn slot(&self, index: usize) -> (*const c_void, usize) {
+        assert!(
+            index < self.len(),
+            "ArgsMut: there is no argument {}",
+            index
+        );
+        unsafe {
+            (
+                *self.args.add(index),
+                (**self.cif.arg_types.add(index)).size,
+            )
+        }
+    }
+
+    // Gets argument `index` for a value of `size` bytes.
+    fn checked(&self, index: usize, size: usize) -> Result<*const c_void, BytesError> {
+        let (arg, expected) = self.slot(index);
+        if size != expected {
+            return Err(BytesError::Size {
+                expected,
+                actual: size,
+            });
+        }
+        Ok(arg)
+    }
+}
+
+impl fmt::Debug for ArgsMut<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArgsMut").field("len", &self.len()).finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::ffi::{CStr, CString};
+    use std::os::raw::c_char;
+
+    use super::*;
+    use crate::middle::intercept::intercept;
+    use crate::middle::{Cif, Closure, CodePtr, Type};
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Pair {
+        tag: u8,
+        value: f64,
+    }
+
+    extern "C" fn describe(path: *const c_char, pair: Pair, flag: u8) -> usize {
+        let path = unsafe { CStr::from_ptr(path) }.to_bytes().len();
+        path * 100 + pair.value as usize + usize::from(pair.tag) + usize::from(flag)
+    }
+
+    #[test]
+    fn redirected_arguments() {
+        let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+        let cif = Cif::new(vec![Type::pointer(), pair, Type::u8()], Type::usize());
+        let redirect = CString::new("/tmp/redirected").unwrap();
+        let interceptor = unsafe {
+            intercept(
+                CodePtr(describe as *mut _),
+                cif,
+                move |call| {
+                    let args = call.args_mut();
+                    assert_eq!(3, args.len());
+                    assert_eq!(16, args.size(1));
+                    args.set(0, redirect.as_ptr()).unwrap();
+                    let pair: Pair = args.get(1).unwrap();
+                    args.set(1, Pair { tag: 0, ..pair }).unwrap();
+                    assert!(args.set(2, 1u32).is_err());
+                    args.set_bytes(2, &[7]).unwrap();
+                },
+                |call| {
+                    let mut flag = [0];
+                    call.args_mut().read_bytes(2, &mut flag).unwrap();
+                    assert_eq!([7], flag);
+                },
+            )
+        };
+        let fun: &extern "C" fn(*const c_char, Pair, u8) -> usize =
+            unsafe { interceptor.instantiate_code_ptr() };
+        let path = CString::new("a").unwrap();
+        assert_eq!(
+            1500 + 30 + 7,
+            fun(
+                path.as_ptr(),
+                Pair {
+                    tag: 9,
+                    value: 30.0
+                },
+                1
+            )
+        );
+    }
+
+    unsafe extern "C" fn clamp_and_add(
+        cif: &low::ffi_cif,
+        result: &mut i64,
+        args: *const *const c_void,
+        limit: &i64,
+    ) {
+        let args = ArgsMut::from_raw(cif, args);
+        let x: i64 = args.get(0).unwrap();
+        args.set(0, x.min(*limit)).unwrap();
+        *result = args.get::<i64>(0).unwrap() + args.get::<i64>(1).unwrap();
+    }
+
+    #[test]
+    fn raw_closures() {
+        let cif = Cif::new(vec![Type::i64(), Type::i64()], Type::i64());
+        let closure = Closure::new(cif, clamp_and_add, &10);
+        let fun: &extern "C" fn(i64, i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(12, fun(50, 2));
+        assert_eq!(7, fun(5, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_range() {
+        let cif = Cif::new(vec![Type::u8()], Type::void());
+        let x = 1u8;
+        let args = [&x as *const u8 as *const c_void];
+        let view = unsafe { ArgsMut::from_raw(&*cif.as_raw_ptr(), args.as_ptr()) };
+        let _ = view.set(1, 0u8);
+    }
+}
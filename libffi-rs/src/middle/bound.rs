@@ -0,0 +1,269 @@
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{Arg, CallReport, Cif, CodePtr};
+
+/// The error returned when calling a [`BoundFn`] whose library has been
+/// closed.
+///
+/// See [`Library::close_when_unused`](crate::library::Library::close_when_unused).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LibraryClosed;
+
+impl fmt::Display for LibraryClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the function’s library has been closed")
+    }
+}
+
+impl error::Error for LibraryClosed {}
+
+/// Something that owns the code a [`BoundFn`] points to, such as a
+/// loaded library, and is kept alive by it.
+pub(crate) trait Owner: fmt::Debug + Send + Sync {
+    /// Whether the owner has been closed, so its functions must no
+    /// longer be called.
+    fn is_closed(&self) -> bool;
+}
+
+/// A function pointer paired with the [CIF](Cif) describing how to call
+/// it.
+///
+/// The CIF is reference counted, so binding many functions with the
+/// same signature (or cloning a `BoundFn`) shares a single CIF.
+///
+/// A `BoundFn` obtained from a [`Library`](crate::library::Library)
+/// keeps the library loaded for as long as the `BoundFn` exists.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn add(x: i32, y: i32) -> i32 {
+///     x + y
+/// }
+///
+/// let cif = Cif::new(vec![Type::i32(), Type::i32()], Type::i32());
+/// let add = BoundFn::new(cif, CodePtr(add as *mut _));
+///
+/// let n: i32 = unsafe { add.call(&[arg(&2i32), arg(&3i32)]) };
+/// assert_eq!(5, n);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BoundFn {
+    cif: Arc<Cif>,
+    code: CodePtr,
+    owner: Option<Arc<dyn Owner>>,
+}
+
+// A `BoundFn` only reads its CIF and code pointer, and its owner is
+// `Send` and `Sync`.
+unsafe impl Send for BoundFn {}
+unsafe impl Sync for BoundFn {}
+
+impl BoundFn {
+    /// Pairs the function at `code` with the CIF describing it.
+    pub fn new<C: Into<Arc<Cif>>>(cif: C, code: CodePtr) -> Self {
+        BoundFn {
+            cif: cif.into(),
+            code,
+            owner: None,
+        }
+    }
+
+    /// Pairs the function at `code`, which belongs to `owner`, with the
+    /// CIF describing it.
+    #[cfg(any(all(unix, feature = "libc"), windows))]
+    pub(crate) fn with_owner<C: Into<Arc<Cif>>>(
+        cif: C,
+        code: CodePtr,
+        owner: Arc<dyn Owner>,
+    ) -> Self {
+        BoundFn {
+            cif: cif.into(),
+            code,
+            owner: Some(owner),
+        }
+    }
+
+    /// Gets the CIF used to call the function.
+    pub fn cif(&self) -> &Cif {
+        &self.cif
+    }
+
+    /// Gets the function’s code pointer.
+    pub fn code_ptr(&self) -> CodePtr {
+        self.code
+    }
+
+    /// Returns whether the library the function belongs to has been
+    /// closed, in which case the function can no longer be called.
+    pub fn is_closed(&self) -> bool {
+        self.owner.as_ref().is_some_and(|owner| owner.is_closed())
+    }
+
+    /// Calls the function with the given arguments.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the function’s library has been closed. Use
+    /// [`try_call`](BoundFn::try_call) to handle that case instead.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`]: the CIF must describe the function, and
+    /// `args` and `R` must match the CIF.
+    pub unsafe fn call<R>(&self, args: &[Arg]) -> R {
+        self.try_call(args)
+            .unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Calls the function with the given arguments, unless its library
+    /// has been closed.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`]: the CIF must describe the function, and
+    /// `args` and `R` must match the CIF.
+    pub unsafe fn try_call<R>(&self, args: &[Arg]) -> Result<R, LibraryClosed> {
+        if self.is_closed() {
+            Err(LibraryClosed)
+        } else {
+            Ok(self.cif.call(self.code, args))
+        }
+    }
+
+    /// Calls the function with the given arguments, unless its library
+    /// has been closed, in which case the failed call is described by a
+    /// [`CallReport`].
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`]: the CIF must describe the function, and
+    /// `args` and `R` must match the CIF.
+    pub unsafe fn try_call_reported<R>(&self, args: &[Arg]) -> Result<R, CallReport> {
+        self.try_call(args)
+            .map_err(|error| CallReport::capture(&self.cif, self.code, args, error.to_string()))
+    }
+}
+
+/// A function pointer returned from a call.
+///
+/// C functions such as `void (*get_handler(void))(int)` return a
+/// function pointer. Calling them with `ReturnedFn` as the result type
+/// (for instance, `cif.call::<ReturnedFn>(...)`) decodes the result,
+/// which can then be [bound](ReturnedFn::bind) to a CIF describing the
+/// returned function, or called immediately.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn negate(x: i32) -> i32 {
+///     -x
+/// }
+///
+/// extern "C" fn get_handler() -> extern "C" fn(i32) -> i32 {
+///     negate
+/// }
+///
+/// let getter = Cif::new(vec![], Type::pointer());
+/// let handler: ReturnedFn = unsafe { getter.call(CodePtr(get_handler as *mut _), &[]) };
+///
+/// let handler = handler
+///     .bind(Cif::new(vec![Type::i32()], Type::i32()))
+///     .expect("handler was NULL");
+///
+/// let n: i32 = unsafe { handler.call(&[arg(&7i32)]) };
+/// assert_eq!(-7, n);
+/// ```
+#[derive(Clone, Copy, Debug)]
+#[repr(transparent)]
+pub struct ReturnedFn(CodePtr);
+
+impl ReturnedFn {
+    /// Gets the returned code pointer.
+    pub fn code_ptr(self) -> CodePtr {
+        self.0
+    }
+
+    /// Returns whether the returned pointer is NULL.
+    pub fn is_null(self) -> bool {
+        self.0.as_ptr().is_null()
+    }
+
+    /// Pairs the returned function with the CIF describing it, or
+    /// returns `None` if the returned pointer is NULL.
+    pub fn bind<C: Into<Arc<Cif>>>(self, cif: C) -> Option<BoundFn> {
+        if self.is_null() {
+            None
+        } else {
+            Some(BoundFn::new(cif, self.0))
+        }
+    }
+
+    /// Calls the returned function immediately.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must not be NULL, and, as for [`Cif::call`], `cif`
+    /// must describe the function, and `args` and `R` must match `cif`.
+    pub unsafe fn call<R>(self, cif: &Cif, args: &[Arg]) -> R {
+        cif.call(self.0, args)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::{arg, Type};
+    use std::os::raw::c_void;
+
+    extern "C" fn double(x: i32) -> i32 {
+        x * 2
+    }
+
+    extern "C" fn square(x: i32) -> i32 {
+        x * x
+    }
+
+    extern "C" fn get_handler(which: i32) -> Option<extern "C" fn(i32) -> i32> {
+        match which {
+            0 => Some(double),
+            1 => Some(square),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn bind_returned_fns() {
+        let getter = BoundFn::new(
+            Cif::new(vec![Type::i32()], Type::pointer()),
+            CodePtr(get_handler as *mut c_void),
+        );
+        let handler_cif = Arc::new(Cif::new(vec![Type::i32()], Type::i32()));
+
+        let handlers: Vec<BoundFn> = (0..3)
+            .filter_map(|i| {
+                let f: ReturnedFn = unsafe { getter.call(&[arg(&i)]) };
+                f.bind(handler_cif.clone())
+            })
+            .collect();
+
+        assert_eq!(2, handlers.len());
+        assert_eq!(10, unsafe { handlers[0].call::<i32>(&[arg(&5i32)]) });
+        assert_eq!(25, unsafe { handlers[1].call::<i32>(&[arg(&5i32)]) });
+    }
+
+    #[test]
+    fn null_is_detected() {
+        let getter = Cif::new(vec![Type::i32()], Type::pointer());
+        let f: ReturnedFn = unsafe { getter.call(CodePtr(get_handler as *mut c_void), &[arg(&9)]) };
+
+        assert!(f.is_null());
+        assert!(f.bind(Cif::new(vec![Type::i32()], Type::i32())).is_none());
+    }
+}
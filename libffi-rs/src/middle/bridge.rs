@@ -0,0 +1,319 @@
+//! Reporting errors from Rust callbacks back across a C call.
+//!
+//! A Rust callback invoked from C cannot return a Rust error to its
+//! caller, and must not unwind into C. The convention supported by this
+//! module is:
+//!
+//!  1. the callback returns a designated *error code* to C (for
+//!     example, `-1`), which the C library is expected to propagate;
+//!  2. the error itself (or the panic payload) is stored in a
+//!     thread-local slot;
+//!  3. once the outer call into C has returned, the Rust caller retrieves
+//!     the error with [`take_callback_error`].
+//!
+//! Closures following this convention can be built with
+//! [`Builder::into_fallible_closure`](super::Builder::into_fallible_closure)
+//! and an [`ErrorPolicy`], or hand-written callbacks can use
+//! [`catch_callback`] directly.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::mem::{self, MaybeUninit};
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use super::Cif;
+use crate::low;
+
+/// An error raised by a Rust callback.
+pub enum CallbackError {
+    /// The callback panicked; contains the panic payload.
+    Panic(Box<dyn Any + Send + 'static>),
+    /// The callback returned an error.
+    Error(Box<dyn Error + Send + Sync + 'static>),
+}
+
+impl CallbackError {
+    /// Gets the panic message, if this is a panic with a string payload.
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            CallbackError::Panic(payload) => payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str)),
+            CallbackError::Error(_) => None,
+        }
+    }
+
+    /// Resumes unwinding if this is a panic, or returns the error
+    /// otherwise.
+    ///
+    /// This allows a panic in a callback to propagate through the Rust
+    /// code that made the outer call, once it is safe to do so.
+    pub fn resume_panic(self) -> Box<dyn Error + Send + Sync + 'static> {
+        match self {
+            CallbackError::Panic(payload) => panic::resume_unwind(payload),
+            CallbackError::Error(error) => error,
+        }
+    }
+}
+
+impl fmt::Debug for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallbackError::Panic(_) => f
+                .debug_tuple("Panic")
+                .field(&self.panic_message().unwrap_or("Box<dyn Any>"))
+                .finish(),
+            CallbackError::Error(error) => f.debug_tuple("Error").field(error).finish(),
+        }
+    }
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallbackError::Panic(_) => match self.panic_message() {
+                Some(msg) => write!(f, "callback panicked: {}", msg),
+                None => f.write_str("callback panicked"),
+            },
+            CallbackError::Error(error) => write!(f, "callback failed: {}", error),
+        }
+    }
+}
+
+impl Error for CallbackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CallbackError::Panic(_) => None,
+            CallbackError::Error(error) => Some(&**error),
+        }
+    }
+}
+
+thread_local! {
+    static CALLBACK_ERROR: RefCell<Option<CallbackError>> = const { RefCell::new(None) };
+}
+
+/// Stores `error` in the current thread’s callback error slot.
+///
+/// If the slot already holds an error, that error is kept and `error`
+/// is discarded, since the first failure is usually the informative
+/// one.
+pub fn set_callback_error(error: CallbackError) {
+    CALLBACK_ERROR.with(|slot| {
+        let mut slot = slot.borrow_mut();
+        if slot.is_none() {
+            *slot = Some(error);
+        }
+    });
+}
+
+/// Takes the error stored by a failing callback on the current thread,
+/// if any, leaving the slot empty.
+pub fn take_callback_error() -> Option<CallbackError> {
+    CALLBACK_ERROR.with(|slot| slot.borrow_mut().take())
+}
+
+/// Runs a fallible callback body, converting errors and panics into an
+/// error code.
+///
+/// If `body` returns `Ok(value)` then `value` is returned. If it returns
+/// an error or panics, the error is stored for [`take_callback_error`]
+/// and `error_code` is returned instead.
+pub fn catch_callback<R, E, F>(error_code: R, body: F) -> R
+where
+    F: FnOnce() -> Result<R, E>,
+    E: Into<Box<dyn Error + Send + Sync + 'static>>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(error)) => {
+            set_callback_error(CallbackError::Error(error.into()));
+            error_code
+        }
+        Err(payload) => {
+            set_callback_error(CallbackError::Panic(payload));
+            error_code
+        }
+    }
+}
+
+/// What a closure does when its Rust callback fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy<R> {
+    /// Abort the process on an error or panic.
+    ///
+    /// This is what the [`high`](crate::high) layer’s closures do.
+    Abort,
+    /// Store the error for [`take_callback_error`] and return the
+    /// given error code to C.
+    ReturnCode(R),
+}
+
+impl<R> ErrorPolicy<R> {
+    /// Runs `body` according to this policy.
+    pub fn run<E, F>(self, body: F) -> R
+    where
+        F: FnOnce() -> Result<R, E>,
+        E: Into<Box<dyn Error + Send + Sync + 'static>>,
+    {
+        match self {
+            ErrorPolicy::ReturnCode(code) => catch_callback(code, body),
+            ErrorPolicy::Abort => {
+                match panic::catch_unwind(AssertUnwindSafe(body)) {
+                    Ok(Ok(value)) => return value,
+                    Ok(Err(error)) => eprintln!("FFI callback failed: {}", error.into()),
+                    Err(_) => eprintln!("Cannot panic inside FFI callback"),
+                }
+                std::process::abort()
+            }
+        }
+    }
+}
+
+impl<R: Copy> ErrorPolicy<R> {
+    // Erases the type of the error code, for closures that fail calls
+    // on their own behalf.
+    pub(crate) fn erase(self) -> ErrorPolicy<Erased> {
+        match self {
+            ErrorPolicy::Abort => ErrorPolicy::Abort,
+            ErrorPolicy::ReturnCode(code) => ErrorPolicy::ReturnCode(erase(&code)),
+        }
+    }
+}
+
+impl ErrorPolicy<Erased> {
+    // Panics, citing `caller`, unless the error code fits in the result
+    // of a closure of `cif`.
+    pub(crate) fn check(&self, cif: &Cif, caller: &str) {
+        if let ErrorPolicy::ReturnCode(code) = self {
+            check_result_size(cif, code.len(), &format!("{}: the error code", caller));
+        }
+    }
+
+    // Fails a call with `error`: aborts, or stores the error and writes
+    // the error code to `result`.
+    pub(crate) unsafe fn fail<E>(&self, result: *mut c_void, error: E)
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        match self {
+            ErrorPolicy::Abort => {
+                eprintln!("FFI callback failed: {}", error);
+                std::process::abort()
+            }
+            ErrorPolicy::ReturnCode(code) => {
+                set_callback_error(CallbackError::Error(Box::new(error)));
+                write_erased(result, code);
+            }
+        }
+    }
+}
+
+// A result with its type erased, for closures that return it on their
+// own behalf: its bytes, padding included, which are only ever copied.
+pub(crate) type Erased = Box<[MaybeUninit<u8>]>;
+
+pub(crate) fn erase<R: Copy>(value: &R) -> Erased {
+    let bytes = value as *const R as *const MaybeUninit<u8>;
+    unsafe { std::slice::from_raw_parts(bytes, mem::size_of::<R>()) }.into()
+}
+
+// Writes an erased result to the result of a closure call.
+pub(crate) unsafe fn write_erased(result: *mut c_void, value: &[MaybeUninit<u8>]) {
+    ptr::copy_nonoverlapping(value.as_ptr(), result as *mut MaybeUninit<u8>, value.len());
+}
+
+// The most bytes a closure of `cif` may write for its result: the size
+// of the result type, or of an `ffi_arg` if that is larger, since
+// libffi widens small integer results to one.
+pub(crate) fn result_capacity(cif: &Cif) -> usize {
+    let rtype = unsafe { &*(*cif.as_raw_ptr()).rtype };
+    rtype.size.max(mem::size_of::<low::ffi_arg>())
+}
+
+// Panics unless a result of `size` bytes, described by `what`, fits in
+// the result of a closure of `cif`.
+pub(crate) fn check_result_size(cif: &Cif, size: usize, what: &str) {
+    assert!(
+        size <= result_capacity(cif),
+        "{} is larger than the CIF’s result type",
+        what
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ok_passes_through() {
+        assert_eq!(5, catch_callback(-1, || Ok::<_, String>(5)));
+        assert!(take_callback_error().is_none());
+    }
+
+    #[test]
+    fn error_is_stored() {
+        assert_eq!(-1, catch_callback(-1, || Err::<i32, _>("bad input")));
+
+        let error = take_callback_error().unwrap();
+        assert_eq!("callback failed: bad input", error.to_string());
+        assert!(take_callback_error().is_none());
+    }
+
+    #[test]
+    fn first_error_wins() {
+        catch_callback(0, || Err::<i32, _>("first"));
+        catch_callback(0, || Err::<i32, _>("second"));
+
+        assert_eq!(
+            "callback failed: first",
+            take_callback_error().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn fallible_closure() {
+        use crate::middle::{Builder, Type};
+
+        let closure = Builder::new()
+            .arg(Type::u64())
+            .res(Type::u64())
+            .into_fallible_closure(ErrorPolicy::ReturnCode(0u64), |_, args| {
+                let n = unsafe { **(args as *const &u64) };
+                if n == 3 {
+                    panic!("three")
+                }
+                n.checked_sub(1).ok_or("underflow")
+            });
+        let fun: &extern "C" fn(u64) -> u64 = unsafe { closure.instantiate_code_ptr() };
+
+        assert_eq!(4, fun(5));
+        assert!(take_callback_error().is_none());
+
+        assert_eq!(0, fun(0));
+        assert_eq!(
+            "callback failed: underflow",
+            take_callback_error().unwrap().to_string()
+        );
+
+        assert_eq!(0, fun(3));
+        assert_eq!(
+            Some("three"),
+            take_callback_error().unwrap().panic_message()
+        );
+    }
+
+    #[test]
+    fn panic_is_stored() {
+        let code = ErrorPolicy::ReturnCode(-2).run(|| -> Result<i32, String> { panic!("oops") });
+        assert_eq!(-2, code);
+
+        let error = take_callback_error().unwrap();
+        assert_eq!(Some("oops"), error.panic_message());
+    }
+}
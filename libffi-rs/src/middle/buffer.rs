@@ -0,0 +1,461 @@
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::{fmt, mem, ptr, slice};
+
+use super::util::{self, Chunk};
+use super::{Arg, BytesError, Type};
+use crate::{low, raw};
+
+/// An owned value of a [`Type`] that is only known at run time.
+///
+/// The value is stored as bytes laid out as C lays out the type, in
+/// storage aligned for any type libffi supports. A buffer can be passed
+/// as an argument with [`arg`](TypedBuffer::arg) and can receive a
+/// result with [`Cif::call_into`](super::Cif::call_into), and the
+/// fields of a struct value can be reached by their offsets.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// #[repr(C)]
+/// struct Point {
+///     x: f64,
+///     y: f64,
+/// }
+///
+/// extern "C" fn swap(p: Point) -> Point {
+///     Point { x: p.y, y: p.x }
+/// }
+///
+/// let point = Type::structure(vec![Type::f64(), Type::f64()]);
+/// let cif = Cif::new(vec![point.clone()], point.clone());
+///
+/// let mut arg = TypedBuffer::new(point.clone());
+/// arg.field_bytes_mut(0).unwrap().copy_from_slice(&1.5f64.to_ne_bytes());
+/// arg.field_bytes_mut(1).unwrap().copy_from_slice(&2.5f64.to_ne_bytes());
+///
+/// let mut result = TypedBuffer::new(point);
+/// unsafe { cif.call_into(CodePtr(swap as *mut _), &[arg.arg()], &mut result) };
+///
+/// assert_eq!(Some(8), result.field_offset(1));
+/// assert_eq!(&2.5f64.to_ne_bytes()[..], result.field_bytes(0).unwrap());
+/// assert_eq!(&1.5f64.to_ne_bytes()[..], result.field_bytes(1).unwrap());
+/// ```
+#[derive(Clone)]
+pub struct TypedBuffer {
+    ty: Type,
+    size: usize,
+    // The offset and size of each field, if `ty` is a struct.
+    fields: Vec<(usize, usize)>,
+    data: Box<[Chunk]>,
+}
+
+impl TypedBuffer {
+    /// Creates a zeroed value of type `ty`.
+    pub fn new(ty: Type) -> Self {
+        let size = value_size(&ty);
+        let fields = unsafe { field_layout(&*ty.as_raw_ptr()) };
+        // Results smaller than `ffi_arg` are widened by libffi, so leave
+        // room for them.
+        let data = util::chunks(size.max(mem::size_of::<low::ffi_arg>()));
+
+        TypedBuffer {
+            ty,
+            size,
+            fields,
+            data,
+        }
+    }
+
+    /// Creates a value of type `ty` by copying its representation from
+    /// `bytes`, which need not be aligned.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the length of `bytes` differs from the size of `ty`.
+    pub fn from_bytes(ty: Type, bytes: &[u8]) -> Result<Self, BytesError> {
+        let mut buffer = TypedBuffer::new(ty);
+        if bytes.len() != buffer.size {
+            return Err(BytesError::Size {
+                expected: buffer.size,
+                actual: bytes.len(),
+            });
+        }
+        buffer.as_bytes_mut().copy_from_slice(bytes);
+        Ok(buffer)
+    }
+
+    /// The type of the value.
+    pub fn ty(&self) -> &Type {
+        &self.ty
+    }
+
+    /// The size of the value in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The bytes of the value.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.as_ptr() as *const u8, self.size) }
+    }
+
+    /// The bytes of the value, for modifying it.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr() as *mut u8, self.size) }
+    }
+
+    /// A pointer to the value.
+    pub fn as_ptr(&self) -> *const c_void {
+        self.data.as_ptr() as *const c_void
+    }
+
+    /// A mutable pointer to the value.
+    pub fn as_mut_ptr(&mut self) -> *mut c_void {
+        self.data.as_mut_ptr() as *mut c_void
+    }
+
+    /// Passes the value as an argument.
+    pub fn arg(&self) -> Arg {
+        Arg(self.as_ptr() as *mut c_void)
+    }
+
+    /// The number of fields, if the value is a struct, or else 0.
+    pub fn field_count(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// The offset in bytes of field `index`, if the value is a struct
+    /// with such a field.
+    pub fn field_offset(&self, index: usize) -> Option<usize> {
+        self.fields.get(index).map(|&(offset, _)| offset)
+    }
+
+    /// The bytes of field `index`, if the value is a struct with such a
+    /// field.
+    pub fn field_bytes(&self, index: usize) -> Option<&[u8]> {
+        let (offset, size) = *self.fields.get(index)?;
+        Some(&self.as_bytes()[offset..offset + size])
+    }
+
+    /// The bytes of field `index`, for modifying it, if the value is a
+    /// struct with such a field.
+    pub fn field_bytes_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        let (offset, size) = *self.fields.get(index)?;
+        Some(&mut self.as_bytes_mut()[offset..offset + size])
+    }
+
+    /// The index of the field called `name`, if the value’s type was
+    /// constructed with [`Type::structure_named`] and has such a field.
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.ty.field_index(name)
+    }
+
+    /// Reads the scalar field called `name` as a `T`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if there is no field called `name`, if the field is a
+    /// struct, or if its size differs from the size of `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Type, TypedBuffer};
+    ///
+    /// let point = Type::structure_named(vec![("x", Type::f64()), ("y", Type::f64())]);
+    /// let mut buffer = TypedBuffer::new(point);
+    ///
+    /// buffer.set_field("y", 2.5f64).unwrap();
+    /// assert_eq!(2.5, buffer.get_field::<f64>("y").unwrap());
+    ///
+    /// assert!(buffer.get_field::<f64>("z").is_err());
+    /// assert!(buffer.get_field::<f32>("x").is_err());
+    /// ```
+    pub fn get_field<T: Scalar>(&self, name: &str) -> Result<T, FieldError> {
+        let offset = self.scalar_field::<T>(name)?;
+        Ok(unsafe { ptr::read_unaligned(self.as_bytes()[offset..].as_ptr() as *const T) })
+    }
+
+    /// Writes `value` to the scalar field called `name`.
+    ///
+    /// # Errors
+    ///
+    /// As for [`get_field`](Self::get_field).
+    pub fn set_field<T: Scalar>(&mut self, name: &str, value: T) -> Result<(), FieldError> {
+        let offset = self.scalar_field::<T>(name)?;
+        unsafe {
+            ptr::write_unaligned(self.as_bytes_mut()[offset..].as_mut_ptr() as *mut T, value);
+        }
+        Ok(())
+    }
+
+    // Finds the offset of a field that can be accessed as a `T`.
+    fn scalar_field<T>(&self, name: &str) -> Result<usize, FieldError> {
+        let no_field = || FieldError::NoField(name.to_owned());
+        let index = self.field_index(name).ok_or_else(no_field)?;
+        let &(offset, size) = self.fields.get(index).ok_or_else(no_field)?;
+
+        let field = unsafe { &**(*self.ty.as_raw_ptr()).elements.add(index) };
+        if field.type_ == low::type_tag::STRUCT {
+            return Err(FieldError::NotScalar(name.to_owned()));
+        }
+        if size != mem::size_of::<T>() {
+            return Err(FieldError::Size {
+                expected: size,
+                actual: mem::size_of::<T>(),
+            });
+        }
+        Ok(offset)
+    }
+
+    /// Consumes the buffer, returning the bytes of the value.
+    pub fn into_bytes(self) -> Box<[u8]> {
+        self.as_bytes().into()
+    }
+
+    /// Consumes the buffer, returning the bytes of the value and its
+    /// type.
+    pub fn into_parts(self) -> (Box<[u8]>, Type) {
+        (self.as_bytes().into(), self.ty)
+    }
+
+    // Moves an integer result that libffi widened to `ffi_arg` to the
+    // start of the buffer.
+    pub(crate) fn narrow_result(&mut self) {
+        let word = mem::size_of::<low::ffi_arg>();
+        let widened = low::is_widened(unsafe { &*self.ty.as_raw_ptr() });
+        if widened && self.size < word && cfg!(target_endian = "big") {
+            // The value is in the low-order, and so last, bytes of the
+            // widened result.
+            let size = self.size;
+            let bytes = unsafe { slice::from_raw_parts_mut(self.as_mut_ptr() as *mut u8, word) };
+            bytes.copy_within(word - size.., 0);
+        }
+    }
+}
+
+impl fmt::Debug for TypedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TypedBuffer")
+            .field("ty", &self.ty)
+            .field("bytes", &self.as_bytes())
+            .finish()
+    }
+}
+
+/// A type that [`TypedBuffer::get_field`] and
+/// [`TypedBuffer::set_field`] may read and write: the integer and
+/// floating-point types, and raw pointers, for which any bytes of the
+/// right size are a valid value.
+///
+/// This trait is sealed, and cannot be implemented outside this crate.
+///
+/// ```compile_fail
+/// use libffi::middle::{Type, TypedBuffer};
+///
+/// let pair = Type::structure_named(vec![("pair", Type::u64())]);
+/// let buffer = TypedBuffer::new(pair);
+/// buffer.get_field::<(u8, u32)>("pair");
+/// ```
+pub trait Scalar: Copy + sealed::Sealed {}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+macro_rules! scalar {
+    ( $( $t:ty ),* ) => {
+        $(
+            impl sealed::Sealed for $t {}
+            impl Scalar for $t {}
+        )*
+    };
+}
+
+scalar!(u8, i8, u16, i16, u32, i32, u64, i64, usize, isize, f32, f64);
+
+impl<T> sealed::Sealed for *const T {}
+impl<T> Scalar for *const T {}
+impl<T> sealed::Sealed for *mut T {}
+impl<T> Scalar for *mut T {}
+
+/// The error returned when a field of a [`TypedBuffer`] can’t be
+/// accessed by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldError {
+    /// The value has no field with the given name.
+    NoField(String),
+    /// The field with the given name is a struct, not a scalar.
+    NotScalar(String),
+    /// The field’s size differs from the size of the accessed type.
+    Size {
+        /// The size of the field.
+        expected: usize,
+        /// The size of the accessed type.
+        actual: usize,
+    },
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldError::NoField(name) => write!(f, "no field named `{}`", name),
+            FieldError::NotScalar(name) => write!(f, "field `{}` is not a scalar", name),
+            FieldError::Size { expected, actual } => write!(
+                f,
+                "accessed a field of {} bytes as a type of {} bytes",
+                expected, actual
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FieldError {}
+
+// The size of a value of type `ty`, which is 0 for `void`.
+pub(crate) fn value_size(ty: &Type) -> usize {
+    let (size, _) = ty.layout();
+    let raw = unsafe { &*ty.as_raw_ptr() };
+    if u32::from(raw.type_) == raw::FFI_TYPE_VOID {
+        0
+    } else {
+        size
+    }
+}
+
+// Lays out the fields of a struct type as libffi does, each at the
+// first offset aligned for it.
+pub(crate) unsafe fn field_layout(ty: &low::ffi_type) -> Vec<(usize, usize)> {
+    let mut fields = vec![];
+    if ty.type_ != low::type_tag::STRUCT || ty.elements.is_null() {
+        return fields;
+    }
+
+    let mut end = 0;
+    let mut element = ty.elements;
+    while !(*element).is_null() {
+        let field = &**element;
+        let align = usize::from(field.alignment).max(1);
+        let offset = (end + align - 1) / align * align;
+        fields.push((offset, field.size));
+        end = offset + field.size;
+        element = element.add(1);
+    }
+    fields
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use crate::middle::{arg, Cif, CodePtr};
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Mixed {
+        a: u8,
+        b: f64,
+        c: u16,
+    }
+
+    extern "C" fn bump(m: Mixed) -> Mixed {
+        Mixed {
+            a: m.a + 1,
+            b: m.b + 1.0,
+            c: m.c + 1,
+        }
+    }
+
+    #[test]
+    fn fields_follow_c_layout() {
+        let mixed = Type::structure(vec![Type::u8(), Type::f64(), Type::u16()]);
+        let buffer = TypedBuffer::new(mixed);
+
+        assert_eq!(mem::size_of::<Mixed>(), buffer.size());
+        assert_eq!(3, buffer.field_count());
+        assert_eq!(Some(0), buffer.field_offset(0));
+        assert_eq!(Some(8), buffer.field_offset(1));
+        assert_eq!(Some(16), buffer.field_offset(2));
+        assert_eq!(None, buffer.field_offset(3));
+        assert_eq!(Some(2), buffer.field_bytes(2).map(<[u8]>::len));
+    }
+
+    #[test]
+    fn buffers_as_arguments_and_results() {
+        let mixed = Type::structure(vec![Type::u8(), Type::f64(), Type::u16()]);
+        let cif = Cif::new(vec![mixed.clone()], mixed.clone());
+
+        let value = Mixed { a: 1, b: 2.5, c: 3 };
+        let bytes = unsafe {
+            slice::from_raw_parts(&value as *const Mixed as *const u8, mem::size_of::<Mixed>())
+        };
+        let arg = TypedBuffer::from_bytes(mixed.clone(), bytes).unwrap();
+        let mut result = TypedBuffer::new(mixed);
+        unsafe { cif.call_into(CodePtr(bump as *mut _), &[arg.arg()], &mut result) };
+
+        let result = unsafe { *(result.as_ptr() as *const Mixed) };
+        assert_eq!(Mixed { a: 2, b: 3.5, c: 4 }, result);
+    }
+
+    extern "C" fn double(n: u16) -> u16 {
+        n * 2
+    }
+
+    #[test]
+    fn small_integer_results_are_narrowed() {
+        let cif = Cif::new(vec![Type::u16()], Type::u16());
+        let mut result = TypedBuffer::new(Type::u16());
+        unsafe { cif.call_into(CodePtr(double as *mut _), &[arg(&300u16)], &mut result) };
+        assert_eq!(&600u16.to_ne_bytes()[..], result.as_bytes());
+
+        assert_eq!(0, TypedBuffer::new(Type::void()).size());
+        assert!(TypedBuffer::from_bytes(Type::u16(), &[0; 4]).is_err());
+    }
+
+    #[test]
+    fn named_fields() {
+        let inner = Type::structure_named(vec![("n", Type::u32())]);
+        let mixed = Type::structure_named(vec![
+            ("a", Type::u8()),
+            ("b", Type::f64()),
+            ("inner", inner),
+        ]);
+        let mut buffer = TypedBuffer::new(mixed.clone());
+
+        buffer.set_field("a", 7u8).unwrap();
+        buffer.set_field("b", -1.25f64).unwrap();
+        assert_eq!(7u8, buffer.get_field("a").unwrap());
+        assert_eq!(-1.25f64, buffer.get_field("b").unwrap());
+        assert_eq!(
+            &(-1.25f64).to_ne_bytes()[..],
+            buffer.field_bytes(1).unwrap()
+        );
+
+        assert_eq!(
+            Err(FieldError::NoField("c".to_owned())),
+            buffer.get_field::<u8>("c")
+        );
+        assert_eq!(
+            Err(FieldError::NotScalar("inner".to_owned())),
+            buffer.get_field::<u32>("inner")
+        );
+        assert_eq!(
+            Err(FieldError::Size {
+                expected: 1,
+                actual: 4
+            }),
+            buffer.set_field("a", 1u32)
+        );
+
+        // Names survive cloning but not unnamed construction.
+        assert_eq!(Some(2), mixed.clone().field_index("inner"));
+        assert_eq!(None, Type::structure(vec![Type::u8()]).field_index("a"));
+    }
+}
@@ -1,6 +1,11 @@
 use std::any::Any;
+use std::error::Error;
+use std::os::raw::c_void;
+use std::ptr;
 
+use super::bridge::{self, ErrorPolicy};
 use super::types::Type;
+use crate::low;
 
 /// Provides a builder-style API for constructing CIFs and closures.
 ///
@@ -15,8 +20,9 @@ use super::types::Type;
 ///
 /// Once the builder is configured, construct a `Cif` with
 /// [`Builder::into_cif`] or a closure with [`Builder::into_closure`],
-/// [`into_closure_mut`](Builder::into_closure_mut), or
-/// [`into_closure_once`](Builder::into_closure_once).
+/// [`into_closure_mut`](Builder::into_closure_mut),
+/// [`into_closure_once`](Builder::into_closure_once), or
+/// [`into_closure_owned`](Builder::into_closure_owned).
 ///
 /// # Examples
 ///
@@ -61,6 +67,7 @@ pub struct Builder {
     args: Vec<Type>,
     res: Type,
     abi: super::FfiAbi,
+    affinity: Option<ErrorPolicy<bridge::Erased>>,
 }
 
 impl Default for Builder {
@@ -76,6 +83,7 @@ impl Builder {
             args: vec![],
             res: Type::void(),
             abi: super::ffi_abi_FFI_DEFAULT_ABI,
+            affinity: None,
         }
     }
 
@@ -90,7 +98,7 @@ impl Builder {
     where
         I: IntoIterator<Item = Type>,
     {
-        self.args.extend(types.into_iter());
+        self.args.extend(types);
         self
     }
 
@@ -100,17 +108,70 @@ impl Builder {
         self
     }
 
-    /// Sets the calling convention.
-    pub fn abi(mut self, abi: super::FfiAbi) -> Self {
-        self.abi = abi;
+    /// Sets the calling convention, either an [`Abi`](super::Abi) or
+    /// libffi’s number for it.
+    pub fn abi<A: Into<super::FfiAbi>>(mut self, abi: A) -> Self {
+        self.abi = abi.into();
+        self
+    }
+
+    /// Pins the closures built from this builder to the thread that
+    /// builds them.
+    ///
+    /// Calls from other threads do not run the callback, but fail with
+    /// [`WrongThread`](super::affinity::WrongThread) as `policy`
+    /// specifies; see [`affinity`](super::affinity). This applies to the
+    /// closures built by the `into_closure` methods, and to
+    /// [`into_fallible_closure`](Builder::into_fallible_closure), which
+    /// panic if `R` is larger than the result type.
+    pub fn thread_affinity<R: Copy>(mut self, policy: ErrorPolicy<R>) -> Self {
+        self.affinity = Some(policy.erase());
         self
     }
 
     /// Builds a CIF.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types or the calling convention;
+    /// see [`Builder::try_into_cif`].
     pub fn into_cif(self) -> super::Cif {
-        let mut result = super::Cif::new(self.args, self.res);
-        result.set_abi(self.abi);
-        result
+        ffi_expect!(
+            self.try_into_cif(),
+            "Builder::into_cif: libffi rejected the CIF"
+        )
+    }
+
+    /// Builds a CIF, prepared for the builder’s calling convention.
+    ///
+    /// # Errors
+    ///
+    /// Fails if libffi rejects the types, or does not support the
+    /// calling convention on this platform.
+    pub fn try_into_cif(self) -> Result<super::Cif, crate::Error> {
+        super::Cif::try_prepare(self.args, None, self.res, self.abi)
+    }
+
+    /// Builds a CIF for a variadic function whose first `nfixed`
+    /// arguments are fixed; see [`Cif::new_variadic`](super::Cif::new_variadic).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `nfixed` is greater than the number of arguments, or if
+    /// libffi rejects the types.
+    pub fn into_variadic_cif(self, nfixed: usize) -> super::Cif {
+        super::Cif::prepare(self.args, Some(nfixed), self.res, self.abi)
+    }
+
+    // Builds the CIF of a closure, with the affinity policy checked
+    // against its result type.
+    fn into_closure_parts(self, caller: &str) -> (super::Cif, Option<ErrorPolicy<bridge::Erased>>) {
+        let affinity = self.affinity.clone();
+        let cif = self.into_cif();
+        if let Some(policy) = &affinity {
+            policy.check(&cif, caller);
+        }
+        (cif, affinity)
     }
 
     /// Builds an immutable closure.
@@ -128,8 +189,16 @@ impl Builder {
         self,
         callback: super::Callback<U, R>,
         userdata: &U,
-    ) -> super::Closure {
-        super::Closure::new(self.into_cif(), callback, userdata)
+    ) -> super::Closure<'_> {
+        let (cif, affinity) = self.into_closure_parts("Builder::into_closure");
+        unsafe {
+            super::Closure::make(
+                cif,
+                std::mem::transmute::<super::Callback<U, R>, low::RawCallback>(callback),
+                userdata as *const U,
+                affinity,
+            )
+        }
     }
 
     /// Builds a mutable closure.
@@ -147,8 +216,16 @@ impl Builder {
         self,
         callback: super::CallbackMut<U, R>,
         userdata: &mut U,
-    ) -> super::Closure {
-        super::Closure::new_mut(self.into_cif(), callback, userdata)
+    ) -> super::Closure<'_> {
+        let (cif, affinity) = self.into_closure_parts("Builder::into_closure_mut");
+        unsafe {
+            super::Closure::make(
+                cif,
+                std::mem::transmute::<super::CallbackMut<U, R>, low::RawCallback>(callback),
+                userdata as *mut U,
+                affinity,
+            )
+        }
     }
 
     /// Builds a one-shot closure.
@@ -167,6 +244,104 @@ impl Builder {
         callback: super::CallbackOnce<U, R>,
         userdata: U,
     ) -> super::ClosureOnce {
-        super::ClosureOnce::new(self.into_cif(), callback, userdata)
+        let (cif, affinity) = self.into_closure_parts("Builder::into_closure_once");
+        super::ClosureOnce::make(cif, callback, userdata, affinity)
+    }
+
+    /// Builds a closure that owns its userdata.
+    ///
+    /// # Arguments
+    ///
+    /// - `callback` — the function to call when the closure is invoked
+    /// - `userdata` — the value to pass to `callback`, by reference,
+    ///   along with the arguments when the closure is called
+    ///
+    /// # Result
+    ///
+    /// The new closure.
+    pub fn into_closure_owned<U, R>(
+        self,
+        callback: super::Callback<U, R>,
+        userdata: U,
+    ) -> super::ClosureOwned<U> {
+        let (cif, affinity) = self.into_closure_parts("Builder::into_closure_owned");
+        super::ClosureOwned::make(cif, callback, Box::new(userdata), affinity)
+    }
+
+    /// Builds a closure that calls a fallible Rust function.
+    ///
+    /// When the closure is invoked, `callback` receives the CIF and the
+    /// C array of argument pointers. If it returns `Ok(value)` then
+    /// `value` is returned to C; if it returns an error or panics, the
+    /// outcome is determined by `policy` (see
+    /// [`bridge`](super::bridge)).
+    ///
+    /// As with the other closure constructors, the result type `R` must
+    /// follow libffi’s rules for widening small integer results to
+    /// [`ffi_arg`](low::ffi_arg).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::os::raw::c_void;
+    ///
+    /// use libffi::middle::*;
+    /// use libffi::middle::bridge::{take_callback_error, ErrorPolicy};
+    ///
+    /// let closure = Builder::new()
+    ///     .arg(Type::u64())
+    ///     .res(Type::i64())
+    ///     .into_fallible_closure(ErrorPolicy::ReturnCode(-1i64), |_cif, args| {
+    ///         let n = unsafe { **(args as *const &u64) };
+    ///         if n > 100 {
+    ///             Err("argument too large")
+    ///         } else {
+    ///             Ok(n as i64 * 2)
+    ///         }
+    ///     });
+    ///
+    /// let fun: &extern "C" fn(u64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+    ///
+    /// assert_eq!(10, fun(5));
+    /// assert!(take_callback_error().is_none());
+    ///
+    /// assert_eq!(-1, fun(500));
+    /// assert_eq!(
+    ///     "callback failed: argument too large",
+    ///     take_callback_error().unwrap().to_string(),
+    /// );
+    /// ```
+    pub fn into_fallible_closure<F, R, E>(
+        self,
+        policy: ErrorPolicy<R>,
+        callback: F,
+    ) -> super::ClosureOnce
+    where
+        F: FnMut(&low::ffi_cif, *const *const c_void) -> Result<R, E> + Any,
+        R: Copy + Any,
+        E: Into<Box<dyn Error + Send + Sync + 'static>>,
+    {
+        let (cif, affinity) = self.into_closure_parts("Builder::into_fallible_closure");
+        super::ClosureOnce::make(
+            cif,
+            fallible_callback::<F, R, E>,
+            (policy, callback),
+            affinity,
+        )
     }
 }
+
+unsafe extern "C" fn fallible_callback<F, R, E>(
+    cif: &low::ffi_cif,
+    result: &mut R,
+    args: *const *const c_void,
+    userdata: &mut Option<(ErrorPolicy<R>, F)>,
+) where
+    F: FnMut(&low::ffi_cif, *const *const c_void) -> Result<R, E>,
+    R: Copy,
+    E: Into<Box<dyn Error + Send + Sync + 'static>>,
+{
+    // The userdata is never taken, so it is always present.
+    let (policy, callback) = userdata.as_mut().unwrap();
+    ptr::write(result, policy.run(|| callback(cif, args)));
+}
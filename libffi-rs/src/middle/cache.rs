@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::{Cif, Type};
+use crate::low;
+
+/// Shares prepared CIFs between everything that needs the same
+/// signature.
+///
+/// Binding code, such as a language runtime creating a CIF for each
+/// foreign function it is asked to call, often prepares the same
+/// signature over and over, which allocates and prepares a new copy of
+/// the types each time. A cache instead keeps one CIF per signature and
+/// hands out [`Arc`]s of it, so that later requests for the signature
+/// only look it up. Signatures are compared structurally, as [`Type`]s
+/// are, so types built separately share a CIF if they describe the
+/// same C types; the CIF keeps the types it was first prepared with,
+/// including the field names of their structures.
+///
+/// CIFs are prepared for the platform’s default calling convention.
+/// The cache never forgets a CIF by itself; [`clear`](CifCache::clear)
+/// empties it. Cloning a cache gives another handle to the same CIFs,
+/// which may be used from any thread. [`Cif::cached`] uses a cache
+/// shared by the whole program.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use libffi::middle::{CifCache, Type};
+///
+/// let cache = CifCache::new();
+/// let point = || Type::structure(vec![Type::f64(), Type::f64()]);
+/// let first = cache.get(vec![point(), Type::pointer()], Type::i32());
+/// let second = cache.get(vec![point(), Type::pointer()], Type::i32());
+/// assert!(Arc::ptr_eq(&first, &second));
+/// assert_eq!(1, cache.len());
+/// ```
+#[derive(Clone, Default)]
+#[cfg_attr(not(feature = "min-size"), derive(Debug))]
+pub struct CifCache {
+    cifs: Arc<RwLock<HashMap<Key, Arc<Cif>>>>,
+}
+
+#[cfg(feature = "min-size")]
+opaque_debug!(CifCache);
+
+// The signature a CIF is cached under.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    args: Vec<Type>,
+    nfixed: Option<usize>,
+    result: Type,
+}
+
+impl CifCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Gets the CIF for the given argument and result types, preparing
+    /// it if the cache has none, as [`Cif::new`] does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types; see [`CifCache::try_get`].
+    pub fn get<I>(&self, args: I, result: Type) -> Arc<Cif>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        ffi_expect!(
+            self.try_get(args, result),
+            "CifCache::get: libffi rejected the CIF"
+        )
+    }
+
+    /// Gets the CIF for the given argument and result types, preparing
+    /// it if the cache has none, as [`Cif::try_new`] does.
+    ///
+    /// # Errors
+    ///
+    /// Fails if libffi rejects the types, in which case nothing is
+    /// cached.
+    pub fn try_get<I>(&self, args: I, result: Type) -> Result<Arc<Cif>, crate::Error>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        self.find_or_prepare(Key {
+            args: args.into_iter().collect(),
+            nfixed: None,
+            result,
+        })
+    }
+
+    /// Gets the variadic CIF for the given argument and result types,
+    /// the first `nfixed` of them fixed, preparing it if the cache has
+    /// none, as [`Cif::new_variadic`] does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `nfixed` is greater than the number of arguments, or
+    /// libffi rejects the types.
+    pub fn get_variadic<I>(&self, args: I, nfixed: usize, result: Type) -> Arc<Cif>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        ffi_expect!(
+            self.find_or_prepare(Key {
+                args: args.into_iter().collect(),
+                nfixed: Some(nfixed),
+                result,
+            }),
+            "CifCache::get_variadic: libffi rejected the CIF"
+        )
+    }
+
+    /// The number of CIFs in the cache.
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Whether the cache has no CIFs.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Empties the cache.
+    ///
+    /// The CIFs already handed out remain valid; they are freed when
+    /// their last [`Arc`] is dropped.
+    pub fn clear(&self) {
+        self.cifs.write().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<Key, Arc<Cif>>> {
+        self.cifs.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn find_or_prepare(&self, key: Key) -> Result<Arc<Cif>, crate::Error> {
+        if let Some(cif) = self.read().get(&key) {
+            return Ok(cif.clone());
+        }
+        // Prepare the CIF without holding the lock, and keep whichever
+        // CIF is cached first if another thread races to prepare it.
+        let cif = Arc::new(Cif::try_prepare(
+            key.args.iter().cloned(),
+            key.nfixed,
+            key.result.clone(),
+            low::ffi_abi_FFI_DEFAULT_ABI,
+        )?);
+        let mut cifs = self.cifs.write().unwrap_or_else(|e| e.into_inner());
+        Ok(cifs.entry(key).or_insert(cif).clone())
+    }
+}
+
+impl Cif {
+    /// Gets the CIF for the given argument and result types from a
+    /// [`CifCache`] shared by the whole program, preparing it the first
+    /// time the signature is asked for.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// use libffi::middle::{Cif, Type};
+    ///
+    /// let cif = Cif::cached(vec![Type::f64(), Type::c_int()], Type::f64());
+    /// let again = Cif::cached(vec![Type::f64(), Type::i32()], Type::f64());
+    /// assert!(Arc::ptr_eq(&cif, &again));
+    /// ```
+    pub fn cached<I>(args: I, result: Type) -> Arc<Cif>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        static CACHE: OnceLock<CifCache> = OnceLock::new();
+        CACHE.get_or_init(CifCache::new).get(args, result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::{arg, CodePtr};
+
+    #[repr(C)]
+    struct Vector {
+        x: f64,
+        y: f64,
+    }
+
+    extern "C" fn dot(a: Vector, b: Vector) -> f64 {
+        a.x * b.x + a.y * b.y
+    }
+
+    #[test]
+    fn shares_cifs_by_signature() {
+        let cache = CifCache::new();
+        let vector = || Type::array(Type::f64(), 2);
+        let first = cache.get(vec![vector(), vector()], Type::f64());
+        let named = Type::structure_named(vec![("x", Type::f64()), ("y", Type::f64())]);
+        let second = cache.get(vec![named.clone(), named], Type::f64());
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let other = cache.get(vec![vector()], Type::f64());
+        assert!(!Arc::ptr_eq(&first, &other));
+        let variadic = cache.get_variadic(vec![vector(), vector()], 1, Type::f64());
+        assert!(!Arc::ptr_eq(&first, &variadic));
+        assert_eq!(3, cache.len());
+
+        let (a, b) = (Vector { x: 1.0, y: 2.0 }, Vector { x: 3.0, y: 4.0 });
+        let n: f64 = unsafe { second.call(CodePtr(dot as *mut _), &[arg(&a), arg(&b)]) };
+        assert_eq!(11.0, n);
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert!(!Arc::ptr_eq(
+            &first,
+            &cache.get(vec![vector(), vector()], Type::f64())
+        ));
+    }
+
+    #[test]
+    fn rejected_cifs_are_not_cached() {
+        let cache = CifCache::new();
+        assert_eq!(
+            Err(crate::Error::BadTypedef),
+            cache
+                .try_get(vec![Type::structure(vec![])], Type::void())
+                .map(|_| ())
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let cache = CifCache::new();
+        let cifs: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                std::thread::spawn(move || cache.get(vec![Type::u64(); 4], Type::void()))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        assert!(cifs.iter().all(|cif| Arc::ptr_eq(cif, &cifs[0])));
+        assert_eq!(1, cache.len());
+    }
+}
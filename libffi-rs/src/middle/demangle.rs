@@ -0,0 +1,861 @@
+//! Best-effort demangling of C++ symbols, and CIFs guessed from them.
+//!
+//! When exploring the `extern` surface of a C++ library, the mangled
+//! names of its functions are often the only record of their
+//! signatures. This module decodes the common subset of the Itanium
+//! (GCC, Clang) and MSVC manglings used by free functions taking
+//! primitives, pointers, and references, and proposes a [`Cif`] for
+//! them with [`Demangled::guess_cif`]. Anything outside that subset,
+//! such as templates, member functions, and classes passed by value, is
+//! reported rather than guessed at.
+//!
+//! The built-in decoders are deliberately small. To use a complete
+//! demangler instead, implement [`Demangler`] (closures already do) and
+//! produce a [`Demangled`] from its output.
+//!
+//! Itanium manglings don’t record the result type of an ordinary
+//! function, so it must be supplied when guessing the CIF.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::middle::demangle::demangle;
+//! use libffi::middle::Type;
+//!
+//! let add = demangle("_ZN4math3addEPKdj").unwrap();
+//! assert_eq!("math::add(double const*, unsigned int)", add.to_string());
+//!
+//! let cif = add.guess_cif(Some(Type::f64())).unwrap();
+//! assert_eq!("(pointer, u32) -> f64", &*cif.signature());
+//! ```
+
+use std::os::raw::c_char;
+use std::{error, fmt};
+
+use super::{Cif, Type};
+
+/// A C++ type, as recorded in a mangled name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CppType {
+    /// `void`.
+    Void,
+    /// `bool`.
+    Bool,
+    /// `char`.
+    Char,
+    /// `signed char`.
+    SignedChar,
+    /// `unsigned char`.
+    UnsignedChar,
+    /// `short`.
+    Short,
+    /// `unsigned short`.
+    UnsignedShort,
+    /// `int`.
+    Int,
+    /// `unsigned int`.
+    UnsignedInt,
+    /// `long`.
+    Long,
+    /// `unsigned long`.
+    UnsignedLong,
+    /// `long long`.
+    LongLong,
+    /// `unsigned long long`.
+    UnsignedLongLong,
+    /// `float`.
+    Float,
+    /// `double`.
+    Double,
+    /// `long double`.
+    LongDouble,
+    /// A class, struct, union, or enum, by its qualified name.
+    Named(String),
+    /// A pointer to a type.
+    Pointer(Box<CppType>),
+    /// An lvalue or rvalue reference to a type.
+    Reference(Box<CppType>),
+    /// A `const`-qualified type.
+    Const(Box<CppType>),
+    /// A type the decoder recognized but has no representation for,
+    /// described in words.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for CppType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CppType::Void => "void",
+            CppType::Bool => "bool",
+            CppType::Char => "char",
+            CppType::SignedChar => "signed char",
+            CppType::UnsignedChar => "unsigned char",
+            CppType::Short => "short",
+            CppType::UnsignedShort => "unsigned short",
+            CppType::Int => "int",
+            CppType::UnsignedInt => "unsigned int",
+            CppType::Long => "long",
+            CppType::UnsignedLong => "unsigned long",
+            CppType::LongLong => "long long",
+            CppType::UnsignedLongLong => "unsigned long long",
+            CppType::Float => "float",
+            CppType::Double => "double",
+            CppType::LongDouble => "long double",
+            CppType::Named(name) => name,
+            CppType::Pointer(ty) => return write!(f, "{}*", ty),
+            CppType::Reference(ty) => return write!(f, "{}&", ty),
+            CppType::Const(ty) => return write!(f, "{} const", ty),
+            CppType::Unsupported(what) => return write!(f, "<{}>", what),
+        };
+        f.write_str(name)
+    }
+}
+
+/// A demangled function symbol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Demangled {
+    /// The function’s qualified name, such as `geo::area`.
+    pub name: String,
+    /// The types of the function’s arguments.
+    pub args: Vec<CppType>,
+    /// The function’s result type, if the mangling records it.
+    pub result: Option<CppType>,
+}
+
+impl fmt::Display for Demangled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(result) = &self.result {
+            write!(f, "{} ", result)?;
+        }
+        write!(f, "{}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl Demangled {
+    /// Proposes a CIF for the function.
+    ///
+    /// Pointers and references become [`Type::pointer`], and `bool`
+    /// becomes [`Type::u8`]; other primitives become the corresponding
+    /// C type. `result` overrides the result type, and is required if
+    /// the mangling doesn’t record one.
+    ///
+    /// The CIF is only a guess: the mangling cannot say, for example,
+    /// whether a reference argument may be null.
+    ///
+    /// # Errors
+    ///
+    /// Fails if an argument has no libffi equivalent, such as a class
+    /// passed by value, or if the result type is unknown.
+    pub fn guess_cif(&self, result: Option<Type>) -> Result<Cif, DemangleError> {
+        let result = match (result, &self.result) {
+            (Some(result), _) => result,
+            (None, Some(CppType::Void)) => Type::void(),
+            (None, Some(result)) => guess_type(result)?,
+            (None, None) => return Err(DemangleError::UnknownResult),
+        };
+        let args = self
+            .args
+            .iter()
+            .map(guess_type)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Cif::new(args, result))
+    }
+}
+
+// The libffi type of a non-void C++ type.
+fn guess_type(ty: &CppType) -> Result<Type, DemangleError> {
+    Ok(match ty {
+        CppType::Bool | CppType::UnsignedChar => Type::c_uchar(),
+        CppType::Char if c_char::MIN == 0 => Type::c_uchar(),
+        CppType::Char | CppType::SignedChar => Type::c_schar(),
+        CppType::Short => Type::c_short(),
+        CppType::UnsignedShort => Type::c_ushort(),
+        CppType::Int => Type::c_int(),
+        CppType::UnsignedInt => Type::c_uint(),
+        CppType::Long => Type::c_long(),
+        CppType::UnsignedLong => Type::c_ulong(),
+        CppType::LongLong => Type::c_longlong(),
+        CppType::UnsignedLongLong => Type::c_ulonglong(),
+        CppType::Float => Type::f32(),
+        CppType::Double => Type::f64(),
+        #[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
+        CppType::LongDouble => Type::longdouble(),
+        CppType::Pointer(_) | CppType::Reference(_) => Type::pointer(),
+        CppType::Const(ty) => guess_type(ty)?,
+        CppType::Void => return Err(DemangleError::Unsupported("void argument")),
+        CppType::Named(_) => return Err(DemangleError::Unsupported("class passed by value")),
+        CppType::Unsupported(what) => return Err(DemangleError::Unsupported(what)),
+        #[allow(unreachable_patterns)]
+        _ => return Err(DemangleError::Unsupported("long double")),
+    })
+}
+
+/// The error returned when a symbol cannot be demangled, or a CIF
+/// cannot be guessed for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DemangleError {
+    /// The symbol is not a mangled name of the expected scheme.
+    NotMangled,
+    /// The mangled name ends early or contains something that is not
+    /// part of the scheme, at the given byte offset.
+    Invalid {
+        /// The offset of the unexpected text.
+        offset: usize,
+    },
+    /// The symbol uses a construct the decoder or guesser does not
+    /// support, described in words.
+    Unsupported(&'static str),
+    /// The mangling does not record the result type, and none was
+    /// given.
+    UnknownResult,
+}
+
+impl fmt::Display for DemangleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DemangleError::NotMangled => write!(f, "not a mangled C++ name"),
+            DemangleError::Invalid { offset } => {
+                write!(f, "invalid mangled name at offset {}", offset)
+            }
+            DemangleError::Unsupported(what) => write!(f, "unsupported construct: {}", what),
+            DemangleError::UnknownResult => write!(f, "the result type is not known"),
+        }
+    }
+}
+
+impl error::Error for DemangleError {}
+
+/// Decodes mangled symbols.
+///
+/// Implemented by the built-in [`Itanium`] and [`Msvc`] decoders, and
+/// by closures, so that a complete demangler can be plugged in where
+/// these are accepted.
+pub trait Demangler {
+    /// Demangles `symbol`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `symbol` is not a mangled name the demangler
+    /// understands.
+    fn demangle(&self, symbol: &str) -> Result<Demangled, DemangleError>;
+}
+
+impl<F> Demangler for F
+where
+    F: Fn(&str) -> Result<Demangled, DemangleError>,
+{
+    fn demangle(&self, symbol: &str) -> Result<Demangled, DemangleError> {
+        self(symbol)
+    }
+}
+
+/// Demangles `symbol` with whichever built-in decoder its prefix
+/// suggests.
+///
+/// # Errors
+///
+/// As for [`Demangler::demangle`].
+pub fn demangle(symbol: &str) -> Result<Demangled, DemangleError> {
+    if symbol.starts_with('?') {
+        Msvc.demangle(symbol)
+    } else {
+        Itanium.demangle(symbol)
+    }
+}
+
+/// The built-in decoder for the Itanium C++ ABI’s manglings, used by
+/// GCC and Clang.
+///
+/// Symbols of the form `_Z<name><argument types>` are supported,
+/// where the name may be qualified by namespaces. The mangling does not
+/// distinguish static member functions from functions in a namespace,
+/// nor record the result type.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Itanium;
+
+impl Demangler for Itanium {
+    fn demangle(&self, symbol: &str) -> Result<Demangled, DemangleError> {
+        // macOS prefixes symbols with an extra underscore.
+        let symbol = symbol.strip_prefix("__Z").map_or(symbol, |_| &symbol[1..]);
+        if !symbol.starts_with("_Z") {
+            return Err(DemangleError::NotMangled);
+        }
+
+        let mut parser = Parser::new(symbol, 2);
+        let name = parser.itanium_name()?;
+        // A function without arguments has the single argument type
+        // `void`.
+        let mut args = Vec::new();
+        if parser.eat("v") {
+            parser.end()?;
+        } else {
+            loop {
+                args.push(parser.itanium_type()?);
+                if parser.at_end() {
+                    break;
+                }
+            }
+        }
+
+        Ok(Demangled {
+            name,
+            args,
+            result: None,
+        })
+    }
+}
+
+/// The built-in decoder for the Microsoft Visual C++ manglings.
+///
+/// Symbols of free functions with the `__cdecl` calling convention
+/// are supported, where the name may be qualified by namespaces.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Msvc;
+
+impl Demangler for Msvc {
+    fn demangle(&self, symbol: &str) -> Result<Demangled, DemangleError> {
+        if !symbol.starts_with('?') {
+            return Err(DemangleError::NotMangled);
+        }
+
+        let mut parser = Parser::new(symbol, 1);
+        if parser.eat("?") {
+            return Err(DemangleError::Unsupported("operator or special member"));
+        }
+        let name = parser.msvc_name()?;
+        match parser.next()? {
+            b'Y' | b'Z' => {}
+            _ => return Err(DemangleError::Unsupported("member function")),
+        }
+        if parser.next()? != b'A' {
+            return Err(DemangleError::Unsupported(
+                "calling convention other than __cdecl",
+            ));
+        }
+
+        // Class results are preceded by their storage class.
+        parser.eat("?A");
+        let result = parser.msvc_type()?;
+
+        let mut args = Vec::new();
+        if !parser.eat("X") {
+            while !parser.eat("@") {
+                if parser.eat("Z") {
+                    return Err(DemangleError::Unsupported("variadic function"));
+                }
+                let start = parser.offset;
+                let arg = match parser.peek()? {
+                    n @ b'0'..=b'9' => {
+                        parser.offset += 1;
+                        parser
+                            .backrefs
+                            .get(usize::from(n - b'0'))
+                            .cloned()
+                            .ok_or(DemangleError::Invalid { offset: start })?
+                    }
+                    _ => parser.msvc_type()?,
+                };
+                // Only types longer than one character can be referred
+                // back to.
+                if parser.offset - start > 1 && parser.backrefs.len() < 10 {
+                    parser.backrefs.push(arg.clone());
+                }
+                args.push(arg);
+            }
+        }
+        if !parser.eat("Z") {
+            return Err(parser.invalid());
+        }
+        parser.end()?;
+
+        Ok(Demangled {
+            name,
+            args,
+            result: Some(result),
+        })
+    }
+}
+
+// The deepest that pointers, references and qualifiers may be nested
+// in a type.
+const MAX_DEPTH: usize = 64;
+
+// A cursor over a mangled name, along with the types and names that
+// later parts of the name may refer back to.
+struct Parser<'a> {
+    symbol: &'a [u8],
+    offset: usize,
+    backrefs: Vec<CppType>,
+    // How deeply the type being parsed is nested.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(symbol: &'a str, offset: usize) -> Self {
+        Parser {
+            symbol: symbol.as_bytes(),
+            offset,
+            backrefs: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    fn invalid(&self) -> DemangleError {
+        DemangleError::Invalid {
+            offset: self.offset,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.offset == self.symbol.len()
+    }
+
+    fn end(&self) -> Result<(), DemangleError> {
+        if self.at_end() {
+            Ok(())
+        } else {
+            Err(self.invalid())
+        }
+    }
+
+    fn peek(&self) -> Result<u8, DemangleError> {
+        self.symbol
+            .get(self.offset)
+            .copied()
+            .ok_or_else(|| self.invalid())
+    }
+
+    fn next(&mut self) -> Result<u8, DemangleError> {
+        let byte = self.peek()?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.symbol[self.offset..].starts_with(token.as_bytes()) {
+            self.offset += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Takes the next `len` bytes as an identifier.
+    fn identifier(&mut self, len: usize) -> Result<String, DemangleError> {
+        let end = self.offset.checked_add(len).ok_or_else(|| self.invalid())?;
+        let bytes = self
+            .symbol
+            .get(self.offset..end)
+            .ok_or_else(|| self.invalid())?;
+        self.offset += len;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    // <source-name> ::= <length> <identifier>
+    fn source_name(&mut self) -> Result<String, DemangleError> {
+        let start = self.offset;
+        while self.peek()?.is_ascii_digit() {
+            self.offset += 1;
+        }
+        let len = std::str::from_utf8(&self.symbol[start..self.offset])
+            .unwrap()
+            .parse()
+            .map_err(|_| DemangleError::Invalid { offset: start })?;
+        self.identifier(len)
+    }
+
+    // <substitution> ::= S_ | S <base 36 number> _
+    fn substitution(&mut self) -> Result<CppType, DemangleError> {
+        let start = self.offset;
+        let mut index = 0;
+        if !self.eat("_") {
+            let mut n = 0usize;
+            loop {
+                let digit = match self.next()? {
+                    b'_' => break,
+                    c @ b'0'..=b'9' => c - b'0',
+                    c @ b'A'..=b'Z' => c - b'A' + 10,
+                    _ => return Err(self.invalid()),
+                };
+                n = n
+                    .checked_mul(36)
+                    .and_then(|n| n.checked_add(usize::from(digit)))
+                    .ok_or(DemangleError::Invalid { offset: start })?;
+            }
+            index = n
+                .checked_add(1)
+                .ok_or(DemangleError::Invalid { offset: start })?;
+        }
+        self.backrefs
+            .get(index)
+            .cloned()
+            .ok_or(DemangleError::Invalid { offset: start })
+    }
+
+    // Parses the components of a name up to `end`, recording each
+    // prefix (and, if `whole`, the whole name) for substitution.
+    fn itanium_components(&mut self, whole: bool) -> Result<String, DemangleError> {
+        let mut name = String::new();
+        // `std` itself is not a candidate for substitution.
+        let mut candidate = false;
+        loop {
+            let component = match self.peek()? {
+                b'E' => break,
+                b'0'..=b'9' => self.source_name()?,
+                b'S' if name.is_empty() && self.eat("St") => {
+                    name.push_str("std");
+                    continue;
+                }
+                b'S' => {
+                    self.offset += 1;
+                    match self.substitution()? {
+                        CppType::Named(prefix) => prefix,
+                        _ => return Err(self.invalid()),
+                    }
+                }
+                b'I' => return Err(DemangleError::Unsupported("template")),
+                b'C' | b'D' => return Err(DemangleError::Unsupported("constructor or destructor")),
+                _ => return Err(self.invalid()),
+            };
+            if name.is_empty() {
+                name = component;
+            } else {
+                if candidate && !self.backrefs.contains(&CppType::Named(name.clone())) {
+                    self.backrefs.push(CppType::Named(name.clone()));
+                }
+                name = format!("{}::{}", name, component);
+            }
+            candidate = true;
+        }
+        self.offset += 1;
+        if whole {
+            self.backrefs.push(CppType::Named(name.clone()));
+        }
+        Ok(name)
+    }
+
+    // The name of a function.
+    fn itanium_name(&mut self) -> Result<String, DemangleError> {
+        let name = if self.eat("N") {
+            match self.peek()? {
+                b'K' | b'V' | b'r' | b'R' | b'O' => {
+                    return Err(DemangleError::Unsupported("qualified member function"))
+                }
+                _ => self.itanium_components(false)?,
+            }
+        } else if self.eat("St") {
+            format!("std::{}", self.source_name()?)
+        } else if self.peek()?.is_ascii_digit() {
+            self.source_name()?
+        } else {
+            return Err(DemangleError::Unsupported("special name"));
+        };
+
+        if self.peek() == Ok(b'I') {
+            return Err(DemangleError::Unsupported("template"));
+        }
+        Ok(name)
+    }
+
+    fn itanium_type(&mut self) -> Result<CppType, DemangleError> {
+        let start = self.offset;
+        let ty = match self.next()? {
+            b'v' => CppType::Void,
+            b'b' => CppType::Bool,
+            b'c' => CppType::Char,
+            b'a' => CppType::SignedChar,
+            b'h' => CppType::UnsignedChar,
+            b's' => CppType::Short,
+            b't' => CppType::UnsignedShort,
+            b'i' => CppType::Int,
+            b'j' => CppType::UnsignedInt,
+            b'l' => CppType::Long,
+            b'm' => CppType::UnsignedLong,
+            b'x' => CppType::LongLong,
+            b'y' => CppType::UnsignedLongLong,
+            b'f' => CppType::Float,
+            b'd' => CppType::Double,
+            b'e' => CppType::LongDouble,
+            b'w' => CppType::Unsupported("wchar_t"),
+            b'n' | b'o' => CppType::Unsupported("128-bit integer"),
+            b'z' => return Err(DemangleError::Unsupported("variadic function")),
+            b'P' => self.compound(CppType::Pointer)?,
+            b'R' | b'O' => self.compound(CppType::Reference)?,
+            b'K' => self.compound(CppType::Const)?,
+            b'0'..=b'9' => {
+                self.offset = start;
+                let name = CppType::Named(self.source_name()?);
+                self.backrefs.push(name.clone());
+                name
+            }
+            b'N' => CppType::Named(self.itanium_components(true)?),
+            b'S' if self.eat("t") => {
+                let name = CppType::Named(format!("std::{}", self.source_name()?));
+                self.backrefs.push(name.clone());
+                name
+            }
+            b'S' => match self.peek()? {
+                b'_' | b'0'..=b'9' | b'A'..=b'Z' => self.substitution()?,
+                _ => return Err(DemangleError::Unsupported("standard library type")),
+            },
+            b'V' => return Err(DemangleError::Unsupported("volatile type")),
+            b'A' => return Err(DemangleError::Unsupported("array type")),
+            b'F' => return Err(DemangleError::Unsupported("function type")),
+            b'M' => return Err(DemangleError::Unsupported("pointer to member")),
+            b'T' => return Err(DemangleError::Unsupported("template parameter")),
+            b'D' => return Err(DemangleError::Unsupported("extended type")),
+            _ => return Err(DemangleError::Invalid { offset: start }),
+        };
+        Ok(ty)
+    }
+
+    // Parses the type a qualifier or pointer applies to, recording the
+    // compound type for substitution.
+    fn compound(&mut self, wrap: fn(Box<CppType>) -> CppType) -> Result<CppType, DemangleError> {
+        let ty = wrap(Box::new(self.nested(Self::itanium_type)?));
+        self.backrefs.push(ty.clone());
+        Ok(ty)
+    }
+
+    // Parses a type within another, failing rather than recursing
+    // without bound if types are nested too deeply.
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<CppType, DemangleError>,
+    ) -> Result<CppType, DemangleError> {
+        if self.depth == MAX_DEPTH {
+            return Err(DemangleError::Unsupported("deeply nested type"));
+        }
+        self.depth += 1;
+        let ty = parse(self);
+        self.depth -= 1;
+        ty
+    }
+
+    // <name> ::= <fragment> @ ... @ @, innermost first
+    fn msvc_name(&mut self) -> Result<String, DemangleError> {
+        let mut fragments = Vec::new();
+        while !self.eat("@") {
+            let start = self.offset;
+            match self.peek()? {
+                b'?' | b'0'..=b'9' => {
+                    return Err(DemangleError::Unsupported(
+                        "template or name back reference",
+                    ))
+                }
+                _ => {}
+            }
+            let len = self.symbol[start..]
+                .iter()
+                .position(|&c| c == b'@')
+                .ok_or_else(|| self.invalid())?;
+            fragments.push(self.identifier(len)?);
+            self.offset += 1;
+        }
+        if fragments.is_empty() {
+            return Err(self.invalid());
+        }
+        fragments.reverse();
+        Ok(fragments.join("::"))
+    }
+
+    fn msvc_type(&mut self) -> Result<CppType, DemangleError> {
+        let start = self.offset;
+        let ty = match self.next()? {
+            b'X' => CppType::Void,
+            b'D' => CppType::Char,
+            b'C' => CppType::SignedChar,
+            b'E' => CppType::UnsignedChar,
+            b'F' => CppType::Short,
+            b'G' => CppType::UnsignedShort,
+            b'H' => CppType::Int,
+            b'I' => CppType::UnsignedInt,
+            b'J' => CppType::Long,
+            b'K' => CppType::UnsignedLong,
+            b'M' => CppType::Float,
+            b'N' => CppType::Double,
+            b'O' => CppType::LongDouble,
+            b'_' => match self.next()? {
+                b'N' => CppType::Bool,
+                b'J' => CppType::LongLong,
+                b'K' => CppType::UnsignedLongLong,
+                b'W' => CppType::Unsupported("wchar_t"),
+                _ => return Err(DemangleError::Unsupported("extended type")),
+            },
+            kind @ (b'P' | b'Q' | b'A' | b'$') => {
+                if kind == b'$' && !self.eat("$Q") {
+                    return Err(DemangleError::Unsupported("extended type"));
+                }
+                // The pointer itself may be marked as 64-bit.
+                self.eat("E");
+                let constant = match self.next()? {
+                    b'A' => false,
+                    b'B' => true,
+                    b'C' | b'D' => return Err(DemangleError::Unsupported("volatile type")),
+                    _ => return Err(self.invalid()),
+                };
+                let mut pointee = self.nested(Self::msvc_type)?;
+                if constant {
+                    pointee = CppType::Const(Box::new(pointee));
+                }
+                match kind {
+                    b'P' | b'Q' => CppType::Pointer(Box::new(pointee)),
+                    _ => CppType::Reference(Box::new(pointee)),
+                }
+            }
+            b'T' | b'U' | b'V' => CppType::Named(self.msvc_name()?),
+            b'W' if self.eat("4") => CppType::Named(self.msvc_name()?),
+            _ => return Err(DemangleError::Invalid { offset: start }),
+        };
+        Ok(ty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn demangled(symbol: &str) -> String {
+        demangle(symbol).unwrap().to_string()
+    }
+
+    #[test]
+    fn itanium() {
+        assert_eq!("f()", demangled("_Z1fv"));
+        assert_eq!("add(int, int)", demangled("_Z3addii"));
+        assert_eq!(
+            "copy(char*, char const*, unsigned long)",
+            demangled("_Z4copyPcPKcm")
+        );
+        assert_eq!("cmp(char const*, char const*)", demangled("__Z3cmpPKcS0_"));
+        assert_eq!(
+            "geo::area(geo::Point const*, geo::Point&, double)",
+            demangled("_ZN3geo4areaEPKNS_5PointERS0_d")
+        );
+        assert_eq!(
+            "std::swap(std::string&, std::string&)",
+            demangled("_ZSt4swapRSt6stringS0_")
+        );
+
+        assert_eq!(
+            Err(DemangleError::Unsupported("template")),
+            demangle("_Z3maxIiET_S0_S0_")
+        );
+        assert_eq!(
+            Err(DemangleError::Unsupported("qualified member function")),
+            demangle("_ZNK3geo5Shape4areaEv")
+        );
+        assert_eq!(Err(DemangleError::NotMangled), demangle("strlen"));
+        assert_eq!(Err(DemangleError::Invalid { offset: 3 }), demangle("_Z9f"));
+    }
+
+    #[test]
+    fn msvc() {
+        assert_eq!("void f()", demangled("?f@@YAXXZ"));
+        assert_eq!("int add(int, int)", demangled("?add@@YAHHH@Z"));
+        assert_eq!(
+            "char* copy(char*, char const*)",
+            demangled("?copy@@YAPEADPEADPEBD@Z")
+        );
+        assert_eq!("void twice(int*, int*)", demangled("?twice@@YAXPEAH0@Z"));
+        assert_eq!(
+            "double geo::area(geo::Point const&)",
+            demangled("?area@geo@@YANAEBUPoint@geo@@@Z")
+        );
+
+        assert_eq!(
+            Err(DemangleError::Unsupported("member function")),
+            demangle("?area@Shape@@QEAANXZ")
+        );
+        assert_eq!(
+            Err(DemangleError::Unsupported(
+                "calling convention other than __cdecl"
+            )),
+            demangle("?f@@YGXXZ")
+        );
+    }
+
+    #[test]
+    fn oversized_numbers() {
+        assert_eq!(
+            Err(DemangleError::Invalid { offset: 22 }),
+            demangle("_Z18446744073709551615f")
+        );
+        assert_eq!(
+            Err(DemangleError::Invalid { offset: 5 }),
+            demangle("_Z1fSZZZZZZZZZZZZZZZZ_")
+        );
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let itanium = |depth| format!("_Z1f{}i", "P".repeat(depth));
+        assert!(demangle(&itanium(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            Err(DemangleError::Unsupported("deeply nested type")),
+            demangle(&itanium(MAX_DEPTH + 1))
+        );
+        assert_eq!(
+            Err(DemangleError::Unsupported("deeply nested type")),
+            demangle(&itanium(100_000))
+        );
+
+        let msvc = |depth| format!("?f@@YAX{}H@Z", "PEA".repeat(depth));
+        assert!(demangle(&msvc(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            Err(DemangleError::Unsupported("deeply nested type")),
+            demangle(&msvc(100_000))
+        );
+    }
+
+    #[test]
+    fn guessed_cifs() {
+        let copy = demangle("_Z4copyPcPKcj").unwrap();
+        assert_eq!(
+            Err(DemangleError::UnknownResult),
+            copy.guess_cif(None).map(drop)
+        );
+        let cif = copy.guess_cif(Some(Type::pointer())).unwrap();
+        assert_eq!("(pointer, pointer, u32) -> pointer", &*cif.signature());
+
+        let add = demangle("?add@@YAHHH@Z").unwrap();
+        assert_eq!(
+            "(i32, i32) -> i32",
+            &*add.guess_cif(None).unwrap().signature()
+        );
+
+        let by_value = demangle("_Z4area5Point").unwrap();
+        assert_eq!(
+            Err(DemangleError::Unsupported("class passed by value")),
+            by_value.guess_cif(Some(Type::f64())).map(drop)
+        );
+    }
+
+    #[test]
+    fn plugged_in_demangler() {
+        let known = |symbol: &str| match symbol {
+            "_ZN1a1fEv" => Ok(Demangled {
+                name: "a::f".to_owned(),
+                args: vec![],
+                result: Some(CppType::Bool),
+            }),
+            _ => Itanium.demangle(symbol),
+        };
+        let demangler: &dyn Demangler = &known;
+        assert_eq!(
+            "bool a::f()",
+            demangler.demangle("_ZN1a1fEv").unwrap().to_string()
+        );
+        assert_eq!(
+            "a::g()",
+            demangler.demangle("_ZN1a1gEv").unwrap().to_string()
+        );
+    }
+}
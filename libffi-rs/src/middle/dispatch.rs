@@ -0,0 +1,1210 @@
+//! Running closure bodies on threads other than the caller’s.
+//!
+//! Some callbacks must not block the C code that invokes them. A
+//! [`QueuedClosure`] copies the arguments of each call into an owned
+//! [`Invocation`], sends it to an [`InvocationQueue`], and immediately
+//! returns a preset result to C. The real handler drains the queue,
+//! usually on another thread.
+//!
+//! Conversely, some callbacks must run on a particular thread, such as
+//! a GUI toolkit’s UI thread, whichever thread C calls them on. A
+//! [`ProxyClosure`] sends each call to a [`ProxyReceiver`] on the
+//! target thread and blocks the caller until the target thread replies
+//! with the result.
+//!
+//! Both kinds of closure can be created with a [`CancellationToken`],
+//! which the host can trip to stop handling calls.
+//!
+//! Arguments are copied according to the CIF’s argument types, so any
+//! argument type can be queued. Only the arguments themselves are
+//! copied, however: if an argument is a pointer, the invocation holds
+//! the pointer, not the data it points to, which may no longer be valid
+//! by the time the invocation is handled.
+
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use std::{error, fmt, mem, ptr, slice};
+
+use super::bridge::{self, ErrorPolicy};
+use super::util::{self, Chunk};
+use super::{Builder, Cif, Closure, CodePtr};
+use crate::low;
+
+/// Where each argument of a CIF is stored in an [`Invocation`].
+#[derive(Debug)]
+struct ArgLayout {
+    // The offset and size of each argument.
+    args: Vec<(usize, usize)>,
+    size: usize,
+}
+
+impl ArgLayout {
+    fn new(cif: &Cif) -> Self {
+        let raw = unsafe { &*cif.as_raw_ptr() };
+        let types = unsafe { slice::from_raw_parts(raw.arg_types, raw.nargs as usize) };
+
+        let mut args = Vec::with_capacity(types.len());
+        let mut end = 0;
+        for &type_ in types {
+            let type_ = unsafe { &*type_ };
+            let align = usize::from(type_.alignment).max(1);
+            assert!(
+                align <= mem::align_of::<Chunk>(),
+                "dispatch: argument alignment {} is not supported",
+                align
+            );
+            let offset = (end + align - 1) / align * align;
+            args.push((offset, type_.size));
+            end = offset + type_.size;
+        }
+
+        ArgLayout { args, size: end }
+    }
+
+    // Copies the arguments a closure was called with.
+    unsafe fn copy(self: &Arc<Self>, args: *const *const c_void) -> Invocation {
+        let mut data = util::chunks(self.size);
+        let base = data.as_mut_ptr() as *mut u8;
+        for (i, &(offset, size)) in self.args.iter().enumerate() {
+            ptr::copy_nonoverlapping(*args.add(i) as *const u8, base.add(offset), size);
+        }
+        Invocation {
+            layout: self.clone(),
+            data,
+        }
+    }
+}
+
+/// The arguments of one call of a closure, copied so that it can be
+/// handled later or elsewhere.
+pub struct Invocation {
+    layout: Arc<ArgLayout>,
+    data: Box<[Chunk]>,
+}
+
+impl Invocation {
+    /// The number of arguments.
+    pub fn len(&self) -> usize {
+        self.layout.args.len()
+    }
+
+    /// Whether there are no arguments.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets a pointer to the `i`th argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no `i`th argument.
+    pub fn arg_ptr(&self, i: usize) -> *const c_void {
+        let (offset, _) = self.layout.args[i];
+        unsafe { (self.data.as_ptr() as *const u8).add(offset) as *const c_void }
+    }
+
+    /// Gets the bytes of the `i`th argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no `i`th argument.
+    pub fn arg_bytes(&self, i: usize) -> &[u8] {
+        let (_, size) = self.layout.args[i];
+        unsafe { slice::from_raw_parts(self.arg_ptr(i) as *const u8, size) }
+    }
+
+    /// Reads the `i`th argument as a `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no `i`th argument, or if it is smaller than a
+    /// `T`.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type of the argument.
+    pub unsafe fn arg<T: Copy>(&self, i: usize) -> T {
+        assert!(
+            mem::size_of::<T>() <= self.arg_bytes(i).len(),
+            "Invocation::arg: argument {} is smaller than the requested type",
+            i
+        );
+        ptr::read(self.arg_ptr(i) as *const T)
+    }
+
+    /// Gets pointers to all of the arguments, in the form closure
+    /// callbacks receive them.
+    pub fn arg_ptrs(&self) -> Vec<*const c_void> {
+        (0..self.len()).map(|i| self.arg_ptr(i)).collect()
+    }
+}
+
+impl fmt::Debug for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries((0..self.len()).map(|i| self.arg_bytes(i)))
+            .finish()
+    }
+}
+
+/// A token for cancelling the calls of queued and proxied closures.
+///
+/// Once a closure created with a token is cancelled:
+///
+///  - new calls return the value given by the closure’s
+///    [`ErrorPolicy`] (or abort, for [`ErrorPolicy::Abort`]);
+///  - calls that are queued or waiting for a reply are dropped, and the
+///    receiving end reports no more calls;
+///  - callers blocked on a [`ProxyClosure`] return immediately, as for
+///    new calls.
+///
+/// Whenever a call returns because of cancellation, [`Cancelled`] is
+/// stored as the [callback error](bridge::take_callback_error) of the
+/// calling thread.
+///
+/// Clones of a token share the same state, so any of them can cancel.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>,
+}
+
+#[derive(Default)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    wakers: Mutex<Wakers>,
+}
+
+#[derive(Default)]
+struct Wakers {
+    next_id: u64,
+    wakers: HashMap<u64, Box<dyn FnOnce() + Send>>,
+}
+
+impl CancellationToken {
+    /// Creates a token that has not been cancelled.
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Cancels the token.
+    pub fn cancel(&self) {
+        // The flag is set before taking the lock, so any waker
+        // registered after the wakers are taken sees it.
+        self.inner.cancelled.store(true, Ordering::Release);
+        let wakers = mem::take(&mut self.lock_wakers().wakers);
+        for (_, wake) in wakers {
+            wake();
+        }
+    }
+
+    /// Returns whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    fn lock_wakers(&self) -> MutexGuard<'_, Wakers> {
+        self.inner.wakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Arranges for `wake` to be called on cancellation, until the
+    // returned registration is dropped. Returns `None` without calling
+    // `wake` if the token is already cancelled.
+    fn on_cancel<F: FnOnce() + Send + 'static>(&self, wake: F) -> Option<Registration> {
+        let mut wakers = self.lock_wakers();
+        if self.is_cancelled() {
+            return None;
+        }
+        let id = wakers.next_id;
+        wakers.next_id += 1;
+        wakers.wakers.insert(id, Box::new(wake));
+        Some(Registration {
+            token: self.clone(),
+            id,
+        })
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+struct Registration {
+    token: CancellationToken,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.token.lock_wakers().wakers.remove(&self.id);
+    }
+}
+
+/// The error reported for calls that return because their closure’s
+/// [`CancellationToken`] was cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the call was cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
+
+// What a closure does once cancelled.
+struct Cancellation {
+    token: CancellationToken,
+    policy: ErrorPolicy<bridge::Erased>,
+    // Wakes the receiving end on cancellation.
+    _registration: Option<Registration>,
+}
+
+impl Cancellation {
+    fn new<R: Copy, T: Send + 'static>(
+        cif: &Cif,
+        token: &CancellationToken,
+        policy: ErrorPolicy<R>,
+        sender: &Sender<Message<T>>,
+    ) -> Self {
+        let policy = policy.erase();
+        policy.check(cif, "dispatch: the cancellation");
+        let sender = sender.clone();
+        Cancellation {
+            token: token.clone(),
+            policy,
+            _registration: token.on_cancel(move || {
+                let _ = sender.send(Message::Cancelled);
+            }),
+        }
+    }
+
+    // Returns the cancelled call’s result according to the policy.
+    unsafe fn cancel_call(&self, result: &mut c_void) {
+        self.policy.fail(result, Cancelled)
+    }
+}
+
+fn is_cancelled(cancellation: &Option<Cancellation>) -> bool {
+    cancellation
+        .as_ref()
+        .is_some_and(|cancellation| cancellation.token.is_cancelled())
+}
+
+// What the receiving ends of closures receive.
+enum Message<T> {
+    Call(T),
+    Cancelled,
+}
+
+enum Wait {
+    Block,
+    Poll,
+    Timeout(Duration),
+}
+
+fn receive<T>(
+    receiver: &Receiver<Message<T>>,
+    token: Option<&CancellationToken>,
+    wait: Wait,
+) -> Option<T> {
+    let cancelled = || token.is_some_and(CancellationToken::is_cancelled);
+    let message = if cancelled() {
+        None
+    } else {
+        match wait {
+            Wait::Block => receiver.recv().ok(),
+            Wait::Poll => receiver.try_recv().ok(),
+            Wait::Timeout(timeout) => receiver.recv_timeout(timeout).ok(),
+        }
+    };
+
+    if cancelled() {
+        // Calls still pending are dropped.
+        while receiver.try_recv().is_ok() {}
+        return None;
+    }
+
+    match message {
+        Some(Message::Call(call)) => Some(call),
+        Some(Message::Cancelled) | None => None,
+    }
+}
+
+/// The receiving end of a [`QueuedClosure`].
+///
+/// Once the closure is dropped and every queued invocation has been
+/// received, or once the closure is cancelled, receiving returns
+/// `None`.
+pub struct InvocationQueue {
+    receiver: Receiver<Message<Invocation>>,
+    token: Option<CancellationToken>,
+}
+
+impl InvocationQueue {
+    /// Waits for the next invocation.
+    pub fn recv(&self) -> Option<Invocation> {
+        receive(&self.receiver, self.token.as_ref(), Wait::Block)
+    }
+
+    /// Gets the next invocation, if one is waiting.
+    pub fn try_recv(&self) -> Option<Invocation> {
+        receive(&self.receiver, self.token.as_ref(), Wait::Poll)
+    }
+
+    /// Waits up to `timeout` for the next invocation.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Invocation> {
+        receive(&self.receiver, self.token.as_ref(), Wait::Timeout(timeout))
+    }
+
+    /// Spawns a thread that passes each invocation to `handler`, until
+    /// the closure is dropped or cancelled.
+    pub fn spawn<F>(self, mut handler: F) -> JoinHandle<()>
+    where
+        F: FnMut(Invocation) + Send + 'static,
+    {
+        thread::spawn(move || {
+            for invocation in self {
+                handler(invocation);
+            }
+        })
+    }
+}
+
+impl fmt::Debug for InvocationQueue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InvocationQueue")
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+impl IntoIterator for InvocationQueue {
+    type Item = Invocation;
+    type IntoIter = Invocations;
+
+    fn into_iter(self) -> Invocations {
+        Invocations { queue: self }
+    }
+}
+
+/// An iterator that waits for the invocations of a [`QueuedClosure`].
+#[derive(Debug)]
+pub struct Invocations {
+    queue: InvocationQueue,
+}
+
+impl Iterator for Invocations {
+    type Item = Invocation;
+
+    fn next(&mut self) -> Option<Invocation> {
+        self.queue.recv()
+    }
+}
+
+struct QueueState {
+    layout: Arc<ArgLayout>,
+    sender: Mutex<Sender<Message<Invocation>>>,
+    result: bridge::Erased,
+    cancellation: Option<Cancellation>,
+}
+
+/// A closure that queues its invocations and returns immediately.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+/// use libffi::middle::dispatch::QueuedClosure;
+///
+/// let cif = Cif::new(vec![Type::i32(), Type::f64()], Type::i32());
+/// let (closure, queue) = QueuedClosure::new(cif, 0i32);
+/// let handler = queue.spawn(|invocation| {
+///     let (n, x): (i32, f64) = unsafe { (invocation.arg(0), invocation.arg(1)) };
+///     println!("{} {}", n, x);
+/// });
+///
+/// let fun: &extern "C" fn(i32, f64) -> i32 = unsafe { closure.instantiate_code_ptr() };
+/// assert_eq!(0, fun(5, 1.5));
+///
+/// drop(closure);
+/// handler.join().unwrap();
+/// ```
+pub struct QueuedClosure {
+    // Dropped before `state`, which it refers to.
+    closure: Closure<'static>,
+    state: Box<QueueState>,
+}
+
+// The callback only uses the state through a mutex, and the closure is
+// freed only when dropped.
+unsafe impl Send for QueuedClosure {}
+unsafe impl Sync for QueuedClosure {}
+
+impl fmt::Debug for QueuedClosure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QueuedClosure")
+            .field("closure", &self.closure)
+            .field("args", &self.state.layout.args.len())
+            .finish()
+    }
+}
+
+impl QueuedClosure {
+    /// Creates a closure that queues its invocations, returning
+    /// `result` to C for every call.
+    ///
+    /// As for other closures, the type `R` of `result` must follow
+    /// libffi’s rules for widening small integer results to
+    /// [`ffi_arg`](low::ffi_arg). For a `void` function, use `()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is larger than the CIF’s result type.
+    pub fn new<R: Copy>(cif: Cif, result: R) -> (Self, InvocationQueue) {
+        Self::build(cif, result, None)
+    }
+
+    /// Creates a closure that queues its invocations until `token` is
+    /// cancelled.
+    ///
+    /// Until then, the closure behaves as for [`new`](Self::new). Once
+    /// `token` is cancelled, invocations still in the queue are dropped
+    /// and calls are handled according to `policy`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is larger than the CIF’s result type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::*;
+    /// use libffi::middle::bridge::{take_callback_error, ErrorPolicy};
+    /// use libffi::middle::dispatch::{CancellationToken, QueuedClosure};
+    ///
+    /// let token = CancellationToken::new();
+    /// let cif = Cif::new(vec![Type::i32()], Type::i32());
+    /// let (closure, queue) =
+    ///     QueuedClosure::new_cancellable(cif, 0i32, &token, ErrorPolicy::ReturnCode(-1i32));
+    /// let fun: &extern "C" fn(i32) -> i32 = unsafe { closure.instantiate_code_ptr() };
+    ///
+    /// assert_eq!(0, fun(1));
+    /// token.cancel();
+    /// assert_eq!(-1, fun(2));
+    /// assert!(take_callback_error().is_some());
+    ///
+    /// // The first call was dropped from the queue.
+    /// assert!(queue.recv().is_none());
+    /// ```
+    pub fn new_cancellable<R: Copy>(
+        cif: Cif,
+        result: R,
+        token: &CancellationToken,
+        policy: ErrorPolicy<R>,
+    ) -> (Self, InvocationQueue) {
+        Self::build(cif, result, Some((token, policy)))
+    }
+
+    fn build<R: Copy>(
+        cif: Cif,
+        result: R,
+        cancel: Option<(&CancellationToken, ErrorPolicy<R>)>,
+    ) -> (Self, InvocationQueue) {
+        bridge::check_result_size(&cif, mem::size_of::<R>(), "QueuedClosure::new: the result");
+        let (sender, receiver) = mpsc::channel();
+        let cancellation =
+            cancel.map(|(token, policy)| Cancellation::new(&cif, token, policy, &sender));
+        let token = cancel.map(|(token, _)| token.clone());
+
+        let state = Box::new(QueueState {
+            layout: Arc::new(ArgLayout::new(&cif)),
+            sender: Mutex::new(sender),
+            result: bridge::erase(&result),
+            cancellation,
+        });
+        // The closure is dropped before the boxed state, so the state
+        // outlives every call.
+        let userdata = unsafe { &*(&*state as *const QueueState) };
+        let closure = Closure::new(cif, queue_callback, userdata);
+
+        (
+            QueuedClosure { closure, state },
+            InvocationQueue { receiver, token },
+        )
+    }
+
+    /// Obtains the callable code pointer for the closure.
+    pub fn code_ptr(&self) -> &unsafe extern "C" fn() {
+        self.closure.code_ptr()
+    }
+
+    /// Transmutes the callable code pointer for the closure to a
+    /// reference to any type.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Closure::instantiate_code_ptr`].
+    pub unsafe fn instantiate_code_ptr<T>(&self) -> &T {
+        self.closure.instantiate_code_ptr()
+    }
+
+    /// Gets the closure’s code pointer as a [`CodePtr`].
+    pub fn as_code_ptr(&self) -> CodePtr {
+        CodePtr(*self.code_ptr() as *mut c_void)
+    }
+}
+
+unsafe extern "C" fn queue_callback(
+    _cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    state: &QueueState,
+) {
+    crate::__private::v1::abort_on_panic("Cannot panic inside FFI callback", || {
+        if let (true, Some(cancellation)) = (is_cancelled(&state.cancellation), &state.cancellation)
+        {
+            return cancellation.cancel_call(result);
+        }
+
+        let invocation = state.layout.copy(args);
+        // If the queue has been dropped then nobody wants the call.
+        let _ = state
+            .sender
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(Message::Call(invocation));
+        bridge::write_erased(result, &state.result);
+    })
+}
+
+/// A call to a [`ProxyClosure`], waiting for a reply from the target
+/// thread.
+///
+/// The caller stays blocked until the call is answered with
+/// [`reply`](ProxyCall::reply) or dropped, so pointer arguments remain
+/// valid until then. Dropping the call without replying returns the
+/// closure’s fallback result to the caller.
+pub struct ProxyCall {
+    invocation: Invocation,
+    // Taken by `reply`; otherwise dropped with the call, after sending
+    // `None` so that the caller stops waiting.
+    reply: Option<SyncSender<Option<bridge::Erased>>>,
+    result_size: usize,
+    token: Option<CancellationToken>,
+}
+
+impl ProxyCall {
+    /// The arguments of the call.
+    pub fn invocation(&self) -> &Invocation {
+        &self.invocation
+    }
+
+    /// Returns whether the closure has been cancelled, in which case
+    /// the caller is no longer waiting for a reply.
+    pub fn is_cancelled(&self) -> bool {
+        self.token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Returns `result` to the blocked caller.
+    ///
+    /// As for other closures, the type `R` must follow libffi’s rules
+    /// for widening small integer results to
+    /// [`ffi_arg`](low::ffi_arg).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is larger than the CIF’s result type.
+    pub fn reply<R: Copy>(mut self, result: R) {
+        assert!(
+            mem::size_of::<R>() <= self.result_size,
+            "ProxyCall::reply: result is larger than the CIF’s result type"
+        );
+        self.send(Some(bridge::erase(&result)));
+    }
+
+    fn send(&mut self, result: Option<bridge::Erased>) {
+        if let Some(reply) = self.reply.take() {
+            // If the caller is gone or cancelled then there is nobody
+            // to reply to.
+            let _ = reply.try_send(result);
+        }
+    }
+}
+
+impl Drop for ProxyCall {
+    fn drop(&mut self) {
+        // The token’s waker may hold another sender, so the caller
+        // cannot rely on the channel disconnecting.
+        self.send(None);
+    }
+}
+
+impl fmt::Debug for ProxyCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProxyCall")
+            .field("invocation", &self.invocation)
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// The receiving end of a [`ProxyClosure`], to be used on the thread
+/// that should run the closure’s body.
+///
+/// Once the closure is dropped and every pending call has been
+/// received, or once the closure is cancelled, receiving returns
+/// `None`.
+pub struct ProxyReceiver {
+    receiver: Receiver<Message<ProxyCall>>,
+    token: Option<CancellationToken>,
+}
+
+impl ProxyReceiver {
+    /// Waits for the next call.
+    pub fn recv(&self) -> Option<ProxyCall> {
+        receive(&self.receiver, self.token.as_ref(), Wait::Block)
+    }
+
+    /// Gets the next call, if one is waiting.
+    pub fn try_recv(&self) -> Option<ProxyCall> {
+        receive(&self.receiver, self.token.as_ref(), Wait::Poll)
+    }
+
+    /// Waits up to `timeout` for the next call.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ProxyCall> {
+        receive(&self.receiver, self.token.as_ref(), Wait::Timeout(timeout))
+    }
+
+    /// Answers every call that is currently waiting by passing its
+    /// arguments to `handler` and replying with the result.
+    ///
+    /// This suits event loops, which can call it on each iteration.
+    /// Returns the number of calls handled.
+    pub fn handle_pending<R, F>(&self, mut handler: F) -> usize
+    where
+        R: Copy,
+        F: FnMut(&Invocation) -> R,
+    {
+        let mut handled = 0;
+        while let Some(call) = self.try_recv() {
+            let result = handler(call.invocation());
+            call.reply(result);
+            handled += 1;
+        }
+        handled
+    }
+}
+
+impl fmt::Debug for ProxyReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProxyReceiver")
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+impl IntoIterator for ProxyReceiver {
+    type Item = ProxyCall;
+    type IntoIter = ProxyCalls;
+
+    fn into_iter(self) -> ProxyCalls {
+        ProxyCalls { receiver: self }
+    }
+}
+
+/// An iterator that waits for the calls of a [`ProxyClosure`].
+#[derive(Debug)]
+pub struct ProxyCalls {
+    receiver: ProxyReceiver,
+}
+
+impl Iterator for ProxyCalls {
+    type Item = ProxyCall;
+
+    fn next(&mut self) -> Option<ProxyCall> {
+        self.receiver.recv()
+    }
+}
+
+struct ProxyState {
+    layout: Arc<ArgLayout>,
+    sender: Mutex<Sender<Message<ProxyCall>>>,
+    result_size: usize,
+    fallback: bridge::Erased,
+    cancellation: Option<Cancellation>,
+}
+
+/// A closure whose body runs on another thread while the caller waits.
+///
+/// Calling the closure from a thread that is itself responsible for
+/// answering its calls deadlocks, since the call can never be
+/// received.
+///
+/// # Examples
+///
+/// ```
+/// use std::thread;
+///
+/// use libffi::middle::*;
+/// use libffi::middle::dispatch::ProxyClosure;
+///
+/// let cif = Cif::new(vec![Type::i32()], Type::i32());
+/// let (closure, receiver) = ProxyClosure::new(cif, -1i32);
+///
+/// let ui_thread = thread::spawn(move || {
+///     for call in receiver {
+///         let n: i32 = unsafe { call.invocation().arg(0) };
+///         call.reply(n * 2);
+///     }
+/// });
+///
+/// let fun: &extern "C" fn(i32) -> i32 = unsafe { closure.instantiate_code_ptr() };
+/// assert_eq!(10, fun(5));
+///
+/// drop(closure);
+/// ui_thread.join().unwrap();
+/// ```
+pub struct ProxyClosure {
+    // Dropped before `state`, which it refers to.
+    closure: Closure<'static>,
+    state: Box<ProxyState>,
+}
+
+// The callback only uses the state through a mutex, and the closure is
+// freed only when dropped.
+unsafe impl Send for ProxyClosure {}
+unsafe impl Sync for ProxyClosure {}
+
+impl fmt::Debug for ProxyClosure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProxyClosure")
+            .field("closure", &self.closure)
+            .field("args", &self.state.layout.args.len())
+            .finish()
+    }
+}
+
+impl ProxyClosure {
+    /// Creates a closure that forwards its calls to the returned
+    /// [`ProxyReceiver`].
+    ///
+    /// `fallback` is returned to C if a call is dropped without a
+    /// reply, or if the receiver has been dropped. As for other
+    /// closures, its type `R` must follow libffi’s rules for widening
+    /// small integer results to [`ffi_arg`](low::ffi_arg). For a `void`
+    /// function, use `()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is larger than the CIF’s result type.
+    pub fn new<R: Copy>(cif: Cif, fallback: R) -> (Self, ProxyReceiver) {
+        Self::build(cif, fallback, None)
+    }
+
+    /// Creates a closure that forwards its calls to the returned
+    /// [`ProxyReceiver`] until `token` is cancelled.
+    ///
+    /// Until then, the closure behaves as for [`new`](Self::new). Once
+    /// `token` is cancelled, callers waiting for a reply stop waiting,
+    /// and they and any later calls are handled according to `policy`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is larger than the CIF’s result type.
+    pub fn new_cancellable<R: Copy>(
+        cif: Cif,
+        fallback: R,
+        token: &CancellationToken,
+        policy: ErrorPolicy<R>,
+    ) -> (Self, ProxyReceiver) {
+        Self::build(cif, fallback, Some((token, policy)))
+    }
+
+    fn build<R: Copy>(
+        cif: Cif,
+        fallback: R,
+        cancel: Option<(&CancellationToken, ErrorPolicy<R>)>,
+    ) -> (Self, ProxyReceiver) {
+        bridge::check_result_size(&cif, mem::size_of::<R>(), "ProxyClosure::new: the fallback");
+        let (sender, receiver) = mpsc::channel();
+        let cancellation =
+            cancel.map(|(token, policy)| Cancellation::new(&cif, token, policy, &sender));
+        let token = cancel.map(|(token, _)| token.clone());
+
+        let result_size = bridge::result_capacity(&cif);
+
+        let state = Box::new(ProxyState {
+            layout: Arc::new(ArgLayout::new(&cif)),
+            sender: Mutex::new(sender),
+            result_size,
+            fallback: bridge::erase(&fallback),
+            cancellation,
+        });
+        // The closure is dropped before the boxed state, so the state
+        // outlives every call.
+        let userdata = unsafe { &*(&*state as *const ProxyState) };
+        let closure = Closure::new(cif, proxy_callback, userdata);
+
+        (
+            ProxyClosure { closure, state },
+            ProxyReceiver { receiver, token },
+        )
+    }
+
+    /// Obtains the callable code pointer for the closure.
+    pub fn code_ptr(&self) -> &unsafe extern "C" fn() {
+        self.closure.code_ptr()
+    }
+
+    /// Transmutes the callable code pointer for the closure to a
+    /// reference to any type.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Closure::instantiate_code_ptr`].
+    pub unsafe fn instantiate_code_ptr<T>(&self) -> &T {
+        self.closure.instantiate_code_ptr()
+    }
+
+    /// Gets the closure’s code pointer as a [`CodePtr`].
+    pub fn as_code_ptr(&self) -> CodePtr {
+        CodePtr(*self.code_ptr() as *mut c_void)
+    }
+}
+
+unsafe extern "C" fn proxy_callback(
+    _cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    state: &ProxyState,
+) {
+    crate::__private::v1::abort_on_panic("Cannot panic inside FFI callback", || {
+        let (reply, response) = mpsc::sync_channel(1);
+
+        // Wake up if cancelled while waiting for the reply.
+        let mut _registration = None;
+        if let Some(cancellation) = &state.cancellation {
+            let waker = reply.clone();
+            _registration = cancellation.token.on_cancel(move || {
+                let _ = waker.try_send(None);
+            });
+            if _registration.is_none() {
+                return cancellation.cancel_call(result);
+            }
+        }
+
+        let call = ProxyCall {
+            invocation: state.layout.copy(args),
+            reply: Some(reply),
+            result_size: state.result_size,
+            token: state.cancellation.as_ref().map(|c| c.token.clone()),
+        };
+        let sent = state
+            .sender
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(Message::Call(call))
+            .is_ok();
+
+        let response = if sent {
+            response.recv().ok().flatten()
+        } else {
+            None
+        };
+        match (response, &state.cancellation) {
+            (Some(bytes), _) => bridge::write_erased(result, &bytes),
+            (_, Some(cancellation)) if cancellation.token.is_cancelled() => {
+                cancellation.cancel_call(result)
+            }
+            _ => bridge::write_erased(result, &state.fallback),
+        }
+    })
+}
+
+impl Builder {
+    /// Builds a closure that queues its invocations, returning `result`
+    /// to C for every call.
+    ///
+    /// See [`QueuedClosure::new`].
+    pub fn into_queued_closure<R: Copy>(self, result: R) -> (QueuedClosure, InvocationQueue) {
+        QueuedClosure::new(self.into_cif(), result)
+    }
+
+    /// Builds a closure whose calls are answered by the returned
+    /// [`ProxyReceiver`], usually on another thread.
+    ///
+    /// See [`ProxyClosure::new`].
+    pub fn into_proxy_closure<R: Copy>(self, fallback: R) -> (ProxyClosure, ProxyReceiver) {
+        ProxyClosure::new(self.into_cif(), fallback)
+    }
+
+    /// Builds a queued closure that stops handling calls once `token`
+    /// is cancelled.
+    ///
+    /// See [`QueuedClosure::new_cancellable`].
+    pub fn into_queued_closure_cancellable<R: Copy>(
+        self,
+        result: R,
+        token: &CancellationToken,
+        policy: ErrorPolicy<R>,
+    ) -> (QueuedClosure, InvocationQueue) {
+        QueuedClosure::new_cancellable(self.into_cif(), result, token, policy)
+    }
+
+    /// Builds a proxy closure that stops handling calls once `token` is
+    /// cancelled.
+    ///
+    /// See [`ProxyClosure::new_cancellable`].
+    pub fn into_proxy_closure_cancellable<R: Copy>(
+        self,
+        fallback: R,
+        token: &CancellationToken,
+        policy: ErrorPolicy<R>,
+    ) -> (ProxyClosure, ProxyReceiver) {
+        ProxyClosure::new_cancellable(self.into_cif(), fallback, token, policy)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::bridge::CallbackError;
+    use crate::middle::Type;
+
+    #[test]
+    fn queued_calls_are_handled_elsewhere() {
+        let (closure, queue) = Builder::new()
+            .args(vec![Type::u8(), Type::f64(), Type::u16()])
+            .res(Type::i64())
+            .into_queued_closure(-1i64);
+
+        let (results, collected) = mpsc::channel();
+        let handler = queue.spawn(move |invocation| {
+            assert_eq!(3, invocation.len());
+            let sum = unsafe {
+                invocation.arg::<u8>(0) as f64
+                    + invocation.arg::<f64>(1)
+                    + invocation.arg::<u16>(2) as f64
+            };
+            results.send((thread::current().id(), sum)).unwrap();
+        });
+
+        let fun: &extern "C" fn(u8, f64, u16) -> i64 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(-1, fun(1, 0.5, 300));
+        assert_eq!(-1, fun(2, 0.25, 400));
+
+        drop(closure);
+        handler.join().unwrap();
+
+        let sums: Vec<_> = collected.iter().collect();
+        assert_eq!(2, sums.len());
+        assert!(sums.iter().all(|&(id, _)| id != thread::current().id()));
+        assert_eq!(301.5, sums[0].1);
+        assert_eq!(402.25, sums[1].1);
+    }
+
+    #[test]
+    fn proxied_calls_run_on_the_target_thread() {
+        let (closure, receiver) = Builder::new()
+            .args(vec![Type::u8(), Type::f32()])
+            .res(Type::u8())
+            .into_proxy_closure(0u8);
+        let fun: &extern "C" fn(u8, f32) -> u8 = unsafe { closure.instantiate_code_ptr() };
+
+        let target = thread::spawn(move || {
+            let mut callers = vec![];
+            for call in receiver {
+                let (n, x): (u8, f32) =
+                    unsafe { (call.invocation().arg(0), call.invocation().arg(1)) };
+                callers.push(thread::current().id());
+                // A `u8` result is widened to `ffi_arg`.
+                call.reply((n as f32 * x) as low::ffi_arg);
+            }
+            callers
+        });
+
+        assert_eq!(15, fun(10, 1.5));
+        assert_eq!(200, fun(100, 2.0));
+
+        drop(closure);
+        let callers = target.join().unwrap();
+        assert_eq!(2, callers.len());
+        assert!(callers.iter().all(|&id| id != thread::current().id()));
+    }
+
+    #[test]
+    fn unanswered_calls_return_the_fallback() {
+        let (closure, receiver) = Builder::new()
+            .arg(Type::i64())
+            .res(Type::i64())
+            .into_proxy_closure(-1i64);
+        let fun: &extern "C" fn(i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+
+        let target = thread::spawn(move || {
+            let answered = receiver.recv().unwrap();
+            answered.reply(7i64);
+            // Dropped without a reply.
+            receiver.recv().unwrap();
+        });
+
+        assert_eq!(7, fun(0));
+        assert_eq!(-1, fun(0));
+        target.join().unwrap();
+
+        // The receiver is gone.
+        assert_eq!(-1, fun(0));
+    }
+
+    #[test]
+    fn event_loop_handles_pending_calls() {
+        let (closure, receiver) = ProxyClosure::new(Cif::new(vec![Type::u32()], Type::u32()), 0u32);
+        let closure = Arc::new(closure);
+
+        let caller = {
+            let closure = closure.clone();
+            thread::spawn(move || {
+                let fun: &extern "C" fn(u32) -> u32 = unsafe { closure.instantiate_code_ptr() };
+                (1..=3).map(|n| fun(n)).sum::<u32>()
+            })
+        };
+
+        let mut handled = 0;
+        while handled < 3 {
+            handled +=
+                receiver.handle_pending(|invocation| unsafe { invocation.arg::<u32>(0) } * 10);
+            thread::yield_now();
+        }
+
+        assert_eq!(60, caller.join().unwrap());
+    }
+
+    #[test]
+    fn struct_arguments_are_copied() {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[repr(C)]
+        struct Pair {
+            a: u8,
+            b: u64,
+        }
+
+        let pair = Type::structure(vec![Type::u8(), Type::u64()]);
+        let cif = Cif::new(vec![Type::u32(), pair], Type::void());
+        let (closure, queue) = QueuedClosure::new(cif, ());
+
+        let fun: &extern "C" fn(u32, Pair) = unsafe { closure.instantiate_code_ptr() };
+        fun(7, Pair { a: 3, b: 1 << 40 });
+
+        let invocation = queue.try_recv().unwrap();
+        assert_eq!(7, unsafe { invocation.arg::<u32>(0) });
+        assert_eq!(Pair { a: 3, b: 1 << 40 }, unsafe {
+            invocation.arg::<Pair>(1)
+        });
+        assert_eq!(16, invocation.arg_bytes(1).len());
+        assert!(queue.try_recv().is_none());
+    }
+
+    #[test]
+    fn cancelling_drops_queued_invocations() {
+        let token = CancellationToken::new();
+        let (closure, queue) = Builder::new()
+            .arg(Type::i32())
+            .res(Type::i32())
+            .into_queued_closure_cancellable(0i32, &token, ErrorPolicy::ReturnCode(-1i32));
+        let fun: &extern "C" fn(i32) -> i32 = unsafe { closure.instantiate_code_ptr() };
+
+        assert_eq!(0, fun(1));
+        assert_eq!(0, fun(2));
+        assert_eq!(1, unsafe { queue.recv().unwrap().arg::<i32>(0) });
+
+        token.cancel();
+        assert_eq!(-1, fun(3));
+        assert!(bridge::take_callback_error().is_some());
+        assert!(queue.try_recv().is_none());
+        assert!(queue.recv().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "larger than the CIF’s result type")]
+    fn oversized_results_are_rejected() {
+        let cif = Cif::new(vec![], Type::u32());
+        let _ = QueuedClosure::new(cif, [0u64; 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "larger than the CIF’s result type")]
+    fn oversized_fallbacks_are_rejected() {
+        let cif = Cif::new(vec![], Type::structure(vec![Type::u8(); 3]));
+        let _ = ProxyClosure::new(cif, [0u64; 2]);
+    }
+
+    #[test]
+    fn cancelling_wakes_waiting_callers() {
+        let token = CancellationToken::new();
+        let (closure, receiver) = Builder::new()
+            .arg(Type::i64())
+            .res(Type::i64())
+            .into_proxy_closure_cancellable(0i64, &token, ErrorPolicy::ReturnCode(-2i64));
+        let closure = Arc::new(closure);
+
+        let caller = {
+            let closure = closure.clone();
+            thread::spawn(move || {
+                let fun: &extern "C" fn(i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+                let result = fun(5);
+                let cancelled = match bridge::take_callback_error() {
+                    Some(CallbackError::Error(error)) => error.is::<Cancelled>(),
+                    _ => false,
+                };
+                (result, cancelled)
+            })
+        };
+
+        // Hold the call without replying, then cancel.
+        let call = receiver.recv().unwrap();
+        assert!(!call.is_cancelled());
+        token.cancel();
+        assert!(call.is_cancelled());
+
+        let (result, cancelled) = caller.join().unwrap();
+        assert_eq!(-2, result);
+        assert!(cancelled);
+
+        // Replying after cancellation is harmless.
+        call.reply(9i64);
+        assert!(receiver.recv().is_none());
+
+        let fun: &extern "C" fn(i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(-2, fun(6));
+    }
+
+    #[test]
+    fn dropping_cancellable_calls_returns_the_fallback() {
+        let token = CancellationToken::new();
+        let (closure, receiver) = Builder::new()
+            .arg(Type::i64())
+            .res(Type::i64())
+            .into_proxy_closure_cancellable(-1i64, &token, ErrorPolicy::ReturnCode(-2i64));
+        let fun: &extern "C" fn(i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+
+        let target = thread::spawn(move || {
+            // Dropped without a reply.
+            receiver.recv().unwrap();
+        });
+
+        assert_eq!(-1, fun(0));
+        target.join().unwrap();
+        assert!(!token.is_cancelled());
+    }
+}
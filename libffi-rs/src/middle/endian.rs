@@ -0,0 +1,136 @@
+use alloc::boxed::Box;
+
+use super::buffer::field_layout;
+use super::{BytesError, Type, TypedBuffer};
+use crate::{low, raw};
+
+/// The order of the bytes of multi-byte scalars.
+///
+/// Typed buffers are stored in the native byte order. To exchange them
+/// with a process on an architecture of the other byte order, such as
+/// a sandboxed executor running under emulation, convert them with
+/// [`TypedBuffer::to_bytes_in`] and [`TypedBuffer::from_bytes_in`],
+/// which swap each scalar according to the buffer’s [`Type`].
+///
+/// Only the byte order is converted: both sides must agree on the
+/// sizes and layout of the types, so types such as pointers and
+/// `long double`, whose sizes differ between architectures, should be
+/// avoided.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ByteOrder {
+    /// Least significant byte first.
+    Little,
+    /// Most significant byte first.
+    Big,
+}
+
+impl ByteOrder {
+    /// The byte order of the target architecture.
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: ByteOrder = ByteOrder::Little;
+
+    /// The byte order of the target architecture.
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: ByteOrder = ByteOrder::Big;
+}
+
+impl TypedBuffer {
+    /// Copies the bytes of the value, converting each scalar to `order`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{ByteOrder, Type, TypedBuffer};
+    ///
+    /// let pair = Type::structure_named(vec![("a", Type::u16()), ("b", Type::u32())]);
+    /// let mut buffer = TypedBuffer::new(pair.clone());
+    /// buffer.set_field("a", 0x0102u16).unwrap();
+    /// buffer.set_field("b", 0x03040506u32).unwrap();
+    ///
+    /// let bytes = buffer.to_bytes_in(ByteOrder::Big);
+    /// assert_eq!(&[1, 2, 0, 0, 3, 4, 5, 6][..], &*bytes);
+    ///
+    /// let copy = TypedBuffer::from_bytes_in(pair, &bytes, ByteOrder::Big).unwrap();
+    /// assert_eq!(0x03040506u32, copy.get_field("b").unwrap());
+    /// ```
+    pub fn to_bytes_in(&self, order: ByteOrder) -> Box<[u8]> {
+        let mut bytes: Box<[u8]> = self.as_bytes().into();
+        if order != ByteOrder::NATIVE {
+            unsafe { swap_scalars(&*self.ty().as_raw_ptr(), &mut bytes) };
+        }
+        bytes
+    }
+
+    /// Creates a value of type `ty` from bytes whose scalars are in
+    /// `order`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the length of `bytes` differs from the size of `ty`.
+    pub fn from_bytes_in(ty: Type, bytes: &[u8], order: ByteOrder) -> Result<Self, BytesError> {
+        let mut buffer = TypedBuffer::from_bytes(ty, bytes)?;
+        if order != ByteOrder::NATIVE {
+            let raw = buffer.ty().as_raw_ptr();
+            unsafe { swap_scalars(&*raw, buffer.as_bytes_mut()) };
+        }
+        Ok(buffer)
+    }
+}
+
+// Reverses the bytes of each scalar in a value of type `ty`, leaving
+// padding in place.
+unsafe fn swap_scalars(ty: &low::ffi_type, bytes: &mut [u8]) {
+    match u32::from(ty.type_) {
+        raw::FFI_TYPE_STRUCT => {
+            for (i, (offset, size)) in field_layout(ty).into_iter().enumerate() {
+                swap_scalars(&**ty.elements.add(i), &mut bytes[offset..offset + size]);
+            }
+        }
+        raw::FFI_TYPE_COMPLEX if !ty.elements.is_null() => {
+            // The real and imaginary parts are swapped separately.
+            let part = &**ty.elements;
+            for chunk in bytes.chunks_mut(part.size) {
+                swap_scalars(part, chunk);
+            }
+        }
+        _ => bytes.reverse(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_scalars_are_swapped() {
+        let inner = Type::structure(vec![Type::u8(), Type::u64()]);
+        let outer = Type::structure(vec![Type::u16(), inner, Type::f32()]);
+
+        let mut buffer = TypedBuffer::new(outer.clone());
+        buffer
+            .field_bytes_mut(0)
+            .unwrap()
+            .copy_from_slice(&0x0102u16.to_ne_bytes());
+        buffer.field_bytes_mut(1).unwrap()[8..].copy_from_slice(&7u64.to_ne_bytes());
+        buffer
+            .field_bytes_mut(2)
+            .unwrap()
+            .copy_from_slice(&1.5f32.to_ne_bytes());
+
+        let other = match ByteOrder::NATIVE {
+            ByteOrder::Little => ByteOrder::Big,
+            ByteOrder::Big => ByteOrder::Little,
+        };
+        let swapped = buffer.to_bytes_in(other);
+        assert_eq!(&0x0201u16.to_ne_bytes()[..], &swapped[..2]);
+        assert_eq!(&7u64.swap_bytes().to_ne_bytes()[..], &swapped[16..24]);
+        assert_eq!(
+            &1.5f32.to_bits().swap_bytes().to_ne_bytes()[..],
+            &swapped[24..28]
+        );
+
+        assert_eq!(buffer.as_bytes(), &*buffer.to_bytes_in(ByteOrder::NATIVE));
+        let back = TypedBuffer::from_bytes_in(outer, &swapped, other).unwrap();
+        assert_eq!(buffer.as_bytes(), back.as_bytes());
+    }
+}
@@ -0,0 +1,608 @@
+//! Wrapping functions with hooks that run before and after each call.
+//!
+//! [`intercept`] takes a function and its CIF, and creates a closure of
+//! the same signature, an [`Interceptor`], that calls a hook, then the
+//! original function with the same arguments, then another hook with
+//! the arguments and the result, which it returns to its caller. Code
+//! that calls the interceptor’s code pointer instead of the function’s,
+//! for instance through a patched function table, is then traced or
+//! instrumented without either side knowing.
+//!
+//! Both hooks are given an [`InterceptedCall`], through which they can
+//! read the arguments, and the post-call hook the result. The pre-call
+//! hooks can also replace arguments through
+//! [`InterceptedCall::args_mut`], and the function is then called with
+//! the replaced values.
+//!
+//! An interceptor may run several pairs of hooks, added with
+//! [`Interceptor::add_hook`]. The pre-call hooks run in order of
+//! priority, lowest first, and hooks of equal priority in the order
+//! they were added; the post-call hooks run in the opposite order, so
+//! each pair wraps the pairs after it. Hooks can be disabled and
+//! enabled again, and removed, while the interceptor is being called on
+//! other threads: a call runs the hooks that were present when it
+//! started, and a removed hook is dropped once the calls that may run
+//! it have returned.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::sync::atomic::{AtomicI64, Ordering};
+//! use std::sync::Arc;
+//!
+//! use libffi::middle::intercept::intercept;
+//! use libffi::middle::{Cif, CodePtr, Type};
+//!
+//! extern "C" fn add(x: i64, y: i64) -> i64 {
+//!     x + y
+//! }
+//!
+//! let first = Arc::new(AtomicI64::new(0));
+//! let total = Arc::new(AtomicI64::new(0));
+//! let interceptor = {
+//!     let (first, total) = (first.clone(), total.clone());
+//!     unsafe {
+//!         intercept(
+//!             CodePtr(add as *mut _),
+//!             Cif::new(vec![Type::i64(), Type::i64()], Type::i64()),
+//!             move |call| first.store(call.arg::<i64>(0), Ordering::Relaxed),
+//!             move |call| {
+//!                 total.fetch_add(call.result::<i64>(), Ordering::Relaxed);
+//!             },
+//!         )
+//!     }
+//! };
+//!
+//! let traced: &extern "C" fn(i64, i64) -> i64 = unsafe { interceptor.instantiate_code_ptr() };
+//! assert_eq!(5, traced(2, 3));
+//! assert_eq!(9, traced(4, 5));
+//! assert_eq!(4, first.load(Ordering::Relaxed));
+//! assert_eq!(14, total.load(Ordering::Relaxed));
+//! ```
+
+use std::marker::PhantomData;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{fmt, mem, ptr, slice};
+
+use super::{ArgsMut, Cif, Closure, CodePtr};
+use crate::low;
+
+/// A call of an [`Interceptor`], as seen by its hooks.
+pub struct InterceptedCall<'a> {
+    cif: &'a low::ffi_cif,
+    args: *const *const c_void,
+    result: *mut c_void,
+    _marker: PhantomData<&'a c_void>,
+}
+
+impl InterceptedCall<'_> {
+    /// The number of arguments.
+    pub fn len(&self) -> usize {
+        self.cif.nargs as usize
+    }
+
+    /// Whether there are no arguments.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Gets a pointer to the `i`th argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no `i`th argument.
+    pub fn arg_ptr(&self, i: usize) -> *const c_void {
+        assert!(
+            i < self.len(),
+            "InterceptedCall::arg_ptr: there is no argument {}",
+            i
+        );
+        unsafe { *self.args.add(i) }
+    }
+
+    /// Gets the bytes of the `i`th argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no `i`th argument.
+    pub fn arg_bytes(&self, i: usize) -> &[u8] {
+        let ptr = self.arg_ptr(i);
+        unsafe {
+            let size = (**self.cif.arg_types.add(i)).size;
+            slice::from_raw_parts(ptr as *const u8, size)
+        }
+    }
+
+    /// Reads the `i`th argument as a `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no `i`th argument, or if it is smaller than a
+    /// `T`.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type of the argument.
+    pub unsafe fn arg<T: Copy>(&self, i: usize) -> T {
+        assert!(
+            mem::size_of::<T>() <= self.arg_bytes(i).len(),
+            "InterceptedCall::arg: argument {} is smaller than the requested type",
+            i
+        );
+        ptr::read(self.arg_ptr(i) as *const T)
+    }
+
+    /// Gets a view through which the arguments can be replaced.
+    ///
+    /// The replacements made by a pre-call hook are seen by the hooks
+    /// after it and are what the function receives; see [`ArgsMut`] for
+    /// the lifetime of replaced pointers.
+    pub fn args_mut(&mut self) -> ArgsMut<'_> {
+        unsafe { ArgsMut::from_raw(self.cif, self.args) }
+    }
+
+    /// Gets a pointer to where the function’s result is stored.
+    ///
+    /// The result is only written once the function returns, so it may
+    /// only be read by the post-call hook.
+    pub fn result_ptr(&self) -> *mut c_void {
+        self.result
+    }
+
+    /// Reads the function’s result as a `R`.
+    ///
+    /// # Safety
+    ///
+    /// This may only be called by the post-call hook, and `R` must be
+    /// the result type, widened to [`ffi_arg`](low::ffi_arg) as libffi
+    /// requires for small integers.
+    pub unsafe fn result<R: Copy>(&self) -> R {
+        ptr::read(self.result as *const R)
+    }
+}
+
+impl fmt::Debug for InterceptedCall<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries((0..self.len()).map(|i| self.arg_bytes(i)))
+            .finish()
+    }
+}
+
+type Hook = Box<dyn Fn(&mut InterceptedCall) + Send + Sync>;
+
+/// Identifies a pair of hooks added to an [`Interceptor`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HookId(u64);
+
+struct Entry {
+    id: HookId,
+    priority: i32,
+    enabled: AtomicBool,
+    pre: Hook,
+    post: Hook,
+}
+
+struct State {
+    fun: CodePtr,
+    // Replaced rather than modified, so that calls in progress keep the
+    // hooks they started with.
+    hooks: Mutex<Arc<Vec<Arc<Entry>>>>,
+    next_id: AtomicU64,
+}
+
+impl State {
+    fn hooks(&self) -> Arc<Vec<Arc<Entry>>> {
+        self.hooks.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // Replaces the hooks with a modified copy.
+    fn update<T>(&self, f: impl FnOnce(&mut Vec<Arc<Entry>>) -> T) -> T {
+        let mut hooks = self.hooks.lock().unwrap_or_else(|e| e.into_inner());
+        let mut copy = Vec::clone(&hooks);
+        let value = f(&mut copy);
+        *hooks = Arc::new(copy);
+        value
+    }
+
+    fn find(&self, id: HookId) -> Option<Arc<Entry>> {
+        self.hooks().iter().find(|entry| entry.id == id).cloned()
+    }
+}
+
+/// A closure that wraps a function with hooks; see [`intercept`] and
+/// [`Interceptor::new`].
+pub struct Interceptor {
+    // Dropped before `state`, which it refers to.
+    closure: Closure<'static>,
+    state: Box<State>,
+}
+
+// The hooks are `Send` and `Sync`, and the wrapped function is only
+// called through its code pointer.
+unsafe impl Send for Interceptor {}
+unsafe impl Sync for Interceptor {}
+
+impl fmt::Debug for Interceptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Interceptor")
+            .field("closure", &self.closure)
+            .field("fun", &self.state.fun)
+            .field("hooks", &self.hook_count())
+            .finish()
+    }
+}
+
+impl Interceptor {
+    /// Wraps the function `fun` in a closure of the same signature,
+    /// without hooks.
+    ///
+    /// Until hooks are added with [`add_hook`](Interceptor::add_hook),
+    /// calling the closure just calls `fun`. The closure is freed, and
+    /// must no longer be called, when the interceptor is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `fun` must be a function with the signature `cif` describes, and
+    /// remain valid for as long as the closure is called.
+    pub unsafe fn new(fun: CodePtr, cif: Cif) -> Self {
+        let state = Box::new(State {
+            fun,
+            hooks: Mutex::new(Arc::new(vec![])),
+            next_id: AtomicU64::new(0),
+        });
+        // The closure is dropped before the boxed state, so the state
+        // outlives every call.
+        let userdata = &*(&*state as *const State);
+        let closure = Closure::new(cif, intercept_callback, userdata);
+        Interceptor { closure, state }
+    }
+
+    /// Adds a pair of hooks, enabled, with the given priority.
+    ///
+    /// Calls that have already started do not run the new hooks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use libffi::middle::intercept::Interceptor;
+    /// use libffi::middle::{Cif, CodePtr, Type};
+    ///
+    /// extern "C" fn tick() {}
+    ///
+    /// let log = Arc::new(Mutex::new(vec![]));
+    /// let interceptor = unsafe { Interceptor::new(CodePtr(tick as *mut _), Cif::new(vec![], Type::void())) };
+    /// for (name, priority) in vec![("timing", 10), ("tracing", -10)] {
+    ///     let (before, after) = (log.clone(), log.clone());
+    ///     interceptor.add_hook(
+    ///         priority,
+    ///         move |_| before.lock().unwrap().push(format!("enter {}", name)),
+    ///         move |_| after.lock().unwrap().push(format!("leave {}", name)),
+    ///     );
+    /// }
+    ///
+    /// let tick: &extern "C" fn() = unsafe { interceptor.instantiate_code_ptr() };
+    /// tick();
+    /// assert_eq!(
+    ///     vec!["enter tracing", "enter timing", "leave timing", "leave tracing"],
+    ///     *log.lock().unwrap()
+    /// );
+    /// ```
+    pub fn add_hook<Pre, Post>(&self, priority: i32, pre: Pre, post: Post) -> HookId
+    where
+        Pre: Fn(&mut InterceptedCall) + Send + Sync + 'static,
+        Post: Fn(&mut InterceptedCall) + Send + Sync + 'static,
+    {
+        let id = HookId(self.state.next_id.fetch_add(1, Ordering::Relaxed));
+        let entry = Arc::new(Entry {
+            id,
+            priority,
+            enabled: AtomicBool::new(true),
+            pre: Box::new(pre),
+            post: Box::new(post),
+        });
+        self.state.update(|hooks| {
+            // After the hooks of lower or equal priority.
+            let index = hooks.partition_point(|other| other.priority <= priority);
+            hooks.insert(index, entry);
+        });
+        id
+    }
+
+    /// Removes the hooks `id`, returning whether they were present.
+    ///
+    /// Calls that have already started may still run the hooks, which
+    /// are dropped when the last of them returns.
+    pub fn remove_hook(&self, id: HookId) -> bool {
+        self.state.update(|hooks| {
+            let len = hooks.len();
+            hooks.retain(|entry| entry.id != id);
+            hooks.len() != len
+        })
+    }
+
+    /// Enables or disables the hooks `id`, returning whether they are
+    /// present.
+    ///
+    /// Whether a pair of hooks is enabled is read before its pre-call
+    /// hook would run, and its post-call hook runs if the pre-call hook
+    /// did.
+    pub fn set_enabled(&self, id: HookId, enabled: bool) -> bool {
+        match self.state.find(id) {
+            Some(entry) => {
+                entry.enabled.store(enabled, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the hooks `id` are enabled, or `None` if they are not
+    /// present.
+    pub fn is_enabled(&self, id: HookId) -> Option<bool> {
+        self.state
+            .find(id)
+            .map(|entry| entry.enabled.load(Ordering::Relaxed))
+    }
+
+    /// The number of pairs of hooks, whether enabled or not.
+    pub fn hook_count(&self) -> usize {
+        self.state.hooks().len()
+    }
+
+    /// The code pointer of the wrapped function.
+    pub fn target(&self) -> CodePtr {
+        self.state.fun
+    }
+
+    /// Obtains the callable code pointer for the closure.
+    pub fn code_ptr(&self) -> &unsafe extern "C" fn() {
+        self.closure.code_ptr()
+    }
+
+    /// Transmutes the callable code pointer for the closure to a
+    /// reference to any type.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Closure::instantiate_code_ptr`].
+    pub unsafe fn instantiate_code_ptr<T>(&self) -> &T {
+        self.closure.instantiate_code_ptr()
+    }
+
+    /// Gets the closure’s code pointer as a [`CodePtr`].
+    pub fn as_code_ptr(&self) -> CodePtr {
+        CodePtr(*self.code_ptr() as *mut c_void)
+    }
+}
+
+/// Wraps the function `fun` with hooks, returning a closure of the same
+/// signature.
+///
+/// Each call of the closure calls `pre`, then `fun` with the same
+/// arguments, then `post`, and returns the result of `fun`. The closure
+/// is freed, and must no longer be called, when the [`Interceptor`] is
+/// dropped. The hooks are added with priority 0, and more can be added
+/// with [`Interceptor::add_hook`].
+///
+/// If a hook panics, the process is aborted rather than unwinding into
+/// C.
+///
+/// # Safety
+///
+/// `fun` must be a function with the signature `cif` describes, and
+/// remain valid for as long as the closure is called.
+pub unsafe fn intercept<Pre, Post>(fun: CodePtr, cif: Cif, pre: Pre, post: Post) -> Interceptor
+where
+    Pre: Fn(&mut InterceptedCall) + Send + Sync + 'static,
+    Post: Fn(&mut InterceptedCall) + Send + Sync + 'static,
+{
+    let interceptor = Interceptor::new(fun, cif);
+    interceptor.add_hook(0, pre, post);
+    interceptor
+}
+
+unsafe extern "C" fn intercept_callback(
+    cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    state: &State,
+) {
+    crate::__private::v1::abort_on_panic("Cannot panic inside FFI callback", || {
+        let mut call = InterceptedCall {
+            cif,
+            args,
+            result,
+            _marker: PhantomData,
+        };
+        let hooks = state.hooks();
+        let enabled: Vec<bool> = hooks
+            .iter()
+            .map(|entry| {
+                let enabled = entry.enabled.load(Ordering::Relaxed);
+                if enabled {
+                    (entry.pre)(&mut call);
+                }
+                enabled
+            })
+            .collect();
+        // The closure’s result buffer is large enough for any result
+        // of the CIF, as libffi requires of `ffi_call`.
+        low::forward_call(cif, state.fun, args, call.result);
+        for (entry, _) in hooks.iter().zip(enabled).rev().filter(|&(_, ran)| ran) {
+            (entry.post)(&mut call);
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::thread;
+
+    use super::*;
+    use crate::middle::Type;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Pair {
+        tag: u8,
+        value: f64,
+    }
+
+    extern "C" fn swap(pair: Pair, tag: u8) -> Pair {
+        Pair {
+            tag,
+            value: pair.value + f64::from(pair.tag),
+        }
+    }
+
+    extern "C" fn narrow(x: u8) -> u8 {
+        x.wrapping_mul(3)
+    }
+
+    #[test]
+    fn struct_arguments_and_results() {
+        let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+        let cif = Cif::new(vec![pair.clone(), Type::u8()], pair);
+        let seen = Arc::new(Mutex::new(vec![]));
+        let interceptor = {
+            let (before, after) = (seen.clone(), seen.clone());
+            unsafe {
+                intercept(
+                    CodePtr(swap as *mut _),
+                    cif,
+                    move |call| {
+                        assert_eq!(16, call.arg_bytes(0).len());
+                        before.lock().unwrap().push(call.arg::<Pair>(0));
+                    },
+                    move |call| after.lock().unwrap().push(call.result::<Pair>()),
+                )
+            }
+        };
+        assert_eq!(swap as *mut c_void, interceptor.target().as_mut_ptr());
+
+        let fun: &extern "C" fn(Pair, u8) -> Pair = unsafe { interceptor.instantiate_code_ptr() };
+        let result = fun(Pair { tag: 2, value: 0.5 }, 9);
+        assert_eq!(Pair { tag: 9, value: 2.5 }, result);
+        assert_eq!(
+            vec![Pair { tag: 2, value: 0.5 }, Pair { tag: 9, value: 2.5 }],
+            *seen.lock().unwrap()
+        );
+    }
+
+    extern "C" fn double(x: u32) -> u32 {
+        x * 2
+    }
+
+    // An interceptor of `double` whose hooks log their names.
+    fn logged(
+        log: &Arc<Mutex<Vec<String>>>,
+        names: &[(&'static str, i32)],
+    ) -> (Interceptor, Vec<HookId>) {
+        let interceptor = unsafe {
+            Interceptor::new(
+                CodePtr(double as *mut _),
+                Cif::new(vec![Type::u32()], Type::u32()),
+            )
+        };
+        let ids = names
+            .iter()
+            .map(|&(name, priority)| {
+                let (before, after) = (log.clone(), log.clone());
+                interceptor.add_hook(
+                    priority,
+                    move |_| before.lock().unwrap().push(format!("+{}", name)),
+                    move |_| after.lock().unwrap().push(format!("-{}", name)),
+                )
+            })
+            .collect();
+        (interceptor, ids)
+    }
+
+    #[test]
+    fn chained_hooks() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let (interceptor, ids) = logged(&log, &[("a", 1), ("b", 0), ("c", 1), ("d", -5)]);
+        assert_eq!(4, interceptor.hook_count());
+        let fun: &extern "C" fn(u32) -> u32 = unsafe { interceptor.instantiate_code_ptr() };
+
+        assert_eq!(6, fun(3));
+        assert_eq!(
+            vec!["+d", "+b", "+a", "+c", "-c", "-a", "-b", "-d"],
+            mem::take(&mut *log.lock().unwrap())
+        );
+
+        assert!(interceptor.set_enabled(ids[1], false));
+        assert_eq!(Some(false), interceptor.is_enabled(ids[1]));
+        assert!(interceptor.remove_hook(ids[3]));
+        assert!(!interceptor.remove_hook(ids[3]));
+        assert!(!interceptor.set_enabled(ids[3], true));
+        assert_eq!(None, interceptor.is_enabled(ids[3]));
+        assert_eq!(8, fun(4));
+        assert_eq!(
+            vec!["+a", "+c", "-c", "-a"],
+            mem::take(&mut *log.lock().unwrap())
+        );
+
+        interceptor.set_enabled(ids[1], true);
+        interceptor.remove_hook(ids[0]);
+        interceptor.remove_hook(ids[2]);
+        assert_eq!(2, fun(1));
+        assert_eq!(vec!["+b", "-b"], *log.lock().unwrap());
+    }
+
+    #[test]
+    fn removal_during_calls() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let (interceptor, _) = logged(&log, &[("outer", 0)]);
+        let interceptor = Arc::new(interceptor);
+
+        // A hook that holds the call until the main thread has removed it.
+        let (entered, wait) = (Arc::new(Barrier::new(2)), Arc::new(Barrier::new(2)));
+        let held = {
+            let (entered, wait) = (entered.clone(), wait.clone());
+            let after = log.clone();
+            interceptor.add_hook(
+                1,
+                move |_| {
+                    entered.wait();
+                    wait.wait();
+                },
+                move |_| after.lock().unwrap().push("-held".to_string()),
+            )
+        };
+
+        let fun: extern "C" fn(u32) -> u32 = *unsafe { interceptor.instantiate_code_ptr() };
+        let call = thread::spawn(move || fun(5));
+        entered.wait();
+        assert!(interceptor.remove_hook(held));
+        wait.wait();
+        assert_eq!(10, call.join().unwrap());
+
+        assert_eq!(12, fun(6));
+        assert_eq!(
+            vec!["+outer", "-held", "-outer", "+outer", "-outer"],
+            *log.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn widened_results() {
+        let interceptor = unsafe {
+            intercept(
+                CodePtr(narrow as *mut _),
+                Cif::new(vec![Type::u8()], Type::u8()),
+                |call| assert_eq!(1, call.len()),
+                |call| assert_eq!(150, call.result::<low::ffi_arg>()),
+            )
+        };
+        let fun: &extern "C" fn(u8) -> u8 = unsafe { interceptor.instantiate_code_ptr() };
+        assert_eq!(150, fun(50));
+        assert!(format!("{:?}", interceptor).starts_with("Interceptor"));
+    }
+}
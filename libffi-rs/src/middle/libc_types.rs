@@ -0,0 +1,127 @@
+use alloc::vec;
+use core::mem;
+
+use super::Type;
+
+/// Types of common structures of the C library, for binding POSIX
+/// APIs.
+///
+/// These are enabled by the `libc-types` feature, on Unix. Each type
+/// has the size and alignment of the corresponding [`libc`] structure
+/// on the target, so that values of it can be passed by value and
+/// their fields read through the `libc` definition. The
+/// [`CType`](crate::high::CType) implementations for the `libc`
+/// structures use them.
+impl Type {
+    /// Returns the type of `struct timespec`, a time in seconds and
+    /// nanoseconds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Type;
+    ///
+    /// assert_eq!(
+    ///     (std::mem::size_of::<libc::timespec>(), std::mem::align_of::<libc::timespec>()),
+    ///     Type::timespec().layout(),
+    /// );
+    /// ```
+    pub fn timespec() -> Self {
+        Type::structure(vec![signed::<libc::time_t>(), signed::<libc::c_long>()])
+    }
+
+    /// Returns the type of `struct timeval`, a time in seconds and
+    /// microseconds.
+    pub fn timeval() -> Self {
+        Type::structure(vec![
+            signed::<libc::time_t>(),
+            signed::<libc::suseconds_t>(),
+        ])
+    }
+
+    /// Returns an opaque type with the size and alignment of
+    /// `struct stat`.
+    ///
+    /// The fields of `struct stat` differ between targets, so the type
+    /// describes only its layout, which is enough to pass a value of it
+    /// to or from C; its fields are read through [`libc::stat`].
+    pub fn stat() -> Self {
+        opaque::<libc::stat>()
+    }
+
+    /// Returns an opaque type with the size and alignment of
+    /// `struct sockaddr_storage`, which is large enough to hold any
+    /// socket address.
+    pub fn sockaddr_storage() -> Self {
+        opaque::<libc::sockaddr_storage>()
+    }
+}
+
+// The signed integer type of the size of `T`.
+fn signed<T>() -> Type {
+    match mem::size_of::<T>() {
+        1 => Type::i8(),
+        2 => Type::i16(),
+        4 => Type::i32(),
+        8 => Type::i64(),
+        _ => panic!("Strange size for C type"),
+    }
+}
+
+// An array of words of the alignment of `T`, whose size and alignment
+// are those of `T`.
+fn opaque<T>() -> Type {
+    let align = mem::align_of::<T>();
+    let word = match align {
+        1 => Type::u8(),
+        2 => Type::u16(),
+        4 => Type::u32(),
+        8 => Type::u64(),
+        _ => panic!("Strange alignment for C type"),
+    };
+    Type::array(word, mem::size_of::<T>() / align)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::{arg, Cif, CodePtr};
+
+    fn layout<T>() -> (usize, usize) {
+        (mem::size_of::<T>(), mem::align_of::<T>())
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(layout::<libc::timespec>(), Type::timespec().layout());
+        assert_eq!(layout::<libc::timeval>(), Type::timeval().layout());
+        assert_eq!(layout::<libc::stat>(), Type::stat().layout());
+        assert_eq!(
+            layout::<libc::sockaddr_storage>(),
+            Type::sockaddr_storage().layout()
+        );
+    }
+
+    extern "C" fn later(a: libc::timespec, b: libc::timespec) -> libc::timespec {
+        if (a.tv_sec, a.tv_nsec) < (b.tv_sec, b.tv_nsec) {
+            b
+        } else {
+            a
+        }
+    }
+
+    #[test]
+    fn timespecs_by_value() {
+        let cif = Cif::new(vec![Type::timespec(), Type::timespec()], Type::timespec());
+        let a = libc::timespec {
+            tv_sec: 5,
+            tv_nsec: 900,
+        };
+        let b = libc::timespec {
+            tv_sec: 5,
+            tv_nsec: 1000,
+        };
+        let c: libc::timespec = unsafe { cif.call(CodePtr(later as *mut _), &[arg(&a), arg(&b)]) };
+        assert_eq!((5, 1000), (c.tv_sec, c.tv_nsec));
+    }
+}
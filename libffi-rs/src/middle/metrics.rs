@@ -0,0 +1,154 @@
+//! Gauges of live closures and CIFs for the `metrics` crate.
+//!
+//! With the `metrics` feature enabled, the crate keeps the following
+//! gauges up to date through the [`metrics`](::metrics) facade, so that
+//! any recorder the host installs can export them:
+//!
+//! | Gauge | Unit | Meaning |
+//! |-------|------|---------|
+//! | `libffi.closures.live` | count | closures that have not been dropped |
+//! | `libffi.closures.executable_bytes` | bytes | trampoline code of live closures |
+//! | `libffi.cifs.live` | count | [`Cif`](super::Cif)s that have not been dropped |
+//!
+//! A closure’s CIF stops counting as live when the closure is dropped,
+//! even if the `deferred-reclaim` feature frees it later.
+//!
+//! Updates that happen before a recorder is installed are lost, so a
+//! host should install its recorder before creating closures or CIFs.
+//! Call [`describe`] to register descriptions of the gauges.
+
+use ::metrics::{describe_gauge, gauge, Unit};
+
+const CLOSURES_LIVE: &str = "libffi.closures.live";
+const CLOSURES_EXECUTABLE_BYTES: &str = "libffi.closures.executable_bytes";
+const CIFS_LIVE: &str = "libffi.cifs.live";
+
+/// Registers descriptions of the gauges with the installed recorder.
+pub fn describe() {
+    describe_gauge!(
+        CLOSURES_LIVE,
+        Unit::Count,
+        "Closures that have not been dropped"
+    );
+    describe_gauge!(
+        CLOSURES_EXECUTABLE_BYTES,
+        Unit::Bytes,
+        "Trampoline code of live closures"
+    );
+    describe_gauge!(CIFS_LIVE, Unit::Count, "CIFs that have not been dropped");
+}
+
+pub(crate) fn closure_created() {
+    gauge!(CLOSURES_LIVE).increment(1.0);
+    gauge!(CLOSURES_EXECUTABLE_BYTES).increment(crate::raw::FFI_TRAMPOLINE_SIZE as f64);
+}
+
+pub(crate) fn closure_dropped() {
+    gauge!(CLOSURES_LIVE).decrement(1.0);
+    gauge!(CLOSURES_EXECUTABLE_BYTES).decrement(crate::raw::FFI_TRAMPOLINE_SIZE as f64);
+}
+
+pub(crate) fn cif_created() {
+    gauge!(CIFS_LIVE).increment(1.0);
+}
+
+pub(crate) fn cif_dropped() {
+    gauge!(CIFS_LIVE).decrement(1.0);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::os::raw::c_void;
+    use std::sync::{Arc, Mutex};
+
+    use ::metrics::{
+        with_local_recorder, Counter, Gauge, GaugeFn, Histogram, Key, KeyName, Metadata, Recorder,
+        SharedString,
+    };
+
+    use crate::low;
+    use crate::middle::{Cif, Closure, Type};
+
+    struct Value(Mutex<f64>);
+
+    impl GaugeFn for Value {
+        fn increment(&self, value: f64) {
+            *self.0.lock().unwrap() += value;
+        }
+
+        fn decrement(&self, value: f64) {
+            *self.0.lock().unwrap() -= value;
+        }
+
+        fn set(&self, value: f64) {
+            *self.0.lock().unwrap() = value;
+        }
+    }
+
+    #[derive(Default)]
+    struct Gauges(Mutex<HashMap<String, Arc<Value>>>);
+
+    impl Gauges {
+        fn get(&self, name: &str) -> f64 {
+            self.0
+                .lock()
+                .unwrap()
+                .get(name)
+                .map_or(0.0, |value| *value.0.lock().unwrap())
+        }
+    }
+
+    impl Recorder for Gauges {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, _: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::noop()
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            let mut gauges = self.0.lock().unwrap();
+            let value = gauges
+                .entry(key.name().to_owned())
+                .or_insert_with(|| Arc::new(Value(Mutex::new(0.0))));
+            Gauge::from_arc(value.clone())
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    unsafe extern "C" fn nothing(
+        _cif: &low::ffi_cif,
+        _result: &mut u64,
+        _args: *const *const c_void,
+        _userdata: &(),
+    ) {
+    }
+
+    #[test]
+    fn gauges_follow_lifetimes() {
+        let gauges = Gauges::default();
+        with_local_recorder(&gauges, || {
+            let cif = Cif::new(vec![], Type::u64());
+            let closure = Closure::new(cif.clone(), nothing, &());
+            assert_eq!(2.0, gauges.get(CIFS_LIVE));
+            assert_eq!(1.0, gauges.get(CLOSURES_LIVE));
+            assert_eq!(
+                crate::raw::FFI_TRAMPOLINE_SIZE as f64,
+                gauges.get(CLOSURES_EXECUTABLE_BYTES)
+            );
+
+            drop(closure);
+            drop(cif);
+        });
+        assert_eq!(0.0, gauges.get(CIFS_LIVE));
+        assert_eq!(0.0, gauges.get(CLOSURES_LIVE));
+        assert_eq!(0.0, gauges.get(CLOSURES_EXECUTABLE_BYTES));
+    }
+}
@@ -8,26 +8,166 @@
 //! aren’t checked. See the [`high`](crate::high) layer for closures
 //! with type-checked arguments.
 
+use alloc::boxed::Box;
+use core::ffi::{c_uint, c_void};
+#[cfg(feature = "std")]
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+use core::{fmt, mem};
+#[cfg(feature = "std")]
 use std::any::Any;
-use std::marker::PhantomData;
-use std::os::raw::c_void;
+#[cfg(feature = "std")]
+use std::error;
 
+#[cfg(feature = "std")]
+use self::bridge::ErrorPolicy;
 use crate::low;
 pub use crate::low::{ffi_abi as FfiAbi, ffi_abi_FFI_DEFAULT_ABI, Callback, CallbackMut, CodePtr};
 
 mod util;
 
+mod abi;
+pub use abi::Abi;
+
+#[cfg(all(unix, feature = "libc-types"))]
+mod libc_types;
+
 mod types;
-pub use types::Type;
+pub use types::{
+    StructTypeBuilder, Type, TypeArray, TypeArrayBuilder, TypeDesc, TypeElements, TypeKind,
+};
+
+mod buffer;
+pub use buffer::{FieldError, Scalar, TypedBuffer};
+
+mod arg_buffer;
+pub use arg_buffer::ArgBuffer;
+
+mod args_mut;
+pub use args_mut::ArgsMut;
+
+mod value;
+pub use value::{Value, ValueError};
+
+#[cfg(feature = "std")]
+mod report;
+#[cfg(feature = "std")]
+pub use report::{CallReport, PointerHandles};
+
+mod endian;
+pub use endian::ByteOrder;
+
+mod method;
+
+mod variadic;
 
+mod signature;
+#[cfg(feature = "std")]
+pub(crate) use signature::canonical_signature;
+pub use signature::ParseError;
+
+// Closures, and everything else from here on, need `std`.
+
+#[cfg(feature = "std")]
+mod owned;
+#[cfg(feature = "std")]
+pub use owned::ClosureOwned;
+
+#[cfg(feature = "std")]
+mod pool;
+#[cfg(feature = "std")]
+pub use pool::ClosurePool;
+
+#[cfg(feature = "std")]
+mod cache;
+#[cfg(feature = "std")]
+pub use cache::CifCache;
+
+#[cfg(feature = "std")]
+mod strings;
+#[cfg(feature = "std")]
+pub use strings::{read_c_str_array, Argv, CStrArray};
+
+#[cfg(feature = "std")]
 mod builder;
+#[cfg(feature = "std")]
 pub use builder::Builder;
 
+#[cfg(feature = "std")]
+pub mod bridge;
+
+#[cfg(feature = "std")]
+pub mod dispatch;
+
+#[cfg(feature = "std")]
+pub mod intercept;
+
+#[cfg(feature = "std")]
+pub mod notify;
+
+#[cfg(feature = "stats")]
+pub mod stats;
+
+#[cfg(feature = "trampoline-registry")]
+pub mod trampolines;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
+#[cfg(feature = "deferred-reclaim")]
+pub mod reclaim;
+
+#[cfg(feature = "std")]
+pub mod affinity;
+
+#[cfg(all(target_os = "linux", feature = "libc", feature = "std"))]
+pub mod syscall;
+
+#[cfg(feature = "std")]
+mod bound;
+#[cfg(all(any(all(unix, feature = "libc"), windows), feature = "std"))]
+pub(crate) use bound::Owner;
+#[cfg(feature = "std")]
+pub use bound::{BoundFn, LibraryClosed, ReturnedFn};
+
+#[cfg(feature = "std")]
+pub mod registry;
+
+#[cfg(feature = "std")]
+pub mod demangle;
+
+#[cfg(feature = "std")]
+mod userdata;
+#[cfg(feature = "std")]
+pub use userdata::UserData;
+
 /// Contains an untyped pointer to a function argument.
 ///
 /// When calling a function via a [CIF](Cif), each argument
-/// must be passed as a C `void*`. Wrapping the argument in the [`Arg`]
-/// struct accomplishes the necessary coercion.
+/// must be passed as a C `void*` pointing to the argument’s value.
+/// Wrapping the argument in the [`Arg`] struct accomplishes the
+/// necessary coercion.
+///
+/// Because libffi wants a pointer to each argument, it is easy to pass
+/// a pointer where a pointer *to* a pointer was needed. Use
+/// [`Arg::val`] to pass the value stored in a variable, and
+/// [`Arg::ptr`] or [`Arg::ptr_mut`] to pass a pointer-typed argument:
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn first(v: *const u32) -> u32 {
+///     unsafe { *v }
+/// }
+///
+/// let values = [7u32, 8, 9];
+/// let ptr = values.as_ptr();
+///
+/// let cif = Cif::new(vec![Type::pointer()], Type::u32());
+/// let n: u32 = unsafe { cif.call(CodePtr(first as *mut _), &[Arg::ptr(&ptr)]) };
+///
+/// assert_eq!(7, n);
+/// ```
 #[derive(Clone, Debug)]
 #[repr(C)]
 pub struct Arg(*mut c_void);
@@ -37,17 +177,210 @@ impl Arg {
     ///
     /// This is used to wrap each argument pointer before passing them
     /// to [`Cif::call`].
+    #[deprecated(
+        since = "3.3.0",
+        note = "use `Arg::val` to pass a value, or `Arg::ptr` to pass a pointer"
+    )]
     pub fn new<T>(r: &T) -> Self {
+        Arg::val(r)
+    }
+
+    /// Passes the value referred to by `r`.
+    ///
+    /// The callee receives a copy of `*r`; for instance, if `T` is
+    /// `f64` then the corresponding C parameter should be a `double`.
+    pub fn val<T>(r: &T) -> Self {
         Arg(r as *const T as *mut c_void)
     }
+
+    /// Passes the pointer stored in `p`.
+    ///
+    /// The callee receives the pointer `*p` itself, so the
+    /// corresponding C parameter should have a pointer type such as
+    /// `const T*`.
+    pub fn ptr<T>(p: &*const T) -> Self {
+        Arg(p as *const *const T as *mut c_void)
+    }
+
+    /// Passes the mutable pointer stored in `p`.
+    ///
+    /// The callee receives the pointer `*p` itself, so the
+    /// corresponding C parameter should have a pointer type such as
+    /// `T*`.
+    pub fn ptr_mut<T>(p: &*mut T) -> Self {
+        Arg(p as *const *mut T as *mut c_void)
+    }
+
+    /// Passes a value of type `ty` whose representation is `bytes`.
+    ///
+    /// This lets values whose type is only known at run time, such as
+    /// structs received from a serialization layer, be passed by value
+    /// without defining a Rust type for them. The bytes must be laid
+    /// out as C would lay out `ty`, including any padding. To copy
+    /// unaligned bytes into suitable storage, use a [`TypedBuffer`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the length of `bytes` differs from the size of `ty`, or
+    /// if `bytes` is not aligned for `ty`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::*;
+    ///
+    /// #[repr(C)]
+    /// struct Pair {
+    ///     a: u32,
+    ///     b: u32,
+    /// }
+    ///
+    /// extern "C" fn sum(pair: Pair) -> u32 {
+    ///     pair.a + pair.b
+    /// }
+    ///
+    /// let pair = Type::structure(vec![Type::u32(), Type::u32()]);
+    /// let cif = Cif::new(vec![pair.clone()], Type::u32());
+    ///
+    /// // A `u32` array has the size and alignment of the struct.
+    /// let values = [3u32, 4];
+    /// let bytes = unsafe {
+    ///     std::slice::from_raw_parts(values.as_ptr() as *const u8, 8)
+    /// };
+    ///
+    /// let arg = Arg::from_bytes(bytes, &pair).unwrap();
+    /// let n: u32 = unsafe { cif.call(CodePtr(sum as *mut _), &[arg]) };
+    /// assert_eq!(7, n);
+    ///
+    /// assert!(Arg::from_bytes(&bytes[..4], &pair).is_err());
+    /// ```
+    pub fn from_bytes(bytes: &[u8], ty: &Type) -> Result<Self, BytesError> {
+        check_layout(bytes, ty)?;
+        Ok(Arg(bytes.as_ptr() as *mut c_void))
+    }
+}
+
+/// The error returned when a byte buffer doesn’t match the layout of a
+/// [`Type`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BytesError {
+    /// The buffer’s length differs from the type’s size.
+    Size {
+        /// The size of the type.
+        expected: usize,
+        /// The length of the buffer.
+        actual: usize,
+    },
+    /// The buffer is not aligned for the type.
+    Alignment {
+        /// The alignment of the type.
+        expected: usize,
+    },
+}
+
+impl fmt::Display for BytesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytesError::Size { expected, actual } => write!(
+                f,
+                "buffer of {} bytes for a type of {} bytes",
+                actual, expected
+            ),
+            BytesError::Alignment { expected } => {
+                write!(f, "buffer is not aligned to {} bytes", expected)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for BytesError {}
+
+fn check_layout(bytes: &[u8], ty: &Type) -> Result<(), BytesError> {
+    let (size, alignment) = ty.layout();
+    if bytes.len() != size {
+        return Err(BytesError::Size {
+            expected: size,
+            actual: bytes.len(),
+        });
+    }
+    if alignment > 1 && bytes.as_ptr() as usize % alignment != 0 {
+        return Err(BytesError::Alignment {
+            expected: alignment,
+        });
+    }
+    Ok(())
+}
+
+// `Option<&T>`, `Option<&mut T>`, and `Option<NonNull<T>>` are
+// guaranteed to have the same representation as a nullable C pointer,
+// with `None` represented as NULL, so we can pass them where a pointer
+// argument is expected.
+
+/// Passes a nullable pointer, where `None` is passed as NULL.
+impl<'a, 'b, T> From<&'a Option<&'b T>> for Arg {
+    fn from(p: &'a Option<&'b T>) -> Self {
+        Arg::val(p)
+    }
+}
+
+/// Passes a nullable pointer, where `None` is passed as NULL.
+impl<'a, 'b, T> From<&'a Option<&'b mut T>> for Arg {
+    fn from(p: &'a Option<&'b mut T>) -> Self {
+        Arg::val(p)
+    }
+}
+
+/// Passes a nullable pointer, where `None` is passed as NULL.
+impl<'a, T> From<&'a Option<NonNull<T>>> for Arg {
+    fn from(p: &'a Option<NonNull<T>>) -> Self {
+        Arg::val(p)
+    }
 }
 
 /// Coerces an argument reference into the [`Arg`] type.
 ///
 /// This is used to wrap each argument pointer before passing them
-/// to [`Cif::call`]. (This is the same as [`Arg::new`]).
+/// to [`Cif::call`]. (This is the same as [`Arg::val`]).
 pub fn arg<T>(r: &T) -> Arg {
-    Arg::new(r)
+    Arg::val(r)
+}
+
+/// Builds a slice of [`Arg`]s for passing to [`Cif::call`].
+///
+/// Each argument is an ordinary expression: a literal, a variable, or
+/// a reference. The macro stores the value of each expression in a
+/// temporary and wraps a reference to that temporary with [`arg`]. The
+/// temporaries live until the end of the enclosing statement, so the
+/// macro should be used directly as the argument to [`Cif::call`]
+/// rather than bound to a variable first.
+///
+/// Note that passing a reference such as `&buf` passes a *pointer* to
+/// `buf`, as would be expected for a C parameter of pointer type.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::args;
+/// use libffi::middle::*;
+///
+/// extern "C" fn scale(x: i32, v: &[f64; 2], y: f64) -> f64 {
+///     (v[0] + v[1]) * x as f64 * y
+/// }
+///
+/// let buf = [1.0, 2.0];
+/// let factor = 0.5;
+///
+/// let cif = Cif::new(vec![Type::i32(), Type::pointer(), Type::f64()], Type::f64());
+/// let n: f64 = unsafe { cif.call(CodePtr(scale as *mut _), args![4i32, &buf, factor]) };
+///
+/// assert_eq!(6.0, n);
+/// ```
+#[macro_export]
+macro_rules! args {
+    ( $( $arg:expr ),* $(,)? ) => {
+        &[ $( $crate::middle::arg(&$arg) ),* ]
+    };
 }
 
 /// Describes the calling convention and types for calling a function.
@@ -75,13 +408,27 @@ pub fn arg<T>(r: &T) -> Arg {
 /// let n = unsafe { cif.call(CodePtr(add as *mut _), &[arg(&5f64), arg(&&6f64)]) };
 /// assert_eq!(11f64, n);
 /// ```
-#[derive(Debug)]
+#[cfg_attr(not(feature = "min-size"), derive(Debug))]
 pub struct Cif {
     cif: low::ffi_cif,
     args: types::TypeArray,
     result: Type,
+    // The number of fixed arguments of a variadic CIF.
+    nfixed: Option<usize>,
+    // Whether the CIF still counts in the gauge of live CIFs.
+    #[cfg(feature = "metrics")]
+    counted: bool,
 }
 
+#[cfg(feature = "min-size")]
+opaque_debug!(Cif);
+
+// A `Cif` owns the types it refers to and libffi only reads the
+// `ffi_cif` when calling through it, so it may be shared between
+// threads.
+unsafe impl Send for Cif {}
+unsafe impl Sync for Cif {}
+
 // To clone a Cif we need to clone the types and then make sure the new
 // ffi_cif refers to the clones of the types.
 impl Clone for Cif {
@@ -90,15 +437,41 @@ impl Clone for Cif {
             cif: self.cif,
             args: self.args.clone(),
             result: self.result.clone(),
+            nfixed: self.nfixed,
+            #[cfg(feature = "metrics")]
+            counted: true,
         };
 
         copy.cif.arg_types = copy.args.as_raw_ptr();
         copy.cif.rtype = copy.result.as_raw_ptr();
 
+        #[cfg(feature = "metrics")]
+        self::metrics::cif_created();
         copy
     }
 }
 
+#[cfg(feature = "metrics")]
+impl Drop for Cif {
+    fn drop(&mut self) {
+        if self.counted {
+            self::metrics::cif_dropped();
+        }
+    }
+}
+
+#[cfg(all(feature = "metrics", feature = "deferred-reclaim"))]
+impl Cif {
+    // Stops counting the CIF as live ahead of its drop, which with
+    // `deferred-reclaim` happens later, on the reclaimer’s thread.
+    pub(crate) fn uncount(&mut self) {
+        if self.counted {
+            self.counted = false;
+            self::metrics::cif_dropped();
+        }
+    }
+}
+
 impl Cif {
     /// Creates a new [CIF](Cif) for the given argument and result
     /// types.
@@ -107,30 +480,166 @@ impl Cif {
     /// the resulting [`Cif`] retains references to them. Defaults to
     /// the platform’s default calling convention; this can be adjusted
     /// using [`Cif::set_abi`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types; see [`Cif::try_new`].
     pub fn new<I>(args: I, result: Type) -> Self
     where
         I: IntoIterator<Item = Type>,
         I::IntoIter: ExactSizeIterator<Item = Type>,
     {
-        let args = args.into_iter();
-        let nargs = args.len();
-        let args = types::TypeArray::new(args);
-        let mut cif: low::ffi_cif = Default::default();
+        Cif::prepare(args, None, result, low::ffi_abi_FFI_DEFAULT_ABI)
+    }
 
-        unsafe {
-            low::prep_cif(
-                &mut cif,
-                low::ffi_abi_FFI_DEFAULT_ABI,
-                nargs,
-                result.as_raw_ptr(),
-                args.as_raw_ptr(),
-            )
-        }
-        .expect("low::prep_cif");
+    /// Creates a new [CIF](Cif) for the given argument and result
+    /// types, as for [`Cif::new`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if libffi rejects the types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Cif, Type};
+    /// use libffi::Error;
+    ///
+    /// assert!(Cif::try_new(vec![Type::u64()], Type::f64()).is_ok());
+    /// assert_eq!(
+    ///     Error::BadTypedef,
+    ///     Cif::try_new(vec![Type::structure(vec![])], Type::void()).unwrap_err()
+    /// );
+    /// ```
+    pub fn try_new<I>(args: I, result: Type) -> Result<Self, crate::Error>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        Cif::try_prepare(args, None, result, low::ffi_abi_FFI_DEFAULT_ABI)
+    }
+
+    /// Creates a new [CIF](Cif) for the given argument and result
+    /// types, prepared for the calling convention `abi`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Abi, Cif, Type};
+    ///
+    /// let cif = Cif::with_abi(vec![Type::i32()], Type::i32(), Abi::Default);
+    /// assert_eq!(Some(Abi::default_abi()), Abi::from_raw(cif.abi()));
+    /// ```
+    pub fn with_abi<I>(args: I, result: Type, abi: Abi) -> Self
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        Cif::prepare(args, None, result, abi.as_raw())
+    }
+
+    // Creates a CIF prepared for the given calling convention, which is
+    // variadic if `nfixed` is given.
+    fn prepare<I>(args: I, nfixed: Option<usize>, result: Type, abi: FfiAbi) -> Self
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        ffi_expect!(
+            Cif::try_prepare(args, nfixed, result, abi),
+            "Cif::new: libffi rejected the CIF"
+        )
+    }
+
+    // As `prepare`, but returns libffi’s error.
+    fn try_prepare<I>(
+        args: I,
+        nfixed: Option<usize>,
+        result: Type,
+        abi: FfiAbi,
+    ) -> Result<Self, crate::Error>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        Cif::try_prepare_array(types::TypeArray::try_new(args)?, nfixed, result, abi)
+    }
+
+    // As `try_prepare`, but with the argument types already in an array.
+    fn try_prepare_array(
+        args: types::TypeArray,
+        nfixed: Option<usize>,
+        result: Type,
+        abi: FfiAbi,
+    ) -> Result<Self, crate::Error> {
+        let cif = Cif::prep(&args, nfixed, &result, abi)?;
+
+        #[cfg(feature = "metrics")]
+        self::metrics::cif_created();
 
         // Note that cif retains references to args and result,
         // which is why we hold onto them here.
-        Cif { cif, args, result }
+        Ok(Cif {
+            cif,
+            args,
+            result,
+            nfixed,
+            #[cfg(feature = "metrics")]
+            counted: true,
+        })
+    }
+
+    // Prepares an `ffi_cif` referring to `args` and `result`.
+    fn prep(
+        args: &types::TypeArray,
+        nfixed: Option<usize>,
+        result: &Type,
+        abi: FfiAbi,
+    ) -> Result<low::ffi_cif, crate::Error> {
+        // libffi would try again to lay out a struct type it rejected,
+        // writing to a description that other threads may be reading.
+        if !result.desc().is_laid_out() || !args.iter().all(|arg| arg.is_laid_out()) {
+            return Err(crate::Error::BadTypedef);
+        }
+
+        let nargs = args.len();
+        let mut cif: low::ffi_cif = Default::default();
+
+        // The statuses are read directly, since `low` does not
+        // distinguish bad variable argument types.
+        let status = match nfixed {
+            None => unsafe {
+                crate::raw::ffi_prep_cif(
+                    &mut cif,
+                    abi,
+                    nargs as c_uint,
+                    result.as_raw_ptr(),
+                    args.as_raw_ptr(),
+                )
+            },
+            Some(nfixed) => {
+                ffi_assert!(
+                    nfixed <= nargs,
+                    "Cif::new_variadic: more fixed arguments than arguments"
+                );
+                unsafe {
+                    crate::raw::ffi_prep_cif_var(
+                        &mut cif,
+                        abi,
+                        nfixed as c_uint,
+                        nargs as c_uint,
+                        result.as_raw_ptr(),
+                        args.as_raw_ptr(),
+                    )
+                }
+            }
+        };
+        crate::Error::check(status)?;
+        Ok(cif)
     }
 
     /// Calls a function with the given arguments.
@@ -138,13 +647,16 @@ impl Cif {
     /// In particular, this method invokes function `fun` passing it
     /// arguments `args`, and returns the result.
     ///
+    /// A pointer result may be received as an `Option<NonNull<T>>` (or
+    /// `Option<&T>`), which decodes NULL as `None`.
+    ///
     /// # Safety
     ///
     /// There is no checking that the calling convention and types
     /// in the `Cif` match the actual calling convention and types of
     /// `fun`, nor that they match the types of `args`.
     pub unsafe fn call<R>(&self, fun: CodePtr, args: &[Arg]) -> R {
-        assert_eq!(
+        ffi_assert_eq!(
             self.cif.nargs as usize,
             args.len(),
             "Cif::call: passed wrong number of arguments"
@@ -157,9 +669,202 @@ impl Cif {
         )
     }
 
-    /// Sets the CIF to use the given calling convention.
+    /// Calls a function with the given arguments, returning the bytes
+    /// of its result along with the result type.
+    ///
+    /// The result is received in a buffer sized and aligned for the
+    /// CIF’s result type, so results whose type is only known at run
+    /// time, such as structs, can be passed on to hosts that marshal
+    /// them across process or language boundaries. Integer results
+    /// that libffi widens to [`ffi_arg`](low::ffi_arg) are narrowed
+    /// back to their own size, and a `void` result has no bytes.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::*;
+    ///
+    /// #[repr(C)]
+    /// struct Pair {
+    ///     a: u16,
+    ///     b: u16,
+    /// }
+    ///
+    /// extern "C" fn pair(a: u16) -> Pair {
+    ///     Pair { a, b: a + 1 }
+    /// }
+    ///
+    /// let cif = Cif::new(vec![Type::u16()], Type::structure(vec![Type::u16(), Type::u16()]));
+    /// let (bytes, _ty) = unsafe { cif.call_to_bytes(CodePtr(pair as *mut _), &[arg(&7u16)]) };
+    ///
+    /// assert_eq!(4, bytes.len());
+    /// assert_eq!(7, u16::from_ne_bytes([bytes[0], bytes[1]]));
+    /// assert_eq!(8, u16::from_ne_bytes([bytes[2], bytes[3]]));
+    /// ```
+    pub unsafe fn call_to_bytes(&self, fun: CodePtr, args: &[Arg]) -> (Box<[u8]>, Type) {
+        self.call_owned(fun, args).into_parts()
+    }
+
+    /// Calls a function with the given arguments, returning its result
+    /// in a new [`TypedBuffer`].
+    ///
+    /// The buffer is sized and aligned for the CIF’s result type, so
+    /// functions returning structs can be called without a Rust type for
+    /// the result; its fields can then be read by offset, or the whole
+    /// value passed on as an argument. Integer results that libffi
+    /// widens to [`ffi_arg`](low::ffi_arg) are narrowed back to their own
+    /// size.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::*;
+    ///
+    /// #[repr(C)]
+    /// struct Stats {
+    ///     count: u64,
+    ///     sum: f64,
+    ///     min: f64,
+    ///     max: f64,
+    /// }
+    ///
+    /// extern "C" fn stats(n: u64) -> Stats {
+    ///     Stats { count: n, sum: 10.0, min: 1.0, max: 4.0 }
+    /// }
+    ///
+    /// let result = Type::structure_named(vec![
+    ///     ("count", Type::u64()),
+    ///     ("sum", Type::f64()),
+    ///     ("min", Type::f64()),
+    ///     ("max", Type::f64()),
+    /// ]);
+    /// let cif = Cif::new(vec![Type::u64()], result);
+    /// let value = unsafe { cif.call_owned(CodePtr(stats as *mut _), &[arg(&4u64)]) };
+    ///
+    /// assert_eq!(32, value.size());
+    /// assert_eq!(4, value.get_field::<u64>("count").unwrap());
+    /// assert_eq!(4.0, value.get_field::<f64>("max").unwrap());
+    /// ```
+    pub unsafe fn call_owned(&self, fun: CodePtr, args: &[Arg]) -> TypedBuffer {
+        let mut result = TypedBuffer::new(self.result.clone());
+        self.call_into(fun, args, &mut result);
+        result
+    }
+
+    /// Calls a function with the given arguments, storing its result
+    /// in `result`.
+    ///
+    /// Integer results that libffi widens to [`ffi_arg`](low::ffi_arg)
+    /// are narrowed back to their own size.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of `result` differs from the size of the
+    /// CIF’s result type.
+    pub unsafe fn call_into(&self, fun: CodePtr, args: &[Arg], result: &mut TypedBuffer) {
+        ffi_assert_eq!(
+            self.cif.nargs as usize,
+            args.len(),
+            "Cif::call_into: passed wrong number of arguments"
+        );
+        ffi_assert_eq!(
+            buffer::value_size(&self.result),
+            result.size(),
+            "Cif::call_into: result buffer has the wrong size"
+        );
+
+        crate::raw::ffi_call(
+            &self.cif as *const _ as *mut _,
+            Some(*fun.as_safe_fun()),
+            result.as_mut_ptr(),
+            args.as_ptr() as *mut *mut c_void,
+        );
+        result.narrow_result();
+    }
+
+    /// Sets the CIF to use the given calling convention, preparing it
+    /// again for the convention.
+    ///
+    /// An [`Abi`] converts into libffi’s number for it with
+    /// [`Abi::as_raw`], or a CIF may be created for it directly with
+    /// [`Cif::with_abi`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects the calling convention, or the types
+    /// for it.
     pub fn set_abi(&mut self, abi: FfiAbi) {
-        self.cif.abi = abi;
+        self.cif = ffi_expect!(
+            Cif::prep(&self.args, self.nfixed, &self.result, abi),
+            "Cif::set_abi: libffi rejected the calling convention"
+        );
+    }
+
+    /// Gets libffi’s number for the CIF’s calling convention; see
+    /// [`Abi::from_raw`].
+    pub fn abi(&self) -> FfiAbi {
+        self.cif.abi
+    }
+
+    /// Creates a CIF like this one, with the same calling convention and
+    /// result type, but with an extra first argument of type `arg`.
+    ///
+    /// This is the signature of a wrapper that takes a context pointer
+    /// before the arguments of the function it wraps. The types of the
+    /// remaining arguments are copied from this CIF. The extra argument
+    /// of a variadic CIF is fixed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects `arg`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Cif, Type};
+    ///
+    /// let cif = Cif::new(vec![Type::i32(), Type::f64()], Type::f64());
+    /// let wrapper = cif.with_prepended_arg(Type::pointer());
+    ///
+    /// assert_eq!(Some((8, 8)), wrapper.arg_layout(2));
+    /// assert_eq!(cif.return_layout(), wrapper.return_layout());
+    /// ```
+    pub fn with_prepended_arg(&self, arg: Type) -> Cif {
+        let args = types::TypeArray::new(Some(arg)).concat(&self.args);
+        self.with_args(args, self.nfixed.map(|nfixed| nfixed + 1))
+    }
+
+    /// Creates a CIF like this one, with the same calling convention and
+    /// result type, but with an extra last argument of type `arg`.
+    ///
+    /// The extra argument of a variadic CIF is a variable argument.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libffi rejects `arg`.
+    pub fn with_appended_arg(&self, arg: Type) -> Cif {
+        let args = self.args.concat(&types::TypeArray::new(Some(arg)));
+        self.with_args(args, self.nfixed)
+    }
+
+    // Creates a CIF like this one with the given argument types.
+    fn with_args(&self, args: types::TypeArray, nfixed: Option<usize>) -> Cif {
+        ffi_expect!(
+            Cif::try_prepare_array(args, nfixed, self.result.clone(), self.cif.abi),
+            "Cif::with_args: libffi rejected the added argument"
+        )
     }
 
     /// Gets a raw pointer to the underlying [`low::ffi_cif`].
@@ -169,8 +874,83 @@ impl Cif {
     pub fn as_raw_ptr(&self) -> *mut low::ffi_cif {
         &self.cif as *const _ as *mut _
     }
+
+    /// A conservative estimate of the stack space, in bytes, that a call
+    /// uses beyond its argument frame: the frames of [`Cif::call`] and of
+    /// libffi’s own entry point, and the register save area libffi
+    /// builds for the callee.
+    pub const CALL_OVERHEAD: usize = 64 * mem::size_of::<usize>();
+
+    /// Gets the size in bytes of the stack frame libffi prepared for the
+    /// arguments of a call, as computed by `ffi_prep_cif`.
+    ///
+    /// Arguments passed in registers take no space in the frame, so this
+    /// may be zero.
+    pub fn frame_size(&self) -> usize {
+        self.cif.bytes as usize
+    }
+
+    /// Estimates the stack space, in bytes, that calling through this
+    /// CIF needs, for instance before calling on a coroutine with a small
+    /// stack.
+    ///
+    /// This is the [frame size](Cif::frame_size), plus space for the
+    /// result, plus [`Cif::CALL_OVERHEAD`]. The callee’s own stack usage
+    /// is not included.
+    pub fn estimated_stack_usage(&self) -> usize {
+        let result = (self.result.layout().0 + 15) & !15;
+        self.frame_size() + result + Self::CALL_OVERHEAD
+    }
+
+    /// Gets the size and alignment of the CIF’s result type, in bytes,
+    /// as computed by `ffi_prep_cif`.
+    ///
+    /// Integer results that libffi widens to [`ffi_arg`](low::ffi_arg)
+    /// have their own size here; a buffer that receives the result
+    /// directly from [`low::call`] must be at least as large as
+    /// `ffi_arg`.
+    pub fn return_layout(&self) -> (usize, usize) {
+        self.result.layout()
+    }
+
+    /// Gets the size and alignment of the type of argument `index`, in
+    /// bytes, as computed by `ffi_prep_cif`, or `None` if the CIF has no
+    /// such argument.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Cif, Type};
+    ///
+    /// #[repr(C)]
+    /// struct Rect {
+    ///     origin: [f32; 2],
+    ///     size: [f32; 2],
+    /// }
+    ///
+    /// let rect = Type::structure(vec![Type::array(Type::f32(), 2), Type::array(Type::f32(), 2)]);
+    /// let cif = Cif::new(vec![Type::pointer(), rect], Type::void());
+    ///
+    /// assert_eq!(Some((16, 4)), cif.arg_layout(1));
+    /// assert_eq!(std::mem::size_of::<Rect>(), cif.arg_layout(1).unwrap().0);
+    /// assert_eq!(None, cif.arg_layout(2));
+    /// ```
+    pub fn arg_layout(&self, index: usize) -> Option<(usize, usize)> {
+        if index >= self.cif.nargs as usize {
+            return None;
+        }
+        // The CIF has been prepared, so its argument types have been
+        // laid out, and the array has `nargs` elements.
+        unsafe {
+            let raw = *self.args.as_raw_ptr().add(index);
+            Some(((*raw).size, (*raw).alignment as usize))
+        }
+    }
 }
 
+// Closures need `std`: they check the threads they are called on,
+// catch panics and pool their allocations.
+
 /// Represents a closure callable from C.
 ///
 /// A libffi closure captures a `void*` (“userdata”) and passes it to a
@@ -223,22 +1003,218 @@ impl Cif {
 /// assert_eq!(11, fun(5, 6));
 /// assert_eq!(12, fun(5, 7));
 /// ```
+///
+/// # Threads
+///
+/// A closure made by [`Closure::new`] or [`Closure::new_mut`] may only
+/// be used on the thread that created it. One made by
+/// [`Closure::new_sync`], whose userdata is [`Sync`], is [`Send`] and
+/// [`Sync`], so it may be moved to, and called from, other threads at
+/// once. One made by [`Closure::new_mut_send`], whose userdata is
+/// [`Send`], is [`Send`] but not [`Sync`], since concurrent calls would
+/// alias its mutable userdata. The type parameter `S` records which kind
+/// a closure is.
+#[cfg(feature = "std")]
+#[cfg_attr(not(feature = "min-size"), derive(Debug))]
+pub struct Closure<'a, S = Local> {
+    _trampoline: Owned,
+    code: CodePtr,
+    _marker: PhantomData<(&'a (), S)>,
+}
+
+/// Marks a [`Closure`] that may only be used on the thread that created
+/// it.
+#[cfg(feature = "std")]
 #[derive(Debug)]
-pub struct Closure<'a> {
-    _cif: Box<Cif>,
+pub enum Local {}
+
+/// Marks a [`Closure`] whose userdata is [`Sync`], so that it may be
+/// sent to and called from any thread.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum Shared {}
+
+/// Marks a [`Closure`] whose mutable userdata is [`Send`], so that it
+/// may be sent to another thread, but not called from several at once.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum Movable {}
+
+// The executable page of a closure is only written by `prep_closure`,
+// before the closure is returned, and libffi’s allocator may be used
+// from any thread, so a closure may be called from and dropped on any
+// thread so long as its userdata allows it. The CIF, the statistics and
+// the registries are read-only or synchronized.
+#[cfg(feature = "std")]
+unsafe impl Send for Closure<'_, Shared> {}
+#[cfg(feature = "std")]
+unsafe impl Sync for Closure<'_, Shared> {}
+#[cfg(feature = "std")]
+unsafe impl Send for Closure<'_, Movable> {}
+
+// What a closure keeps to record its latency statistics, if enabled.
+#[cfg(feature = "stats")]
+type Stats = Box<stats::Instrumented>;
+#[cfg(all(feature = "std", not(feature = "stats")))]
+#[derive(Debug)]
+struct Stats;
+
+// What a closure’s trampoline uses while it may be called: its
+// executable memory, the CIF libffi reads and the userdata of the
+// thread-checking, timing and counting trampolines, where enabled.
+// Dropping it frees the memory, or returns it to the pool it came from.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub(crate) struct Trampoline {
     alloc: *mut low::ffi_closure,
-    code: CodePtr,
-    _marker: PhantomData<&'a ()>,
+    _cif: Box<Cif>,
+    _affinity: Option<Box<affinity::Pinned>>,
+    _stats: Stats,
+    _guard: Guard,
+    pool: Option<pool::Slot>,
 }
 
-impl<'a> Drop for Closure<'a> {
+#[cfg(feature = "std")]
+impl Drop for Trampoline {
     fn drop(&mut self) {
         unsafe {
-            low::closure_free(self.alloc);
+            release(self.alloc, self.pool.take());
         }
     }
 }
 
+// Frees the closure allocation `alloc`, or returns it to its pool.
+#[cfg(feature = "std")]
+unsafe fn release(alloc: *mut low::ffi_closure, pool: Option<pool::Slot>) {
+    match pool {
+        Some(slot) => slot.release(alloc),
+        None => low::closure_free(alloc),
+    }
+}
+
+// How a closure owns its trampoline. With `deferred-reclaim`, dropping
+// it hands the trampoline to the background reclaimer rather than
+// freeing it.
+#[cfg(all(feature = "std", not(feature = "deferred-reclaim")))]
+type Owned = Trampoline;
+#[cfg(feature = "deferred-reclaim")]
+type Owned = reclaim::Deferred;
+
+#[cfg(feature = "std")]
+fn own(trampoline: Trampoline) -> Owned {
+    #[cfg(not(feature = "deferred-reclaim"))]
+    return trampoline;
+    #[cfg(feature = "deferred-reclaim")]
+    return reclaim::Deferred::new(trampoline);
+}
+
+// What a closure keeps to count its calls in progress, if enabled.
+#[cfg(feature = "safe-reclaim")]
+type Guard = Box<reclaim::Guard>;
+#[cfg(all(feature = "std", not(feature = "safe-reclaim")))]
+#[derive(Debug)]
+struct Guard;
+
+/// Initializes the closure at `alloc` to call `callback` with
+/// `userdata`, returning the trampoline that owns it.
+///
+/// Given an `affinity` policy, the closure calls a trampoline that
+/// checks it is called on the current thread. With the `stats` feature
+/// enabled, it calls one that times `callback`, and with `safe-reclaim`,
+/// one that counts the calls in progress.
+///
+/// If libffi fails to initialize the closure, `alloc` is left for the
+/// caller to free.
+#[cfg(feature = "std")]
+unsafe fn prep_closure(
+    alloc: *mut low::ffi_closure,
+    cif: Box<Cif>,
+    callback: low::RawCallback,
+    userdata: *mut c_void,
+    code: CodePtr,
+    affinity: Option<ErrorPolicy<bridge::Erased>>,
+) -> Result<Trampoline, crate::Error> {
+    #[cfg(feature = "trampoline-registry")]
+    let original = callback;
+
+    let (pinned, callback, userdata) = match affinity {
+        Some(policy) => {
+            let pinned = affinity::Pinned::new(callback, userdata, policy);
+            let pointer = &*pinned as *const affinity::Pinned as *mut c_void;
+            let trampoline = mem::transmute::<
+                low::Callback<affinity::Pinned, c_void>,
+                low::RawCallback,
+            >(affinity::pinned);
+            (Some(pinned), trampoline, pointer)
+        }
+        None => (None, callback, userdata),
+    };
+
+    #[cfg(feature = "stats")]
+    let (stats, callback, userdata) = {
+        let instrumented = stats::Instrumented::new(callback, userdata);
+        let pointer = &*instrumented as *const stats::Instrumented as *mut c_void;
+        let trampoline = mem::transmute::<
+            low::Callback<stats::Instrumented, c_void>,
+            low::RawCallback,
+        >(stats::trampoline);
+        (instrumented, trampoline, pointer)
+    };
+    #[cfg(not(feature = "stats"))]
+    let stats = Stats;
+
+    #[cfg(feature = "safe-reclaim")]
+    let (guard, callback, userdata) = {
+        let guard = reclaim::Guard::new(callback, userdata);
+        let pointer = &*guard as *const reclaim::Guard as *mut c_void;
+        let trampoline = mem::transmute::<low::Callback<reclaim::Guard, c_void>, low::RawCallback>(
+            reclaim::guarded,
+        );
+        (guard, trampoline, pointer)
+    };
+    #[cfg(not(feature = "safe-reclaim"))]
+    let guard = Guard;
+
+    let callback = mem::transmute::<low::RawCallback, low::Callback<c_void, c_void>>(callback);
+    low::prep_closure(alloc, cif.as_raw_ptr(), callback, userdata, code)?;
+
+    #[cfg(feature = "trampoline-registry")]
+    trampolines::register(code, &cif, original);
+    #[cfg(feature = "metrics")]
+    self::metrics::closure_created();
+
+    Ok(Trampoline {
+        alloc,
+        _cif: cif,
+        _affinity: pinned,
+        _stats: stats,
+        _guard: guard,
+        pool: None,
+    })
+}
+
+#[cfg(all(feature = "std", feature = "min-size"))]
+opaque_debug!(Closure<'a, S>);
+
+// The trampoline is freed, or retired, when the field is dropped.
+#[cfg(feature = "std")]
+impl<'a, S> Drop for Closure<'a, S> {
+    fn drop(&mut self) {
+        #[cfg(feature = "trampoline-registry")]
+        trampolines::deregister(self.code);
+        #[cfg(feature = "metrics")]
+        self::metrics::closure_dropped();
+    }
+}
+
+// Whether `addr` lies within the trampoline at `code`.
+#[cfg(feature = "std")]
+fn trampoline_contains(code: CodePtr, addr: *const c_void) -> bool {
+    let start = code.as_ptr() as usize;
+    (start..start + crate::raw::FFI_TRAMPOLINE_SIZE).contains(&(addr as usize))
+}
+
+#[cfg(feature = "std")]
 impl<'a> Closure<'a> {
     /// Creates a new closure with immutable userdata.
     ///
@@ -254,25 +1230,13 @@ impl<'a> Closure<'a> {
     ///
     /// The new closure.
     pub fn new<U, R>(cif: Cif, callback: Callback<U, R>, userdata: &'a U) -> Self {
-        let cif = Box::new(cif);
-        let (alloc, code) = low::closure_alloc();
-
         unsafe {
-            low::prep_closure(
-                alloc,
-                cif.as_raw_ptr(),
-                callback,
+            Closure::make(
+                cif,
+                mem::transmute::<Callback<U, R>, low::RawCallback>(callback),
                 userdata as *const U,
-                code,
+                None,
             )
-            .unwrap();
-        }
-
-        Closure {
-            _cif: cif,
-            alloc,
-            code,
-            _marker: PhantomData,
         }
     }
 
@@ -290,20 +1254,204 @@ impl<'a> Closure<'a> {
     ///
     /// The new closure.
     pub fn new_mut<U, R>(cif: Cif, callback: CallbackMut<U, R>, userdata: &'a mut U) -> Self {
-        let cif = Box::new(cif);
-        let (alloc, code) = low::closure_alloc();
+        unsafe {
+            Closure::make(
+                cif,
+                mem::transmute::<CallbackMut<U, R>, low::RawCallback>(callback),
+                userdata as *mut U,
+                None,
+            )
+        }
+    }
+
+    /// Creates a new closure with immutable userdata, as for
+    /// [`Closure::new`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if libffi cannot allocate or initialize the closure.
+    pub fn try_new<U, R>(
+        cif: Cif,
+        callback: Callback<U, R>,
+        userdata: &'a U,
+    ) -> Result<Self, crate::Error> {
+        unsafe {
+            Closure::try_make(
+                cif,
+                mem::transmute::<Callback<U, R>, low::RawCallback>(callback),
+                userdata as *const U,
+                None,
+            )
+        }
+    }
+
+    /// Creates a new closure with mutable userdata, as for
+    /// [`Closure::new_mut`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if libffi cannot allocate or initialize the closure.
+    pub fn try_new_mut<U, R>(
+        cif: Cif,
+        callback: CallbackMut<U, R>,
+        userdata: &'a mut U,
+    ) -> Result<Self, crate::Error> {
+        unsafe {
+            Closure::try_make(
+                cif,
+                mem::transmute::<CallbackMut<U, R>, low::RawCallback>(callback),
+                userdata as *mut U,
+                None,
+            )
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> Closure<'a, Shared> {
+    /// Creates a new closure with immutable userdata that may be sent to
+    /// and called from any thread, as for a callback that C invokes on
+    /// threads of its own.
+    ///
+    /// The arguments are as for [`Closure::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::os::raw::c_void;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// use libffi::low::ffi_cif;
+    /// use libffi::middle::{Cif, Closure, Type};
+    ///
+    /// unsafe extern "C" fn count(
+    ///     _cif: &ffi_cif,
+    ///     _result: &mut c_void,
+    ///     _args: *const *const c_void,
+    ///     calls: &AtomicU64,
+    /// ) {
+    ///     calls.fetch_add(1, Ordering::Relaxed);
+    /// }
+    ///
+    /// let calls = AtomicU64::new(0);
+    /// let closure = Closure::new_sync(Cif::new(vec![], Type::void()), count, &calls);
+    /// let fun: &unsafe extern "C" fn() = unsafe { closure.instantiate_code_ptr() };
+    ///
+    /// std::thread::scope(|scope| {
+    ///     for _ in 0..4 {
+    ///         scope.spawn(|| unsafe { fun() });
+    ///     }
+    /// });
+    /// assert_eq!(4, calls.load(Ordering::Relaxed));
+    /// ```
+    pub fn new_sync<U: Sync, R>(cif: Cif, callback: Callback<U, R>, userdata: &'a U) -> Self {
+        unsafe {
+            Closure::make(
+                cif,
+                mem::transmute::<Callback<U, R>, low::RawCallback>(callback),
+                userdata as *const U,
+                None,
+            )
+        }
+    }
+}
 
+#[cfg(feature = "std")]
+impl<'a> Closure<'a, Movable> {
+    /// Creates a new closure with mutable userdata that may be sent to
+    /// another thread.
+    ///
+    /// The arguments are as for [`Closure::new_mut`]. The closure is not
+    /// [`Sync`], and must not be called from several threads at once.
+    ///
+    /// ```compile_fail
+    /// use libffi::middle::{Closure, Movable};
+    ///
+    /// fn share<T: Sync>(_: &T) {}
+    ///
+    /// fn check(closure: &Closure<'_, Movable>) {
+    ///     share(closure); // error: `Closure<'_, Movable>` is not `Sync`
+    /// }
+    /// ```
+    pub fn new_mut_send<U: Send, R>(
+        cif: Cif,
+        callback: CallbackMut<U, R>,
+        userdata: &'a mut U,
+    ) -> Self {
         unsafe {
-            low::prep_closure_mut(alloc, cif.as_raw_ptr(), callback, userdata as *mut U, code)
-                .unwrap();
+            Closure::make(
+                cif,
+                mem::transmute::<CallbackMut<U, R>, low::RawCallback>(callback),
+                userdata as *mut U,
+                None,
+            )
         }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, S> Closure<'a, S> {
+    // Creates a closure calling `callback` with `userdata`, which must
+    // live for `'a` and be valid to use as the kind of closure `S`
+    // describes, and checking the calling thread given an `affinity`
+    // policy.
+    unsafe fn make<U>(
+        cif: Cif,
+        callback: low::RawCallback,
+        userdata: *const U,
+        affinity: Option<ErrorPolicy<bridge::Erased>>,
+    ) -> Self {
+        ffi_expect!(
+            Closure::try_make(cif, callback, userdata, affinity),
+            "Closure::new: libffi failed to create the closure"
+        )
+    }
 
-        Closure {
-            _cif: cif,
+    // As `make`, but returns libffi’s error.
+    unsafe fn try_make<U>(
+        cif: Cif,
+        callback: low::RawCallback,
+        userdata: *const U,
+        affinity: Option<ErrorPolicy<bridge::Erased>>,
+    ) -> Result<Self, crate::Error> {
+        let (alloc, code) = low::closure_alloc();
+        if alloc.is_null() {
+            return Err(crate::Error::AllocationFailed);
+        }
+        let trampoline = prep_closure(
             alloc,
+            Box::new(cif),
+            callback,
+            userdata as *mut c_void,
+            code,
+            affinity,
+        )
+        .map_err(|error| {
+            low::closure_free(alloc);
+            error
+        })?;
+
+        Ok(Closure {
+            _trampoline: own(trampoline),
             code,
             _marker: PhantomData,
-        }
+        })
+    }
+
+    /// Gets the histogram of the latencies of this closure’s calls.
+    #[cfg(feature = "stats")]
+    pub fn latency_histogram(&self) -> std::sync::Arc<stats::LatencyHistogram> {
+        self._trampoline._stats.histogram().clone()
+    }
+
+    /// Returns whether `addr` lies within the closure’s trampoline, the
+    /// executable code its code pointer refers to.
+    ///
+    /// Crash reporters can use this to attribute a faulting program
+    /// counter to a closure. See also `middle::trampolines::lookup`,
+    /// enabled by the `trampoline-registry` feature.
+    pub fn contains_address(&self, addr: *const c_void) -> bool {
+        trampoline_contains(self.code, addr)
     }
 
     /// Obtains the callable code pointer for a closure.
@@ -332,29 +1480,68 @@ impl<'a> Closure<'a> {
 }
 
 /// The type of callback invoked by a [`ClosureOnce`].
+#[cfg(feature = "std")]
 pub type CallbackOnce<U, R> = CallbackMut<Option<U>, R>;
 
 /// A closure that owns needs-drop data.
 ///
 /// This allows the closure’s callback to take ownership of the data, in
 /// which case the userdata will be gone if called again.
-#[derive(Debug)]
+///
+/// Userdata of up to [`ClosureOnce::INLINE_SIZE`] bytes (as an
+/// `Option<U>`) is stored in the closure’s own allocation, so closures
+/// with small environments need no separate heap allocation; larger
+/// userdata is boxed.
+#[cfg(feature = "std")]
+#[cfg_attr(not(feature = "min-size"), derive(Debug))]
 pub struct ClosureOnce {
-    alloc: *mut low::ffi_closure,
+    trampoline: Owned,
     code: CodePtr,
-    _cif: Box<Cif>,
-    _userdata: Box<dyn Any>,
+    userdata: Userdata,
 }
 
+// Where a `ClosureOnce` keeps its userdata.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+enum Userdata {
+    // In the bytes that follow the closure, with the function that
+    // drops it there.
+    Inline(unsafe fn(*mut c_void)),
+    Boxed { _userdata: Box<dyn Any> },
+}
+
+#[cfg(feature = "std")]
+unsafe fn drop_inline<T>(userdata: *mut c_void) {
+    std::ptr::drop_in_place(userdata as *mut T);
+}
+
+#[cfg(all(feature = "std", feature = "min-size"))]
+opaque_debug!(ClosureOnce);
+
+#[cfg(feature = "std")]
 impl Drop for ClosureOnce {
     fn drop(&mut self) {
-        unsafe {
-            low::closure_free(self.alloc);
+        #[cfg(feature = "trampoline-registry")]
+        trampolines::deregister(self.code);
+        #[cfg(feature = "metrics")]
+        self::metrics::closure_dropped();
+        // Calls on other threads may still be using the userdata, so
+        // stop them and wait for them before dropping it.
+        #[cfg(feature = "safe-reclaim")]
+        self.trampoline._guard.kill();
+        // The trampoline is freed, or retired, when the field is
+        // dropped; the userdata is dropped here, on the closure’s thread.
+        if let Userdata::Inline(drop) = self.userdata {
+            unsafe { drop(self.trampoline.alloc.add(1) as *mut c_void) };
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl ClosureOnce {
+    /// The largest userdata, in bytes, that is stored inline.
+    pub const INLINE_SIZE: usize = 32;
+
     /// Creates a new closure with owned userdata.
     ///
     /// # Arguments
@@ -369,32 +1556,120 @@ impl ClosureOnce {
     ///
     /// The new closure.
     pub fn new<U: Any, R>(cif: Cif, callback: CallbackOnce<U, R>, userdata: U) -> Self {
-        let _cif = Box::new(cif);
-        let _userdata = Box::new(Some(userdata)) as Box<dyn Any>;
-        let (alloc, code) = low::closure_alloc();
+        ClosureOnce::make(cif, callback, userdata, None)
+    }
+
+    /// Creates a new closure with owned userdata, as for
+    /// [`ClosureOnce::new`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if libffi cannot allocate or initialize the closure, in
+    /// which case `userdata` is dropped.
+    pub fn try_new<U: Any, R>(
+        cif: Cif,
+        callback: CallbackOnce<U, R>,
+        userdata: U,
+    ) -> Result<Self, crate::Error> {
+        ClosureOnce::try_make(cif, callback, userdata, None)
+    }
+
+    // Creates a new closure with owned userdata, checking the calling
+    // thread given an `affinity` policy.
+    fn make<U: Any, R>(
+        cif: Cif,
+        callback: CallbackOnce<U, R>,
+        userdata: U,
+        affinity: Option<ErrorPolicy<bridge::Erased>>,
+    ) -> Self {
+        ffi_expect!(
+            ClosureOnce::try_make(cif, callback, userdata, affinity),
+            "ClosureOnce::new: libffi failed to create the closure"
+        )
+    }
+
+    // As `make`, but returns libffi’s error, dropping the userdata.
+    fn try_make<U: Any, R>(
+        cif: Cif,
+        callback: CallbackOnce<U, R>,
+        userdata: U,
+        affinity: Option<ErrorPolicy<bridge::Erased>>,
+    ) -> Result<Self, crate::Error> {
+        let size = std::mem::size_of::<Option<U>>();
+        let inline = size <= Self::INLINE_SIZE
+            && std::mem::align_of::<Option<U>>() <= std::mem::align_of::<low::ffi_closure>();
+        let (alloc, code) = if inline {
+            low::closure_alloc_extra(size)
+        } else {
+            low::closure_alloc()
+        };
 
-        assert!(!alloc.is_null(), "closure_alloc: returned null");
+        if alloc.is_null() {
+            return Err(crate::Error::AllocationFailed);
+        }
 
-        {
-            let borrow = _userdata.downcast_ref::<Option<U>>().unwrap();
+        let (pointer, userdata) = if inline {
             unsafe {
-                low::prep_closure_mut(
-                    alloc,
-                    _cif.as_raw_ptr(),
-                    callback,
-                    borrow as *const _ as *mut _,
-                    code,
+                let slot = alloc.add(1) as *mut Option<U>;
+                slot.write(Some(userdata));
+                (
+                    slot as *mut c_void,
+                    Userdata::Inline(drop_inline::<Option<U>>),
                 )
-                .unwrap();
             }
-        }
+        } else {
+            let boxed = Box::new(Some(userdata)) as Box<dyn Any>;
+            let borrow = boxed.downcast_ref::<Option<U>>().unwrap();
+            (
+                borrow as *const _ as *mut c_void,
+                Userdata::Boxed { _userdata: boxed },
+            )
+        };
 
-        ClosureOnce {
-            alloc,
+        let trampoline = unsafe {
+            prep_closure(
+                alloc,
+                Box::new(cif),
+                std::mem::transmute::<CallbackOnce<U, R>, low::RawCallback>(callback),
+                pointer,
+                code,
+                affinity,
+            )
+            .map_err(|error| {
+                if let Userdata::Inline(drop) = userdata {
+                    drop(pointer);
+                }
+                low::closure_free(alloc);
+                error
+            })?
+        };
+
+        Ok(ClosureOnce {
+            trampoline: own(trampoline),
             code,
-            _cif,
-            _userdata,
-        }
+            userdata,
+        })
+    }
+
+    /// Returns whether the closure’s userdata is stored inline.
+    pub fn is_inline(&self) -> bool {
+        matches!(self.userdata, Userdata::Inline(_))
+    }
+
+    /// Gets the histogram of the latencies of this closure’s calls.
+    #[cfg(feature = "stats")]
+    pub fn latency_histogram(&self) -> std::sync::Arc<stats::LatencyHistogram> {
+        self.trampoline._stats.histogram().clone()
+    }
+
+    /// Returns whether `addr` lies within the closure’s trampoline, the
+    /// executable code its code pointer refers to.
+    ///
+    /// Crash reporters can use this to attribute a faulting program
+    /// counter to a closure. See also `middle::trampolines::lookup`,
+    /// enabled by the `trampoline-registry` feature.
+    pub fn contains_address(&self, addr: *const c_void) -> bool {
+        trampoline_contains(self.code, addr)
     }
 
     /// Obtains the callable code pointer for a closure.
@@ -422,7 +1697,7 @@ impl ClosureOnce {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
     use super::*;
     use crate::low;
@@ -430,7 +1705,7 @@ mod test {
 
     #[test]
     fn call() {
-        let cif = Cif::new(vec![Type::i64(), Type::i64()].into_iter(), Type::i64());
+        let cif = Cif::new(vec![Type::i64(), Type::i64()], Type::i64());
         let f = |m: i64, n: i64| -> i64 {
             unsafe { cif.call(CodePtr(add_it as *mut c_void), &[arg(&m), arg(&n)]) }
         };
@@ -444,9 +1719,134 @@ mod test {
         n + m
     }
 
+    extern "C" fn negate_i8(n: i8) -> i8 {
+        -n
+    }
+
+    extern "C" fn double_u16(n: u16) -> u16 {
+        n * 2
+    }
+
+    // libffi writes these results as whole `ffi_arg`s, which must not
+    // overflow the result.
+    #[test]
+    fn small_integer_results() {
+        let cif = Cif::new(vec![Type::i8()], Type::i8());
+        let n: i8 = unsafe { cif.call(CodePtr(negate_i8 as *mut _), &[arg(&5i8)]) };
+        assert_eq!(-5, n);
+
+        let cif = Cif::new(vec![Type::u16()], Type::u16());
+        let n: u16 = unsafe { cif.call(CodePtr(double_u16 as *mut _), &[arg(&300u16)]) };
+        assert_eq!(600, n);
+    }
+
+    #[test]
+    fn fallible_constructors() {
+        use crate::Error;
+
+        let empty = || Type::structure(vec![]);
+        assert_eq!(
+            Some(Error::BadTypedef),
+            Cif::try_new(vec![empty()], Type::i64()).err()
+        );
+        assert_eq!(Some(Error::BadTypedef), Cif::try_new(vec![], empty()).err());
+        assert_eq!(
+            Some(Error::BadAbi),
+            Builder::new().abi(9999 as FfiAbi).try_into_cif().err()
+        );
+        assert!(Error::BadAbi.to_string().contains("calling convention"));
+
+        let cif = Cif::try_new(vec![Type::i64(), Type::i64()], Type::i64()).unwrap();
+        let closure = Closure::try_new(cif.clone(), add_userdata, &5i64).unwrap();
+        let fun: &extern "C" fn(i64, i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(8, fun(1, 2));
+
+        let once = ClosureOnce::try_new(cif, add_taken, 7i64).unwrap();
+        let fun: &extern "C" fn(i64, i64) -> i64 = unsafe { once.instantiate_code_ptr() };
+        assert_eq!(10, fun(1, 2));
+    }
+
+    unsafe extern "C" fn add_userdata(
+        _cif: &low::ffi_cif,
+        result: &mut i64,
+        args: *const *const c_void,
+        userdata: &i64,
+    ) {
+        let args = args as *const &i64;
+        *result = **args + **args.add(1) + userdata;
+    }
+
+    unsafe extern "C" fn add_taken(
+        _cif: &low::ffi_cif,
+        result: &mut i64,
+        args: *const *const c_void,
+        userdata: &mut Option<i64>,
+    ) {
+        let args = args as *const &i64;
+        *result = **args + **args.add(1) + userdata.take().unwrap_or(0);
+    }
+
+    #[test]
+    fn call_with_args_macro() {
+        let cif = Cif::new(vec![Type::i64(), Type::pointer(), Type::i64()], Type::i64());
+        let m = 3i64;
+        let n: i64 = unsafe { cif.call(CodePtr(add_deref as *mut c_void), args![4i64, &m, m + 1]) };
+
+        assert_eq!(11, n);
+    }
+
+    extern "C" fn add_deref(n: i64, m: &i64, o: i64) -> i64 {
+        n + *m + o
+    }
+
+    #[test]
+    fn call_with_val_and_ptr() {
+        let cif = Cif::new(vec![Type::i64(), Type::pointer(), Type::i64()], Type::i64());
+        let m = 3i64;
+        let p: *const i64 = &m;
+        let mut o = 4i64;
+        let q: *mut i64 = &mut o;
+
+        let n: i64 = unsafe {
+            cif.call(
+                CodePtr(add_deref as *mut c_void),
+                &[Arg::val(&1i64), Arg::ptr(&p), Arg::val(&o)],
+            )
+        };
+        assert_eq!(8, n);
+
+        let n: i64 = unsafe {
+            cif.call(
+                CodePtr(add_deref as *mut c_void),
+                &[Arg::val(&1i64), Arg::ptr_mut(&q), Arg::val(&2i64)],
+            )
+        };
+        assert_eq!(7, n);
+    }
+
+    #[test]
+    fn nullable_pointers() {
+        let cif = Cif::new(vec![Type::pointer()], Type::pointer());
+        let fun = CodePtr(identity as *mut c_void);
+        let x = 5u32;
+
+        let some: Option<&u32> = Some(&x);
+        let none: Option<NonNull<u32>> = None;
+
+        let result: Option<NonNull<u32>> = unsafe { cif.call(fun, &[Arg::from(&some)]) };
+        assert_eq!(Some(NonNull::from(&x)), result);
+
+        let result: Option<&u32> = unsafe { cif.call(fun, &[Arg::from(&none)]) };
+        assert_eq!(None, result);
+    }
+
+    extern "C" fn identity(p: *const u32) -> *const u32 {
+        p
+    }
+
     #[test]
     fn closure() {
-        let cif = Cif::new(vec![Type::u64()].into_iter(), Type::u64());
+        let cif = Cif::new(vec![Type::u64()], Type::u64());
         let env: u64 = 5;
         let closure = Closure::new(cif, callback, &env);
 
@@ -468,7 +1868,7 @@ mod test {
 
     #[test]
     fn rust_lambda() {
-        let cif = Cif::new(vec![Type::u64(), Type::u64()].into_iter(), Type::u64());
+        let cif = Cif::new(vec![Type::u64(), Type::u64()], Type::u64());
         let env = |x: u64, y: u64| x + y;
         let closure = Closure::new(cif, callback2, &env);
 
@@ -490,6 +1890,76 @@ mod test {
         *result = userdata(arg1, arg2);
     }
 
+    #[test]
+    fn closure_once_userdata() {
+        use std::rc::Rc;
+
+        unsafe extern "C" fn sum<T: AsRef<[u64]>>(
+            _cif: &low::ffi_cif,
+            result: &mut u64,
+            _args: *const *const c_void,
+            userdata: &mut Option<(Rc<()>, T)>,
+        ) {
+            *result = userdata.as_ref().unwrap().1.as_ref().iter().sum();
+        }
+
+        let token = Rc::new(());
+        let small = ClosureOnce::new(
+            Cif::new(vec![], Type::u64()),
+            sum,
+            (token.clone(), [1u64, 2]),
+        );
+        let large = ClosureOnce::new(
+            Cif::new(vec![], Type::u64()),
+            sum,
+            (token.clone(), [1u64; 8]),
+        );
+        assert!(small.is_inline());
+        assert!(!large.is_inline());
+
+        let small_fun: &extern "C" fn() -> u64 = unsafe { small.instantiate_code_ptr() };
+        let large_fun: &extern "C" fn() -> u64 = unsafe { large.instantiate_code_ptr() };
+        assert_eq!(3, small_fun());
+        assert_eq!(8, large_fun());
+
+        assert_eq!(3, Rc::strong_count(&token));
+        drop(small);
+        drop(large);
+        assert_eq!(1, Rc::strong_count(&token));
+    }
+
+    extern "C" fn scaled_sum(scale: &i64, x: i64, y: i64) -> i64 {
+        scale * (x + y)
+    }
+
+    #[test]
+    fn prepended_and_appended_args() {
+        let cif = Cif::new(vec![Type::i64()], Type::i64());
+        let wrapper = cif
+            .with_appended_arg(Type::i64())
+            .with_prepended_arg(Type::pointer());
+        drop(cif);
+        assert_eq!(None, wrapper.fixed_args());
+
+        let scale = 3i64;
+        let n: i64 = unsafe {
+            wrapper.call(
+                CodePtr(scaled_sum as *mut _),
+                &[arg(&&scale), arg(&4i64), arg(&5i64)],
+            )
+        };
+        assert_eq!(27, n);
+
+        let variadic = Cif::new_variadic(vec![Type::pointer(), Type::i32()], 1, Type::i32());
+        assert_eq!(
+            Some(2),
+            variadic.with_prepended_arg(Type::pointer()).fixed_args()
+        );
+        let appended = variadic.with_appended_arg(Type::f64());
+        assert_eq!(Some(1), appended.fixed_args());
+        assert_eq!(Some((8, 8)), appended.arg_layout(2));
+    }
+
     #[test]
     fn clone_cif() {
         let cif = Cif::new(
@@ -500,8 +1970,7 @@ mod test {
                     Type::i64(),
                 ]),
                 Type::u64(),
-            ]
-            .into_iter(),
+            ],
             Type::u64(),
         );
         let clone_cif = cif.clone();
@@ -544,4 +2013,242 @@ mod test {
             assert_eq!(substruct_size, clone_substruct_size);
         }
     }
+
+    #[repr(C)]
+    struct Mixed {
+        a: u8,
+        b: f64,
+        c: u16,
+    }
+
+    extern "C" fn read_mixed(m: Mixed) -> f64 {
+        m.a as f64 + m.b + m.c as f64
+    }
+
+    #[test]
+    fn struct_arg_from_bytes() {
+        let mixed = Type::structure(vec![Type::u8(), Type::f64(), Type::u16()]);
+        let cif = Cif::new(vec![mixed.clone()], Type::f64());
+
+        // Build the struct’s bytes by hand in a buffer aligned for it.
+        let mut storage = [0u64; 3];
+        let bytes = unsafe { std::slice::from_raw_parts_mut(storage.as_mut_ptr() as *mut u8, 24) };
+        bytes[0] = 1;
+        bytes[8..16].copy_from_slice(&0.5f64.to_ne_bytes());
+        bytes[16..18].copy_from_slice(&300u16.to_ne_bytes());
+
+        let arg = Arg::from_bytes(bytes, &mixed).unwrap();
+        let n: f64 = unsafe { cif.call(CodePtr(read_mixed as *mut c_void), &[arg]) };
+        assert_eq!(301.5, n);
+
+        assert_eq!(
+            Err(BytesError::Size {
+                expected: 24,
+                actual: 16
+            }),
+            Arg::from_bytes(&bytes[..16], &mixed).map(drop)
+        );
+        assert_eq!(
+            Err(BytesError::Alignment { expected: 8 }),
+            Arg::from_bytes(&bytes[1..9], &Type::u64()).map(drop)
+        );
+    }
+
+    #[test]
+    fn unused_struct_types_are_laid_out() {
+        let mixed = Type::structure(vec![Type::u8(), Type::f64(), Type::u16()]);
+        assert_eq!(
+            (std::mem::size_of::<Mixed>(), std::mem::align_of::<Mixed>()),
+            mixed.layout()
+        );
+    }
+
+    extern "C" fn make_mixed(a: u8) -> Mixed {
+        Mixed { a, b: 2.5, c: 7 }
+    }
+
+    extern "C" fn negate(n: i8) -> i8 {
+        -n
+    }
+
+    extern "C" fn nothing() {}
+
+    #[test]
+    fn results_to_bytes() {
+        let mixed = Type::structure(vec![Type::u8(), Type::f64(), Type::u16()]);
+        let cif = Cif::new(vec![Type::u8()], mixed);
+        let (bytes, ty) =
+            unsafe { cif.call_to_bytes(CodePtr(make_mixed as *mut c_void), &[arg(&3u8)]) };
+        assert_eq!(ty.layout(), (bytes.len(), 8));
+        let value = unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const Mixed) };
+        assert_eq!((3, 2.5, 7), (value.a, value.b, value.c));
+
+        let cif = Cif::new(vec![Type::i8()], Type::i8());
+        let (bytes, _) = unsafe { cif.call_to_bytes(CodePtr(negate as *mut c_void), &[arg(&5i8)]) };
+        assert_eq!(&[-5i8 as u8][..], &*bytes);
+
+        let cif = Cif::new(vec![], Type::void());
+        let (bytes, _) = unsafe { cif.call_to_bytes(CodePtr(nothing as *mut c_void), &[]) };
+        assert!(bytes.is_empty());
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Large {
+        words: [u64; 6],
+        tag: u8,
+    }
+
+    extern "C" fn make_large(seed: u64) -> Large {
+        let mut words = [0; 6];
+        words
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, w)| *w = seed * i as u64);
+        Large { words, tag: 9 }
+    }
+
+    #[test]
+    fn owned_results() {
+        let large = Type::structure(vec![Type::array(Type::u64(), 6), Type::u8()]);
+        let cif = Cif::new(vec![Type::u64()], large);
+        let value = unsafe { cif.call_owned(CodePtr(make_large as *mut c_void), &[arg(&3u64)]) };
+
+        assert_eq!(cif.return_layout(), (value.size(), 8));
+        assert_eq!(0, value.as_ptr() as usize % std::mem::align_of::<Large>());
+        assert_eq!(make_large(3), unsafe { *(value.as_ptr() as *const Large) });
+        assert_eq!(Some(&[9][..]), value.field_bytes(1));
+
+        let cif = Cif::new(vec![Type::i8()], Type::i8());
+        let value = unsafe { cif.call_owned(CodePtr(negate as *mut c_void), &[arg(&-7i8)]) };
+        assert_eq!(&[7][..], value.as_bytes());
+    }
+
+    #[test]
+    fn contains_address() {
+        let cif = Cif::new(vec![Type::u64(), Type::u64()], Type::u64());
+        let closure = Closure::new(cif, callback, &5u64);
+        let start = *closure.code_ptr() as usize;
+        assert!(closure.contains_address(start as *const c_void));
+        assert!(!closure.contains_address((start - 1) as *const c_void));
+        assert!(
+            !closure.contains_address((start + crate::raw::FFI_TRAMPOLINE_SIZE) as *const c_void)
+        );
+    }
+
+    #[test]
+    fn estimated_stack_usage() {
+        let few = Cif::new(vec![Type::u64()], Type::void());
+        let many = Cif::new(vec![Type::u64(); 16], Type::void());
+        assert!(many.frame_size() > few.frame_size());
+        assert!(many.estimated_stack_usage() >= many.frame_size() + Cif::CALL_OVERHEAD);
+
+        let large = Type::structure(vec![Type::u64(); 8]);
+        let returning = Cif::new(vec![Type::u64()], large);
+        assert!(returning.estimated_stack_usage() > few.estimated_stack_usage());
+    }
+
+    #[test]
+    fn layouts() {
+        #[repr(C)]
+        struct Header {
+            tag: u8,
+            len: u32,
+            flags: u16,
+        }
+
+        let header = Type::structure(vec![Type::u8(), Type::u32(), Type::u16()]);
+        let expected = (mem::size_of::<Header>(), mem::align_of::<Header>());
+        assert_eq!(expected, header.layout());
+
+        let cif = Cif::new(vec![Type::i8(), header.clone(), Type::f64()], header);
+        assert_eq!(Some((1, 1)), cif.arg_layout(0));
+        assert_eq!(Some(expected), cif.arg_layout(1));
+        assert_eq!(Some((8, mem::align_of::<f64>())), cif.arg_layout(2));
+        assert_eq!(None, cif.arg_layout(3));
+        assert_eq!(expected, cif.return_layout());
+        assert_eq!(expected, cif.clone().return_layout());
+
+        let cif = Cif::new(vec![], Type::u16());
+        assert_eq!(None, cif.arg_layout(0));
+        assert_eq!((2, 2), cif.return_layout());
+    }
+
+    unsafe extern "C" fn accumulate(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        args: *const *const c_void,
+        total: &mut u64,
+    ) {
+        *total += *(*args as *const u64);
+        *result = *total;
+    }
+
+    #[test]
+    fn closures_on_other_threads() {
+        let cif = Cif::new(vec![Type::u64(), Type::u64()], Type::u64());
+        let shared = Closure::new_sync(cif, callback, &5u64);
+        std::thread::scope(|scope| {
+            let calls: Vec<_> = (0..4u64)
+                .map(|i| {
+                    let shared = &shared;
+                    scope.spawn(move || {
+                        let fun: &extern "C" fn(u64, u64) -> u64 =
+                            unsafe { shared.instantiate_code_ptr() };
+                        fun(i, 1)
+                    })
+                })
+                .collect();
+            for (i, call) in calls.into_iter().enumerate() {
+                assert_eq!(i as u64 + 5, call.join().unwrap());
+            }
+        });
+
+        let mut total = 0u64;
+        let cif = Cif::new(vec![Type::u64()], Type::u64());
+        let movable = Closure::new_mut_send(cif, accumulate, &mut total);
+        let last = std::thread::scope(|scope| {
+            scope
+                .spawn(move || {
+                    let fun: &extern "C" fn(u64) -> u64 = unsafe { movable.instantiate_code_ptr() };
+                    fun(3);
+                    fun(4)
+                })
+                .join()
+                .unwrap()
+        });
+        assert_eq!(7, last);
+        assert_eq!(7, total);
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Span {
+        start: u16,
+        len: f64,
+    }
+
+    extern "C" fn span_end(span: Span, scale: u8) -> u8 {
+        (span.start as f64 + span.len * scale as f64) as u8
+    }
+
+    unsafe extern "C" fn forward(
+        cif: &low::ffi_cif,
+        result: &mut low::ffi_arg,
+        args: *const *const c_void,
+        target: &CodePtr,
+    ) {
+        low::forward_call(cif, *target, args, result as *mut _ as *mut c_void);
+    }
+
+    #[test]
+    fn forwarded_calls() {
+        let span = Type::structure(vec![Type::u16(), Type::f64()]);
+        let cif = Cif::new(vec![span, Type::u8()], Type::u8());
+        let target = CodePtr(span_end as *mut c_void);
+        let closure = Closure::new(cif, forward, &target);
+        let fun: &extern "C" fn(Span, u8) -> u8 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(23, fun(Span { start: 3, len: 2.5 }, 8));
+        assert_eq!(3, fun(Span { start: 3, len: 2.5 }, 0));
+    }
 }
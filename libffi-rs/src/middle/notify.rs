@@ -0,0 +1,533 @@
+//! Closures freed by C code through a destroy-notify function.
+//!
+//! Many C APIs take, along with a callback and its `void*` data, a
+//! “destroy notify” function that they call with the data once they
+//! will no longer invoke the callback. [`DestroyNotify`] provides all
+//! three for a closure: C then owns the closure, and calling the
+//! destroy notify frees it along with its userdata.
+//!
+//! The userdata is dropped exactly once, after the last call of the
+//! callback has returned: if C calls the destroy notify from inside the
+//! callback (as APIs that let a callback unregister itself may), the
+//! userdata is dropped when the callback returns, and the closure
+//! itself is freed later, once the call has completely unwound: by the
+//! next closure created or released on the same thread, or on any
+//! thread once that one has exited.
+//!
+//! C may call the callback from any thread, so the userdata must be
+//! [`Send`]. Since the callback gets it mutably, calls are serialized:
+//! a call made while another is in progress waits for it to return, so
+//! the callback must not call its own closure.
+//!
+//! A [`SharedClosure`] can be registered with several C APIs at once,
+//! each of which releases its registration with the destroy notify.
+//! The closure stays alive until the last registration, and the last
+//! clone of the `SharedClosure` itself, are released.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::low::ffi_cif;
+//! use libffi::middle::notify::DestroyNotify;
+//! use libffi::middle::{Cif, Type};
+//! use std::os::raw::c_void;
+//!
+//! // A C API that stores a callback until it is unregistered.
+//! struct Registry {
+//!     callback: extern "C" fn(u32) -> u32,
+//!     data: *mut c_void,
+//!     destroy: unsafe extern "C" fn(*mut c_void),
+//! }
+//!
+//! impl Drop for Registry {
+//!     fn drop(&mut self) {
+//!         unsafe { (self.destroy)(self.data) }
+//!     }
+//! }
+//!
+//! unsafe extern "C" fn scale(
+//!     _cif: &ffi_cif,
+//!     result: &mut u32,
+//!     args: *const *const c_void,
+//!     factor: &mut Vec<u32>,
+//! ) {
+//!     *result = *(*args as *const u32) * factor[0];
+//! }
+//!
+//! let cif = Cif::new(vec![Type::u32()], Type::u32());
+//! let notify = DestroyNotify::new(cif, scale, vec![3]);
+//! let registry = Registry {
+//!     callback: unsafe { std::mem::transmute(notify.code.as_ptr()) },
+//!     data: notify.user_data,
+//!     destroy: notify.destroy,
+//! };
+//!
+//! assert_eq!(21, (registry.callback)(7));
+//! drop(registry); // Frees the closure and its `Vec`.
+//! ```
+
+use std::os::raw::c_void;
+use std::process;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+
+use super::{Cif, Closure, CodePtr};
+use crate::low::{self, CallbackMut};
+
+/// A closure along with the data pointer and destroy notify that free
+/// it.
+///
+/// Register all three with the C API. The destroy notify must be
+/// called exactly once, with `user_data`; the callback must not be
+/// called after it returns, except by a call of the callback that was
+/// already running. Calling the callback after the destroy notify, or
+/// calling the destroy notify twice, aborts the process if detected,
+/// and is otherwise undefined behavior.
+#[derive(Debug)]
+pub struct DestroyNotify {
+    /// The closure’s code pointer, to register as the callback.
+    pub code: CodePtr,
+    /// The data pointer to register, which is passed to `destroy`.
+    pub user_data: *mut c_void,
+    /// The destroy-notify function to register.
+    pub destroy: unsafe extern "C" fn(*mut c_void),
+}
+
+impl DestroyNotify {
+    /// Creates a closure owned by C code.
+    ///
+    /// # Arguments
+    ///
+    /// - `cif` — describes the calling convention and argument and
+    ///   result types
+    /// - `callback` — the function to call when the closure is invoked
+    /// - `userdata` — the value to pass to `callback` along with the
+    ///   arguments when the closure is called, which is dropped by the
+    ///   destroy notify
+    ///
+    /// For a one-shot callback, make `userdata` an `Option` and take
+    /// its contents in `callback`.
+    pub fn new<U: Send + 'static, R: 'static>(
+        cif: Cif,
+        callback: CallbackMut<U, R>,
+        userdata: U,
+    ) -> Self {
+        let shared = Shared::new(cif, callback, userdata, 1);
+        DestroyNotify {
+            code: unsafe { (*shared).code() },
+            user_data: shared as *mut c_void,
+            destroy: destroy::<U, R>,
+        }
+    }
+}
+
+/// A reference-counted closure that can be registered with several C
+/// APIs, each of which releases it through a destroy notify.
+///
+/// Cloning a `SharedClosure` and calling [`register`](Self::register)
+/// both add a reference; dropping a clone or calling the destroy notify
+/// of a registration releases one. The closure and its userdata are
+/// freed when the last reference is released.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::low::ffi_cif;
+/// use libffi::middle::notify::SharedClosure;
+/// use libffi::middle::{Cif, Type};
+/// use std::os::raw::c_void;
+///
+/// unsafe extern "C" fn next(
+///     _cif: &ffi_cif,
+///     result: &mut u32,
+///     _args: *const *const c_void,
+///     counter: &mut u32,
+/// ) {
+///     *counter += 1;
+///     *result = *counter;
+/// }
+///
+/// let closure = SharedClosure::new(Cif::new(vec![], Type::u32()), next, 0);
+/// let first = closure.register();
+/// let second = closure.register();
+/// drop(closure);
+///
+/// let fun: extern "C" fn() -> u32 = unsafe { std::mem::transmute(first.code.as_ptr()) };
+/// assert_eq!(1, fun());
+///
+/// unsafe { (first.destroy)(first.user_data) };
+/// assert_eq!(2, fun()); // Still registered through `second`.
+/// unsafe { (second.destroy)(second.user_data) };
+/// ```
+pub struct SharedClosure<U: Send + 'static, R: 'static> {
+    shared: *mut Shared<U, R>,
+}
+
+impl<U: Send + 'static, R: 'static> SharedClosure<U, R> {
+    /// Creates a shared closure.
+    ///
+    /// The arguments are as for [`DestroyNotify::new`].
+    pub fn new(cif: Cif, callback: CallbackMut<U, R>, userdata: U) -> Self {
+        SharedClosure {
+            shared: Shared::new(cif, callback, userdata, 1),
+        }
+    }
+
+    /// Obtains the callable code pointer for the closure.
+    ///
+    /// The code pointer remains valid while this `SharedClosure` or any
+    /// of its registrations is alive.
+    pub fn code_ptr(&self) -> CodePtr {
+        unsafe { (*self.shared).code() }
+    }
+
+    /// Adds a registration, which must be released by calling its
+    /// destroy notify.
+    pub fn register(&self) -> DestroyNotify {
+        unsafe { (*self.shared).retain() };
+        DestroyNotify {
+            code: self.code_ptr(),
+            user_data: self.shared as *mut c_void,
+            destroy: destroy::<U, R>,
+        }
+    }
+
+    /// Returns the number of clones and registrations not yet released.
+    pub fn ref_count(&self) -> usize {
+        unsafe { (*self.shared).lock().refs }
+    }
+}
+
+impl<U: Send + 'static, R: 'static> Clone for SharedClosure<U, R> {
+    fn clone(&self) -> Self {
+        unsafe { (*self.shared).retain() };
+        SharedClosure {
+            shared: self.shared,
+        }
+    }
+}
+
+impl<U: Send + 'static, R: 'static> Drop for SharedClosure<U, R> {
+    fn drop(&mut self) {
+        unsafe { Shared::release(self.shared) }
+    }
+}
+
+impl<U: Send + 'static, R: 'static> std::fmt::Debug for SharedClosure<U, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SharedClosure")
+            .field("code", &self.code_ptr())
+            .field("refs", &self.ref_count())
+            .finish()
+    }
+}
+
+// The closure, its userdata, and the number of live references to
+// them, shared by the trampoline and the destroy notify.
+pub(crate) struct Shared<U, R> {
+    // Set once, just after the closure is created.
+    closure: OnceLock<Closure<'static, super::Shared>>,
+    callback: CallbackMut<U, R>,
+    // Locked for each call, which gets the userdata mutably.
+    userdata: Mutex<U>,
+    state: Mutex<State>,
+}
+
+struct State {
+    // The number of registrations and other handles not yet released.
+    refs: usize,
+    // The number of calls of the callback in progress.
+    active: usize,
+}
+
+impl<U: Send + 'static, R: 'static> Shared<U, R> {
+    // Creates a closure with `refs` references to it, which must each
+    // be released with `release`.
+    pub(crate) fn new(
+        cif: Cif,
+        callback: CallbackMut<U, R>,
+        userdata: U,
+        refs: usize,
+    ) -> *mut Self {
+        free_retired();
+        let shared = Box::into_raw(Box::new(Shared {
+            closure: OnceLock::new(),
+            callback,
+            userdata: Mutex::new(userdata),
+            state: Mutex::new(State { refs, active: 0 }),
+        }));
+
+        unsafe {
+            // The trampoline takes a raw pointer, since it may free the
+            // closure before returning.
+            let trampoline = std::mem::transmute::<RawTrampoline<U, R>, low::Callback<Self, R>>(
+                trampoline::<U, R>,
+            );
+            let closure = Closure::new_sync(cif, trampoline, &*shared);
+            let _ = (*shared).closure.set(closure);
+        }
+        shared
+    }
+
+    pub(crate) unsafe fn code(&self) -> CodePtr {
+        CodePtr::from_fun(*self.closure.get().unwrap().code_ptr())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Adds a reference. There must be a reference already, so that the
+    // closure cannot have been freed.
+    fn retain(&self) {
+        self.lock().refs += 1;
+    }
+
+    // Releases a reference, freeing the closure if it was the last and
+    // no call is in progress.
+    pub(crate) unsafe fn release(this: *mut Self) {
+        free_retired();
+        let free = {
+            let mut state = (*this).lock();
+            if state.refs == 0 {
+                abort("destroy notify called too many times");
+            }
+            state.refs -= 1;
+            state.refs == 0 && state.active == 0
+        };
+        if free {
+            drop(Box::from_raw(this));
+        }
+    }
+}
+
+type RawTrampoline<U, R> =
+    unsafe extern "C" fn(&low::ffi_cif, &mut R, *const *const c_void, *mut Shared<U, R>);
+
+unsafe extern "C" fn trampoline<U: Send + 'static, R: 'static>(
+    cif: &low::ffi_cif,
+    result: &mut R,
+    args: *const *const c_void,
+    shared: *mut Shared<U, R>,
+) {
+    {
+        let mut state = (*shared).lock();
+        if state.refs == 0 {
+            abort("closure called after its destroy notify");
+        }
+        state.active += 1;
+    }
+
+    {
+        let mut userdata = (*shared).userdata.lock().unwrap_or_else(|e| e.into_inner());
+        ((*shared).callback)(cif, result, args, &mut userdata);
+    }
+
+    let free = {
+        let mut state = (*shared).lock();
+        state.active -= 1;
+        state.refs == 0 && state.active == 0
+    };
+    if free {
+        // The destroy notify was called during the callback. The closure
+        // may still be used on the way out of this call (to record its
+        // latency, for example), so it is only retired for now.
+        let mut shared = Box::from_raw(shared);
+        if let Some(closure) = shared.closure.take() {
+            retire(closure);
+        }
+    }
+}
+
+// A closure released during its own call, which is freed once that
+// call has returned.
+struct Retired {
+    _closure: Closure<'static, super::Shared>,
+    // The thread of the call, which is dead once the thread has exited.
+    thread: Weak<()>,
+}
+
+// Closures retired on any thread.
+static RETIRED: Mutex<Vec<Retired>> = Mutex::new(Vec::new());
+
+thread_local! {
+    // Identifies this thread in `RETIRED`.
+    static THREAD: Arc<()> = Arc::new(());
+}
+
+fn lock_retired() -> MutexGuard<'static, Vec<Retired>> {
+    RETIRED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn retire(closure: Closure<'static, super::Shared>) {
+    match THREAD.try_with(Arc::downgrade) {
+        Ok(thread) => lock_retired().push(Retired {
+            _closure: closure,
+            thread,
+        }),
+        // The thread is exiting, so nothing could tell when the call
+        // has returned.
+        Err(_) => std::mem::forget(closure),
+    }
+}
+
+// Frees the closures retired on this thread, and on threads that have
+// exited. Calls that retire closures return before anything else on
+// their thread can call this.
+fn free_retired() {
+    let current = THREAD.try_with(Arc::downgrade).ok();
+    let freed: Vec<_> = {
+        let mut retired = lock_retired();
+        if retired.is_empty() {
+            return;
+        }
+        let (freed, kept) = std::mem::take(&mut *retired).into_iter().partition(|r| {
+            r.thread.strong_count() == 0
+                || current.as_ref().is_some_and(|t| Weak::ptr_eq(t, &r.thread))
+        });
+        *retired = kept;
+        freed
+    };
+    // Freeing a closure may take libffi’s allocator lock.
+    drop(freed);
+}
+
+unsafe extern "C" fn destroy<U: Send + 'static, R: 'static>(user_data: *mut c_void) {
+    Shared::<U, R>::release(user_data as *mut Shared<U, R>);
+}
+
+fn abort(msg: &str) -> ! {
+    use std::io::Write;
+    let _ = writeln!(std::io::stderr(), "{}", msg);
+    process::abort()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+    use std::sync::Arc;
+    use std::thread;
+
+    unsafe extern "C" fn count(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        _args: *const *const c_void,
+        userdata: &mut (Arc<()>, u64),
+    ) {
+        userdata.1 += 1;
+        *result = userdata.1;
+    }
+
+    #[test]
+    fn destroy_frees_userdata() {
+        let token = Arc::new(());
+        let notify = DestroyNotify::new(Cif::new(vec![], Type::u64()), count, (token.clone(), 0));
+        let fun: extern "C" fn() -> u64 = unsafe { std::mem::transmute(notify.code.as_ptr()) };
+
+        assert_eq!(1, fun());
+        assert_eq!(2, fun());
+        assert_eq!(2, Arc::strong_count(&token));
+        unsafe { (notify.destroy)(notify.user_data) };
+        assert_eq!(1, Arc::strong_count(&token));
+    }
+
+    // Unregisters itself: calls the destroy notify it is passed.
+    unsafe extern "C" fn unregister(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        args: *const *const c_void,
+        userdata: &mut Arc<()>,
+    ) {
+        let destroy = *(*args as *const unsafe extern "C" fn(*mut c_void));
+        let user_data = *(*args.add(1) as *const *mut c_void);
+        destroy(user_data);
+        // The userdata is still alive until the callback returns.
+        *result = Arc::strong_count(userdata) as u64;
+    }
+
+    #[test]
+    fn destroy_during_callback() {
+        let token = Arc::new(());
+        let cif = Cif::new(vec![Type::pointer(), Type::pointer()], Type::u64());
+        let notify = DestroyNotify::new(cif, unregister, token.clone());
+        let fun: extern "C" fn(unsafe extern "C" fn(*mut c_void), *mut c_void) -> u64 =
+            unsafe { std::mem::transmute(notify.code.as_ptr()) };
+
+        assert_eq!(2, fun(notify.destroy, notify.user_data));
+        assert_eq!(1, Arc::strong_count(&token));
+    }
+
+    #[test]
+    fn closures_retired_on_exited_threads_are_freed() {
+        let token = Arc::new(());
+        let thread = {
+            let token = token.clone();
+            thread::spawn(move || {
+                let cif = Cif::new(vec![Type::pointer(), Type::pointer()], Type::u64());
+                let notify = DestroyNotify::new(cif, unregister, token);
+                let fun: extern "C" fn(unsafe extern "C" fn(*mut c_void), *mut c_void) -> u64 =
+                    unsafe { std::mem::transmute(notify.code.as_ptr()) };
+                fun(notify.destroy, notify.user_data);
+                THREAD.with(Arc::downgrade)
+            })
+            .join()
+            .unwrap()
+        };
+        assert_eq!(1, Arc::strong_count(&token));
+
+        free_retired();
+        assert!(!lock_retired()
+            .iter()
+            .any(|retired| Weak::ptr_eq(&retired.thread, &thread)));
+    }
+
+    #[test]
+    fn shared_registrations() {
+        let token = Arc::new(());
+        let closure = SharedClosure::new(Cif::new(vec![], Type::u64()), count, (token.clone(), 0));
+        let first = closure.register();
+        let second = closure.clone().register();
+        assert_eq!(3, closure.ref_count());
+
+        let fun: extern "C" fn() -> u64 = unsafe { std::mem::transmute(first.code.as_ptr()) };
+        assert_eq!(1, fun());
+        drop(closure);
+        unsafe { (first.destroy)(first.user_data) };
+        assert_eq!(2, fun());
+        assert_eq!(2, Arc::strong_count(&token));
+
+        unsafe { (second.destroy)(second.user_data) };
+        assert_eq!(1, Arc::strong_count(&token));
+    }
+
+    // Reads and writes the count in separate steps, which concurrent
+    // calls would interleave.
+    unsafe extern "C" fn count_slowly(
+        _cif: &low::ffi_cif,
+        _result: &mut c_void,
+        _args: *const *const c_void,
+        userdata: &mut u64,
+    ) {
+        let n = *userdata;
+        thread::yield_now();
+        *userdata = n + 1;
+    }
+
+    #[test]
+    fn calls_are_serialized() {
+        let closure = SharedClosure::new(Cif::new(vec![], Type::void()), count_slowly, 0);
+        let notify = closure.register();
+        let fun: extern "C" fn() = unsafe { std::mem::transmute(notify.code.as_ptr()) };
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| (0..100).for_each(|_| fun()));
+            }
+        });
+        drop(closure);
+
+        let shared = notify.user_data as *mut Shared<u64, c_void>;
+        assert_eq!(400, unsafe { *(*shared).userdata.lock().unwrap() });
+        unsafe { (notify.destroy)(notify.user_data) };
+    }
+}
@@ -0,0 +1,273 @@
+use std::fmt;
+use std::mem;
+use std::os::raw::c_void;
+
+use super::bridge::{self, ErrorPolicy};
+use super::{Callback, Cif, Closure, Shared};
+use crate::low;
+
+/// A closure that owns its userdata.
+///
+/// A [`Closure`] borrows its userdata, so it cannot outlive the scope
+/// that owns the data. A `ClosureOwned` instead takes the userdata in a
+/// [`Box`], passes the callback a reference to it, and drops it when the
+/// closure is dropped, after the closure’s trampoline is gone. This
+/// suits callbacks registered with a C library for longer than any one
+/// scope.
+///
+/// Unlike a [`ClosureOnce`](super::ClosureOnce), the callback only
+/// borrows the userdata, so the closure can be called any number of
+/// times. It is [`Send`] if the userdata is `Send`, and [`Sync`] if the
+/// userdata is `Sync`.
+///
+/// Construct with [`ClosureOwned::new`] or [`ClosureOwned::from_box`].
+///
+/// # Examples
+///
+/// ```
+/// use std::os::raw::c_void;
+///
+/// use libffi::low;
+/// use libffi::middle::{Cif, ClosureOwned, Type};
+///
+/// unsafe extern "C" fn lookup(
+///     _cif: &low::ffi_cif,
+///     result: &mut u32,
+///     args: *const *const c_void,
+///     table: &Vec<u32>,
+/// ) {
+///     let index = **(args as *const &usize);
+///     *result = table.get(index).copied().unwrap_or(0);
+/// }
+///
+/// fn make_lookup() -> ClosureOwned<Vec<u32>> {
+///     let table = vec![2, 3, 5, 7, 11];
+///     ClosureOwned::new(Cif::new(vec![Type::usize()], Type::u32()), lookup, table)
+/// }
+///
+/// let closure = make_lookup();
+/// let fun: &extern "C" fn(usize) -> u32 = unsafe { closure.instantiate_code_ptr() };
+/// assert_eq!(7, fun(3));
+/// assert_eq!(0, fun(9));
+/// ```
+pub struct ClosureOwned<U> {
+    // Declared first, so that the trampoline is dropped before the
+    // userdata it refers to.
+    closure: Closure<'static, Shared>,
+    userdata: Box<U>,
+}
+
+impl<U> ClosureOwned<U> {
+    /// Creates a new closure that owns `userdata`.
+    ///
+    /// # Arguments
+    ///
+    /// - `cif` — describes the calling convention and argument and
+    ///   result types
+    /// - `callback` — the function to call when the closure is invoked
+    /// - `userdata` — the value to pass to `callback`, by reference,
+    ///   along with the arguments when the closure is called
+    ///
+    /// # Result
+    ///
+    /// The new closure.
+    pub fn new<R>(cif: Cif, callback: Callback<U, R>, userdata: U) -> Self {
+        ClosureOwned::from_box(cif, callback, Box::new(userdata))
+    }
+
+    /// Creates a new closure that owns the boxed `userdata`.
+    ///
+    /// The arguments are as for [`ClosureOwned::new`].
+    pub fn from_box<R>(cif: Cif, callback: Callback<U, R>, userdata: Box<U>) -> Self {
+        ClosureOwned::make(cif, callback, userdata, None)
+    }
+
+    // Creates a new closure that owns the boxed `userdata`, checking the
+    // calling thread given an `affinity` policy.
+    pub(super) fn make<R>(
+        cif: Cif,
+        callback: Callback<U, R>,
+        userdata: Box<U>,
+        affinity: Option<ErrorPolicy<bridge::Erased>>,
+    ) -> Self {
+        // The box’s contents do not move, and outlive the closure. Its
+        // `Send` and `Sync` impls are masked by those of the box, so
+        // they follow the userdata’s.
+        let closure = unsafe {
+            Closure::make(
+                cif,
+                mem::transmute::<Callback<U, R>, low::RawCallback>(callback),
+                &*userdata as *const U,
+                affinity,
+            )
+        };
+        ClosureOwned { closure, userdata }
+    }
+
+    /// Gets the userdata.
+    pub fn userdata(&self) -> &U {
+        &self.userdata
+    }
+
+    /// Destroys the closure, returning its userdata.
+    pub fn into_userdata(self) -> Box<U> {
+        let ClosureOwned { closure, userdata } = self;
+        drop(closure);
+        userdata
+    }
+
+    /// Closes the closure and waits for the calls in progress to return,
+    /// for at most `timeout`, as for [`Closure::close_and_wait`], then
+    /// returns the userdata.
+    ///
+    /// # Errors
+    ///
+    /// Returns the closure, still closed, if calls were still in
+    /// progress after `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is larger than the CIF’s result type.
+    ///
+    /// This item is enabled by `#[cfg(feature = "safe-reclaim")]`.
+    #[cfg(feature = "safe-reclaim")]
+    pub fn close_and_wait<R: Copy>(
+        self,
+        policy: ErrorPolicy<R>,
+        timeout: std::time::Duration,
+    ) -> Result<Box<U>, Self> {
+        let ClosureOwned { closure, userdata } = self;
+        match closure.close_and_wait(policy, timeout) {
+            Ok(()) => Ok(userdata),
+            Err(closure) => Err(ClosureOwned { closure, userdata }),
+        }
+    }
+
+    /// Gets the histogram of the latencies of this closure’s calls.
+    #[cfg(feature = "stats")]
+    pub fn latency_histogram(&self) -> std::sync::Arc<super::stats::LatencyHistogram> {
+        self.closure.latency_histogram()
+    }
+
+    /// Returns whether `addr` lies within the closure’s trampoline, as
+    /// for [`Closure::contains_address`].
+    pub fn contains_address(&self, addr: *const c_void) -> bool {
+        self.closure.contains_address(addr)
+    }
+
+    /// Obtains the callable code pointer for a closure.
+    ///
+    /// # Safety
+    ///
+    /// The result needs to be transmuted to the correct type before
+    /// it can be called. If the type is wrong then undefined behavior
+    /// will result.
+    pub fn code_ptr(&self) -> &unsafe extern "C" fn() {
+        self.closure.code_ptr()
+    }
+
+    /// Transmutes the callable code pointer for a closure to a reference
+    /// to any type. This is intended to be used to transmute it to its
+    /// correct function type in order to call it.
+    ///
+    /// # Safety
+    ///
+    /// This method allows transmuting to a reference to *any* sized type,
+    /// and cannot check whether the code pointer actually has that type.
+    /// If the type is wrong then undefined behavior will result.
+    pub unsafe fn instantiate_code_ptr<T>(&self) -> &T {
+        self.closure.instantiate_code_ptr()
+    }
+}
+
+impl<U> fmt::Debug for ClosureOwned<U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClosureOwned")
+            .field("closure", &self.closure)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::middle::{Builder, Type};
+
+    // Userdata that counts its drops.
+    struct Counter {
+        step: u64,
+        drops: Arc<AtomicUsize>,
+    }
+
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    unsafe extern "C" fn step(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        args: *const *const c_void,
+        counter: &Counter,
+    ) {
+        *result = **(args as *const &u64) + counter.step;
+    }
+
+    fn make(step: u64, drops: &Arc<AtomicUsize>) -> ClosureOwned<Counter> {
+        let counter = Counter {
+            step,
+            drops: drops.clone(),
+        };
+        ClosureOwned::new(
+            Cif::new(vec![Type::u64()], Type::u64()),
+            self::step,
+            counter,
+        )
+    }
+
+    #[test]
+    fn owns_userdata() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let closure = make(3, &drops);
+        let fun: &extern "C" fn(u64) -> u64 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(8, fun(5));
+        assert_eq!(3, closure.userdata().step);
+        assert!(format!("{:?}", closure).starts_with("ClosureOwned"));
+        drop(closure);
+        assert_eq!(1, drops.load(Ordering::SeqCst));
+
+        let closure = make(4, &drops);
+        let counter = closure.into_userdata();
+        assert_eq!(1, drops.load(Ordering::SeqCst));
+        assert_eq!(4, counter.step);
+        drop(counter);
+        assert_eq!(2, drops.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn sent_to_other_threads() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let closure = Builder::new()
+            .arg(Type::u64())
+            .res(Type::u64())
+            .into_closure_owned(
+                step,
+                Counter {
+                    step: 10,
+                    drops: drops.clone(),
+                },
+            );
+        let result = std::thread::spawn(move || {
+            let fun: &extern "C" fn(u64) -> u64 = unsafe { closure.instantiate_code_ptr() };
+            fun(1)
+        })
+        .join()
+        .unwrap();
+        assert_eq!(11, result);
+        assert_eq!(1, drops.load(Ordering::SeqCst));
+    }
+}
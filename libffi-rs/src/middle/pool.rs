@@ -0,0 +1,308 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::c_void;
+use std::sync::{Arc, Mutex};
+
+use super::{prep_closure, release, Callback, CallbackMut, Cif, Closure, CodePtr};
+use crate::low;
+
+/// Recycles the executable memory of closures.
+///
+/// Creating a closure allocates its trampoline from libffi’s closure
+/// allocator, which maps executable memory and takes a global lock,
+/// and dropping the closure frees it again. A program that creates and
+/// drops many short-lived closures, such as one per request handed to
+/// a C library, pays for both each time. Closures created through a
+/// pool instead return their allocation to the pool when dropped, and
+/// the next closure created through it reuses the allocation, only
+/// initializing it for its own CIF, callback and userdata.
+///
+/// The closures are ordinary [`Closure`]s. The pool keeps at most
+/// `capacity` idle allocations, freeing any others as they are
+/// returned, and frees the idle ones when it and all of its closures
+/// have been dropped. With the `deferred-reclaim` feature, an
+/// allocation returns to the pool once the reclaimer retires it, rather
+/// than when its closure is dropped.
+///
+/// Cloning a pool gives another handle to the same allocations, which
+/// may be used from any thread.
+///
+/// # Examples
+///
+/// ```
+/// use std::os::raw::c_void;
+///
+/// use libffi::low::ffi_cif;
+/// use libffi::middle::{Cif, ClosurePool, Type};
+///
+/// unsafe extern "C" fn add(
+///     _cif: &ffi_cif,
+///     result: &mut u64,
+///     args: *const *const c_void,
+///     offset: &u64,
+/// ) {
+///     *result = *(*args as *const u64) + *offset;
+/// }
+///
+/// let pool = ClosurePool::new(16);
+/// for offset in 0..100u64 {
+///     let cif = Cif::new(vec![Type::u64()], Type::u64());
+///     let closure = pool.closure(cif, add, &offset);
+///     let fun: &extern "C" fn(u64) -> u64 = unsafe { closure.instantiate_code_ptr() };
+///     assert_eq!(offset + 1, fun(1));
+/// }
+/// ```
+#[derive(Clone)]
+#[cfg_attr(not(feature = "min-size"), derive(Debug))]
+pub struct ClosurePool {
+    free: Arc<Free>,
+}
+
+#[cfg(feature = "min-size")]
+opaque_debug!(ClosurePool);
+
+// The idle allocations of a pool, with their code pointers.
+#[derive(Debug)]
+struct Free {
+    idle: Mutex<Vec<(*mut low::ffi_closure, CodePtr)>>,
+    capacity: usize,
+}
+
+// libffi’s closure allocations may be initialized, used and freed on
+// any thread.
+unsafe impl Send for Free {}
+unsafe impl Sync for Free {}
+
+impl Free {
+    fn idle(&self) -> std::sync::MutexGuard<'_, Vec<(*mut low::ffi_closure, CodePtr)>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Free {
+    fn drop(&mut self) {
+        for (alloc, _) in self.idle().drain(..) {
+            unsafe { low::closure_free(alloc) };
+        }
+    }
+}
+
+/// Where a pooled closure returns its allocation when dropped.
+#[derive(Debug)]
+pub(crate) struct Slot {
+    free: Arc<Free>,
+    code: CodePtr,
+}
+
+impl Slot {
+    // Returns `alloc`, whose code pointer is `self.code`, to the pool,
+    // or frees it if the pool is full.
+    pub(crate) unsafe fn release(self, alloc: *mut low::ffi_closure) {
+        let mut idle = self.free.idle();
+        if idle.len() < self.free.capacity {
+            idle.push((alloc, self.code));
+        } else {
+            drop(idle);
+            low::closure_free(alloc);
+        }
+    }
+}
+
+impl ClosurePool {
+    /// Creates an empty pool that keeps up to `capacity` idle
+    /// allocations.
+    pub fn new(capacity: usize) -> Self {
+        ClosurePool {
+            free: Arc::new(Free {
+                idle: Mutex::new(Vec::with_capacity(capacity)),
+                capacity,
+            }),
+        }
+    }
+
+    /// The number of idle allocations the pool keeps.
+    pub fn capacity(&self) -> usize {
+        self.free.capacity
+    }
+
+    /// The number of idle allocations, which closures created through
+    /// the pool will reuse.
+    pub fn idle(&self) -> usize {
+        self.free.idle().len()
+    }
+
+    /// Allocates closures until `count` are idle, or the pool is full.
+    ///
+    /// # Errors
+    ///
+    /// Fails if libffi cannot allocate a closure.
+    pub fn fill(&self, count: usize) -> Result<(), crate::Error> {
+        let count = count.min(self.free.capacity);
+        let mut idle = self.free.idle();
+        while idle.len() < count {
+            let (alloc, code) = low::closure_alloc();
+            if alloc.is_null() {
+                return Err(crate::Error::AllocationFailed);
+            }
+            idle.push((alloc, code));
+        }
+        Ok(())
+    }
+
+    /// Creates a closure with immutable userdata, as
+    /// [`Closure::new`] does, reusing an idle allocation if there is
+    /// one.
+    pub fn closure<'a, U, R>(
+        &self,
+        cif: Cif,
+        callback: Callback<U, R>,
+        userdata: &'a U,
+    ) -> Closure<'a> {
+        ffi_expect!(
+            unsafe {
+                self.make(
+                    cif,
+                    mem::transmute::<Callback<U, R>, low::RawCallback>(callback),
+                    userdata as *const U,
+                )
+            },
+            "ClosurePool::closure: libffi failed to create the closure"
+        )
+    }
+
+    /// Creates a closure with mutable userdata, as
+    /// [`Closure::new_mut`] does, reusing an idle allocation if there
+    /// is one.
+    pub fn closure_mut<'a, U, R>(
+        &self,
+        cif: Cif,
+        callback: CallbackMut<U, R>,
+        userdata: &'a mut U,
+    ) -> Closure<'a> {
+        ffi_expect!(
+            unsafe {
+                self.make(
+                    cif,
+                    mem::transmute::<CallbackMut<U, R>, low::RawCallback>(callback),
+                    userdata as *mut U,
+                )
+            },
+            "ClosurePool::closure_mut: libffi failed to create the closure"
+        )
+    }
+
+    // As `Closure::try_make`, but with an allocation from the pool.
+    unsafe fn make<'a, U>(
+        &self,
+        cif: Cif,
+        callback: low::RawCallback,
+        userdata: *const U,
+    ) -> Result<Closure<'a>, crate::Error> {
+        let popped = self.free.idle().pop();
+        let (alloc, code) = match popped {
+            Some(idle) => idle,
+            None => low::closure_alloc(),
+        };
+        if alloc.is_null() {
+            return Err(crate::Error::AllocationFailed);
+        }
+        let slot = Slot {
+            free: self.free.clone(),
+            code,
+        };
+        match prep_closure(
+            alloc,
+            Box::new(cif),
+            callback,
+            userdata as *mut c_void,
+            code,
+            None,
+        ) {
+            Ok(mut trampoline) => {
+                trampoline.pool = Some(slot);
+                Ok(Closure {
+                    _trampoline: super::own(trampoline),
+                    code,
+                    _marker: PhantomData,
+                })
+            }
+            Err(error) => {
+                release(alloc, Some(slot));
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+
+    unsafe extern "C" fn scale(
+        _cif: &low::ffi_cif,
+        result: &mut i64,
+        args: *const *const c_void,
+        factor: &i64,
+    ) {
+        *result = *(*args as *const i64) * *factor;
+    }
+
+    unsafe extern "C" fn count(
+        _cif: &low::ffi_cif,
+        result: &mut u32,
+        _args: *const *const c_void,
+        calls: &mut u32,
+    ) {
+        *calls += 1;
+        *result = *calls;
+    }
+
+    #[test]
+    fn reuses_allocations() {
+        let pool = ClosurePool::new(2);
+        pool.fill(5).unwrap();
+        assert_eq!(2, pool.idle());
+
+        let factors = [3i64, -2, 7];
+        let closures: Vec<_> = factors
+            .iter()
+            .map(|factor| pool.closure(Cif::new(vec![Type::i64()], Type::i64()), scale, factor))
+            .collect();
+        assert_eq!(0, pool.idle());
+        for (closure, factor) in closures.iter().zip(&factors) {
+            let fun: &extern "C" fn(i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+            assert_eq!(5 * factor, fun(5));
+        }
+        let codes: Vec<_> = closures.iter().map(|c| *c.code_ptr() as usize).collect();
+        drop(closures);
+
+        // A closure of another signature and kind reuses an allocation,
+        // once the reclaimer, if any, has returned them.
+        #[cfg(not(feature = "deferred-reclaim"))]
+        assert_eq!(2, pool.idle());
+        let mut calls = 0;
+        let counter = pool.closure_mut(Cif::new(vec![], Type::u32()), count, &mut calls);
+        #[cfg(not(feature = "deferred-reclaim"))]
+        assert!(codes.contains(&(*counter.code_ptr() as usize)));
+        #[cfg(feature = "deferred-reclaim")]
+        let _ = codes;
+        let fun: &extern "C" fn() -> u32 = unsafe { counter.instantiate_code_ptr() };
+        assert_eq!(1, fun());
+        assert_eq!(2, fun());
+        drop(counter);
+        assert_eq!(2, calls);
+    }
+
+    #[test]
+    fn outlives_the_pool() {
+        let pool = ClosurePool::new(4);
+        let factor = 4;
+        let closure =
+            pool.clone()
+                .closure(Cif::new(vec![Type::i64()], Type::i64()), scale, &factor);
+        drop(pool);
+        let fun: &extern "C" fn(i64) -> i64 = unsafe { closure.instantiate_code_ptr() };
+        assert_eq!(12, fun(3));
+    }
+}
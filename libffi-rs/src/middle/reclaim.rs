@@ -0,0 +1,689 @@
+//! Deferred reclamation of closure trampolines.
+//!
+//! Freeing a closure’s executable memory takes the lock of libffi’s
+//! closure allocator, which another thread may hold while it creates a
+//! closure, so dropping a closure may block for as long as that takes.
+//! With the `deferred-reclaim` feature enabled, dropping a
+//! [`Closure`](super::Closure) or [`ClosureOnce`](super::ClosureOnce)
+//! neither locks nor allocates: it pushes the closure’s trampoline—its
+//! executable memory, its CIF and, with `stats`, its timing data—onto a
+//! lock-free list, and wakes a background thread, the reclaimer, which
+//! frees retired trampolines in batches.
+//!
+//! The reclaimer works in epochs. At the end of each it takes the
+//! trampolines retired since the last, and frees them at the end of the
+//! next, which begins no sooner than [`GRACE_PERIOD`] later. A call
+//! that had already entered a trampoline when its closure was dropped
+//! thus usually finishes before the memory is freed, but nothing
+//! ensures it: calling a closure that is being dropped is still
+//! undefined behavior, which the `safe-reclaim` feature narrows to a
+//! short window.
+//!
+//! The reclaimer is started by the first closure created, and runs for
+//! the rest of the process.
+//!
+//! # Guarded reclamation
+//!
+//! The `safe-reclaim` feature, which implies `deferred-reclaim`, guards
+//! against C calling a closure while another thread drops it, as
+//! happens when a library delivers callbacks on threads of its own
+//! until it is told to stop. Each closure then counts the calls in
+//! progress, and:
+//!
+//!   - dropping the closure marks it dead, then waits for the calls
+//!     already running its callback to return, since they may use the
+//!     userdata that the closure borrows or owns;
+//!   - a call that begins once the closure is dead does not run the
+//!     callback, and returns zero, or a zeroed struct, instead;
+//!   - the reclaimer frees a trampoline only once no call is in
+//!     progress and the grace period has passed.
+//!
+//! The guard is best-effort. libffi runs a few instructions in the
+//! trampoline before a call is counted, and nothing but the grace
+//! period keeps the memory alive for them: a call preempted there for
+//! longer, or one that begins more than a grace period after the drop,
+//! may still find the memory freed. C must therefore still be told to
+//! stop calling, and the drop should follow soon after.
+//! A callback may drop its own closure; the drop does not wait for the
+//! call that makes it. Each call pays for two atomic read-modify-write
+//! operations and a thread-local access.
+//!
+//! # Closing
+//!
+//! With `safe-reclaim`, closures also have a `close_and_wait` method,
+//! for tearing down a callback deterministically. It marks the closure
+//! dead, so that new calls return the value chosen by an
+//! [`ErrorPolicy`] and report [`Closed`] as their
+//! [callback error](super::bridge::take_callback_error), then waits for
+//! the calls in progress to return, for at most a given time. If they
+//! do, the closure is dropped; if not, it is handed back, still closed.
+//! `Closure::close_and_wait` has an example.
+//!
+//! # Locks on the drop path
+//!
+//! With this feature, dropping a closure takes no lock and makes at most
+//! one system call, to wake the reclaimer, except that:
+//!
+//!   - with `trampoline-registry`, the closure is removed from the
+//!     registry, which takes the registry’s write lock;
+//!   - with `metrics`, the closure and CIF gauges are updated through
+//!     whatever recorder is installed;
+//!   - the userdata of a [`ClosureOnce`](super::ClosureOnce) is dropped
+//!     by the dropping thread, since it need not be [`Send`];
+//!   - the scalar closures of `high::scalar` free their memory directly.
+//!
+//! Creating a closure allocates, and may take the allocator’s lock, as
+//! before.
+
+#[cfg(feature = "safe-reclaim")]
+use std::cell::Cell;
+#[cfg(feature = "safe-reclaim")]
+use std::error;
+use std::fmt;
+use std::ops::Deref;
+#[cfg(feature = "safe-reclaim")]
+use std::os::raw::c_void;
+use std::ptr::{self, NonNull};
+#[cfg(feature = "safe-reclaim")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::thread::{self, Thread};
+use std::time::Duration;
+#[cfg(feature = "safe-reclaim")]
+use std::time::Instant;
+
+#[cfg(feature = "safe-reclaim")]
+use super::bridge::{self, ErrorPolicy};
+use super::Trampoline;
+#[cfg(feature = "safe-reclaim")]
+use super::{Closure, ClosureOnce};
+#[cfg(feature = "safe-reclaim")]
+use crate::low;
+
+/// The least time for which a retired trampoline is kept before it is
+/// freed.
+///
+/// With `safe-reclaim`, this is all that covers a call between its
+/// entry into the trampoline and the point where it is counted, so the
+/// guard against such calls is best-effort.
+pub const GRACE_PERIOD: Duration = Duration::from_millis(10);
+
+// A retired trampoline, linked into `RETIRED`. Nodes are allocated when
+// closures are created, so retiring one need not allocate.
+struct Node {
+    next: *mut Node,
+    trampoline: Trampoline,
+}
+
+// The trampolines retired since the reclaimer last took them.
+static RETIRED: AtomicPtr<Node> = AtomicPtr::new(ptr::null_mut());
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+static RECLAIMED: AtomicUsize = AtomicUsize::new(0);
+static RECLAIMER: OnceLock<Thread> = OnceLock::new();
+
+/// Returns the number of trampolines that have been retired but not yet
+/// freed.
+pub fn pending() -> usize {
+    PENDING.load(Ordering::Relaxed)
+}
+
+/// Returns the number of trampolines the reclaimer has freed since the
+/// process started.
+pub fn reclaimed() -> usize {
+    RECLAIMED.load(Ordering::Relaxed)
+}
+
+fn start() {
+    RECLAIMER.get_or_init(|| {
+        thread::Builder::new()
+            .name("libffi-reclaim".into())
+            .spawn(run)
+            .expect("libffi: cannot start the reclaimer thread")
+            .thread()
+            .clone()
+    });
+}
+
+fn run() {
+    let mut waiting: *mut Node = ptr::null_mut();
+    loop {
+        if waiting.is_null() {
+            thread::park();
+        } else {
+            thread::sleep(GRACE_PERIOD);
+        }
+        let mut retired = RETIRED.swap(ptr::null_mut(), Ordering::Acquire);
+        unsafe { free(waiting, &mut retired) };
+        waiting = retired;
+    }
+}
+
+// Frees a list of retired trampolines, moving those still in use to
+// `keep`.
+unsafe fn free(mut node: *mut Node, keep: &mut *mut Node) {
+    while !node.is_null() {
+        let next = (*node).next;
+        if (*node).trampoline.in_use() {
+            (*node).next = *keep;
+            *keep = node;
+        } else {
+            drop(Box::from_raw(node));
+            PENDING.fetch_sub(1, Ordering::Relaxed);
+            RECLAIMED.fetch_add(1, Ordering::Relaxed);
+        }
+        node = next;
+    }
+}
+
+impl Trampoline {
+    // Whether a call may still be running in the trampoline.
+    fn in_use(&self) -> bool {
+        #[cfg(feature = "safe-reclaim")]
+        return self._guard.in_flight.load(Ordering::SeqCst) > 0;
+        #[cfg(not(feature = "safe-reclaim"))]
+        return false;
+    }
+}
+
+/// A trampoline owned by a closure, which is retired to the reclaimer
+/// when dropped.
+pub(crate) struct Deferred(NonNull<Node>);
+
+impl Deferred {
+    pub(crate) fn new(trampoline: Trampoline) -> Self {
+        start();
+        let node = Box::new(Node {
+            next: ptr::null_mut(),
+            trampoline,
+        });
+        Deferred(NonNull::from(Box::leak(node)))
+    }
+}
+
+impl Deref for Deferred {
+    type Target = Trampoline;
+
+    fn deref(&self) -> &Trampoline {
+        unsafe { &self.0.as_ref().trampoline }
+    }
+}
+
+impl Drop for Deferred {
+    fn drop(&mut self) {
+        #[cfg(feature = "safe-reclaim")]
+        self._guard.kill();
+        // The CIF is freed with the trampoline, but counts as dropped
+        // with the closure, as the closure does.
+        #[cfg(feature = "metrics")]
+        unsafe { self.0.as_mut() }.trampoline._cif.uncount();
+
+        let node = self.0.as_ptr();
+        PENDING.fetch_add(1, Ordering::Relaxed);
+        // The reclaimer only ever takes the whole list, so pushing is
+        // free of ABA problems.
+        let mut head = RETIRED.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head };
+            match RETIRED.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        if let Some(reclaimer) = RECLAIMER.get() {
+            reclaimer.unpark();
+        }
+    }
+}
+
+impl fmt::Debug for Deferred {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Deferred").field(&**self).finish()
+    }
+}
+
+/// The error reported for calls made to a closure after it has been
+/// closed by `close_and_wait`.
+///
+/// This item is enabled by `#[cfg(feature = "safe-reclaim")]`.
+#[cfg(feature = "safe-reclaim")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Closed;
+
+#[cfg(feature = "safe-reclaim")]
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the closure was closed")
+    }
+}
+
+#[cfg(feature = "safe-reclaim")]
+impl error::Error for Closed {}
+
+/// The userdata of a closure’s counting trampoline: the closure’s real
+/// callback and userdata, the count of calls in progress, and what to
+/// return once the closure is closed.
+#[cfg(feature = "safe-reclaim")]
+#[derive(Debug)]
+pub(crate) struct Guard {
+    callback: low::RawCallback,
+    userdata: *mut c_void,
+    in_flight: AtomicUsize,
+    dead: AtomicBool,
+    policy: OnceLock<ErrorPolicy<bridge::Erased>>,
+}
+
+#[cfg(feature = "safe-reclaim")]
+thread_local! {
+    // The guard of the closure whose callback this thread is running.
+    static CURRENT: Cell<*const Guard> = const { Cell::new(ptr::null()) };
+}
+
+#[cfg(feature = "safe-reclaim")]
+impl Guard {
+    pub(crate) fn new(callback: low::RawCallback, userdata: *mut c_void) -> Box<Self> {
+        Box::new(Guard {
+            callback,
+            userdata,
+            in_flight: AtomicUsize::new(0),
+            dead: AtomicBool::new(false),
+            policy: OnceLock::new(),
+        })
+    }
+
+    // Stops new calls from running the callback, and waits for those
+    // running it on other threads to return.
+    pub(super) fn kill(&self) {
+        self.dead.store(true, Ordering::SeqCst);
+        self.wait(None);
+    }
+
+    // Closes the closure, so that new calls return according to
+    // `policy`, and waits for the calls running the callback on other
+    // threads to return, until `deadline`. Returns whether they did.
+    fn close(&self, policy: ErrorPolicy<bridge::Erased>, deadline: Instant) -> bool {
+        // The policy of the first close stands.
+        let _ = self.policy.set(policy);
+        self.dead.store(true, Ordering::SeqCst);
+        self.wait(Some(deadline))
+    }
+
+    fn wait(&self, deadline: Option<Instant>) -> bool {
+        let own = CURRENT.with(|current| ptr::eq(current.get(), self)) as usize;
+        while self.in_flight.load(Ordering::SeqCst) > own {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return false;
+            }
+            thread::yield_now();
+        }
+        true
+    }
+}
+
+/// Calls the real callback, unless the closure is dead, counting the
+/// call while it is in progress.
+#[cfg(feature = "safe-reclaim")]
+pub(crate) unsafe extern "C" fn guarded(
+    cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    guard: &Guard,
+) {
+    guard.in_flight.fetch_add(1, Ordering::SeqCst);
+    if guard.dead.load(Ordering::SeqCst) {
+        match guard.policy.get() {
+            None => zero_result(cif, result),
+            Some(policy) => policy.fail(result, Closed),
+        }
+    } else {
+        let outer = CURRENT.with(|current| current.replace(guard));
+        (guard.callback)(
+            cif as *const _ as *mut _,
+            result,
+            args as *mut *mut c_void,
+            guard.userdata,
+        );
+        CURRENT.with(|current| current.set(outer));
+    }
+    guard.in_flight.fetch_sub(1, Ordering::SeqCst);
+}
+
+// Stores a zero result of the CIF’s result type. Integer results are
+// widened to `ffi_arg`, as libffi expects of closures.
+#[cfg(feature = "safe-reclaim")]
+unsafe fn zero_result(cif: &low::ffi_cif, result: &mut c_void) {
+    let rtype = &*cif.rtype;
+    let size = match u32::from(rtype.type_) {
+        crate::raw::FFI_TYPE_VOID => 0,
+        crate::raw::FFI_TYPE_STRUCT => rtype.size,
+        _ => rtype.size.max(std::mem::size_of::<low::ffi_arg>()),
+    };
+    ptr::write_bytes(result as *mut c_void as *mut u8, 0, size);
+}
+
+#[cfg(feature = "safe-reclaim")]
+impl<'a, S> Closure<'a, S> {
+    /// Closes the closure and waits for the calls in progress to return,
+    /// for at most `timeout`, before dropping it.
+    ///
+    /// Calls that begin once the closure is closed do not run its
+    /// callback, but return the value chosen by `policy`, which, as for
+    /// [`Builder::into_fallible_closure`](super::Builder::into_fallible_closure),
+    /// must follow libffi’s rules for widening small integer results.
+    /// See [`reclaim`](super::reclaim) for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::os::raw::c_void;
+    /// use std::time::Duration;
+    ///
+    /// use libffi::low;
+    /// use libffi::middle::bridge::ErrorPolicy;
+    /// use libffi::middle::{Cif, Closure, Type};
+    ///
+    /// unsafe extern "C" fn answer(
+    ///     _cif: &low::ffi_cif,
+    ///     result: &mut i32,
+    ///     _args: *const *const c_void,
+    ///     value: &i32,
+    /// ) {
+    ///     *result = *value;
+    /// }
+    ///
+    /// let closure = Closure::new_sync(Cif::new(vec![], Type::i32()), answer, &42);
+    /// let fun: extern "C" fn() -> i32 = *unsafe { closure.instantiate_code_ptr() };
+    /// assert_eq!(42, fun());
+    ///
+    /// closure
+    ///     .close_and_wait(ErrorPolicy::ReturnCode(-1i32), Duration::from_secs(1))
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns the closure, still closed, if calls were still in
+    /// progress after `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is larger than the CIF’s result type.
+    ///
+    /// This item is enabled by `#[cfg(feature = "safe-reclaim")]`.
+    pub fn close_and_wait<R: Copy>(
+        self,
+        policy: ErrorPolicy<R>,
+        timeout: Duration,
+    ) -> Result<(), Self> {
+        let policy = policy.erase();
+        policy.check(&self._trampoline._cif, "Closure::close_and_wait");
+        if self
+            ._trampoline
+            ._guard
+            .close(policy, Instant::now() + timeout)
+        {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+#[cfg(feature = "safe-reclaim")]
+impl ClosureOnce {
+    /// Closes the closure and waits for the calls in progress to return,
+    /// for at most `timeout`, before dropping it, as for
+    /// [`Closure::close_and_wait`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the closure, still closed, if calls were still in
+    /// progress after `timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is larger than the CIF’s result type.
+    ///
+    /// This item is enabled by `#[cfg(feature = "safe-reclaim")]`.
+    pub fn close_and_wait<R: Copy>(
+        self,
+        policy: ErrorPolicy<R>,
+        timeout: Duration,
+    ) -> Result<(), Self> {
+        let policy = policy.erase();
+        policy.check(&self.trampoline._cif, "ClosureOnce::close_and_wait");
+        if self
+            .trampoline
+            ._guard
+            .close(policy, Instant::now() + timeout)
+        {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::raw::c_void;
+    use std::sync::atomic::AtomicU64;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::low;
+    use crate::middle::{Cif, Closure, ClosureOnce, Type};
+
+    unsafe extern "C" fn count(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        args: *const *const c_void,
+        calls: &AtomicU64,
+    ) {
+        *result = calls.fetch_add(*(*args as *const u64), Ordering::Relaxed);
+    }
+
+    unsafe extern "C" fn ignore(
+        _cif: &low::ffi_cif,
+        _result: &mut c_void,
+        _args: *const *const c_void,
+        _userdata: &mut Option<String>,
+    ) {
+    }
+
+    #[cfg(feature = "safe-reclaim")]
+    unsafe extern "C" fn tick(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        _args: *const *const c_void,
+        calls: &AtomicU64,
+    ) {
+        *result = calls.fetch_add(1, Ordering::SeqCst) + 1;
+    }
+
+    #[cfg(feature = "safe-reclaim")]
+    #[test]
+    fn calls_racing_a_drop() {
+        let calls = AtomicU64::new(0);
+        let cif = Cif::new(vec![], Type::u64());
+        let closure = Closure::new_sync(cif, tick, &calls);
+        let fun: extern "C" fn() -> u64 = *unsafe { closure.instantiate_code_ptr() };
+
+        thread::scope(|scope| {
+            let callers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(move || {
+                        // Calls until the closure is dead and returns 0.
+                        let mut made = 0u64;
+                        while fun() != 0 {
+                            made += 1;
+                        }
+                        made
+                    })
+                })
+                .collect();
+
+            while calls.load(Ordering::SeqCst) < 1000 {
+                thread::yield_now();
+            }
+            drop(closure);
+            let at_drop = calls.load(Ordering::SeqCst);
+
+            let made: u64 = callers.into_iter().map(|c| c.join().unwrap()).sum();
+            assert_eq!(at_drop, made);
+            assert_eq!(at_drop, calls.load(Ordering::SeqCst));
+        });
+    }
+
+    // Userdata that records its drop, for checking that no call is
+    // still using it then.
+    #[cfg(feature = "safe-reclaim")]
+    struct Witness(std::sync::Arc<(AtomicBool, AtomicBool)>);
+
+    #[cfg(feature = "safe-reclaim")]
+    impl Drop for Witness {
+        fn drop(&mut self) {
+            (self.0).0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[cfg(feature = "safe-reclaim")]
+    unsafe extern "C" fn witness(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        _args: *const *const c_void,
+        witness: &mut Option<Witness>,
+    ) {
+        let (dropped, overlapped) = &*witness.as_ref().unwrap().0;
+        for _ in 0..10 {
+            if dropped.load(Ordering::SeqCst) {
+                overlapped.store(true, Ordering::SeqCst);
+            }
+            thread::yield_now();
+        }
+        *result = 1;
+    }
+
+    #[cfg(feature = "safe-reclaim")]
+    #[test]
+    fn inline_userdata_outlives_calls() {
+        let flags = std::sync::Arc::new((AtomicBool::new(false), AtomicBool::new(false)));
+        let cif = Cif::new(vec![], Type::u64());
+        let closure = ClosureOnce::new(cif, witness, Witness(flags.clone()));
+        let fun: extern "C" fn() -> u64 = *unsafe { closure.instantiate_code_ptr() };
+
+        thread::scope(|scope| {
+            let callers: Vec<_> = (0..4)
+                .map(|_| scope.spawn(move || while fun() != 0 {}))
+                .collect();
+            thread::sleep(Duration::from_millis(10));
+            drop(closure);
+            for caller in callers {
+                caller.join().unwrap();
+            }
+        });
+        assert!(flags.0.load(Ordering::SeqCst));
+        assert!(!flags.1.load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "safe-reclaim")]
+    unsafe extern "C" fn hold(
+        _cif: &low::ffi_cif,
+        result: &mut i32,
+        _args: *const *const c_void,
+        gate: &(AtomicBool, AtomicBool),
+    ) {
+        gate.0.store(true, Ordering::SeqCst);
+        while !gate.1.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        *result = 1;
+    }
+
+    #[cfg(feature = "safe-reclaim")]
+    #[test]
+    fn close_and_wait() {
+        use crate::middle::bridge::take_callback_error;
+
+        // Entered, and released.
+        let gate = (AtomicBool::new(false), AtomicBool::new(false));
+        let cif = Cif::new(vec![], Type::i32());
+        let closure = Closure::new_sync(cif, hold, &gate);
+        let fun: extern "C" fn() -> i32 = *unsafe { closure.instantiate_code_ptr() };
+
+        thread::scope(|scope| {
+            let running = scope.spawn(move || fun());
+            while !gate.0.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+
+            let policy = ErrorPolicy::ReturnCode(-1i32);
+            let closure = closure
+                .close_and_wait(policy, Duration::from_millis(20))
+                .unwrap_err();
+            assert_eq!(-1, fun());
+            let error = take_callback_error().unwrap();
+            assert!(error.to_string().contains("closure was closed"));
+
+            gate.1.store(true, Ordering::SeqCst);
+            closure
+                .close_and_wait(policy, Duration::from_secs(10))
+                .unwrap();
+            assert_eq!(1, running.join().unwrap());
+        });
+    }
+
+    #[cfg(feature = "safe-reclaim")]
+    #[test]
+    fn close_owned_closures() {
+        let cif = Cif::new(vec![Type::u64()], Type::u64());
+        let calls = AtomicU64::new(0);
+        let closure = crate::middle::ClosureOwned::new(cif, count, calls);
+        let fun: extern "C" fn(u64) -> u64 = *unsafe { closure.instantiate_code_ptr() };
+        fun(2);
+        let calls = closure
+            .close_and_wait(ErrorPolicy::ReturnCode(0u64), Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "safe-reclaim")]
+    #[test]
+    #[should_panic(expected = "larger than the CIF’s result type")]
+    fn oversized_error_codes_are_rejected() {
+        let cif = Cif::new(vec![], Type::u8());
+        let gate = (AtomicBool::new(true), AtomicBool::new(true));
+        let closure = Closure::new_sync(cif, hold, &gate);
+        let _ = closure.close_and_wait(ErrorPolicy::ReturnCode([0u64; 2]), Duration::ZERO);
+    }
+
+    #[test]
+    fn dropped_closures_are_reclaimed() {
+        let before = reclaimed();
+        let calls = AtomicU64::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        let cif = Cif::new(vec![Type::u64()], Type::u64());
+                        let closure = Closure::new_sync(cif, count, &calls);
+                        let fun: &extern "C" fn(u64) -> u64 =
+                            unsafe { closure.instantiate_code_ptr() };
+                        fun(1);
+                    }
+                });
+            }
+        });
+        assert_eq!(100, calls.load(Ordering::Relaxed));
+
+        let cif = Cif::new(vec![], Type::void());
+        drop(ClosureOnce::new(cif, ignore, "owned".to_string()));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while reclaimed() < before + 101 {
+            assert!(Instant::now() < deadline, "trampolines were not reclaimed");
+            thread::sleep(GRACE_PERIOD);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! A named, thread-safe registry of [CIF](super::Cif)s.
+//!
+//! Hosts that bind many C functions dynamically often need to look up
+//! a prepared CIF by the name of the function it describes. A
+//! [`Registry`] maps names to shared [`Cif`]s and refuses to silently
+//! replace an existing entry, so two modules that disagree about a
+//! signature are detected at registration time rather than at call time.
+//!
+//! A process-wide registry is available through [`register_cif`] and
+//! [`lookup_cif`]; independent registries can be created with
+//! [`Registry::new`].
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::middle::*;
+//! use libffi::middle::registry::Registry;
+//!
+//! let registry = Registry::new();
+//! registry
+//!     .register("add", Cif::new(vec![Type::i32(), Type::i32()], Type::i32()))
+//!     .unwrap();
+//!
+//! assert!(registry.get("add").is_some());
+//! assert!(registry.get("sub").is_none());
+//! ```
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::Cif;
+
+/// The error returned when registering a CIF under a name that is
+/// already taken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateName(pub String);
+
+impl fmt::Display for DuplicateName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a CIF named `{}` is already registered", self.0)
+    }
+}
+
+impl error::Error for DuplicateName {}
+
+/// A collection of [`Cif`]s indexed by name.
+///
+/// All methods take `&self`, so a registry can be shared between
+/// threads (for example, in a `static` or an [`Arc`]).
+#[derive(Debug, Default)]
+pub struct Registry {
+    cifs: RwLock<HashMap<String, Arc<Cif>>>,
+}
+
+impl Registry {
+    /// Constructs an empty registry.
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Registers `cif` under `name`, returning a shared handle to it.
+    ///
+    /// Fails with [`DuplicateName`] if a CIF is already registered
+    /// under `name`; the existing entry is left untouched.
+    pub fn register<S: Into<String>>(&self, name: S, cif: Cif) -> Result<Arc<Cif>, DuplicateName> {
+        let name = name.into();
+        let mut cifs = self.cifs.write().unwrap();
+
+        if cifs.contains_key(&name) {
+            return Err(DuplicateName(name));
+        }
+
+        let cif = Arc::new(cif);
+        cifs.insert(name, cif.clone());
+        Ok(cif)
+    }
+
+    /// Looks up the CIF registered under `name`.
+    pub fn get(&self, name: &str) -> Option<Arc<Cif>> {
+        self.cifs.read().unwrap().get(name).cloned()
+    }
+
+    /// Removes and returns the CIF registered under `name`.
+    ///
+    /// Handles previously returned by [`Registry::get`] remain valid.
+    pub fn remove(&self, name: &str) -> Option<Arc<Cif>> {
+        self.cifs.write().unwrap().remove(name)
+    }
+
+    /// Returns whether a CIF is registered under `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.cifs.read().unwrap().contains_key(name)
+    }
+
+    /// Returns the names of all registered CIFs, in no particular order.
+    pub fn names(&self) -> Vec<String> {
+        self.cifs.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Returns the number of registered CIFs.
+    pub fn len(&self) -> usize {
+        self.cifs.read().unwrap().len()
+    }
+
+    /// Returns whether the registry is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Returns the process-wide registry used by [`register_cif`] and
+/// [`lookup_cif`].
+pub fn global() -> &'static Registry {
+    static GLOBAL: OnceLock<Registry> = OnceLock::new();
+    GLOBAL.get_or_init(Registry::new)
+}
+
+/// Registers `cif` under `name` in the [global](global) registry.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+/// use libffi::middle::registry::{lookup_cif, register_cif};
+///
+/// register_cif("sqlite3_open", Cif::new(vec![Type::pointer(); 2], Type::c_int()))
+///     .unwrap();
+///
+/// let cif = lookup_cif("sqlite3_open").unwrap();
+/// ```
+pub fn register_cif<S: Into<String>>(name: S, cif: Cif) -> Result<Arc<Cif>, DuplicateName> {
+    global().register(name, cif)
+}
+
+/// Looks up the CIF registered under `name` in the [global](global)
+/// registry.
+pub fn lookup_cif(name: &str) -> Option<Arc<Cif>> {
+    global().get(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::{arg, CodePtr, Type};
+    use std::thread;
+
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+        x + y
+    }
+
+    #[test]
+    fn duplicate_is_rejected() {
+        let registry = Registry::new();
+        registry
+            .register("f", Cif::new(vec![Type::i32()], Type::i32()))
+            .unwrap();
+
+        let err = registry
+            .register("f", Cif::new(vec![Type::u8()], Type::void()))
+            .unwrap_err();
+
+        assert_eq!(DuplicateName("f".to_owned()), err);
+        assert_eq!(1, registry.len());
+    }
+
+    #[test]
+    fn remove_keeps_handles_alive() {
+        let registry = Registry::new();
+        let cif = registry
+            .register("add", Cif::new(vec![Type::i32(), Type::i32()], Type::i32()))
+            .unwrap();
+
+        assert!(registry.remove("add").is_some());
+        assert!(!registry.contains("add"));
+
+        let n: i32 = unsafe { cif.call(CodePtr(add as *mut _), &[arg(&2i32), arg(&3i32)]) };
+        assert_eq!(5, n);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let registry = Arc::new(Registry::new());
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    registry
+                        .register(
+                            format!("f{}", i),
+                            Cif::new(vec![Type::i32(); 2], Type::i32()),
+                        )
+                        .unwrap();
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut names = registry.names();
+        names.sort();
+        assert_eq!(vec!["f0", "f1", "f2", "f3"], names);
+
+        let cif = registry.get("f2").unwrap();
+        let n: i32 = unsafe { cif.call(CodePtr(add as *mut _), &[arg(&4i32), arg(&5i32)]) };
+        assert_eq!(9, n);
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::{error, fmt, slice};
+
+use super::value::read_value;
+use super::{Arg, Cif, CodePtr, Value};
+
+/// A record of a failed call, for post-mortem debugging.
+///
+/// When a call cannot be made, or fails in a way the host wants to log,
+/// a `CallReport` captures what was being called and with which
+/// arguments: the function’s address, its CIF’s
+/// [signature](Cif::signature), and each argument decoded as a
+/// [`Value`]. Pointer arguments are recorded as addresses; the data they
+/// point to is not captured. With the `serde` feature enabled, reports
+/// implement `Serialize` and `Deserialize`, so they can be attached to
+/// crash logs as they are.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::*;
+///
+/// extern "C" fn add(x: i32, y: i32) -> i32 {
+///     x + y
+/// }
+///
+/// let cif = Cif::new(vec![Type::i32(), Type::i32()], Type::i32());
+/// let fun = CodePtr(add as *mut _);
+/// let report = unsafe { CallReport::capture(&cif, fun, &[arg(&3i32), arg(&4i32)], "timed out") };
+///
+/// assert_eq!("(i32, i32) -> i32", report.signature);
+/// assert_eq!(vec![Value::I32(3), Value::I32(4)], report.args);
+/// assert!(report.to_string().ends_with("failed: timed out\n  arg 0: 3\n  arg 1: 4"));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CallReport {
+    /// Why the call failed.
+    pub reason: String,
+    /// The address of the function.
+    pub function: u64,
+    /// The signature of the function’s CIF.
+    pub signature: String,
+    /// The arguments, in order.
+    pub args: Vec<Value>,
+}
+
+impl CallReport {
+    /// Captures a report of a call of `fun` through `cif` with `args`
+    /// that failed for `reason`.
+    ///
+    /// If `args` and the CIF disagree on the number of arguments, only
+    /// the arguments both describe are captured.
+    ///
+    /// # Safety
+    ///
+    /// Each argument must point to a value of the type the CIF gives
+    /// it, as for [`Cif::call`].
+    pub unsafe fn capture(
+        cif: &Cif,
+        fun: CodePtr,
+        args: &[Arg],
+        reason: impl Into<String>,
+    ) -> Self {
+        let raw = &*cif.as_raw_ptr();
+        let args = args
+            .iter()
+            .take(raw.nargs as usize)
+            .enumerate()
+            .map(|(i, arg)| {
+                let ty = &**raw.arg_types.add(i);
+                read_value(ty, slice::from_raw_parts(arg.0 as *const u8, ty.size))
+            })
+            .collect();
+
+        CallReport {
+            reason: reason.into(),
+            function: fun.as_ptr() as usize as u64,
+            signature: cif.signature().to_string(),
+            args,
+        }
+    }
+}
+
+impl CallReport {
+    /// Replaces the function’s address and the addresses of pointer
+    /// arguments by their handles in `handles`.
+    ///
+    /// Addresses differ between runs of a program and between machines,
+    /// so reports of the same calls rarely compare equal. Normalizing a
+    /// sequence of reports, such as a log of calls, with one
+    /// [`PointerHandles`] numbers each address by when it first
+    /// appeared, which is the same in every run that makes the same
+    /// calls; logs normalized this way can be diffed, and a replayed run
+    /// checked against a recorded one. The data pointers point to is
+    /// still not captured.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::*;
+    ///
+    /// extern "C" fn touch(_p: *const u8) {}
+    ///
+    /// let cif = Cif::new(vec![Type::pointer()], Type::void());
+    /// let fun = CodePtr(touch as *mut _);
+    /// let (a, b) = (Box::new(1u8), Box::new(2u8));
+    ///
+    /// let mut handles = PointerHandles::new();
+    /// let mut log = Vec::new();
+    /// for p in [&*a as *const u8, &*b, &*a, std::ptr::null()] {
+    ///     let mut report = unsafe { CallReport::capture(&cif, fun, &[arg(&p)], "logged") };
+    ///     report.normalize(&mut handles);
+    ///     log.push(report.args[0].clone());
+    /// }
+    /// assert_eq!(
+    ///     vec![Value::Pointer(2), Value::Pointer(3), Value::Pointer(2), Value::Pointer(0)],
+    ///     log
+    /// );
+    /// ```
+    pub fn normalize(&mut self, handles: &mut PointerHandles) {
+        self.function = handles.handle(self.function);
+        for arg in &mut self.args {
+            handles.normalize(arg);
+        }
+    }
+}
+
+/// Stable numbers for addresses, which make records of calls
+/// independent of where things happened to be in memory.
+///
+/// Each distinct address is given the next handle, counting from 1, the
+/// first time it is seen; the null address is always handle 0. See
+/// [`CallReport::normalize`].
+#[derive(Clone, Debug, Default)]
+pub struct PointerHandles {
+    handles: HashMap<u64, u64>,
+    addresses: Vec<u64>,
+}
+
+impl PointerHandles {
+    /// Creates a table that has seen no addresses.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Gets the handle of `address`, giving it the next one if it has
+    /// not been seen.
+    pub fn handle(&mut self, address: u64) -> u64 {
+        if address == 0 {
+            return 0;
+        }
+        let next = self.addresses.len() as u64 + 1;
+        let addresses = &mut self.addresses;
+        *self.handles.entry(address).or_insert_with(|| {
+            addresses.push(address);
+            next
+        })
+    }
+
+    /// Gets the address that was given `handle`, if any.
+    ///
+    /// A replayer normalizing the addresses of its own run can use this
+    /// to find the address in its run of a pointer that a recorded
+    /// call passed.
+    pub fn address(&self, handle: u64) -> Option<u64> {
+        match handle {
+            0 => Some(0),
+            _ => {
+                let index = usize::try_from(handle - 1).ok()?;
+                self.addresses.get(index).copied()
+            }
+        }
+    }
+
+    /// The number of distinct non-null addresses seen.
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Whether no non-null address has been seen.
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Replaces each pointer in `value`, including those in structs, by
+    /// its handle.
+    pub fn normalize(&mut self, value: &mut Value) {
+        match value {
+            Value::Pointer(address) => *address = self.handle(*address),
+            Value::Struct(fields) => fields.iter_mut().for_each(|field| self.normalize(field)),
+            _ => {}
+        }
+    }
+}
+
+fn write_value(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
+    match value {
+        Value::Void => f.write_str("void"),
+        Value::U8(n) => write!(f, "{}", n),
+        Value::I8(n) => write!(f, "{}", n),
+        Value::U16(n) => write!(f, "{}", n),
+        Value::I16(n) => write!(f, "{}", n),
+        Value::U32(n) => write!(f, "{}", n),
+        Value::I32(n) => write!(f, "{}", n),
+        Value::U64(n) => write!(f, "{}", n),
+        Value::I64(n) => write!(f, "{}", n),
+        Value::F32(x) => write!(f, "{:?}", x),
+        Value::F64(x) => write!(f, "{:?}", x),
+        Value::Pointer(address) => write!(f, "{:#x}", address),
+        Value::Struct(fields) => {
+            f.write_str("{")?;
+            for (i, field) in fields.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write_value(f, field)?;
+            }
+            f.write_str("}")
+        }
+        Value::Bytes(bytes) => {
+            f.write_str("0x")?;
+            bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+        }
+    }
+}
+
+impl fmt::Display for CallReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "call to {:#x} with signature {} failed: {}",
+            self.function, self.signature, self.reason
+        )?;
+        for (i, arg) in self.args.iter().enumerate() {
+            write!(f, "\n  arg {}: ", i)?;
+            write_value(f, arg)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for CallReport {}
+
+#[cfg(test)]
+mod test {
+    use std::os::raw::c_void;
+
+    use super::*;
+    use crate::middle::{arg, Type};
+
+    #[repr(C)]
+    struct Pair {
+        a: u8,
+        b: f64,
+    }
+
+    extern "C" fn consume(_p: *const c_void, _pair: Pair, _x: f32) {}
+
+    #[test]
+    fn captures_pointers_and_structs() {
+        let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+        let cif = Cif::new(vec![Type::pointer(), pair, Type::f32()], Type::void());
+        let p = 0x1000 as *const c_void;
+        let value = Pair { a: 7, b: 0.5 };
+        let report = unsafe {
+            CallReport::capture(
+                &cif,
+                CodePtr(consume as *mut _),
+                &[arg(&p), arg(&value), arg(&1.5f32)],
+                "fault",
+            )
+        };
+
+        assert_eq!(consume as *const c_void as u64, report.function);
+        assert_eq!(
+            vec![
+                Value::Pointer(0x1000),
+                Value::Struct(vec![Value::U8(7), Value::F64(0.5)]),
+                Value::F32(1.5),
+            ],
+            report.args
+        );
+        assert!(report
+            .to_string()
+            .ends_with("\n  arg 0: 0x1000\n  arg 1: {7, 0.5}\n  arg 2: 1.5"));
+    }
+
+    extern "C" fn link(_node: *const c_void, _pair: Pair) {}
+
+    #[test]
+    fn normalizes_addresses() {
+        let pair = Type::structure(vec![Type::pointer(), Type::u64()]);
+        let cif = Cif::new(vec![Type::pointer(), pair], Type::void());
+        let fun = CodePtr(link as *mut _);
+        let capture = |node: u64, next: u64| {
+            #[repr(C)]
+            struct Node(*const c_void, u64);
+            let args = (
+                node as usize as *const c_void,
+                Node(next as usize as *const _, 9),
+            );
+            unsafe { CallReport::capture(&cif, fun, &[arg(&args.0), arg(&args.1)], "") }
+        };
+
+        // The same calls, with the nodes at different addresses.
+        let record = |base: u64| {
+            let mut handles = PointerHandles::new();
+            let mut log = vec![
+                capture(base, base + 0x40),
+                capture(base + 0x40, 0),
+                capture(base, base + 0x40),
+            ];
+            log.iter_mut()
+                .for_each(|report| report.normalize(&mut handles));
+            (log, handles)
+        };
+        let (first, handles) = record(0x10_0000);
+        let (second, _) = record(0x7f00_0000);
+        assert_eq!(first, second);
+
+        assert_eq!(1, first[0].function);
+        assert_eq!(
+            vec![
+                Value::Pointer(2),
+                Value::Struct(vec![Value::Pointer(3), Value::U64(9)]),
+            ],
+            first[0].args
+        );
+        assert_eq!(
+            Value::Struct(vec![Value::Pointer(0), Value::U64(9)]),
+            first[1].args[1]
+        );
+        assert_eq!(3, handles.len());
+        assert_eq!(Some(0x10_0040), handles.address(3));
+        assert_eq!(Some(0), handles.address(0));
+        assert_eq!(None, handles.address(4));
+        assert_eq!(None, handles.address(u64::MAX));
+    }
+}
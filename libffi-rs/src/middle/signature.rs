@@ -0,0 +1,885 @@
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::num::IntErrorKind;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::error;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use super::{Cif, FfiAbi, Type};
+use crate::{low, raw};
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        unsafe { write_type(f, &*self.as_raw_ptr()) }
+    }
+}
+
+impl fmt::Display for Cif {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cif = unsafe { &*self.as_raw_ptr() };
+        let args = unsafe { core::slice::from_raw_parts(cif.arg_types, cif.nargs as usize) };
+        unsafe {
+            write_signature(
+                f,
+                cif.abi,
+                args.iter().map(|&arg| &*arg),
+                self.nfixed,
+                &*cif.rtype,
+            )
+        }
+    }
+}
+
+// A signature as parsed, before a CIF is prepared for it.
+struct Parsed {
+    // The calling convention, if given, and the offset of its number.
+    abi: Option<(usize, FfiAbi)>,
+    args: Vec<Type>,
+    nfixed: Option<usize>,
+    result: Type,
+}
+
+impl fmt::Display for Parsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let abi = self
+            .abi
+            .map_or(low::ffi_abi_FFI_DEFAULT_ABI, |(_, abi)| abi);
+        unsafe {
+            write_signature(
+                f,
+                abi,
+                self.args.iter().map(|arg| &*arg.as_raw_ptr()),
+                self.nfixed,
+                &*self.result.as_raw_ptr(),
+            )
+        }
+    }
+}
+
+/// Rewrites a signature in its canonical text form, as described at
+/// [`Cif::signature`], without preparing a CIF for it.
+#[cfg(feature = "std")]
+pub(crate) fn canonical_signature(text: &str) -> Result<String, ParseError> {
+    let mut parser = Parser::new(text);
+    let parsed = parser.signature()?;
+    parser.end()?;
+    Ok(parsed.to_string())
+}
+
+impl Cif {
+    /// Returns the canonical text form of the CIF’s signature.
+    ///
+    /// Types are written as their names (`void`, `u8`, `i8`, `u16`,
+    /// `i16`, `u32`, `i32`, `u64`, `i64`, `f32`, `f64`, `pointer`,
+    /// `longdouble`, `c32`, `c64`, and `complex_longdouble`), and
+    /// structs as their field types in braces, such as
+    /// `{u8, {f64, pointer}}`. A signature is its argument types in
+    /// parentheses followed by its result type, such as
+    /// `(u32, pointer) -> i32`, preceded by `abi(n) ` if the CIF uses a
+    /// calling convention other than the default. The variable
+    /// arguments of a [variadic](Cif::new_variadic) CIF follow a `...`,
+    /// as in `(pointer, ..., f64) -> i32`. Field names are not
+    /// part of the form. This is also the [`Display`](fmt::Display)
+    /// form of CIFs and [`Type`]s, and CIFs and types can be parsed
+    /// back from it with [`str::parse`].
+    ///
+    /// Parsing also accepts two forms that are not canonical. A pointer
+    /// may be written as `*` followed by the type it points to, such as
+    /// `*void` or `**u8`, which only documents the pointee, and a C
+    /// array as `[T; N]`, such as `[f64; 3]`, which is the structure
+    /// [`Type::array`] describes and is written as one.
+    ///
+    /// Parsing rejects types nested more than 64 deep, and text whose
+    /// arrays describe more than 1 MiB in all, since each array element
+    /// is a copy of its type.
+    ///
+    /// The text is interned: CIFs with the same signature share one
+    /// allocation while any of its [`Arc`]s is alive, which makes it
+    /// cheap to keep as a cache or log key. The string is freed when
+    /// its last `Arc` is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Cif, Type};
+    ///
+    /// let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+    /// let cif = Cif::new(vec![Type::u32(), Type::pointer(), pair], Type::i32());
+    /// assert_eq!("(u32, pointer, {u8, f64}) -> i32", &*cif.signature());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn signature(&self) -> Arc<str> {
+        static SIGNATURES: OnceLock<Mutex<Interned>> = OnceLock::new();
+        let text = self.to_string();
+        let mut signatures = SIGNATURES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        signatures.intern(text)
+    }
+}
+
+// Signatures handed out by `Cif::signature`, held weakly so that each
+// is freed with its last `Arc`.
+#[cfg(feature = "std")]
+#[derive(Default)]
+struct Interned {
+    signatures: HashMap<String, Weak<str>>,
+    // The number of entries at which dead ones are next pruned, kept at
+    // twice the number left by the last pruning so that pruning takes
+    // amortized constant time per signature.
+    prune_at: usize,
+}
+
+#[cfg(feature = "std")]
+impl Interned {
+    fn intern(&mut self, text: String) -> Arc<str> {
+        if let Some(signature) = self.signatures.get(&text).and_then(Weak::upgrade) {
+            return signature;
+        }
+
+        if self.signatures.len() >= self.prune_at {
+            self.signatures
+                .retain(|_, signature| signature.strong_count() > 0);
+            self.prune_at = (self.signatures.len() * 2).max(16);
+        }
+        let signature: Arc<str> = text.as_str().into();
+        self.signatures.insert(text, Arc::downgrade(&signature));
+        signature
+    }
+}
+
+// The deepest that braces, pointers and arrays may be nested in a
+// parsed type.
+const MAX_DEPTH: usize = 64;
+
+// The most bytes that the arrays in a parsed type or signature may
+// describe, in all.
+const MAX_ARRAY_BYTES: usize = 1 << 20;
+
+/// The error returned when a type or signature fails to parse.
+///
+/// Each variant carries the byte offset in the text at which the
+/// problem was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Something other than what the grammar allows was found.
+    Expected {
+        /// The offset of the unexpected text.
+        offset: usize,
+        /// A description of what was expected.
+        expected: &'static str,
+    },
+    /// A name is not the name of a type.
+    UnknownType {
+        /// The offset of the name.
+        offset: usize,
+        /// The name.
+        name: String,
+    },
+    /// `void` was used as an argument or field type.
+    Void {
+        /// The offset of the `void`.
+        offset: usize,
+    },
+    /// The calling convention is not supported on this platform.
+    Abi {
+        /// The offset of the calling convention’s number.
+        offset: usize,
+        /// The calling convention’s number.
+        abi: FfiAbi,
+    },
+    /// Types are nested more than 64 deep.
+    TooDeep {
+        /// The offset of the type that is too deep.
+        offset: usize,
+    },
+    /// The arrays describe more than 1 MiB.
+    TooLarge {
+        /// The offset of the array length that exceeds the limit.
+        offset: usize,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Expected { offset, expected } => {
+                write!(f, "expected {} at offset {}", expected, offset)
+            }
+            ParseError::UnknownType { offset, name } => {
+                write!(f, "unknown type `{}` at offset {}", name, offset)
+            }
+            ParseError::Void { offset } => write!(
+                f,
+                "`void` at offset {} is only allowed as a result type",
+                offset
+            ),
+            ParseError::Abi { offset, abi } => write!(
+                f,
+                "calling convention {} at offset {} is not supported",
+                abi, offset
+            ),
+            ParseError::TooDeep { offset } => write!(
+                f,
+                "type at offset {} is nested more than {} deep",
+                offset, MAX_DEPTH
+            ),
+            ParseError::TooLarge { offset } => write!(
+                f,
+                "array at offset {} takes the arrays past {} bytes",
+                offset, MAX_ARRAY_BYTES
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for ParseError {}
+
+impl FromStr for Type {
+    type Err = ParseError;
+
+    /// Parses a type from its canonical text form, as described at
+    /// [`Cif::signature`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Type;
+    ///
+    /// let pair: Type = "{u8, f64}".parse().unwrap();
+    /// assert_eq!("{u8, f64}", pair.to_string());
+    ///
+    /// let node: Type = "{*void, [i16; 2]}".parse().unwrap();
+    /// assert_eq!("{pointer, {i16, i16}}", node.to_string());
+    /// ```
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(text);
+        let ty = parser.result_type()?;
+        parser.end()?;
+        Ok(ty)
+    }
+}
+
+impl FromStr for Cif {
+    type Err = ParseError;
+
+    /// Parses a signature from its canonical text form, as described at
+    /// [`Cif::signature`], and prepares a CIF for it.
+    ///
+    /// The parentheses around the argument types may be left out, and
+    /// any amount of whitespace may separate the parts.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Cif;
+    ///
+    /// let cif: Cif = "u32, pointer -> i32".parse().unwrap();
+    /// assert_eq!("(u32, pointer) -> i32", cif.to_string());
+    ///
+    /// let cif: Cif = "(i32, f64, *void) -> u64".parse().unwrap();
+    /// assert_eq!("(i32, f64, pointer) -> u64", cif.to_string());
+    /// ```
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(text);
+        let parsed = parser.signature()?;
+        parser.end()?;
+
+        let Parsed {
+            abi,
+            args,
+            nfixed,
+            result,
+        } = parsed;
+        match abi {
+            None => Ok(Cif::prepare(
+                args,
+                nfixed,
+                result,
+                low::ffi_abi_FFI_DEFAULT_ABI,
+            )),
+            Some((offset, abi)) => Cif::try_prepare(args, nfixed, result, abi)
+                .map_err(|_| ParseError::Abi { offset, abi }),
+        }
+    }
+}
+
+impl TryFrom<&str> for Type {
+    type Error = ParseError;
+
+    fn try_from(text: &str) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl TryFrom<&str> for Cif {
+    type Error = ParseError;
+
+    fn try_from(text: &str) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+// A recursive descent parser for the canonical form.
+struct Parser<'a> {
+    text: &'a str,
+    offset: usize,
+    // How deeply the type being parsed is nested.
+    depth: usize,
+    // The bytes described by the arrays parsed so far.
+    array_bytes: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Parser {
+            text,
+            offset: 0,
+            depth: 0,
+            array_bytes: 0,
+        }
+    }
+
+    // Skips whitespace, returning the rest of the text.
+    fn rest(&mut self) -> &str {
+        let rest = &self.text[self.offset..];
+        self.offset += rest.len() - rest.trim_start().len();
+        &self.text[self.offset..]
+    }
+
+    // Consumes `token` if the text continues with it.
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.offset += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str, expected: &'static str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.expected(expected))
+        }
+    }
+
+    fn expected(&self, expected: &'static str) -> ParseError {
+        ParseError::Expected {
+            offset: self.offset,
+            expected,
+        }
+    }
+
+    fn end(&mut self) -> Result<(), ParseError> {
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            Err(self.expected("end of input"))
+        }
+    }
+
+    // Consumes a run of characters matching `pred`.
+    fn word(&mut self, pred: impl Fn(char) -> bool) -> (usize, &str) {
+        let start = self.offset;
+        let len = self
+            .rest()
+            .find(|c: char| !pred(c))
+            .unwrap_or(self.text.len() - start);
+        self.offset += len;
+        (start, &self.text[start..self.offset])
+    }
+
+    fn signature(&mut self) -> Result<Parsed, ParseError> {
+        let abi = if self.eat("abi") {
+            self.expect("(", "`(`")?;
+            self.rest();
+            let (offset, digits) = self.word(|c| c.is_ascii_digit());
+            let abi = digits.parse().map_err(|_| ParseError::Expected {
+                offset,
+                expected: "a calling convention number",
+            })?;
+            // These are the bounds libffi checks when preparing a CIF.
+            if abi <= raw::ffi_abi_FFI_FIRST_ABI || abi >= raw::ffi_abi_FFI_LAST_ABI {
+                return Err(ParseError::Abi { offset, abi });
+            }
+            self.expect(")", "`)`")?;
+            Some((offset, abi))
+        } else {
+            None
+        };
+
+        let mut args = (Vec::new(), None);
+        if self.eat("(") {
+            if !self.eat(")") {
+                args = self.arguments()?;
+                self.expect(")", "`,` or `)`")?;
+            }
+        } else if !self.rest().starts_with("->") {
+            args = self.arguments()?;
+        }
+        self.expect("->", "`->`")?;
+        let result = self.result_type()?;
+
+        let (args, nfixed) = args;
+        Ok(Parsed {
+            abi,
+            args,
+            nfixed,
+            result,
+        })
+    }
+
+    // Parses a non-empty, comma-separated list of argument types, with
+    // at most one `...` marking the start of the variable arguments.
+    fn arguments(&mut self) -> Result<(Vec<Type>, Option<usize>), ParseError> {
+        let mut args = Vec::new();
+        let mut nfixed = None;
+        loop {
+            if nfixed.is_none() && self.eat("...") {
+                nfixed = Some(args.len());
+            } else {
+                args.push(self.field()?);
+            }
+            if !self.eat(",") {
+                return Ok((args, nfixed));
+            }
+        }
+    }
+
+    // Parses a non-empty, comma-separated list of non-void types.
+    fn fields(&mut self) -> Result<Vec<Type>, ParseError> {
+        let mut fields = vec![self.field()?];
+        while self.eat(",") {
+            fields.push(self.field()?);
+        }
+        Ok(fields)
+    }
+
+    fn field(&mut self) -> Result<Type, ParseError> {
+        self.rest();
+        let offset = self.offset;
+        let ty = self.result_type()?;
+        if u32::from(unsafe { (*ty.as_raw_ptr()).type_ }) == raw::FFI_TYPE_VOID {
+            return Err(ParseError::Void { offset });
+        }
+        Ok(ty)
+    }
+
+    fn result_type(&mut self) -> Result<Type, ParseError> {
+        self.rest();
+        if self.depth == MAX_DEPTH {
+            return Err(ParseError::TooDeep {
+                offset: self.offset,
+            });
+        }
+        self.depth += 1;
+        let ty = self.nested_type();
+        self.depth -= 1;
+        ty
+    }
+
+    fn nested_type(&mut self) -> Result<Type, ParseError> {
+        if self.eat("{") {
+            let fields = self.fields()?;
+            self.expect("}", "`,` or `}`")?;
+            return Ok(Type::structure(fields));
+        }
+        if self.eat("*") {
+            // The pointee, which may be `void`, only documents the
+            // pointer.
+            self.result_type()?;
+            return Ok(Type::pointer());
+        }
+        if self.eat("[") {
+            let element = self.field()?;
+            self.expect(";", "`;`")?;
+            self.rest();
+            let (offset, digits) = self.word(|c| c.is_ascii_digit());
+            let count = match digits.parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                Err(error) if *error.kind() == IntErrorKind::PosOverflow => {
+                    return Err(ParseError::TooLarge { offset })
+                }
+                _ => {
+                    return Err(ParseError::Expected {
+                        offset,
+                        expected: "a positive array length",
+                    })
+                }
+            };
+            // Check the size before `Type::array` copies the element.
+            self.array_bytes = element
+                .layout()
+                .0
+                .checked_mul(count)
+                .and_then(|size| self.array_bytes.checked_add(size))
+                .filter(|&bytes| bytes <= MAX_ARRAY_BYTES)
+                .ok_or(ParseError::TooLarge { offset })?;
+            self.expect("]", "`]`")?;
+            return Ok(Type::array(element, count));
+        }
+
+        self.rest();
+        let (offset, name) = self.word(|c| c.is_ascii_alphanumeric() || c == '_');
+        let ty = match name {
+            "void" => Type::void(),
+            "u8" => Type::u8(),
+            "i8" => Type::i8(),
+            "u16" => Type::u16(),
+            "i16" => Type::i16(),
+            "u32" => Type::u32(),
+            "i32" => Type::i32(),
+            "u64" => Type::u64(),
+            "i64" => Type::i64(),
+            "f32" => Type::f32(),
+            "f64" => Type::f64(),
+            "pointer" => Type::pointer(),
+            #[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
+            "longdouble" => Type::longdouble(),
+            #[cfg(feature = "complex")]
+            "c32" => Type::c32(),
+            #[cfg(feature = "complex")]
+            "c64" => Type::c64(),
+            #[cfg(feature = "complex")]
+            #[cfg(not(all(target_arch = "arm")))]
+            "complex_longdouble" => Type::complex_longdouble(),
+            "" => return Err(self.expected("a type")),
+            _ => {
+                return Err(ParseError::UnknownType {
+                    offset,
+                    name: name.to_owned(),
+                })
+            }
+        };
+        Ok(ty)
+    }
+}
+
+impl Cif {
+    // Whether the CIF’s result type has the same canonical form as
+    // `result`.
+    #[cfg(feature = "libloading")]
+    pub(crate) fn has_result(&self, result: &Type) -> bool {
+        unsafe { same_type(&*self.cif.rtype, &*result.as_raw_ptr()) }
+    }
+
+    // Whether the CIF’s argument types have the same canonical forms as
+    // `args`.
+    #[cfg(feature = "libloading")]
+    pub(crate) fn has_args<'t, I>(&self, args: I) -> bool
+    where
+        I: ExactSizeIterator<Item = &'t Type>,
+    {
+        self.cif.nargs as usize == args.len()
+            && args.enumerate().all(|(i, arg)| unsafe {
+                same_type(&**self.cif.arg_types.add(i), &*arg.as_raw_ptr())
+            })
+    }
+}
+
+// Whether `a` and `b` have the same canonical form.
+#[cfg(feature = "libloading")]
+unsafe fn same_type(a: &low::ffi_type, b: &low::ffi_type) -> bool {
+    let tag = |ty: &low::ffi_type| match u32::from(ty.type_) {
+        raw::FFI_TYPE_INT => raw::FFI_TYPE_SINT32,
+        tag => tag,
+    };
+    if tag(a) != tag(b) {
+        return false;
+    }
+    if !matches!(tag(a), raw::FFI_TYPE_STRUCT | raw::FFI_TYPE_COMPLEX) {
+        return true;
+    }
+
+    let (mut x, mut y) = (a.elements, b.elements);
+    loop {
+        match ((*x).is_null(), (*y).is_null()) {
+            (true, true) => return true,
+            (false, false) if same_type(&**x, &**y) => {
+                x = x.add(1);
+                y = y.add(1);
+            }
+            _ => return false,
+        }
+    }
+}
+
+// Writes the canonical form of a signature.
+unsafe fn write_signature<'t, I>(
+    f: &mut fmt::Formatter,
+    abi: FfiAbi,
+    args: I,
+    nfixed: Option<usize>,
+    rtype: &low::ffi_type,
+) -> fmt::Result
+where
+    I: ExactSizeIterator<Item = &'t low::ffi_type>,
+{
+    if abi != low::ffi_abi_FFI_DEFAULT_ABI {
+        write!(f, "abi({}) ", abi)?;
+    }
+
+    f.write_str("(")?;
+    let nargs = args.len();
+    for (i, arg) in args.enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        if nfixed == Some(i) {
+            f.write_str("..., ")?;
+        }
+        write_type(f, arg)?;
+    }
+    match nfixed {
+        Some(0) if nargs == 0 => f.write_str("...")?,
+        Some(n) if n == nargs => f.write_str(", ...")?,
+        _ => {}
+    }
+    f.write_str(") -> ")?;
+    write_type(f, rtype)
+}
+
+// Writes the canonical form of `ty`.
+unsafe fn write_type(f: &mut fmt::Formatter, ty: &low::ffi_type) -> fmt::Result {
+    let name = match u32::from(ty.type_) {
+        raw::FFI_TYPE_VOID => "void",
+        raw::FFI_TYPE_UINT8 => "u8",
+        raw::FFI_TYPE_SINT8 => "i8",
+        raw::FFI_TYPE_UINT16 => "u16",
+        raw::FFI_TYPE_SINT16 => "i16",
+        raw::FFI_TYPE_UINT32 => "u32",
+        raw::FFI_TYPE_SINT32 | raw::FFI_TYPE_INT => "i32",
+        raw::FFI_TYPE_UINT64 => "u64",
+        raw::FFI_TYPE_SINT64 => "i64",
+        raw::FFI_TYPE_FLOAT => "f32",
+        raw::FFI_TYPE_DOUBLE => "f64",
+        raw::FFI_TYPE_POINTER => "pointer",
+        raw::FFI_TYPE_STRUCT => {
+            f.write_str("{")?;
+            let mut element = ty.elements;
+            while !(*element).is_null() {
+                if element != ty.elements {
+                    f.write_str(", ")?;
+                }
+                write_type(f, &**element)?;
+                element = element.add(1);
+            }
+            return f.write_str("}");
+        }
+        raw::FFI_TYPE_COMPLEX => match u32::from((**ty.elements).type_) {
+            raw::FFI_TYPE_FLOAT => "c32",
+            raw::FFI_TYPE_DOUBLE => "c64",
+            _ => "complex_longdouble",
+        },
+        // Only `long double` is left. On targets where it is the same
+        // as `double`, libffi gives it the tag of `double` instead.
+        _ => "longdouble",
+    };
+    f.write_str(name)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signatures_are_interned() {
+        let cif = || Cif::new(vec![Type::pointer(), Type::c_int()], Type::void());
+        let first = cif().signature();
+        assert_eq!("(pointer, i32) -> void", &*first);
+        assert!(Arc::ptr_eq(&first, &cif().signature()));
+
+        // A signature no other test uses, so that nothing else holds it.
+        let unique = || Cif::new(vec![Type::u16(), Type::f32(), Type::u16()], Type::i8());
+        let weak = Arc::downgrade(&unique().signature());
+        assert!(weak.upgrade().is_none());
+        assert_eq!("(u16, f32, u16) -> i8", &*unique().signature());
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let abi = crate::middle::Abi::Win64.as_raw();
+            let mut other = cif();
+            other.set_abi(abi);
+            assert_eq!(
+                format!("abi({}) (pointer, i32) -> void", abi),
+                &*other.signature()
+            );
+        }
+    }
+
+    #[test]
+    fn nested_structs() {
+        let inner = Type::structure(vec![Type::i16(), Type::f32()]);
+        let outer = Type::structure_named(vec![("a", Type::u64()), ("b", inner)]);
+        assert_eq!("{u64, {i16, f32}}", outer.to_string());
+        assert_eq!(
+            "() -> {u64, {i16, f32}}",
+            Cif::new(vec![], outer).to_string()
+        );
+    }
+
+    #[test]
+    fn signatures_round_trip() {
+        for text in [
+            "() -> void",
+            "(u8, i8, u16, i16, u32, i32, u64, i64) -> f32",
+            "({pointer, {f64}}, f32) -> {u8, u8}",
+        ] {
+            let cif: Cif = text.parse().unwrap();
+            assert_eq!(text, &*cif.signature());
+            assert_eq!(cif.signature(), cif.clone().signature());
+        }
+
+        let cif = Cif::try_from("  u64 ,{ i8,pointer }->void ").unwrap();
+        assert_eq!("(u64, {i8, pointer}) -> void", cif.to_string());
+        assert_eq!("-> i32".parse::<Cif>().unwrap().to_string(), "() -> i32");
+    }
+
+    #[test]
+    fn pointers_and_arrays() {
+        let cif: Cif = "(*void, **u8, *{i32, *void}) -> *f64".parse().unwrap();
+        assert_eq!("(pointer, pointer, pointer) -> pointer", cif.to_string());
+
+        let matrix: Type = "[[f32; 3]; 2]".parse().unwrap();
+        assert_eq!("{{f32, f32, f32}, {f32, f32, f32}}", matrix.to_string());
+        assert_eq!((24, 4), matrix.layout());
+
+        let cif: Cif = "({u8, [u16 ; 3]}, [*void;1]) -> void".parse().unwrap();
+        assert_eq!(Some((8, 2)), cif.arg_layout(0));
+        assert_eq!(
+            "({u8, {u16, u16, u16}}, {pointer}) -> void",
+            cif.to_string()
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| text.parse::<Cif>().unwrap_err();
+
+        assert_eq!(
+            ParseError::UnknownType {
+                offset: 5,
+                name: "u128".to_owned()
+            },
+            error("(u8, u128) -> void")
+        );
+        assert_eq!(ParseError::Void { offset: 1 }, error("(void) -> void"));
+        assert_eq!(ParseError::Void { offset: 5 }, error("{u8, void} -> u8"));
+        assert_eq!(
+            ParseError::Expected {
+                offset: 3,
+                expected: "`->`"
+            },
+            error("u8 i32")
+        );
+        assert_eq!(
+            ParseError::Expected {
+                offset: 1,
+                expected: "a type"
+            },
+            error("{} -> void")
+        );
+        assert_eq!(
+            ParseError::Expected {
+                offset: 8,
+                expected: "end of input"
+            },
+            error("() -> u8, u8")
+        );
+        assert_eq!(
+            ParseError::Abi {
+                offset: 4,
+                abi: raw::ffi_abi_FFI_LAST_ABI
+            },
+            error(&format!("abi({}) () -> void", raw::ffi_abi_FFI_LAST_ABI))
+        );
+        assert_eq!(
+            ParseError::Expected {
+                offset: 8,
+                expected: "end of input"
+            },
+            "pointer *".parse::<Type>().unwrap_err()
+        );
+        assert_eq!(
+            ParseError::Expected {
+                offset: 5,
+                expected: "a positive array length"
+            },
+            "[u8; 0]".parse::<Type>().unwrap_err()
+        );
+        assert_eq!(
+            ParseError::Expected {
+                offset: 3,
+                expected: "`;`"
+            },
+            "[u8]".parse::<Type>().unwrap_err()
+        );
+        assert_eq!(ParseError::Void { offset: 1 }, error("[void; 2] -> void"));
+        assert_eq!(
+            ParseError::Expected {
+                offset: 1,
+                expected: "a type"
+            },
+            "*".parse::<Type>().unwrap_err()
+        );
+    }
+
+    #[test]
+    fn parse_limits() {
+        let nested = |depth| format!("{}u8{}", "{".repeat(depth), "}".repeat(depth));
+        assert!(nested(MAX_DEPTH - 1).parse::<Type>().is_ok());
+        assert_eq!(
+            ParseError::TooDeep { offset: MAX_DEPTH },
+            nested(MAX_DEPTH).parse::<Type>().unwrap_err()
+        );
+        assert_eq!(
+            ParseError::TooDeep { offset: 64 },
+            format!("{}void", "*".repeat(100))
+                .parse::<Type>()
+                .unwrap_err()
+        );
+
+        assert!("[u8; 1048576]".parse::<Type>().is_ok());
+        assert_eq!(
+            ParseError::TooLarge { offset: 5 },
+            "[u8; 1048577]".parse::<Type>().unwrap_err()
+        );
+        assert_eq!(
+            ParseError::TooLarge { offset: 15 },
+            "[[u64; 65536]; 65536]".parse::<Type>().unwrap_err()
+        );
+        assert_eq!(
+            ParseError::TooLarge { offset: 6 },
+            "[u64; 99999999999999999999]".parse::<Type>().unwrap_err()
+        );
+        assert_eq!(
+            ParseError::TooLarge { offset: 23 },
+            "({[u8; 1048576]}, [u8; 1]) -> void"
+                .parse::<Cif>()
+                .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn abi_is_prepared() {
+        let abi = low::ffi_abi_FFI_DEFAULT_ABI;
+        let cif: Cif = format!("abi({}) (u8) -> i32", abi).parse().unwrap();
+        assert_eq!(abi, cif.abi());
+        assert_eq!(
+            cif.signature(),
+            "(u8) -> i32".parse::<Cif>().unwrap().signature()
+        );
+    }
+}
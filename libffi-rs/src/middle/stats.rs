@@ -0,0 +1,322 @@
+//! Latency statistics for closures.
+//!
+//! With the `stats` feature enabled, every [`Closure`](super::Closure)
+//! and [`ClosureOnce`](super::ClosureOnce) records how long each call
+//! of its callback takes in a [`LatencyHistogram`], which can be
+//! retrieved with `latency_histogram()`. This makes it possible to find
+//! slow callbacks without an external profiler. Without the feature,
+//! closures call their callbacks directly and this module is absent.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::high::Closure1;
+//!
+//! let f = |x: u32| x + 1;
+//! let closure = Closure1::new(&f);
+//! for i in 0..100 {
+//!     closure.code_ptr().call(i);
+//! }
+//!
+//! let histogram = closure.latency_histogram();
+//! assert_eq!(100, histogram.count());
+//! assert!(histogram.percentile(50.0).unwrap() <= histogram.max().unwrap());
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::low;
+
+// Each power of two is split into 2^SUB_BITS linearly spaced buckets,
+// so a bucket’s width is at most 1/2^SUB_BITS of its lower bound.
+const SUB_BITS: u32 = 3;
+const SUB_COUNT: usize = 1 << SUB_BITS;
+const BUCKET_COUNT: usize = SUB_COUNT * (64 - SUB_BITS as usize + 1);
+
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < SUB_COUNT as u64 {
+        return nanos as usize;
+    }
+    let exp = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (exp - SUB_BITS)) as usize & (SUB_COUNT - 1);
+    SUB_COUNT * (exp - SUB_BITS + 1) as usize + sub
+}
+
+// The inclusive lower and exclusive upper bounds of a bucket, in
+// nanoseconds.
+fn bucket_bounds(index: usize) -> (u64, u64) {
+    if index < SUB_COUNT {
+        return (index as u64, index as u64 + 1);
+    }
+    let shift = (index / SUB_COUNT - 1) as u32;
+    let sub = (index % SUB_COUNT) as u64;
+    let low = (SUB_COUNT as u64 + sub) << shift;
+    (low, low.saturating_add(1 << shift))
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+/// A histogram of call latencies with logarithmically sized buckets.
+///
+/// Each power of two of nanoseconds is divided into eight buckets, so
+/// latencies are recorded with a relative precision of 12.5% over the
+/// whole range of [`Duration`]s that fit in 64 bits of nanoseconds.
+/// Recording is lock-free, so callbacks invoked concurrently from many
+/// threads can share a histogram.
+pub struct LatencyHistogram {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+    total: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+/// One bucket of a [`LatencyHistogram`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bucket {
+    /// The smallest latency counted by the bucket.
+    pub low: Duration,
+    /// The smallest latency above `low` not counted by the bucket.
+    pub high: Duration,
+    /// The number of calls whose latency fell in the bucket.
+    pub count: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram::new()
+    }
+}
+
+impl LatencyHistogram {
+    /// Creates an empty histogram.
+    pub fn new() -> Self {
+        LatencyHistogram {
+            buckets: (0..BUCKET_COUNT).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    /// Records one call that took `latency`.
+    pub fn record(&self, latency: Duration) {
+        let nanos = nanos(latency);
+        self.buckets[bucket_index(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(nanos, Ordering::Relaxed);
+        self.min.fetch_min(nanos, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// The number of calls recorded.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// The shortest latency recorded, if any.
+    pub fn min(&self) -> Option<Duration> {
+        match self.min.load(Ordering::Relaxed) {
+            u64::MAX if self.count() == 0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// The longest latency recorded, if any.
+    pub fn max(&self) -> Option<Duration> {
+        if self.count() == 0 {
+            None
+        } else {
+            Some(Duration::from_nanos(self.max.load(Ordering::Relaxed)))
+        }
+    }
+
+    /// The mean latency, if any calls were recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                self.total.load(Ordering::Relaxed) / count,
+            )),
+        }
+    }
+
+    /// An upper bound on the latency of the given percentage of calls.
+    ///
+    /// The result is the upper end of the bucket containing the
+    /// requested percentile, but never more than [`max`](Self::max).
+    /// Returns `None` if no calls were recorded.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0.0 <= percent <= 100.0`.
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        assert!(
+            (0.0..=100.0).contains(&percent),
+            "LatencyHistogram::percentile: {} is not a percentage",
+            percent
+        );
+
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percent / 100.0 * count as f64).ceil() as u64).max(1);
+
+        let max = self.max.load(Ordering::Relaxed);
+        let mut seen = 0;
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                let (_, high) = bucket_bounds(index);
+                return Some(Duration::from_nanos((high - 1).min(max)));
+            }
+        }
+        Some(Duration::from_nanos(max))
+    }
+
+    /// The non-empty buckets, in increasing order of latency.
+    pub fn buckets(&self) -> Vec<Bucket> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bucket)| {
+                let count = bucket.load(Ordering::Relaxed);
+                if count == 0 {
+                    return None;
+                }
+                let (low, high) = bucket_bounds(index);
+                Some(Bucket {
+                    low: Duration::from_nanos(low),
+                    high: Duration::from_nanos(high),
+                    count,
+                })
+            })
+            .collect()
+    }
+
+    /// Discards all recorded calls.
+    pub fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.total.store(0, Ordering::Relaxed);
+        self.min.store(u64::MAX, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LatencyHistogram")
+            .field("count", &self.count())
+            .field("min", &self.min())
+            .field("mean", &self.mean())
+            .field("max", &self.max())
+            .finish()
+    }
+}
+
+/// The userdata of a closure’s timing trampoline: the closure’s real
+/// callback and userdata, and the histogram to record calls in.
+#[derive(Debug)]
+pub(crate) struct Instrumented {
+    callback: low::RawCallback,
+    userdata: *mut c_void,
+    histogram: Arc<LatencyHistogram>,
+}
+
+impl Instrumented {
+    pub(crate) fn new(callback: low::RawCallback, userdata: *mut c_void) -> Box<Self> {
+        Box::new(Instrumented {
+            callback,
+            userdata,
+            histogram: Arc::new(LatencyHistogram::new()),
+        })
+    }
+
+    pub(crate) fn histogram(&self) -> &Arc<LatencyHistogram> {
+        &self.histogram
+    }
+}
+
+/// Calls the real callback, recording how long it takes.
+pub(crate) unsafe extern "C" fn trampoline(
+    cif: &low::ffi_cif,
+    result: &mut c_void,
+    args: *const *const c_void,
+    instrumented: &Instrumented,
+) {
+    let start = Instant::now();
+    (instrumented.callback)(
+        cif as *const _ as *mut _,
+        result,
+        args as *mut *mut c_void,
+        instrumented.userdata,
+    );
+    instrumented.histogram.record(start.elapsed());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket_bounds_contain_values() {
+        for &nanos in &[0, 1, 7, 8, 9, 15, 16, 17, 1000, 123_456_789, u64::MAX] {
+            let (low, high) = bucket_bounds(bucket_index(nanos));
+            assert!(low <= nanos, "{} < {}", nanos, low);
+            assert!(nanos < high || high == u64::MAX, "{} >= {}", nanos, high);
+        }
+        assert_eq!(BUCKET_COUNT - 1, bucket_index(u64::MAX));
+    }
+
+    #[test]
+    fn summary_statistics() {
+        let histogram = LatencyHistogram::new();
+        assert_eq!(None, histogram.percentile(50.0));
+
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+
+        assert_eq!(100, histogram.count());
+        assert_eq!(Some(Duration::from_micros(1)), histogram.min());
+        assert_eq!(Some(Duration::from_micros(100)), histogram.max());
+        assert_eq!(Some(Duration::from_nanos(50_500)), histogram.mean());
+
+        let median = histogram.percentile(50.0).unwrap();
+        assert!(median >= Duration::from_micros(50));
+        assert!(median <= Duration::from_micros(50) * 9 / 8);
+        assert_eq!(histogram.max(), histogram.percentile(100.0));
+
+        let total: u64 = histogram.buckets().iter().map(|b| b.count).sum();
+        assert_eq!(100, total);
+
+        histogram.reset();
+        assert_eq!(0, histogram.count());
+        assert_eq!(None, histogram.max());
+    }
+
+    #[test]
+    fn closures_record_calls() {
+        use crate::high::Closure1;
+
+        let f = |x: u32| x * 2;
+        let closure = Closure1::new(&f);
+
+        assert_eq!(0, closure.latency_histogram().count());
+        assert_eq!(6, closure.code_ptr().call(3));
+        assert_eq!(8, closure.code_ptr().call(4));
+        assert_eq!(2, closure.latency_histogram().count());
+    }
+}
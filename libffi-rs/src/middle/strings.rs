@@ -0,0 +1,437 @@
+use std::ffi::{CStr, CString, NulError};
+use std::os::raw::{c_char, c_int};
+use std::{ptr, slice};
+
+use super::{arg, Cif, CodePtr, Type};
+
+/// The arguments of a C `main`-style entry point, `argc` and `argv`.
+///
+/// Interpreters and tools embedded as libraries are often entered
+/// through a function with the signature of `main`:
+///
+/// ```c
+/// int entry(int argc, char **argv);
+/// ```
+///
+/// An `Argv` owns NUL-terminated copies of the arguments and the
+/// null-terminated array of pointers to them, a [`CStrArray`], so they
+/// remain valid for the whole call. The function may modify the
+/// strings in place and permute the array, as `main` may and `getopt`
+/// does; [`Argv::args`] reads them back afterwards.
+///
+/// # Examples
+///
+/// ```
+/// use std::ffi::CStr;
+/// use std::os::raw::{c_char, c_int};
+///
+/// use libffi::middle::{Argv, CodePtr};
+///
+/// extern "C" fn entry(argc: c_int, argv: *mut *mut c_char) -> c_int {
+///     let name = unsafe { CStr::from_ptr(*argv.add(1)) };
+///     assert!(unsafe { (*argv.add(argc as usize)).is_null() });
+///     name.to_bytes().len() as c_int * 10 + argc
+/// }
+///
+/// let mut argv = Argv::new(&["tool", "input.txt"]).unwrap();
+/// let status = unsafe { argv.call_main(&Argv::main_cif(), CodePtr(entry as *mut _)) };
+/// assert_eq!(92, status);
+/// ```
+#[derive(Debug)]
+pub struct Argv {
+    args: CStrArray,
+}
+
+impl Argv {
+    /// Copies `args` into C strings.
+    ///
+    /// # Errors
+    ///
+    /// Fails if an argument contains a NUL byte, which would end it
+    /// early in C.
+    pub fn new<I, S>(args: I) -> Result<Self, NulError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Ok(Argv::from_array(CStrArray::new(args)?))
+    }
+
+    /// Uses the strings of `args` as the arguments.
+    pub fn from_array(args: CStrArray) -> Self {
+        Argv { args }
+    }
+
+    /// The number of arguments, `argc`.
+    pub fn argc(&self) -> c_int {
+        self.args.len() as c_int
+    }
+
+    /// The null-terminated array of arguments, `argv`.
+    ///
+    /// The pointers remain valid, and may be written through, for as
+    /// long as the `Argv` is neither moved out of nor dropped.
+    pub fn as_mut_ptr(&mut self) -> *mut *mut c_char {
+        self.args.as_mut_ptr()
+    }
+
+    /// Reads the arguments as they are now, following the pointers of
+    /// the array, which the entry point may have changed.
+    ///
+    /// Each argument is read up to its first NUL byte, and converted
+    /// lossily if it is not UTF-8.
+    ///
+    /// # Safety
+    ///
+    /// Any pointer the entry point stored in the array must point to a
+    /// valid C string.
+    pub unsafe fn args(&self) -> Vec<String> {
+        self.args.to_strings()
+    }
+
+    /// The CIF of an entry point with the signature of `main`, taking
+    /// an `int` and a pointer and returning an `int`.
+    pub fn main_cif() -> Cif {
+        Cif::new(vec![Type::c_int(), Type::pointer()], Type::c_int())
+    }
+
+    /// Calls the entry point `fun` with `argc` and `argv`, through
+    /// `cif`, and returns its result.
+    ///
+    /// # Safety
+    ///
+    /// `cif` must describe `fun`, as [`Argv::main_cif`] does a function
+    /// with the signature of `main`.
+    pub unsafe fn call_main(&mut self, cif: &Cif, fun: CodePtr) -> c_int {
+        let argc = self.argc();
+        let argv = self.as_mut_ptr();
+        cif.call(fun, &[arg(&argc), arg(&argv)])
+    }
+}
+
+/// A null-terminated array of C strings, a `char **`, together with the
+/// strings.
+///
+/// C passes lists of strings, such as the arguments and environment of
+/// `execve` or the search paths of a configuration API, as an array of
+/// pointers to NUL-terminated strings, ending with a null pointer. A
+/// `CStrArray` owns copies of the strings and the array of pointers to
+/// them, which [`CStrArray::as_ptr`] passes to C; the pointers remain
+/// valid until the `CStrArray` is dropped, and moving it does not move
+/// the strings. The C side may modify the strings in place and rearrange
+/// the array, but not make it longer.
+///
+/// Arrays that C returns are read with [`read_c_str_array`], or copied
+/// into a `CStrArray` with [`CStrArray::copy_from_ptr`].
+///
+/// # Examples
+///
+/// ```
+/// use std::ffi::CStr;
+/// use std::os::raw::c_char;
+///
+/// use libffi::middle::{arg, Cif, CodePtr, CStrArray, Type};
+///
+/// // Counts the variables of an environment block that set `key`.
+/// extern "C" fn count_set(envp: *const *const c_char, key: *const c_char) -> usize {
+///     let key = unsafe { CStr::from_ptr(key) }.to_bytes();
+///     let mut count = 0;
+///     let mut var = envp;
+///     while !unsafe { *var }.is_null() {
+///         let entry = unsafe { CStr::from_ptr(*var) }.to_bytes();
+///         if entry.starts_with(key) && entry.get(key.len()) == Some(&b'=') {
+///             count += 1;
+///         }
+///         var = unsafe { var.add(1) };
+///     }
+///     count
+/// }
+///
+/// let env = CStrArray::environment(vec![("HOME", "/home/ada"), ("LANG", "C")]).unwrap();
+/// let key = CStrArray::new(["LANG"]).unwrap();
+/// let cif = Cif::new(vec![Type::pointer(), Type::pointer()], Type::usize());
+/// let n: usize = unsafe {
+///     cif.call(
+///         CodePtr(count_set as *mut _),
+///         &[arg(&env.as_ptr()), arg(&key.get(0).unwrap().as_ptr())],
+///     )
+/// };
+/// assert_eq!(1, n);
+/// ```
+#[derive(Debug)]
+pub struct CStrArray {
+    // The strings, with their NUL terminators, which the pointers point
+    // into. Boxed slices never move their contents.
+    strings: Vec<Box<[u8]>>,
+    // The pointers to the strings, followed by a null pointer.
+    ptrs: Vec<*mut c_char>,
+}
+
+// A `CStrArray` owns the strings its pointers point to.
+unsafe impl Send for CStrArray {}
+unsafe impl Sync for CStrArray {}
+
+impl CStrArray {
+    /// Copies `strings` into C strings.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a string contains a NUL byte, which would end it early
+    /// in C.
+    pub fn new<I, S>(strings: I) -> Result<Self, NulError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let strings = strings
+            .into_iter()
+            .map(|s| CString::new(s.as_ref()))
+            .collect::<Result<Vec<_>, NulError>>()?;
+        Ok(CStrArray::from_c_strings(strings))
+    }
+
+    /// Creates an array of `strings`, which may be in any encoding.
+    pub fn from_c_strings<I: IntoIterator<Item = CString>>(strings: I) -> Self {
+        let mut strings: Vec<Box<[u8]>> = strings
+            .into_iter()
+            .map(|string| string.into_bytes_with_nul().into_boxed_slice())
+            .collect();
+        let mut ptrs: Vec<*mut c_char> = strings
+            .iter_mut()
+            .map(|string| string.as_mut_ptr() as *mut c_char)
+            .collect();
+        ptrs.push(ptr::null_mut());
+        CStrArray { strings, ptrs }
+    }
+
+    /// Creates an environment block, as `execve` and `envp` take, of
+    /// `KEY=value` strings.
+    ///
+    /// # Errors
+    ///
+    /// Fails if a key or value contains a NUL byte. Keys are not
+    /// otherwise checked, so a key containing `=` is split differently
+    /// by C.
+    pub fn environment<I, K, V>(vars: I) -> Result<Self, NulError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        CStrArray::new(
+            vars.into_iter()
+                .map(|(key, value)| format!("{}={}", key.as_ref(), value.as_ref())),
+        )
+    }
+
+    /// Copies a null-terminated array of C strings, such as one that C
+    /// returned.
+    ///
+    /// A null `ptr` is read as an empty array.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be null or point to an array of pointers to valid C
+    /// strings, ending with a null pointer.
+    pub unsafe fn copy_from_ptr(ptr: *const *const c_char) -> Self {
+        CStrArray::from_c_strings(
+            read_c_str_array(ptr)
+                .into_iter()
+                .map(|string| string.to_owned()),
+        )
+    }
+
+    /// The number of strings, not counting the null terminator.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Whether there are no strings.
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Gets the `i`th string as it was created, or `None` if there is
+    /// none.
+    ///
+    /// This reads the string the `CStrArray` owns, regardless of where
+    /// C has since moved its pointer in the array.
+    pub fn get(&self, i: usize) -> Option<&CStr> {
+        let string = self.strings.get(i)?;
+        let len = string.iter().position(|&byte| byte == 0)?;
+        // The string ends at its first NUL byte, which C may have
+        // moved.
+        CStr::from_bytes_with_nul(&string[..=len]).ok()
+    }
+
+    /// The null-terminated array of pointers, as a `const char **`.
+    pub fn as_ptr(&self) -> *const *const c_char {
+        self.ptrs.as_ptr() as *const *const c_char
+    }
+
+    /// The null-terminated array of pointers, as a `char **` through
+    /// which C may modify the strings and rearrange the array.
+    pub fn as_mut_ptr(&mut self) -> *mut *mut c_char {
+        self.ptrs.as_mut_ptr()
+    }
+
+    /// Reads the strings the array points to now, which C may have
+    /// changed, converting them lossily if they are not UTF-8.
+    ///
+    /// # Safety
+    ///
+    /// Any pointer C stored in the array must point to a valid C
+    /// string.
+    pub unsafe fn to_strings(&self) -> Vec<String> {
+        self.ptrs[..self.len()]
+            .iter()
+            .map(|&ptr| CStr::from_ptr(ptr).to_string_lossy().into_owned())
+            .collect()
+    }
+}
+
+/// Reads a null-terminated array of C strings, such as one that C
+/// returned, without copying the strings.
+///
+/// A null `ptr` is read as an empty array.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to an array of pointers to valid C
+/// strings, ending with a null pointer, and the array and the strings
+/// must remain valid and unchanged for `'a`.
+///
+/// # Examples
+///
+/// ```
+/// use std::os::raw::c_char;
+///
+/// use libffi::middle::{read_c_str_array, Cif, CodePtr, Type};
+///
+/// extern "C" fn names() -> *const *const c_char {
+///     let names = Box::new([
+///         b"alpha\0".as_ptr() as *const c_char,
+///         b"beta\0".as_ptr() as *const c_char,
+///         std::ptr::null(),
+///     ]);
+///     Box::leak(names).as_ptr()
+/// }
+///
+/// let cif = Cif::new(vec![], Type::pointer());
+/// let array: *const *const c_char = unsafe { cif.call(CodePtr(names as *mut _), &[]) };
+/// let names = unsafe { read_c_str_array(array) };
+/// assert_eq!(vec!["alpha", "beta"], names.iter().map(|n| n.to_str().unwrap()).collect::<Vec<_>>());
+/// ```
+pub unsafe fn read_c_str_array<'a>(ptr: *const *const c_char) -> Vec<&'a CStr> {
+    if ptr.is_null() {
+        return vec![];
+    }
+    let mut len = 0;
+    while !(*ptr.add(len)).is_null() {
+        len += 1;
+    }
+    slice::from_raw_parts(ptr, len)
+        .iter()
+        .map(|&string| CStr::from_ptr(string))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Reverses the arguments after the program name, and upper-cases
+    // the first letter of each.
+    extern "C" fn shuffle(argc: c_int, argv: *mut *mut c_char) -> c_int {
+        unsafe {
+            let args = std::slice::from_raw_parts_mut(argv.add(1), argc as usize - 1);
+            args.reverse();
+            for &arg in args.iter() {
+                *arg = (*arg as u8).to_ascii_uppercase() as c_char;
+            }
+            args.iter()
+                .map(|&arg| CStr::from_ptr(arg).to_bytes().len() as c_int)
+                .sum()
+        }
+    }
+
+    #[test]
+    fn entry_points_modify_arguments() {
+        let mut argv = Argv::new(vec!["prog".to_string(), "alpha".into(), "be".into()]).unwrap();
+        assert_eq!(3, argv.argc());
+        let cif = Argv::main_cif();
+        assert_eq!(7, unsafe {
+            argv.call_main(&cif, CodePtr(shuffle as *mut _))
+        });
+        assert_eq!(vec!["prog", "Be", "Alpha"], unsafe { argv.args() });
+    }
+
+    #[test]
+    fn empty_and_invalid() {
+        let mut argv = Argv::new(Vec::<&str>::new()).unwrap();
+        assert_eq!(0, argv.argc());
+        assert!(unsafe { *argv.as_mut_ptr() }.is_null());
+        assert!(Argv::new(["a\0b"]).is_err());
+    }
+
+    // Joins the strings of a null-terminated array with commas, and
+    // returns a new array of the strings in reverse order.
+    extern "C" fn reverse(strings: *const *const c_char, joined: *mut c_char) -> *mut *mut c_char {
+        unsafe {
+            let strings = read_c_str_array(strings);
+            let mut all = strings
+                .iter()
+                .map(|s| s.to_bytes())
+                .collect::<Vec<_>>()
+                .join(&b","[..]);
+            all.push(0);
+            ptr::copy_nonoverlapping(all.as_ptr() as *const c_char, joined, all.len());
+            let mut reversed: Vec<*mut c_char> = strings
+                .iter()
+                .rev()
+                .map(|s| CString::from(*s).into_raw())
+                .collect();
+            reversed.push(ptr::null_mut());
+            Box::leak(reversed.into_boxed_slice()).as_mut_ptr()
+        }
+    }
+
+    #[test]
+    fn string_arrays() {
+        let env = CStrArray::environment(vec![("PATH", "/bin"), ("TERM", "dumb")]).unwrap();
+        assert_eq!(2, env.len());
+        assert_eq!(Some(&b"TERM=dumb"[..]), env.get(1).map(CStr::to_bytes));
+        assert!(env.get(2).is_none());
+
+        let cif = Cif::new(vec![Type::pointer(), Type::pointer()], Type::pointer());
+        let mut joined = [0 as c_char; 64];
+        let reversed: *mut *mut c_char = unsafe {
+            cif.call(
+                CodePtr(reverse as *mut _),
+                &[arg(&env.as_ptr()), arg(&joined.as_mut_ptr())],
+            )
+        };
+        assert_eq!(
+            &b"PATH=/bin,TERM=dumb"[..],
+            unsafe { CStr::from_ptr(joined.as_ptr()) }.to_bytes()
+        );
+
+        let copy = unsafe { CStrArray::copy_from_ptr(reversed as *const *const c_char) };
+        assert_eq!(vec!["TERM=dumb", "PATH=/bin"], unsafe { copy.to_strings() });
+        unsafe {
+            let len = read_c_str_array(reversed as *const *const c_char).len();
+            for i in 0..len {
+                drop(CString::from_raw(*reversed.add(i)));
+            }
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                reversed,
+                len + 1,
+            )));
+            assert!(read_c_str_array(ptr::null()).is_empty());
+        }
+
+        let bytes = CStrArray::from_c_strings(vec![CString::new(vec![0xff, b'x']).unwrap()]);
+        assert_eq!(vec!["\u{fffd}x"], unsafe { bytes.to_strings() });
+        assert!(CStrArray::environment(vec![("K", "a\0b")]).is_err());
+    }
+}
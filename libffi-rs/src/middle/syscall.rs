@@ -0,0 +1,168 @@
+//! Raw Linux system calls through the C library’s `syscall(2)`.
+//!
+//! This module is enabled on Linux with the `libc` feature.
+//!
+//! `syscall` is variadic, so calling it from Rust with a varying number
+//! of arguments means either a `match` over every arity or inline
+//! assembly. [`syscall`](fn@syscall) instead calls it through a variadic
+//! CIF for the number of arguments given, prepared once per arity with
+//! `ffi_prep_cif_var`, and decodes a failure into the `errno` it set.
+//!
+//! The arguments are passed as C `long`s, as the kernel receives them;
+//! [`SyscallArg`] converts integers and pointers to them.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::middle::syscall::syscall;
+//!
+//! let pid = unsafe { syscall(libc::SYS_getpid, &[]) }.unwrap();
+//! assert_eq!(std::process::id() as libc::c_long, pid);
+//!
+//! let message = b"hello\n";
+//! let written = unsafe {
+//!     syscall(
+//!         libc::SYS_write,
+//!         &[(-1).into(), message.as_ptr().into(), message.len().into()],
+//!     )
+//! };
+//! assert_eq!(Some(libc::EBADF), written.unwrap_err().raw_os_error());
+//! ```
+
+use std::io;
+use std::os::raw::{c_long, c_void};
+use std::sync::OnceLock;
+
+use super::{arg, Cif, CodePtr, Type};
+
+/// The most arguments a system call takes.
+pub const MAX_ARGS: usize = 6;
+
+/// An argument of a system call, as the C `long` the kernel receives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SyscallArg(pub c_long);
+
+macro_rules! impl_syscall_arg {
+    ($($type_:ty),*) => {$(
+        impl From<$type_> for SyscallArg {
+            fn from(value: $type_) -> Self {
+                SyscallArg(value as c_long)
+            }
+        }
+    )*};
+}
+
+// Signed integers are sign-extended and unsigned ones zero-extended, as
+// C converts them to `long`; 64-bit values are truncated on 32-bit
+// targets, where the kernel takes them as pairs of arguments.
+impl_syscall_arg!(i8, u8, i16, u16, i32, u32, i64, u64, isize, usize);
+
+impl<T> From<*const T> for SyscallArg {
+    fn from(value: *const T) -> Self {
+        SyscallArg(value as usize as c_long)
+    }
+}
+
+impl<T> From<*mut T> for SyscallArg {
+    fn from(value: *mut T) -> Self {
+        SyscallArg(value as usize as c_long)
+    }
+}
+
+// The CIFs of `long syscall(long number, ...)` with 0 to `MAX_ARGS`
+// variable `long` arguments.
+fn cifs() -> &'static [Cif] {
+    static CIFS: OnceLock<Vec<Cif>> = OnceLock::new();
+    CIFS.get_or_init(|| {
+        (0..=MAX_ARGS)
+            .map(|n| Cif::new_variadic(vec![Type::c_long(); n + 1], 1, Type::c_long()))
+            .collect()
+    })
+}
+
+/// Makes the system call `number` with the given arguments.
+///
+/// The system call numbers are the `SYS_*` constants of [`libc`].
+///
+/// # Errors
+///
+/// Fails with the error given by `errno` if the system call returns
+/// `-1`.
+///
+/// # Panics
+///
+/// Panics if there are more than [`MAX_ARGS`] arguments.
+///
+/// # Safety
+///
+/// The arguments must be valid for the system call, as for any call to
+/// `syscall(2)`: pointers must point to memory of the size and
+/// mutability it expects, and the call must not break the invariants
+/// of the program, as closing a file descriptor owned elsewhere or
+/// unmapping memory in use would.
+pub unsafe fn syscall(number: c_long, args: &[SyscallArg]) -> io::Result<c_long> {
+    assert!(
+        args.len() <= MAX_ARGS,
+        "syscall: system calls take at most {} arguments",
+        MAX_ARGS
+    );
+    let cif = &cifs()[args.len()];
+    let values = Some(arg(&number))
+        .into_iter()
+        .chain(args.iter().map(|value| arg(&value.0)))
+        .collect::<Vec<_>>();
+    let result: c_long = cif.call(CodePtr(libc::syscall as *mut c_void), &values);
+    // Read `errno` before anything else, such as freeing `values`, can
+    // change it.
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pipes() {
+        let mut fds = [0 as libc::c_int; 2];
+        unsafe { syscall(libc::SYS_pipe2, &[fds.as_mut_ptr().into(), 0.into()]) }.unwrap();
+
+        let message = b"through the kernel";
+        let written = unsafe {
+            syscall(
+                libc::SYS_write,
+                &[fds[1].into(), message.as_ptr().into(), message.len().into()],
+            )
+        };
+        assert_eq!(message.len() as c_long, written.unwrap());
+
+        let mut buffer = [0u8; 64];
+        let read = unsafe {
+            syscall(
+                libc::SYS_read,
+                &[
+                    fds[0].into(),
+                    buffer.as_mut_ptr().into(),
+                    buffer.len().into(),
+                ],
+            )
+        }
+        .unwrap();
+        assert_eq!(&message[..], &buffer[..read as usize]);
+
+        for &fd in &fds {
+            unsafe { syscall(libc::SYS_close, &[fd.into()]) }.unwrap();
+        }
+        let closed = unsafe { syscall(libc::SYS_close, &[fds[0].into()]) };
+        assert_eq!(Some(libc::EBADF), closed.unwrap_err().raw_os_error());
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_many_arguments() {
+        let _ = unsafe { syscall(libc::SYS_getpid, &[SyscallArg(0); MAX_ARGS + 1]) };
+    }
+}
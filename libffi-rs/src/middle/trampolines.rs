@@ -0,0 +1,137 @@
+//! A registry of live closure trampolines.
+//!
+//! With the `trampoline-registry` feature enabled, every
+//! [`Closure`](super::Closure) and [`ClosureOnce`](super::ClosureOnce)
+//! records its trampoline here while it is alive, so that [`lookup`] can
+//! map an arbitrary code address back to the closure it belongs to. This
+//! is meant for crash reporters, profilers, and debuggers, which see
+//! only program counters.
+//!
+//! The registry is guarded by a lock, so none of these functions are
+//! async-signal-safe: a signal handler should copy the registry with
+//! [`live`] beforehand rather than call [`lookup`] itself.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::os::raw::c_void;
+//!
+//! use libffi::low;
+//! use libffi::middle::{trampolines, Cif, Closure, Type};
+//!
+//! unsafe extern "C" fn callback(
+//!     _cif: &low::ffi_cif,
+//!     result: &mut u32,
+//!     args: *const *const c_void,
+//!     _userdata: &(),
+//! ) {
+//!     *result = **(args as *const &u32) + 1;
+//! }
+//!
+//! let cif = Cif::new(vec![Type::u32()], Type::u32());
+//! let closure = Closure::new(cif, callback, &());
+//! let code = *closure.code_ptr() as *const c_void;
+//!
+//! let info = trampolines::lookup(code).unwrap();
+//! assert_eq!(code as usize, info.code);
+//! assert_eq!("(u32) -> u32", &*info.signature);
+//! ```
+
+use std::collections::BTreeMap;
+use std::os::raw::c_void;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::{Cif, CodePtr};
+use crate::low;
+
+/// What the registry knows about a live closure.
+#[derive(Clone, Debug)]
+pub struct ClosureInfo {
+    /// The address of the closure’s trampoline.
+    pub code: usize,
+    /// The length of the trampoline in bytes.
+    pub len: usize,
+    /// The address of the callback the trampoline calls.
+    pub callback: usize,
+    /// The closure’s signature, as formatted by [`Cif::signature`].
+    pub signature: Arc<str>,
+}
+
+impl ClosureInfo {
+    /// Returns whether `addr` lies within the trampoline.
+    pub fn contains(&self, addr: *const c_void) -> bool {
+        (self.code..self.code + self.len).contains(&(addr as usize))
+    }
+}
+
+fn registry() -> &'static RwLock<BTreeMap<usize, ClosureInfo>> {
+    static REGISTRY: OnceLock<RwLock<BTreeMap<usize, ClosureInfo>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+pub(crate) fn register(code: CodePtr, cif: &Cif, callback: low::RawCallback) {
+    let info = ClosureInfo {
+        code: code.as_ptr() as usize,
+        len: crate::raw::FFI_TRAMPOLINE_SIZE,
+        callback: callback as usize,
+        signature: cif.signature(),
+    };
+    // A poisoned lock still holds a consistent map, since no insertion
+    // or removal can panic halfway.
+    registry()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(info.code, info);
+}
+
+pub(crate) fn deregister(code: CodePtr) {
+    registry()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&(code.as_ptr() as usize));
+}
+
+/// Finds the live closure whose trampoline contains `addr`, if any.
+pub fn lookup(addr: *const c_void) -> Option<ClosureInfo> {
+    let registry = registry().read().unwrap_or_else(|e| e.into_inner());
+    let (_, info) = registry.range(..=addr as usize).next_back()?;
+    info.contains(addr).then(|| info.clone())
+}
+
+/// Returns every live closure, ordered by address.
+pub fn live() -> Vec<ClosureInfo> {
+    let registry = registry().read().unwrap_or_else(|e| e.into_inner());
+    registry.values().cloned().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::low;
+    use crate::middle::{Closure, Type};
+
+    unsafe extern "C" fn callback(
+        _cif: &low::ffi_cif,
+        result: &mut u64,
+        _args: *const *const c_void,
+        _userdata: &u64,
+    ) {
+        *result = 0;
+    }
+
+    #[test]
+    fn lookup_follows_lifetime() {
+        let cif = Cif::new(vec![Type::u64()], Type::u64());
+        let closure = Closure::new(cif, callback, &0);
+        let code = *closure.code_ptr() as *const c_void as usize;
+
+        let info = lookup((code + 1) as *const c_void).unwrap();
+        assert_eq!(code, info.code);
+        assert_eq!("(u64) -> u64", &*info.signature);
+        assert!(live().iter().any(|info| info.code == code));
+        assert!(lookup((code + info.len) as *const c_void).map_or(true, |other| other.code != code));
+
+        drop(closure);
+        assert!(lookup(code as *const c_void).map_or(true, |other| other.code != code));
+    }
+}
@@ -6,10 +6,21 @@
 //! and a result type, and libffi uses this to figure out how to set up
 //! a call to a function with those types.
 
-use libc;
-use std::fmt;
-use std::mem;
-use std::ptr;
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ffi as raw;
+#[cfg(not(feature = "min-size"))]
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::iter::FromIterator;
+use core::mem;
+use core::ops::{Bound, Index, RangeBounds};
+use core::{ptr, slice};
 
 use crate::low;
 
@@ -50,26 +61,45 @@ type Owned<T> = T;
 ///     Type::u16(),
 /// ]);
 /// ```
-pub struct Type(Unique<low::ffi_type>);
+pub struct Type(Unique<low::ffi_type>, Option<Arc<[String]>>);
 
 /// Represents a sequence of C types.
 ///
 /// This can be used to construct a struct type or as the arguments
 /// when creating a [`Cif`].
-pub struct TypeArray(Unique<*mut low::ffi_type>);
-
+// The length is kept alongside the null-terminated C array, so that it
+// need not be found by walking the array.
+pub struct TypeArray(Unique<*mut low::ffi_type>, usize);
+
+// Types own their descriptions, apart from the predefined primitive
+// types, which libffi never modifies. Struct types are laid out when
+// they are created, before they can be shared, and CIFs are only
+// prepared with types that have been, so libffi never writes to a
+// description after that.
+unsafe impl Send for Type {}
+unsafe impl Sync for Type {}
+unsafe impl Send for TypeArray {}
+unsafe impl Sync for TypeArray {}
+
+#[cfg(not(feature = "min-size"))]
 impl fmt::Debug for Type {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         formatter.write_fmt(format_args!("Type({:?})", *self.0))
     }
 }
 
+#[cfg(not(feature = "min-size"))]
 impl fmt::Debug for TypeArray {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         formatter.write_fmt(format_args!("TypeArray({:?})", *self.0))
     }
 }
 
+#[cfg(feature = "min-size")]
+opaque_debug!(Type);
+#[cfg(feature = "min-size")]
+opaque_debug!(TypeArray);
+
 /// Computes the length of a raw `TypeArray_` by searching for the
 /// null terminator.
 unsafe fn ffi_type_array_len(mut array: TypeArray_) -> usize {
@@ -81,73 +111,236 @@ unsafe fn ffi_type_array_len(mut array: TypeArray_) -> usize {
     count
 }
 
-/// Creates an empty `TypeArray_` with null terminator.
-unsafe fn ffi_type_array_create_empty(len: usize) -> Owned<TypeArray_> {
-    let array = libc::malloc((len + 1) * mem::size_of::<Type_>()) as TypeArray_;
-    assert!(
-        !array.is_null(),
-        "ffi_type_array_create_empty: out of memory"
-    );
-    *array.add(len) = ptr::null_mut::<low::ffi_type>() as Type_;
-    array
+// Type arrays and struct types are allocated with Rust’s allocator: an
+// array of `len` types is a boxed slice of `len + 1` pointers, the last
+// of them null, and a struct type is a boxed `ffi_type`. The array’s
+// length is not stored, but found from its terminator, as libffi does.
+
+/// Creates a `TypeArray_` of `len` null types and a null terminator.
+///
+/// Until every element is filled in, the array must be freed with
+/// [`ffi_type_array_free`], which is given its length.
+unsafe fn ffi_type_array_create_empty(len: usize) -> Result<Owned<TypeArray_>, crate::Error> {
+    let layout = len
+        .checked_add(1)
+        .and_then(|size| Layout::array::<Type_>(size).ok())
+        .ok_or(crate::Error::AllocationFailed)?;
+    let array = alloc::alloc::alloc_zeroed(layout) as TypeArray_;
+    if array.is_null() {
+        return Err(crate::Error::AllocationFailed);
+    }
+    Ok(array)
 }
 
 /// Creates a null-terminated array of Type_. Takes ownership of
 /// the elements.
-unsafe fn ffi_type_array_create<I>(elements: I) -> Owned<TypeArray_>
+///
+/// Every `Type` owns its own description (cloning a struct type copies
+/// it), so each element of the new array is owned by the array alone,
+/// even if some elements were clones of each other. Primitive types
+/// are shared statics that are never freed, so they are not copied.
+unsafe fn ffi_type_array_create<I>(elements: I) -> Result<Owned<TypeArray_>, crate::Error>
 where
     I: ExactSizeIterator<Item = Type>,
 {
-    let size = elements.len();
-    let new = ffi_type_array_create_empty(size);
-    for (i, element) in elements.enumerate() {
-        *new.add(i) = *element.0;
-        mem::forget(element);
+    let len = elements.len();
+    let new = ffi_type_array_create_empty(len)?;
+    let mut filled = 0;
+    for element in elements.take(len) {
+        *new.add(filled) = element.into_raw();
+        filled += 1;
+    }
+    if filled < len {
+        ffi_type_array_free(new, len);
+        panic!("ffi_type_array_create: the iterator was shorter than its length");
     }
 
-    new
+    Ok(new)
 }
 
-/// Creates a struct type from a raw array of element types.
+/// Creates a struct type from a raw array of element types. Takes
+/// ownership of the elements, and frees them if it fails.
 unsafe fn ffi_type_struct_create_raw(
     elements: Owned<TypeArray_>,
     size: usize,
     alignment: u16,
-) -> Owned<Type_> {
-    let new = libc::malloc(mem::size_of::<low::ffi_type>()) as Type_;
-    assert!(!new.is_null(), "ffi_type_struct_create_raw: out of memory");
+) -> Result<Owned<Type_>, crate::Error> {
+    let new = alloc::alloc::alloc(Layout::new::<low::ffi_type>()) as Type_;
+    if new.is_null() {
+        ffi_type_array_destroy(elements);
+        return Err(crate::Error::AllocationFailed);
+    }
 
-    (*new).size = size;
-    (*new).alignment = alignment;
-    (*new).type_ = low::type_tag::STRUCT;
-    (*new).elements = elements;
+    new.write(low::ffi_type {
+        size,
+        alignment,
+        type_: low::type_tag::STRUCT,
+        elements,
+    });
+    if size == 0 {
+        ffi_type_lay_out(new);
+    }
 
-    new
+    Ok(new)
+}
+
+/// Lays out a new struct type, by preparing a CIF that returns it, as
+/// libffi would do the first time the type is used. A type that libffi
+/// rejects is left with size 0.
+unsafe fn ffi_type_lay_out(new: Type_) {
+    let mut cif: low::ffi_cif = Default::default();
+    let _ = low::prep_cif(
+        &mut cif,
+        low::ffi_abi_FFI_DEFAULT_ABI,
+        0,
+        new,
+        ptr::null_mut(),
+    );
 }
 
 /// Creates a struct `ffi_type` with the given elements. Takes ownership
 /// of the elements.
-unsafe fn ffi_type_struct_create<I>(elements: I) -> Owned<Type_>
+unsafe fn ffi_type_struct_create<I>(elements: I) -> Result<Owned<Type_>, crate::Error>
 where
     I: ExactSizeIterator<Item = Type>,
 {
-    ffi_type_struct_create_raw(ffi_type_array_create(elements), 0, 0)
+    ffi_type_struct_create_raw(ffi_type_array_create(elements)?, 0, 0)
 }
 
-/// Makes a copy of a type array.
-unsafe fn ffi_type_array_clone(old: TypeArray_) -> Owned<TypeArray_> {
-    let size = ffi_type_array_len(old);
-    let new = ffi_type_array_create_empty(size);
+/// Returns whether a type consists only of floating-point values.
+unsafe fn floating(type_: Type_) -> bool {
+    match u32::from((*type_).type_) {
+        crate::raw::FFI_TYPE_FLOAT
+        | crate::raw::FFI_TYPE_DOUBLE
+        | crate::raw::FFI_TYPE_LONGDOUBLE => true,
+        crate::raw::FFI_TYPE_STRUCT => {
+            let mut element = (*type_).elements;
+            let mut any = false;
+            while !(*element).is_null() {
+                if !floating(*element) {
+                    return false;
+                }
+                any = true;
+                element = element.add(1);
+            }
+            any
+        }
+        _ => false,
+    }
+}
+
+// The fields of a structure emulating a union of `members`, whose
+// largest member is `members[largest]`, with the given size and
+// alignment. See `Type::union_`.
+fn union_fields(members: &[Type], largest: usize, size: usize, alignment: usize) -> Vec<Type> {
+    // On x86-64, eightbytes covered only by floating-point values are
+    // passed in SSE registers, so their padding must be floating point
+    // as well. Elsewhere, floating-point padding could make the union a
+    // homogeneous aggregate, which it is not.
+    let x86_64 = cfg!(target_arch = "x86_64");
+
+    if members
+        .iter()
+        .all(|member| unsafe { floating(member.as_raw_ptr()) })
+    {
+        let member = members[largest].clone();
+        let mut offset = member.layout().0;
+        let mut fields = vec![member];
+        while offset < size {
+            if x86_64 && offset % 4 == 0 && size - offset >= 4 {
+                fields.push(Type::f32());
+                offset += 4;
+            } else {
+                fields.push(Type::u8());
+                offset += 1;
+            }
+        }
+        return fields;
+    }
+
+    let (word, width) = match alignment {
+        1 => (Type::u8(), 1),
+        2 => (Type::u16(), 2),
+        4 => (Type::u32(), 4),
+        _ => (Type::u64(), 8),
+    };
+    if !x86_64 {
+        return (0..size / width).map(|_| word.clone()).collect();
+    }
+
+    let mut sse = vec![true; (size + 7) / 8];
+    for member in members {
+        unsafe {
+            for_each_scalar(member.as_raw_ptr(), 0, &mut |offset, scalar| {
+                let float = matches!(
+                    u32::from(scalar.type_),
+                    crate::raw::FFI_TYPE_FLOAT | crate::raw::FFI_TYPE_DOUBLE
+                );
+                if !float && scalar.size > 0 {
+                    for eightbyte in &mut sse[offset / 8..=(offset + scalar.size - 1) / 8] {
+                        *eightbyte = false;
+                    }
+                }
+            });
+        }
+    }
+    let mut fields = Vec::new();
+    for (i, &sse) in sse.iter().enumerate() {
+        let len = (size - i * 8).min(8);
+        if sse && len % 4 == 0 {
+            fields.extend((0..len / 4).map(|_| Type::f32()));
+        } else {
+            fields.extend((0..len / width).map(|_| word.clone()));
+        }
+    }
+    fields
+}
+
+/// Calls `f` with the offset and type of each scalar of `type_`, which
+/// is at `offset`.
+unsafe fn for_each_scalar(type_: Type_, offset: usize, f: &mut impl FnMut(usize, &low::ffi_type)) {
+    if u32::from((*type_).type_) != crate::raw::FFI_TYPE_STRUCT {
+        f(offset, &*type_);
+        return;
+    }
+    let mut offset = offset;
+    let mut element = (*type_).elements;
+    while !(*element).is_null() {
+        let alignment = usize::from((**element).alignment);
+        offset = (offset + alignment - 1) / alignment * alignment;
+        for_each_scalar(*element, offset, f);
+        offset += (**element).size;
+        element = element.add(1);
+    }
+}
 
-    for i in 0..size {
-        *new.add(i) = ffi_type_clone(*old.add(i));
+/// Makes an array of copies of the `len` types of `elements`.
+unsafe fn ffi_type_array_copy<I>(elements: I, len: usize) -> Result<Owned<TypeArray_>, crate::Error>
+where
+    I: Iterator<Item = Type_>,
+{
+    let new = ffi_type_array_create_empty(len)?;
+    for (i, element) in elements.take(len).enumerate() {
+        match ffi_type_clone(element) {
+            Ok(copy) => *new.add(i) = copy,
+            Err(error) => {
+                ffi_type_array_free(new, len);
+                return Err(error);
+            }
+        }
     }
 
-    new
+    Ok(new)
+}
+
+/// Makes a copy of a type array.
+unsafe fn ffi_type_array_clone(old: TypeArray_) -> Result<Owned<TypeArray_>, crate::Error> {
+    let len = ffi_type_array_len(old);
+    ffi_type_array_copy(slice::from_raw_parts(old, len).iter().copied(), len)
 }
 
 /// Makes a copy of a type.
-unsafe fn ffi_type_clone(old: Type_) -> Owned<Type_> {
+unsafe fn ffi_type_clone(old: Type_) -> Result<Owned<Type_>, crate::Error> {
     if (*old).type_ == low::type_tag::STRUCT {
         let low::ffi_type {
             alignment,
@@ -155,29 +348,42 @@ unsafe fn ffi_type_clone(old: Type_) -> Owned<Type_> {
             size,
             ..
         } = *old;
-        let new = ffi_type_struct_create_raw(ffi_type_array_clone(elements), size, alignment);
-        new
+        ffi_type_struct_create_raw(ffi_type_array_clone(elements)?, size, alignment)
     } else {
-        old
+        Ok(old)
     }
 }
 
 /// Destroys a `TypeArray_` and all of its elements.
 unsafe fn ffi_type_array_destroy(victim: Owned<TypeArray_>) {
-    let mut current = victim;
-    while !(*current).is_null() {
-        ffi_type_destroy(*current);
-        current = current.offset(1);
+    ffi_type_array_free(victim, ffi_type_array_len(victim));
+}
+
+/// Destroys a `TypeArray_` of length `len` and those of its elements
+/// that are not null.
+unsafe fn ffi_type_array_free(victim: Owned<TypeArray_>, len: usize) {
+    for i in 0..len {
+        let element = *victim.add(i);
+        if !element.is_null() {
+            ffi_type_destroy(element);
+        }
     }
+    ffi_type_array_dealloc(victim, len);
+}
 
-    libc::free(victim as *mut libc::c_void);
+/// Frees a `TypeArray_` of length `len`, but not its elements.
+unsafe fn ffi_type_array_dealloc(victim: Owned<TypeArray_>, len: usize) {
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+        victim,
+        len + 1,
+    )));
 }
 
 /// Destroys a `Type_` if it was dynamically allocated.
 unsafe fn ffi_type_destroy(victim: Owned<Type_>) {
     if (*victim).type_ == low::type_tag::STRUCT {
         ffi_type_array_destroy((*victim).elements);
-        libc::free(victim as *mut libc::c_void);
+        drop(Box::from_raw(victim));
     }
 }
 
@@ -193,21 +399,64 @@ impl Drop for TypeArray {
     }
 }
 
+/// Types are compared structurally, as [`TypeDesc`]s are; the field
+/// names of a structure are not compared, since they do not change how
+/// it is passed.
+impl PartialEq for Type {
+    fn eq(&self, other: &Type) -> bool {
+        self.desc() == other.desc()
+    }
+}
+
+impl Eq for Type {}
+
+impl Hash for Type {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.desc().hash(state);
+    }
+}
+
+/// Arrays are equal if their elements are, compared as [`TypeDesc`]s.
+impl PartialEq for TypeArray {
+    fn eq(&self, other: &TypeArray) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for TypeArray {}
+
+impl Hash for TypeArray {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+        for element in self {
+            element.hash(state);
+        }
+    }
+}
+
 impl Clone for Type {
     fn clone(&self) -> Self {
-        Type(unsafe { Unique::new(ffi_type_clone(*self.0)) })
+        let copy = ffi_expect!(
+            unsafe { ffi_type_clone(*self.0) },
+            "Type::clone: out of memory"
+        );
+        Type(unsafe { Unique::new(copy) }, self.1.clone())
     }
 }
 
 impl Clone for TypeArray {
     fn clone(&self) -> Self {
-        TypeArray(unsafe { Unique::new(ffi_type_array_clone(*self.0)) })
+        let copy = ffi_expect!(
+            unsafe { ffi_type_array_clone(*self.0) },
+            "TypeArray::clone: out of memory"
+        );
+        TypeArray(unsafe { Unique::new(copy) }, self.1)
     }
 }
 
 macro_rules! match_size_signed {
     ( $name:ident ) => {
-        match mem::size_of::<libc::$name>() {
+        match mem::size_of::<raw::$name>() {
             1 => Self::i8(),
             2 => Self::i16(),
             4 => Self::i32(),
@@ -219,7 +468,7 @@ macro_rules! match_size_signed {
 
 macro_rules! match_size_unsigned {
     ( $name:ident ) => {
-        match mem::size_of::<libc::$name>() {
+        match mem::size_of::<raw::$name>() {
             1 => Self::u8(),
             2 => Self::u16(),
             4 => Self::u32(),
@@ -229,53 +478,69 @@ macro_rules! match_size_unsigned {
     };
 }
 
+// Wraps one of libffi’s predeclared primitive types.
+//
+// The statics are only ever read: libffi lays out struct types in
+// place, but never writes to a primitive type, and dropping or cloning
+// one leaves it alone. Taking the address with `addr_of!` rather than
+// `addr_of_mut!` keeps concurrent construction of primitive types free
+// of data races. (Taking the address of a static is safe on recent
+// compilers, but not on our minimum supported version.)
+macro_rules! primitive {
+    ( $name:ident ) => {{
+        #[allow(unused_unsafe)]
+        let raw: *const low::ffi_type = unsafe { ptr::addr_of!(low::types::$name) };
+        Type(unsafe { Unique::new(raw as Type_) }, None)
+    }};
+}
+
 impl Type {
     /// Returns the representation of the C `void` type.
     ///
     /// This is used only for the return type of a [CIF](super::Cif),
     /// not for an argument or struct member.
     pub fn void() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::void) })
+        primitive!(void)
     }
 
     /// Returns the unsigned 8-bit numeric type.
     pub fn u8() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::uint8) })
+        primitive!(uint8)
     }
 
     /// Returns the signed 8-bit numeric type.
     pub fn i8() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::sint8) })
+        primitive!(sint8)
     }
 
     /// Returns the unsigned 16-bit numeric type.
     pub fn u16() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::uint16) })
+        primitive!(uint16)
     }
 
     /// Returns the signed 16-bit numeric type.
     pub fn i16() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::sint16) })
+        primitive!(sint16)
     }
 
     /// Returns the unsigned 32-bit numeric type.
     pub fn u32() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::uint32) })
+        primitive!(uint32)
     }
 
     /// Returns the signed 32-bit numeric type.
     pub fn i32() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::sint32) })
+        primitive!(sint32)
     }
 
     /// Returns the unsigned 64-bit numeric type.
     pub fn u64() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::uint64) })
+        primitive!(uint64)
     }
 
     /// Returns the signed 64-bit numeric type.
     pub fn i64() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::sint64) })
+        primitive!(sint64)
     }
 
     #[cfg(target_pointer_width = "16")]
@@ -366,23 +631,44 @@ impl Type {
 
     /// Returns the C `float` (32-bit floating point) type.
     pub fn f32() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::float) })
+        primitive!(float)
     }
 
     /// Returns the C `double` (64-bit floating point) type.
     pub fn f64() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::double) })
+        primitive!(double)
     }
 
     /// Returns the C `void*` type, for passing any kind of pointer.
     pub fn pointer() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::pointer) })
+        primitive!(pointer)
+    }
+
+    /// Returns the type that describes the Rust type `T`.
+    ///
+    /// `T` may be any [`CType`](crate::high::CType), including the C
+    /// type aliases of [`std::os::raw`] and `core::ffi`, so binding code
+    /// can name C types symbolically rather than committing to fixed
+    /// widths.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::os::raw::{c_int, c_long};
+    ///
+    /// use libffi::middle::{Cif, Type};
+    ///
+    /// let cif = Cif::new(vec![Type::of::<c_int>(), Type::of::<*const u8>()], Type::of::<c_long>());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn of<T: crate::high::CType>() -> Self {
+        T::reify().into_middle()
     }
 
     /// Returns the C `long double` (extended-precision floating point) type.
     #[cfg(not(any(target_arch = "arm", target_arch = "aarch64")))]
     pub fn longdouble() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::longdouble) })
+        primitive!(longdouble)
     }
 
     /// Returns the C `_Complex float` type.
@@ -390,7 +676,7 @@ impl Type {
     /// This item is enabled by `#[cfg(feature = "complex")]`.
     #[cfg(feature = "complex")]
     pub fn c32() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::complex_float) })
+        primitive!(complex_float)
     }
 
     /// Returns the C `_Complex double` type.
@@ -398,7 +684,7 @@ impl Type {
     /// This item is enabled by `#[cfg(feature = "complex")]`.
     #[cfg(feature = "complex")]
     pub fn c64() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::complex_double) })
+        primitive!(complex_double)
     }
 
     /// Returns the C `_Complex long double` type.
@@ -407,16 +693,258 @@ impl Type {
     #[cfg(feature = "complex")]
     #[cfg(not(all(target_arch = "arm")))]
     pub fn complex_longdouble() -> Self {
-        Type(unsafe { Unique::new(&mut low::types::complex_longdouble) })
+        primitive!(complex_longdouble)
     }
 
     /// Constructs a structure type whose fields have the given types.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the type description cannot be allocated; see
+    /// [`Type::try_structure`].
     pub fn structure<I>(fields: I) -> Self
     where
         I: IntoIterator<Item = Type>,
         I::IntoIter: ExactSizeIterator<Item = Type>,
     {
-        Type(unsafe { Unique::new(ffi_type_struct_create(fields.into_iter())) })
+        ffi_expect!(
+            Type::try_structure(fields),
+            "Type::structure: out of memory"
+        )
+    }
+
+    /// Constructs a structure type whose fields have the given types,
+    /// or fails if its description cannot be allocated.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::AllocationFailed`](crate::Error::AllocationFailed)
+    /// if the allocator fails, or the fields are too many to allocate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Type;
+    ///
+    /// let pair = Type::try_structure(vec![Type::u8(), Type::f64()]).unwrap();
+    /// assert_eq!((16, 8), pair.layout());
+    /// ```
+    pub fn try_structure<I>(fields: I) -> Result<Self, crate::Error>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        let raw = unsafe { ffi_type_struct_create(fields.into_iter())? };
+        Ok(Type(unsafe { Unique::new(raw) }, None))
+    }
+
+    /// Constructs the type of a C array of `count` elements of type
+    /// `element`, such as `double[16]`.
+    ///
+    /// libffi has no array types, so the array is described as a
+    /// structure of `count` fields of type `element`, which has the
+    /// same size and alignment. Use it for array members of structures,
+    /// including arrays nested in other arrays; C passes array
+    /// arguments as pointers, so a CIF should describe those with
+    /// [`Type::pointer`] instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero, since C has no empty arrays.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Type;
+    ///
+    /// // struct { double m[16]; }
+    /// let matrix = Type::structure(vec![Type::array(Type::f64(), 16)]);
+    /// ```
+    pub fn array(element: Type, count: usize) -> Self {
+        assert!(
+            count > 0,
+            "Type::array: arrays must have at least one element"
+        );
+        Type::structure((0..count).map(|_| element.clone()))
+    }
+
+    /// Constructs the type of a C union of members of the given types.
+    ///
+    /// libffi has no union types, so the union is emulated by a
+    /// structure with the union’s size and alignment, those of its
+    /// largest and most strictly aligned members. The layout is computed
+    /// here and stored in the type, so libffi uses it as it is rather
+    /// than laying out the structure itself.
+    ///
+    /// How a union is passed by value depends on the ABI, which may
+    /// pass it in registers according to the types of its members. If
+    /// all members are floating point, the emulating structure consists
+    /// of the largest member, padded to the union’s size; otherwise, it
+    /// consists of integers covering the union. On x86-64, where each
+    /// eightbyte of the union is classified on its own, the padding and
+    /// the eightbytes that only floating-point members cover are
+    /// `f32`s instead. This matches how common ABIs such as x86-64
+    /// System V and AArch64 pass most unions. For unions whose members
+    /// mix types within a register in other ways, check the
+    /// platform’s ABI, or pass them by pointer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no members, since C has no empty unions.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Type;
+    ///
+    /// #[repr(C)]
+    /// union Value {
+    ///     tag: u8,
+    ///     number: f64,
+    ///     pair: [u32; 3],
+    /// }
+    ///
+    /// let value = Type::union_(vec![
+    ///     Type::u8(),
+    ///     Type::f64(),
+    ///     Type::array(Type::u32(), 3),
+    /// ]);
+    /// assert_eq!(
+    ///     (std::mem::size_of::<Value>(), std::mem::align_of::<Value>()),
+    ///     value.layout()
+    /// );
+    /// ```
+    pub fn union_(members: Vec<Type>) -> Self {
+        assert!(
+            !members.is_empty(),
+            "Type::union_: unions must have at least one member"
+        );
+
+        let mut size = 0;
+        let mut alignment = 1;
+        let mut largest = 0;
+        for (i, member) in members.iter().enumerate() {
+            let (member_size, member_alignment) = member.layout();
+            if member_size > size {
+                size = member_size;
+                largest = i;
+            }
+            alignment = alignment.max(member_alignment);
+        }
+        let size = (size + alignment - 1) / alignment * alignment;
+
+        let fields = union_fields(&members, largest, size, alignment);
+
+        let raw = ffi_expect!(
+            unsafe {
+                ffi_type_array_create(fields.into_iter()).and_then(|elements| {
+                    ffi_type_struct_create_raw(elements, size, alignment as u16)
+                })
+            },
+            "Type::union_: out of memory"
+        );
+        Type(unsafe { Unique::new(raw) }, None)
+    }
+
+    /// Constructs a structure type whose fields are the elements of
+    /// `fields`.
+    ///
+    /// The structure takes ownership of the array, so the array’s
+    /// elements are freed exactly once, when the structure is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Type, TypeArray};
+    ///
+    /// let fields = TypeArray::new(vec![Type::u16(), Type::f64()]);
+    /// let pair = Type::structure_from_array(fields);
+    /// let copy = pair.clone();
+    /// drop(pair);
+    /// # drop(copy);
+    /// ```
+    pub fn structure_from_array(fields: TypeArray) -> Self {
+        let elements = fields.into_raw();
+        let raw = ffi_expect!(
+            unsafe { ffi_type_struct_create_raw(elements, 0, 0) },
+            "Type::structure_from_array: out of memory"
+        );
+        Type(unsafe { Unique::new(raw) }, None)
+    }
+
+    /// Constructs a structure type whose fields have the given names and
+    /// types.
+    ///
+    /// The names let the fields of values of the type be accessed by
+    /// name, as with [`TypedBuffer::get_field`](super::TypedBuffer::get_field).
+    /// They are not part of the C type, so only the outermost struct
+    /// keeps them when the type is itself used as a field.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Type;
+    ///
+    /// let point = Type::structure_named(vec![("x", Type::f64()), ("y", Type::f64())]);
+    /// assert_eq!(Some(1), point.field_index("y"));
+    /// ```
+    pub fn structure_named<I, S>(fields: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Type)>,
+        S: Into<String>,
+    {
+        let (names, types): (Vec<String>, Vec<Type>) = fields
+            .into_iter()
+            .map(|(name, type_)| (name.into(), type_))
+            .unzip();
+        let mut structure = Type::structure(types);
+        structure.1 = Some(names.into());
+        structure
+    }
+
+    /// The names of the fields, if this is a structure type constructed
+    /// with [`Type::structure_named`].
+    pub fn field_names(&self) -> Option<&[String]> {
+        self.1.as_deref()
+    }
+
+    /// The index of the field called `name`, if this is a structure type
+    /// with such a field.
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.field_names()?.iter().position(|field| field == name)
+    }
+
+    /// The offset in bytes of field `index`, if this is a structure type
+    /// with such a field.
+    pub fn field_offset(&self, index: usize) -> Option<usize> {
+        let fields = unsafe { super::buffer::field_layout(&*self.as_raw_ptr()) };
+        fields.get(index).map(|&(offset, _)| offset)
+    }
+
+    /// The offset in bytes of the field called `name`, if this is a
+    /// structure type with such a field.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{StructTypeBuilder, Type};
+    ///
+    /// #[repr(C)]
+    /// struct Reading {
+    ///     sensor: u16,
+    ///     value: f64,
+    /// }
+    ///
+    /// let reading = StructTypeBuilder::new()
+    ///     .field("sensor", Type::u16())
+    ///     .field("value", Type::f64())
+    ///     .build();
+    /// assert_eq!(Some(0), reading.offset_of("sensor"));
+    /// assert_eq!(Some(8), reading.offset_of("value"));
+    /// assert_eq!(None, reading.offset_of("unit"));
+    /// ```
+    pub fn offset_of(&self, name: &str) -> Option<usize> {
+        self.field_offset(self.field_index(name)?)
     }
 
     /// Gets a raw pointer to the underlying [`low::ffi_type`].
@@ -426,16 +954,444 @@ impl Type {
     pub fn as_raw_ptr(&self) -> *mut low::ffi_type {
         *self.0
     }
+
+    // Releases ownership of the description to the caller, discarding
+    // any field names.
+    fn into_raw(mut self) -> Owned<Type_> {
+        let raw = *self.0;
+        self.1 = None;
+        mem::forget(self);
+        raw
+    }
+
+    /// Gets the size and alignment of the type, in bytes, as libffi
+    /// computes them.
+    ///
+    /// Struct types are laid out as they are created, as libffi would
+    /// lay them out in a CIF. A struct type that libffi rejects, such
+    /// as an empty one, has size and alignment 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::Type;
+    ///
+    /// #[repr(C)]
+    /// struct Sample {
+    ///     channel: u8,
+    ///     value: f64,
+    /// }
+    ///
+    /// let sample = Type::structure(vec![Type::u8(), Type::f64()]);
+    /// assert_eq!(
+    ///     (std::mem::size_of::<Sample>(), std::mem::align_of::<Sample>()),
+    ///     sample.layout()
+    /// );
+    /// ```
+    pub fn layout(&self) -> (usize, usize) {
+        self.desc().layout()
+    }
+
+    /// What kind of type this is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Type, TypeKind};
+    ///
+    /// assert_eq!(TypeKind::I32, Type::i32().kind());
+    /// assert_eq!(TypeKind::Pointer, Type::pointer().kind());
+    /// assert_eq!(TypeKind::Struct, Type::structure(vec![Type::u8()]).kind());
+    /// ```
+    pub fn kind(&self) -> TypeKind {
+        self.desc().kind()
+    }
+
+    /// Iterates over the types of the fields, if this is a structure
+    /// type; other types have no elements.
+    ///
+    /// Arrays and unions are structure types too, so this walks their
+    /// elements and the fields libffi uses to describe them.
+    ///
+    /// # Examples
+    ///
+    /// Walking a type description:
+    ///
+    /// ```
+    /// use libffi::middle::{Type, TypeDesc, TypeKind};
+    ///
+    /// fn describe(desc: &TypeDesc) -> String {
+    ///     match desc.kind() {
+    ///         TypeKind::Struct => {
+    ///             let fields: Vec<_> = desc.elements().map(describe).collect();
+    ///             format!("{{{}}}", fields.join(", "))
+    ///         }
+    ///         kind => format!("{:?}", kind),
+    ///     }
+    /// }
+    ///
+    /// let point = Type::structure(vec![Type::f64(), Type::f64()]);
+    /// let segment = Type::structure(vec![point.clone(), point, Type::u8()]);
+    /// assert_eq!("{{F64, F64}, {F64, F64}, U8}", describe(segment.desc()));
+    /// ```
+    pub fn elements(&self) -> TypeElements<'_> {
+        self.desc().elements()
+    }
+
+    /// Gets the borrowed description of the type, which is how the
+    /// elements of structure types are seen.
+    pub fn desc(&self) -> &TypeDesc {
+        unsafe { TypeDesc::from_raw(self.as_raw_ptr()) }
+    }
+}
+
+/// The kinds of type that libffi distinguishes.
+///
+/// Each primitive type has its own kind, apart from the C types that
+/// libffi describes by size, such as `int` and `size_t`, which have the
+/// kind of the fixed-size integer of the same size and signedness.
+/// Arrays and unions are described as structures, and have kind
+/// [`TypeKind::Struct`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TypeKind {
+    /// `void`, which is only a result type.
+    Void,
+    /// An unsigned 8-bit integer.
+    U8,
+    /// A signed 8-bit integer.
+    I8,
+    /// An unsigned 16-bit integer.
+    U16,
+    /// A signed 16-bit integer.
+    I16,
+    /// An unsigned 32-bit integer.
+    U32,
+    /// A signed 32-bit integer.
+    I32,
+    /// An unsigned 64-bit integer.
+    U64,
+    /// A signed 64-bit integer.
+    I64,
+    /// C’s `float`.
+    F32,
+    /// C’s `double`.
+    F64,
+    /// C’s `long double`, on targets where it differs from `double`.
+    LongDouble,
+    /// A pointer.
+    Pointer,
+    /// A C complex number type.
+    Complex,
+    /// A structure, whose fields are its elements.
+    Struct,
+}
+
+impl TypeKind {
+    // Converts libffi’s type tag.
+    fn from_tag(tag: u16) -> TypeKind {
+        match u32::from(tag) {
+            crate::raw::FFI_TYPE_VOID => TypeKind::Void,
+            crate::raw::FFI_TYPE_UINT8 => TypeKind::U8,
+            crate::raw::FFI_TYPE_SINT8 => TypeKind::I8,
+            crate::raw::FFI_TYPE_UINT16 => TypeKind::U16,
+            crate::raw::FFI_TYPE_SINT16 => TypeKind::I16,
+            crate::raw::FFI_TYPE_UINT32 => TypeKind::U32,
+            // libffi’s own types never use `FFI_TYPE_INT`, which means
+            // a C `int`.
+            crate::raw::FFI_TYPE_SINT32 | crate::raw::FFI_TYPE_INT => TypeKind::I32,
+            crate::raw::FFI_TYPE_UINT64 => TypeKind::U64,
+            crate::raw::FFI_TYPE_SINT64 => TypeKind::I64,
+            crate::raw::FFI_TYPE_FLOAT => TypeKind::F32,
+            crate::raw::FFI_TYPE_DOUBLE => TypeKind::F64,
+            crate::raw::FFI_TYPE_LONGDOUBLE => TypeKind::LongDouble,
+            crate::raw::FFI_TYPE_POINTER => TypeKind::Pointer,
+            crate::raw::FFI_TYPE_COMPLEX => TypeKind::Complex,
+            crate::raw::FFI_TYPE_STRUCT => TypeKind::Struct,
+            _ => panic!("TypeKind: unknown libffi type tag {}", tag),
+        }
+    }
+}
+
+/// A borrowed description of a C type.
+///
+/// This is how the elements of a structure type are seen, without
+/// copying them; [`Type::desc`] gives the description of a [`Type`],
+/// and [`TypeDesc::to_type`] copies a description into a new `Type`.
+#[repr(transparent)]
+pub struct TypeDesc(UnsafeCell<low::ffi_type>);
+
+// As for `Type`: descriptions are laid out before they can be shared,
+// and are not written to after that.
+unsafe impl Sync for TypeDesc {}
+
+#[cfg(not(feature = "min-size"))]
+impl fmt::Debug for TypeDesc {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_fmt(format_args!("TypeDesc({:?})", self.as_raw_ptr()))
+    }
+}
+
+#[cfg(feature = "min-size")]
+opaque_debug!(TypeDesc);
+
+/// Descriptions are compared structurally: two are equal if they are of
+/// the same kind and, for structures, have the same layout and equal
+/// elements, wherever they are stored.
+impl PartialEq for TypeDesc {
+    fn eq(&self, other: &TypeDesc) -> bool {
+        if ptr::eq(self, other) {
+            return true;
+        }
+        // The sizes and alignments compared are the ones computed when
+        // the structures were created; comparing never lays anything
+        // out.
+        let (a, b) = unsafe { (&*self.as_raw_ptr(), &*other.as_raw_ptr()) };
+        self.kind() == other.kind()
+            && (a.type_ != low::type_tag::STRUCT
+                || (a.size, a.alignment) == (b.size, b.alignment)
+                    && self.elements().eq(other.elements()))
+    }
 }
 
+impl Eq for TypeDesc {}
+
+// Hashes the kind and elements, but not the layout, which equal
+// structures share.
+impl Hash for TypeDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind().hash(state);
+        let elements = self.elements();
+        state.write_usize(elements.len());
+        for element in elements {
+            element.hash(state);
+        }
+    }
+}
+
+impl TypeDesc {
+    // Borrows the description at `raw`, which must outlive `'a`.
+    unsafe fn from_raw<'a>(raw: *const low::ffi_type) -> &'a TypeDesc {
+        &*(raw as *const TypeDesc)
+    }
+
+    /// What kind of type this is.
+    pub fn kind(&self) -> TypeKind {
+        TypeKind::from_tag(unsafe { (*self.as_raw_ptr()).type_ })
+    }
+
+    /// Iterates over the types of the fields, if this is a structure
+    /// type.
+    pub fn elements(&self) -> TypeElements<'_> {
+        let raw = self.as_raw_ptr();
+        let elements = unsafe {
+            if (*raw).type_ == low::type_tag::STRUCT && !(*raw).elements.is_null() {
+                slice::from_raw_parts((*raw).elements, ffi_type_array_len((*raw).elements))
+            } else {
+                &[]
+            }
+        };
+        TypeElements(elements.iter())
+    }
+
+    /// Gets the size and alignment of the type, as [`Type::layout`]
+    /// does.
+    pub fn layout(&self) -> (usize, usize) {
+        let raw = self.as_raw_ptr();
+        unsafe { ((*raw).size, (*raw).alignment as usize) }
+    }
+
+    // Whether libffi could lay out the type, which it will then not
+    // write to when preparing a CIF; a struct type it rejected still
+    // has size 0.
+    pub(crate) fn is_laid_out(&self) -> bool {
+        let raw = self.as_raw_ptr();
+        unsafe { (*raw).type_ != low::type_tag::STRUCT || (*raw).size != 0 }
+    }
+
+    /// Copies the description into a new [`Type`].
+    pub fn to_type(&self) -> Type {
+        let copy = ffi_expect!(
+            unsafe { ffi_type_clone(self.as_raw_ptr()) },
+            "TypeDesc::to_type: out of memory"
+        );
+        Type(unsafe { Unique::new(copy) }, None)
+    }
+
+    /// Gets a raw pointer to the underlying [`low::ffi_type`].
+    pub fn as_raw_ptr(&self) -> *mut low::ffi_type {
+        self.0.get()
+    }
+}
+
+/// An iterator over the element types of a structure type, returned by
+/// [`Type::elements`] and [`TypeDesc::elements`].
+#[derive(Clone)]
+#[cfg_attr(not(feature = "min-size"), derive(Debug))]
+pub struct TypeElements<'a>(slice::Iter<'a, Type_>);
+
+#[cfg(feature = "min-size")]
+opaque_debug!(TypeElements<'a>);
+
+impl<'a> Iterator for TypeElements<'a> {
+    type Item = &'a TypeDesc;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|&raw| unsafe { TypeDesc::from_raw(raw) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for TypeElements<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0
+            .next_back()
+            .map(|&raw| unsafe { TypeDesc::from_raw(raw) })
+    }
+}
+
+impl ExactSizeIterator for TypeElements<'_> {}
+
 impl TypeArray {
     /// Constructs an array the given `Type`s.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the array cannot be allocated; see
+    /// [`TypeArray::try_new`].
     pub fn new<I>(elements: I) -> Self
     where
         I: IntoIterator<Item = Type>,
         I::IntoIter: ExactSizeIterator<Item = Type>,
     {
-        TypeArray(unsafe { Unique::new(ffi_type_array_create(elements.into_iter())) })
+        ffi_expect!(
+            TypeArray::try_new(elements),
+            "TypeArray::new: out of memory"
+        )
+    }
+
+    /// Constructs an array of the given `Type`s, or fails if it cannot
+    /// be allocated.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`Error::AllocationFailed`](crate::Error::AllocationFailed)
+    /// if the allocator fails, or the elements are too many to allocate.
+    pub fn try_new<I>(elements: I) -> Result<Self, crate::Error>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        let elements = elements.into_iter();
+        let len = elements.len();
+        let raw = unsafe { ffi_type_array_create(elements)? };
+        Ok(TypeArray(unsafe { Unique::new(raw) }, len))
+    }
+
+    /// The number of types in the array.
+    pub fn len(&self) -> usize {
+        self.1
+    }
+
+    /// Returns whether the array is empty.
+    pub fn is_empty(&self) -> bool {
+        self.1 == 0
+    }
+
+    /// Gets the description of the `i`th type, or `None` if there is
+    /// none; `array[i]` panics instead.
+    pub fn get(&self, i: usize) -> Option<&TypeDesc> {
+        self.elements()
+            .get(i)
+            .map(|&raw| unsafe { TypeDesc::from_raw(raw) })
+    }
+
+    /// Iterates over the descriptions of the types.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Type, TypeArray, TypeKind};
+    ///
+    /// let args: TypeArray = vec![Type::pointer(), Type::u32()].into_iter().collect();
+    /// assert_eq!(TypeKind::U32, args[1].kind());
+    ///
+    /// let sizes: Vec<usize> = args.iter().map(|arg| arg.layout().0).collect();
+    /// assert_eq!(vec![std::mem::size_of::<usize>(), 4], sizes);
+    /// ```
+    pub fn iter(&self) -> TypeElements<'_> {
+        TypeElements(self.elements().iter())
+    }
+
+    /// Creates an array of copies of the types of `self` followed by
+    /// copies of the types of `other`.
+    ///
+    /// # Examples
+    ///
+    /// Prepending the object pointer of a method:
+    ///
+    /// ```
+    /// use libffi::middle::{Type, TypeArray};
+    ///
+    /// let params = TypeArray::new(vec![Type::i32(), Type::f64()]);
+    /// let this = TypeArray::new(vec![Type::pointer()]);
+    ///
+    /// let method = this.concat(&params);
+    /// assert_eq!(3, method.len());
+    /// assert_eq!(params.len(), method.slice(1..).len());
+    /// ```
+    pub fn concat(&self, other: &TypeArray) -> TypeArray {
+        unsafe { TypeArray::copy_from(&[self.elements(), other.elements()]) }
+    }
+
+    /// Creates an array of copies of the types in `range`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds, as slicing would.
+    ///
+    /// # Examples
+    ///
+    /// Dropping the variable arguments of a variadic signature:
+    ///
+    /// ```
+    /// use libffi::middle::{Type, TypeArray};
+    ///
+    /// let printf = TypeArray::new(vec![Type::pointer(), Type::i32(), Type::f64()]);
+    /// let fixed = printf.slice(..1);
+    /// assert_eq!(1, fixed.len());
+    /// ```
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> TypeArray {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len(),
+        };
+        let elements = &self.elements()[start..end];
+        unsafe { TypeArray::copy_from(&[elements]) }
+    }
+
+    // The raw elements, without the null terminator.
+    fn elements(&self) -> &[Type_] {
+        unsafe { slice::from_raw_parts(*self.0, self.1) }
+    }
+
+    // Creates an array of copies of the raw types in `parts`.
+    unsafe fn copy_from(parts: &[&[Type_]]) -> TypeArray {
+        let len = parts.iter().map(|part| part.len()).sum();
+        let new = ffi_expect!(
+            ffi_type_array_copy(parts.iter().flat_map(|part| part.iter().copied()), len),
+            "TypeArray: out of memory"
+        );
+        TypeArray(Unique::new(new), len)
     }
 
     /// Gets a raw pointer to the underlying C array of
@@ -448,9 +1404,248 @@ impl TypeArray {
     pub fn as_raw_ptr(&self) -> *mut *mut low::ffi_type {
         *self.0
     }
+
+    // Releases ownership of the C array to the caller.
+    fn into_raw(self) -> Owned<TypeArray_> {
+        let raw = *self.0;
+        mem::forget(self);
+        raw
+    }
+}
+
+impl Index<usize> for TypeArray {
+    type Output = TypeDesc;
+
+    fn index(&self, i: usize) -> &TypeDesc {
+        unsafe { TypeDesc::from_raw(self.elements()[i]) }
+    }
+}
+
+impl<'a> IntoIterator for &'a TypeArray {
+    type Item = &'a TypeDesc;
+    type IntoIter = TypeElements<'a>;
+
+    fn into_iter(self) -> TypeElements<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for TypeArray {
+    type Item = Type;
+    type IntoIter = vec::IntoIter<Type>;
+
+    /// Takes the types out of the array, without copying them.
+    fn into_iter(self) -> Self::IntoIter {
+        let types: Vec<Type> = self
+            .elements()
+            .iter()
+            .map(|&raw| Type(unsafe { Unique::new(raw) }, None))
+            .collect();
+        // The types now own the elements, so only the array itself is
+        // freed.
+        let len = self.len();
+        unsafe { ffi_type_array_dealloc(self.into_raw(), len) };
+        types.into_iter()
+    }
 }
 
-#[cfg(test)]
+impl FromIterator<Type> for TypeArray {
+    fn from_iter<I: IntoIterator<Item = Type>>(types: I) -> Self {
+        TypeArray::new(types.into_iter().collect::<Vec<_>>())
+    }
+}
+
+/// Builds structure types field by field, with named fields.
+///
+/// This is an alternative to [`Type::structure_named`] for structures
+/// assembled one field at a time, such as those read from a binding
+/// description. The resulting type knows the names of its fields, so
+/// their offsets can be found with [`Type::offset_of`] and values
+/// accessed with [`TypedBuffer`](super::TypedBuffer)’s named field
+/// methods, without duplicating libffi’s layout rules.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::{StructTypeBuilder, Type, TypedBuffer};
+///
+/// #[repr(C)]
+/// struct Particle {
+///     id: u32,
+///     position: [f64; 2],
+///     charge: i8,
+/// }
+///
+/// let particle = StructTypeBuilder::new()
+///     .field("id", Type::u32())
+///     .field("position", Type::array(Type::f64(), 2))
+///     .field("charge", Type::i8())
+///     .build();
+///
+/// assert_eq!(Some(8), particle.offset_of("position"));
+/// assert_eq!(Some(24), particle.offset_of("charge"));
+/// assert_eq!(std::mem::size_of::<Particle>(), particle.layout().0);
+///
+/// let mut value = TypedBuffer::new(particle);
+/// value.set_field("charge", -1i8).unwrap();
+/// assert_eq!(-1, value.as_bytes()[24] as i8);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StructTypeBuilder {
+    names: Vec<String>,
+    types: Vec<Type>,
+}
+
+impl StructTypeBuilder {
+    /// Creates a builder for a structure with no fields yet.
+    pub fn new() -> Self {
+        StructTypeBuilder::default()
+    }
+
+    /// Adds a field called `name` of type `type_`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the structure already has a field called `name`.
+    pub fn field<S: Into<String>>(mut self, name: S, type_: Type) -> Self {
+        let name = name.into();
+        assert!(
+            !self.names.contains(&name),
+            "StructTypeBuilder::field: duplicate field name"
+        );
+        self.names.push(name);
+        self.types.push(type_);
+        self
+    }
+
+    /// Adds several fields, as for [`StructTypeBuilder::field`].
+    pub fn fields<I, S>(self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Type)>,
+        S: Into<String>,
+    {
+        fields
+            .into_iter()
+            .fold(self, |builder, (name, type_)| builder.field(name, type_))
+    }
+
+    /// The number of fields added so far.
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    /// Returns whether no fields have been added.
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// Builds the structure type.
+    pub fn build(self) -> Type {
+        Type::structure_named(self.names.into_iter().zip(self.types))
+    }
+}
+
+/// Accumulates types for building [`TypeArray`]s, reusing its storage.
+///
+/// Binding generators typically describe function after function by
+/// pushing argument types in a loop. A builder keeps the types in a
+/// buffer whose capacity survives [`build`](TypeArrayBuilder::build)
+/// and [`clear`](TypeArrayBuilder::clear), so describing many functions
+/// allocates only the arrays themselves, once each, rather than growing
+/// a new buffer for every function.
+///
+/// # Examples
+///
+/// ```
+/// use libffi::middle::{Type, TypeArrayBuilder};
+///
+/// let mut builder = TypeArrayBuilder::with_capacity(8);
+/// let mut arrays = vec![];
+/// for arity in 0..4 {
+///     builder.extend((0..arity).map(|_| Type::i32()));
+///     builder.push(Type::pointer());
+///     assert_eq!(arity + 1, builder.len());
+///     arrays.push(builder.build());
+/// }
+/// assert!(builder.is_empty());
+/// assert!(builder.capacity() >= 8);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct TypeArrayBuilder {
+    types: Vec<Type>,
+}
+
+impl TypeArrayBuilder {
+    /// Creates an empty builder.
+    pub fn new() -> Self {
+        TypeArrayBuilder::default()
+    }
+
+    /// Creates an empty builder with room for `capacity` types.
+    pub fn with_capacity(capacity: usize) -> Self {
+        TypeArrayBuilder {
+            types: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Appends a type.
+    pub fn push(&mut self, type_: Type) -> &mut Self {
+        self.types.push(type_);
+        self
+    }
+
+    /// Reserves room for at least `additional` more types.
+    pub fn reserve(&mut self, additional: usize) {
+        self.types.reserve(additional);
+    }
+
+    /// Removes all types, keeping the builder’s storage.
+    pub fn clear(&mut self) {
+        self.types.clear();
+    }
+
+    /// The number of types.
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    /// Returns whether the builder holds no types.
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+
+    /// The number of types the builder can hold without allocating.
+    pub fn capacity(&self) -> usize {
+        self.types.capacity()
+    }
+
+    /// The types pushed so far.
+    pub fn types(&self) -> &[Type] {
+        &self.types
+    }
+
+    /// Builds an array of the types, leaving the builder empty, with
+    /// its storage kept for reuse.
+    pub fn build(&mut self) -> TypeArray {
+        TypeArray::new(self.types.drain(..))
+    }
+}
+
+impl Extend<Type> for TypeArrayBuilder {
+    fn extend<I: IntoIterator<Item = Type>>(&mut self, types: I) {
+        self.types.extend(types);
+    }
+}
+
+impl FromIterator<Type> for TypeArrayBuilder {
+    fn from_iter<I: IntoIterator<Item = Type>>(types: I) -> Self {
+        TypeArrayBuilder {
+            types: types.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
     use super::*;
 
@@ -475,4 +1670,560 @@ mod test {
             .clone()
             .clone();
     }
+
+    #[test]
+    fn kinds_and_elements() {
+        let primitives = vec![
+            (Type::void(), TypeKind::Void),
+            (Type::u8(), TypeKind::U8),
+            (Type::i16(), TypeKind::I16),
+            (Type::u32(), TypeKind::U32),
+            (Type::i64(), TypeKind::I64),
+            (Type::f32(), TypeKind::F32),
+            (Type::f64(), TypeKind::F64),
+            (Type::pointer(), TypeKind::Pointer),
+            (Type::c_int(), TypeKind::I32),
+        ];
+        for (type_, kind) in primitives {
+            assert_eq!(kind, type_.kind());
+            assert_eq!(0, type_.elements().len());
+        }
+
+        let inner = Type::structure(vec![Type::u8(), Type::f64()]);
+        let outer = Type::structure(vec![Type::u16(), inner, Type::array(Type::i8(), 3)]);
+        assert_eq!(TypeKind::Struct, outer.kind());
+        let kinds: Vec<_> = outer.elements().map(TypeDesc::kind).collect();
+        assert_eq!(
+            vec![TypeKind::U16, TypeKind::Struct, TypeKind::Struct],
+            kinds
+        );
+
+        let inner = outer.elements().nth(1).unwrap();
+        assert_eq!((16, 8), inner.layout());
+        let kinds: Vec<_> = inner.elements().rev().map(TypeDesc::kind).collect();
+        assert_eq!(vec![TypeKind::F64, TypeKind::U8], kinds);
+        assert_eq!(3, outer.elements().next_back().unwrap().elements().len());
+
+        // The copy outlives the type it was taken from.
+        let copy = inner.to_type();
+        drop(outer);
+        assert_eq!((16, 8), copy.layout());
+        assert_eq!(2, copy.elements().len());
+    }
+
+    #[test]
+    fn type_array_iteration() {
+        let point = Type::structure(vec![Type::f32(), Type::f32()]);
+        let array: TypeArray = (0..3)
+            .map(|i| if i == 1 { point.clone() } else { Type::i64() })
+            .collect();
+        assert_eq!(3, array.len());
+        assert_eq!(TypeKind::Struct, array[1].kind());
+        assert_eq!(2, array[1].elements().len());
+        assert!(array.get(3).is_none());
+
+        let kinds: Vec<_> = (&array).into_iter().map(TypeDesc::kind).collect();
+        assert_eq!(vec![TypeKind::I64, TypeKind::Struct, TypeKind::I64], kinds);
+
+        // Taking the types out leaves them usable after the array is
+        // gone.
+        let types: Vec<Type> = array.into_iter().collect();
+        assert_eq!((8, 4), types[1].layout());
+        let cif = crate::middle::Cif::new(types, Type::void());
+        assert_eq!(Some((8, 4)), cif.arg_layout(1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn type_array_index_out_of_bounds() {
+        let array = TypeArray::new(vec![Type::u8()]);
+        let _ = array[1].kind();
+    }
+
+    #[test]
+    fn struct_from_array() {
+        let inner = Type::structure(vec![Type::u8(), Type::u64()]);
+        let fields = TypeArray::new(vec![inner.clone(), Type::i16(), inner]);
+        let outer = Type::structure_from_array(fields);
+
+        // The clone owns separate copies of the nested structs, so the
+        // types can be dropped in either order.
+        let copy = outer.clone();
+        assert_eq!((40, 8), copy.layout());
+        drop(outer);
+        assert_eq!((40, 8), copy.layout());
+        drop(copy);
+    }
+
+    #[test]
+    fn arrays_own_their_elements() {
+        let pair = Type::structure(vec![Type::u32(), Type::u32()]);
+        let fields = vec![pair.clone(), pair.clone(), Type::u8(), Type::u8(), pair];
+        let array = TypeArray::new(fields);
+
+        let elements: Vec<_> = (0..5)
+            .map(|i| unsafe { *array.as_raw_ptr().add(i) })
+            .collect();
+        // Struct elements are distinct allocations, so freeing the array
+        // frees each once; primitive elements share the static.
+        assert_ne!(elements[0], elements[1]);
+        assert_ne!(elements[1], elements[4]);
+        assert_eq!(elements[2], elements[3]);
+
+        let copy = array.clone();
+        drop(array);
+        assert_eq!(5, copy.len());
+        assert_eq!(5, unsafe { ffi_type_array_len(copy.as_raw_ptr()) });
+        let outer = Type::structure_from_array(copy);
+        assert_eq!((28, 4), outer.layout());
+    }
+
+    // An iterator whose length is wrong, as a buggy one’s may be.
+    struct Claims(usize, Vec<Type>);
+
+    impl Iterator for Claims {
+        type Item = Type;
+
+        fn next(&mut self) -> Option<Type> {
+            self.1.pop()
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (self.0, Some(self.0))
+        }
+    }
+
+    impl ExactSizeIterator for Claims {}
+
+    #[test]
+    fn allocation_failures() {
+        let pair = Type::structure(vec![Type::u8(), Type::u64()]);
+        assert_eq!(
+            Err(crate::Error::AllocationFailed),
+            TypeArray::try_new(Claims(usize::MAX, vec![pair.clone()])).map(|_| ())
+        );
+        assert_eq!(
+            Err(crate::Error::AllocationFailed),
+            Type::try_structure(Claims(usize::MAX / 4, vec![])).map(|_| ())
+        );
+        assert_eq!(
+            crate::Error::AllocationFailed,
+            crate::middle::Cif::try_new(Claims(usize::MAX, vec![]), Type::void()).unwrap_err()
+        );
+
+        // Extra elements are dropped rather than written past the end.
+        let array = TypeArray::try_new(Claims(1, vec![pair.clone(), pair])).unwrap();
+        assert_eq!(1, array.len());
+        assert_eq!(TypeKind::Struct, array[0].kind());
+    }
+
+    #[test]
+    #[should_panic]
+    fn short_iterator() {
+        let pair = Type::structure(vec![Type::u8(), Type::u64()]);
+        let _ = TypeArray::new(Claims(3, vec![pair.clone(), pair]));
+    }
+
+    #[test]
+    fn structural_equality() {
+        use std::collections::HashSet;
+
+        let pair = || Type::structure(vec![Type::u8(), Type::u64()]);
+        assert_eq!(pair(), pair());
+        assert_eq!(pair(), pair().clone());
+        assert_eq!(Type::c_int(), Type::i32());
+        assert_ne!(Type::i32(), Type::u32());
+        assert_ne!(pair(), Type::structure(vec![Type::u64(), Type::u8()]));
+        assert_ne!(pair(), Type::structure(vec![pair()]));
+        assert_eq!(
+            pair(),
+            Type::structure_named(vec![("tag", Type::u8()), ("value", Type::u64())])
+        );
+
+        let types: HashSet<Type> = vec![
+            pair(),
+            Type::f64(),
+            pair().clone(),
+            Type::structure(vec![pair(), pair()]),
+            Type::structure(vec![pair(), pair()]),
+            Type::f64(),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(3, types.len());
+        assert!(types.contains(&Type::structure(vec![Type::u8(), Type::u64()])));
+
+        let args = TypeArray::new(vec![pair(), Type::pointer()]);
+        assert_eq!(args, args.clone());
+        assert_ne!(args, TypeArray::new(vec![pair()]));
+        assert_eq!(args[0], *pair().desc());
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    union Mixed {
+        word: u32,
+        real: f32,
+        wide: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    union Real {
+        single: f32,
+        double: f64,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Tagged {
+        tag: u8,
+        value: Mixed,
+    }
+
+    extern "C" fn bump(mixed: Mixed, real: Real, tagged: Tagged) -> Mixed {
+        let wide = unsafe { mixed.wide + tagged.value.wide } + u64::from(tagged.tag);
+        let real = unsafe { real.double };
+        Mixed {
+            wide: wide + real as u64,
+        }
+    }
+
+    #[test]
+    fn unions_by_value() {
+        use crate::middle::{arg, Cif, CodePtr};
+
+        let mixed = Type::union_(vec![Type::u32(), Type::f32(), Type::u64()]);
+        let real = Type::union_(vec![Type::f32(), Type::f64()]);
+        assert_eq!((8, 8), mixed.layout());
+        assert_eq!((8, 8), real.clone().layout());
+        let tagged = Type::structure(vec![Type::u8(), mixed.clone()]);
+        assert_eq!(
+            (mem::size_of::<Tagged>(), mem::align_of::<Tagged>()),
+            tagged.layout()
+        );
+
+        let cif = Cif::new(vec![mixed.clone(), real, tagged], mixed);
+        let result: Mixed = unsafe {
+            cif.call(
+                CodePtr(bump as *mut _),
+                &[
+                    arg(&Mixed { wide: 1 << 40 }),
+                    arg(&Real { double: 2.0 }),
+                    arg(&Tagged {
+                        tag: 3,
+                        value: Mixed { wide: 4 },
+                    }),
+                ],
+            )
+        };
+        assert_eq!((1 << 40) + 9, unsafe { result.wide });
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    union Floats {
+        triple: [f32; 3],
+        double: f64,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    union Split {
+        pair: [f64; 2],
+        word: i64,
+    }
+
+    extern "C" fn third(floats: Floats) -> f32 {
+        unsafe { floats.triple[2] }
+    }
+
+    extern "C" fn flip(split: Split) -> Split {
+        let [a, b] = unsafe { split.pair };
+        Split { pair: [b, a] }
+    }
+
+    #[test]
+    fn floating_point_eightbytes() {
+        use crate::middle::{arg, Cif, CodePtr};
+
+        let floats = Type::union_(vec![Type::array(Type::f32(), 3), Type::f64()]);
+        let cif = Cif::new(vec![floats], Type::f32());
+        let value = Floats {
+            triple: [1.0, 2.0, 3.0],
+        };
+        let n: f32 = unsafe { cif.call(CodePtr(third as *mut _), &[arg(&value)]) };
+        assert_eq!(3.0, n);
+
+        let split = || Type::union_(vec![Type::array(Type::f64(), 2), Type::i64()]);
+        let cif = Cif::new(vec![split()], split());
+        let value = Split { pair: [1.5, 2.5] };
+        let result: Split = unsafe { cif.call(CodePtr(flip as *mut _), &[arg(&value)]) };
+        assert_eq!([2.5, 1.5], unsafe { result.pair });
+    }
+
+    #[test]
+    fn union_padding() {
+        let odd = Type::union_(vec![Type::array(Type::u8(), 5), Type::u16()]);
+        assert_eq!((6, 2), odd.layout());
+        let floats = Type::union_(vec![Type::array(Type::f32(), 3), Type::f64()]);
+        assert_eq!((16, 8), floats.layout());
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_union() {
+        let _ = Type::union_(vec![]);
+    }
+
+    #[test]
+    fn concat_and_slice() {
+        let pair = || Type::structure(vec![Type::u8(), Type::u64()]);
+        let left = TypeArray::new(vec![Type::pointer(), pair()]);
+        let right = TypeArray::new(vec![pair(), Type::i16()]);
+
+        let both = left.concat(&right);
+        assert_eq!(4, both.len());
+        assert_eq!(4, unsafe { ffi_type_array_len(both.as_raw_ptr()) });
+        // The copies own their struct elements.
+        let raw = |array: &TypeArray, i| unsafe { *array.as_raw_ptr().add(i) };
+        assert_ne!(raw(&left, 1), raw(&both, 1));
+        assert_eq!(raw(&left, 0), raw(&both, 0));
+        drop(left);
+        drop(right);
+
+        let middle = both.slice(1..=2);
+        assert_eq!(2, middle.len());
+        assert_eq!((32, 8), Type::structure_from_array(middle).layout());
+        assert!(both.slice(4..).is_empty());
+        assert_eq!(4, both.slice(..).len());
+        assert_eq!(
+            (16, 8),
+            Type::structure_from_array(both.slice(1..2)).layout()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn slice_out_of_bounds() {
+        let _ = TypeArray::new(vec![Type::u8()]).slice(..2);
+    }
+
+    #[test]
+    fn struct_builder_offsets() {
+        let inner = StructTypeBuilder::new()
+            .field("tag", Type::u8())
+            .field("value", Type::u64())
+            .build();
+        let outer = StructTypeBuilder::new()
+            .fields(vec![("flag", Type::u16()), ("inner", inner)])
+            .field("tail", Type::u8())
+            .build();
+        assert_eq!(
+            Some(&["flag".to_owned(), "inner".to_owned(), "tail".to_owned()][..]),
+            outer.field_names()
+        );
+        assert_eq!(Some(8), outer.offset_of("inner"));
+        assert_eq!(Some(24), outer.offset_of("tail"));
+        assert_eq!(Some(0), outer.field_offset(0));
+        assert_eq!(None, outer.field_offset(3));
+        assert_eq!((32, 8), outer.layout());
+        assert_eq!(None, Type::u32().offset_of("x"));
+        assert!(StructTypeBuilder::new().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate field name")]
+    fn struct_builder_duplicates() {
+        let _ = StructTypeBuilder::new()
+            .field("x", Type::u8())
+            .field("x", Type::u8());
+    }
+
+    #[test]
+    fn builder_reuses_storage() {
+        let mut builder: TypeArrayBuilder = vec![Type::u8(), Type::u64()].into_iter().collect();
+        builder.reserve(14);
+        let capacity = builder.capacity();
+        assert!(capacity >= 16);
+
+        let first = Type::structure_from_array(builder.build());
+        assert_eq!((16, 8), first.layout());
+        assert!(builder.is_empty());
+
+        builder
+            .push(Type::u16())
+            .push(Type::structure(vec![Type::u8(), Type::u8()]));
+        assert_eq!(2, builder.types().len());
+        let copy = builder.clone();
+        builder.clear();
+        assert_eq!(capacity, builder.capacity());
+
+        let mut copy = copy;
+        let second = Type::structure_from_array(copy.build());
+        assert_eq!((4, 2), second.layout());
+        assert!(builder.build().is_empty());
+    }
+
+    // Run under ThreadSanitizer to check for races on the primitive
+    // statics:
+    //
+    //     RUSTFLAGS=-Zsanitizer=thread cargo +nightly test -Zbuild-std \
+    //         --target x86_64-unknown-linux-gnu concurrent_construction
+    #[test]
+    fn concurrent_construction() {
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..100 {
+                        let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+                        let cif = crate::middle::Cif::new(
+                            vec![Type::pointer(), pair.clone(), Type::i32()],
+                            Type::void(),
+                        );
+                        drop(cif);
+                        assert_eq!(
+                            (mem::size_of::<(u8, f64)>(), mem::align_of::<f64>()),
+                            pair.layout()
+                        );
+                        assert_eq!(Type::u64().as_raw_ptr(), Type::u64().as_raw_ptr());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn laid_out_when_created() {
+        let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+        let raw = unsafe { &*pair.as_raw_ptr() };
+        assert_eq!(mem::size_of::<(u8, f64)>(), raw.size);
+
+        // Sharing a struct type between threads that prepare CIFs with
+        // it, compare it and read its layout never writes to it.
+        let shared = std::sync::Arc::new(pair);
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let copy = (*shared).clone();
+                        let cif = crate::middle::Cif::new(vec![(*shared).clone()], copy.clone());
+                        drop(cif);
+                        assert_eq!(*shared, copy);
+                        assert_eq!(shared.layout(), copy.layout());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let empty = Type::structure(vec![]);
+        assert_eq!((0, 0), empty.layout());
+        assert_eq!(
+            Err(crate::Error::BadTypedef),
+            crate::middle::Cif::try_new(vec![empty], Type::void()).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn of_c_aliases() {
+        use std::os::raw::*;
+
+        macro_rules! check {
+            ( $( $alias:ident ),* ) => {
+                $(
+                    assert_eq!(
+                        (mem::size_of::<$alias>(), mem::align_of::<$alias>()),
+                        Type::of::<$alias>().layout(),
+                        stringify!($alias)
+                    );
+                    assert_eq!(
+                        Type::$alias().to_string(),
+                        Type::of::<$alias>().to_string(),
+                        stringify!($alias)
+                    );
+                )*
+            };
+        }
+
+        check!(
+            c_schar,
+            c_uchar,
+            c_short,
+            c_ushort,
+            c_int,
+            c_uint,
+            c_long,
+            c_ulong,
+            c_longlong,
+            c_ulonglong
+        );
+        assert_eq!("f32", Type::of::<c_float>().to_string());
+        assert_eq!("f64", Type::of::<c_double>().to_string());
+        assert_eq!((1, 1), Type::of::<c_char>().layout());
+        assert_eq!("pointer", Type::of::<*mut c_void>().to_string());
+        assert_eq!("void", Type::of::<()>().to_string());
+    }
+
+    #[test]
+    fn arrays_in_structures() {
+        use crate::middle::{arg, Cif, CodePtr};
+
+        #[repr(C)]
+        struct Matrix {
+            m: [f64; 16],
+        }
+
+        #[repr(C)]
+        struct Mixed {
+            tag: u8,
+            grid: [[u16; 3]; 2],
+            scale: f32,
+        }
+
+        extern "C" fn trace(matrix: Matrix) -> f64 {
+            (0..4).map(|i| matrix.m[5 * i]).sum()
+        }
+
+        extern "C" fn total(mixed: Mixed) -> f32 {
+            let sum: u16 = mixed.grid.iter().flatten().sum();
+            (sum + mixed.tag as u16) as f32 * mixed.scale
+        }
+
+        let matrix = Type::structure(vec![Type::array(Type::f64(), 16)]);
+        assert_eq!(
+            (mem::size_of::<Matrix>(), mem::align_of::<Matrix>()),
+            matrix.layout()
+        );
+        let mixed = Type::structure(vec![
+            Type::u8(),
+            Type::array(Type::array(Type::u16(), 3), 2),
+            Type::f32(),
+        ]);
+        assert_eq!(
+            (mem::size_of::<Mixed>(), mem::align_of::<Mixed>()),
+            mixed.layout()
+        );
+
+        let mut m = [0.0; 16];
+        m.iter_mut().enumerate().for_each(|(i, x)| *x = i as f64);
+        let cif = Cif::new(vec![matrix], Type::f64());
+        let n: f64 = unsafe { cif.call(CodePtr(trace as *mut _), &[arg(&Matrix { m })]) };
+        assert_eq!(30.0, n);
+
+        let value = Mixed {
+            tag: 4,
+            grid: [[1, 2, 3], [4, 5, 6]],
+            scale: 0.5,
+        };
+        let cif = Cif::new(vec![mixed], Type::f32());
+        let n: f32 = unsafe { cif.call(CodePtr(total as *mut _), &[arg(&value)]) };
+        assert_eq!(12.5, n);
+    }
 }
@@ -0,0 +1,168 @@
+#[cfg(debug_assertions)]
+use std::any::{self, TypeId};
+use std::os::raw::c_void;
+
+/// Closure userdata that can be recovered with its type from a `void*`.
+///
+/// Callbacks that receive their userdata as an untyped pointer (for
+/// instance, callbacks with the [`low::RawCallback`](crate::low::RawCallback)
+/// signature, or C APIs that pass a separate `void*` context) must cast
+/// it back to the right type. Wrapping the userdata in a `UserData<T>`
+/// makes that cast a single call to [`UserData::from_raw`] or
+/// [`UserData::from_raw_mut`].
+///
+/// In builds with debug assertions enabled, a `UserData` also records
+/// the [`TypeId`](std::any::TypeId) of `T`, and recovering it as any
+/// other type panics instead of silently reinterpreting the data.
+///
+/// # Examples
+///
+/// ```
+/// use std::os::raw::c_void;
+///
+/// use libffi::low;
+/// use libffi::middle::*;
+///
+/// unsafe extern "C" fn callback(
+///     _cif: &low::ffi_cif,
+///     result: &mut u64,
+///     args: *const *const c_void,
+///     userdata: &c_void,
+/// ) {
+///     let offset = UserData::<u64>::from_raw(userdata).as_ref();
+///     *result = **(args as *const &u64) + offset;
+/// }
+///
+/// let offset = UserData::new(5u64);
+/// let cif = Cif::new(vec![Type::u64()], Type::u64());
+/// let closure = Closure::new(cif, callback, unsafe { &*offset.as_ptr() });
+///
+/// let fun: &extern "C" fn(u64) -> u64 = unsafe { closure.instantiate_code_ptr() };
+/// assert_eq!(11, fun(6));
+/// ```
+#[derive(Debug)]
+#[repr(C)]
+pub struct UserData<T> {
+    // Kept first so that it is at the same offset for every `T`.
+    #[cfg(debug_assertions)]
+    tag: TypeId,
+    value: T,
+}
+
+impl<T: 'static> UserData<T> {
+    /// Wraps `value` for passing as closure userdata.
+    pub fn new(value: T) -> Self {
+        UserData {
+            #[cfg(debug_assertions)]
+            tag: TypeId::of::<T>(),
+            value,
+        }
+    }
+
+    /// Unwraps the value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// Gets an untyped pointer to this `UserData`, for passing to C.
+    pub fn as_ptr(&self) -> *const c_void {
+        self as *const Self as *const c_void
+    }
+
+    /// Gets an untyped mutable pointer to this `UserData`, for passing
+    /// to C.
+    pub fn as_mut_ptr(&mut self) -> *mut c_void {
+        self as *mut Self as *mut c_void
+    }
+
+    /// Recovers a `UserData` from an untyped pointer.
+    ///
+    /// # Panics
+    ///
+    /// With debug assertions enabled, panics if `ptr` points to a
+    /// `UserData` of a type other than `T`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been obtained from [`UserData::as_ptr`] or
+    /// [`UserData::as_mut_ptr`] on a `UserData<T>` that is still live,
+    /// and must not be mutated for the lifetime `'a`.
+    pub unsafe fn from_raw<'a>(ptr: *const c_void) -> &'a Self {
+        Self::check_tag(ptr);
+        &*(ptr as *const Self)
+    }
+
+    /// Recovers a mutable `UserData` from an untyped pointer.
+    ///
+    /// # Panics
+    ///
+    /// With debug assertions enabled, panics if `ptr` points to a
+    /// `UserData` of a type other than `T`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been obtained from [`UserData::as_mut_ptr`] on a
+    /// `UserData<T>` that is still live, and must not be otherwise
+    /// accessed for the lifetime `'a`.
+    pub unsafe fn from_raw_mut<'a>(ptr: *mut c_void) -> &'a mut Self {
+        Self::check_tag(ptr);
+        &mut *(ptr as *mut Self)
+    }
+
+    // Reads the tag through the raw pointer, before any reference to a
+    // possibly mistyped `Self` exists.
+    #[cfg(debug_assertions)]
+    unsafe fn check_tag(ptr: *const c_void) {
+        assert!(
+            ptr.cast::<TypeId>().read() == TypeId::of::<T>(),
+            "UserData: userdata is not a `UserData<{}>`",
+            any::type_name::<T>()
+        );
+    }
+
+    #[cfg(not(debug_assertions))]
+    unsafe fn check_tag(_ptr: *const c_void) {}
+}
+
+impl<T> AsRef<T> for UserData<T> {
+    fn as_ref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> AsMut<T> for UserData<T> {
+    fn as_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut data = UserData::new(vec![1, 2, 3]);
+        let ptr = data.as_mut_ptr();
+
+        unsafe {
+            UserData::<Vec<i32>>::from_raw_mut(ptr).as_mut().push(4);
+            assert_eq!(
+                &[1, 2, 3, 4],
+                &UserData::<Vec<i32>>::from_raw(ptr).as_ref()[..]
+            );
+        }
+
+        assert_eq!(vec![1, 2, 3, 4], data.into_inner());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is not a `UserData<u32>`")]
+    fn mismatched_type() {
+        let data = UserData::new(5u64);
+        unsafe {
+            UserData::<u32>::from_raw(data.as_ptr());
+        }
+    }
+}
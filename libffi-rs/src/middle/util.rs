@@ -1,5 +1,8 @@
-use std::marker::PhantomData;
-use std::ops::Deref;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::Deref;
 
 pub struct Unique<T> {
     contents: *mut T,
@@ -21,3 +24,14 @@ impl<T> Unique<T> {
         }
     }
 }
+
+// Storage for values of C types, aligned for any type libffi supports.
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+pub struct Chunk(pub [u8; 16]);
+
+// Allocates zeroed, suitably aligned storage for `size` bytes.
+pub fn chunks(size: usize) -> Box<[Chunk]> {
+    let count = (size + mem::size_of::<Chunk>() - 1) / mem::size_of::<Chunk>();
+    vec![Chunk([0; 16]); count].into_boxed_slice()
+}
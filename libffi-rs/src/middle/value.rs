@@ -0,0 +1,326 @@
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::{fmt, ptr};
+
+use super::buffer::field_layout;
+use super::{Type, TypedBuffer};
+use crate::{low, raw};
+
+/// A structured representation of a C value, using its [`Type`] as the
+/// schema.
+///
+/// Values convert to and from the C layout of a [`TypedBuffer`] with
+/// [`TypedBuffer::to_value`] and [`TypedBuffer::from_value`], which lets
+/// hosts configured with JSON or similar formats build and inspect
+/// arguments and results. With the `serde` feature enabled, values
+/// implement `Serialize` and `Deserialize`.
+///
+/// Pointers are represented as opaque addresses, since the data they
+/// point to is not part of the value. Types with no Rust equivalent,
+/// such as `long double` and complex types, are represented by their
+/// bytes.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Value {
+    /// The value of a `void` type.
+    Void,
+    /// A `uint8_t`.
+    U8(u8),
+    /// An `int8_t`.
+    I8(i8),
+    /// A `uint16_t`.
+    U16(u16),
+    /// An `int16_t`.
+    I16(i16),
+    /// A `uint32_t`.
+    U32(u32),
+    /// An `int32_t` or `int`.
+    I32(i32),
+    /// A `uint64_t`.
+    U64(u64),
+    /// An `int64_t`.
+    I64(i64),
+    /// A `float`.
+    F32(f32),
+    /// A `double`.
+    F64(f64),
+    /// A pointer, as an opaque address.
+    Pointer(u64),
+    /// The fields of a struct, in order.
+    Struct(Vec<Value>),
+    /// The representation of a value with no Rust equivalent.
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    // A description of the kind of value, for errors.
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Void => "void",
+            Value::U8(_) => "u8",
+            Value::I8(_) => "i8",
+            Value::U16(_) => "u16",
+            Value::I16(_) => "i16",
+            Value::U32(_) => "u32",
+            Value::I32(_) => "i32",
+            Value::U64(_) => "u64",
+            Value::I64(_) => "i64",
+            Value::F32(_) => "f32",
+            Value::F64(_) => "f64",
+            Value::Pointer(_) => "pointer",
+            Value::Struct(_) => "struct",
+            Value::Bytes(_) => "bytes",
+        }
+    }
+}
+
+/// The error returned when a [`Value`] doesn’t match the [`Type`] it is
+/// converted to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValueError {
+    /// A value is of a different kind than its type.
+    Mismatch {
+        /// The kind of value the type requires.
+        expected: &'static str,
+        /// The kind of value given.
+        found: &'static str,
+    },
+    /// A struct value has a different number of fields than its type.
+    FieldCount {
+        /// The number of fields of the type.
+        expected: usize,
+        /// The number of fields of the value.
+        actual: usize,
+    },
+    /// A bytes value’s length differs from the size of its type.
+    Size {
+        /// The size of the type.
+        expected: usize,
+        /// The length of the value.
+        actual: usize,
+    },
+    /// A pointer value does not fit in a pointer.
+    Address(u64),
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueError::Mismatch { expected, found } => {
+                write!(f, "expected a {} value, found a {} value", expected, found)
+            }
+            ValueError::FieldCount { expected, actual } => write!(
+                f,
+                "struct value has {} fields but its type has {}",
+                actual, expected
+            ),
+            ValueError::Size { expected, actual } => write!(
+                f,
+                "value of {} bytes for a type of {} bytes",
+                actual, expected
+            ),
+            ValueError::Address(address) => {
+                write!(f, "address {:#x} does not fit in a pointer", address)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ValueError {}
+
+impl TypedBuffer {
+    /// Converts the value to a [`Value`] tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Type, TypedBuffer, Value};
+    ///
+    /// let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+    /// let value = Value::Struct(vec![Value::U8(1), Value::F64(2.5)]);
+    ///
+    /// let buffer = TypedBuffer::from_value(pair, &value).unwrap();
+    /// assert_eq!(&2.5f64.to_ne_bytes()[..], buffer.field_bytes(1).unwrap());
+    /// assert_eq!(value, buffer.to_value());
+    /// ```
+    pub fn to_value(&self) -> Value {
+        unsafe { read_value(&*self.ty().as_raw_ptr(), self.as_bytes()) }
+    }
+
+    /// Creates a value of type `ty` from a [`Value`] tree.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `value` doesn’t match `ty`.
+    pub fn from_value(ty: Type, value: &Value) -> Result<Self, ValueError> {
+        let mut buffer = TypedBuffer::new(ty);
+        let raw = buffer.ty().as_raw_ptr();
+        unsafe { write_value(&*raw, buffer.as_bytes_mut(), value)? };
+        Ok(buffer)
+    }
+}
+
+// Reads a value of type `ty` from exactly its bytes.
+pub(super) unsafe fn read_value(ty: &low::ffi_type, bytes: &[u8]) -> Value {
+    macro_rules! read {
+        ( $variant:ident, $type:ty ) => {
+            Value::$variant(<$type>::from_ne_bytes(bytes.try_into().unwrap()))
+        };
+    }
+
+    match u32::from(ty.type_) {
+        raw::FFI_TYPE_VOID => Value::Void,
+        raw::FFI_TYPE_UINT8 => read!(U8, u8),
+        raw::FFI_TYPE_SINT8 => read!(I8, i8),
+        raw::FFI_TYPE_UINT16 => read!(U16, u16),
+        raw::FFI_TYPE_SINT16 => read!(I16, i16),
+        raw::FFI_TYPE_UINT32 => read!(U32, u32),
+        raw::FFI_TYPE_SINT32 | raw::FFI_TYPE_INT => read!(I32, i32),
+        raw::FFI_TYPE_UINT64 => read!(U64, u64),
+        raw::FFI_TYPE_SINT64 => read!(I64, i64),
+        raw::FFI_TYPE_FLOAT => read!(F32, f32),
+        raw::FFI_TYPE_DOUBLE => read!(F64, f64),
+        raw::FFI_TYPE_POINTER => {
+            Value::Pointer(ptr::read_unaligned(bytes.as_ptr() as *const usize) as u64)
+        }
+        raw::FFI_TYPE_STRUCT => Value::Struct(
+            field_layout(ty)
+                .into_iter()
+                .enumerate()
+                .map(|(i, (offset, size))| {
+                    read_value(&**ty.elements.add(i), &bytes[offset..offset + size])
+                })
+                .collect(),
+        ),
+        _ => Value::Bytes(bytes.to_vec()),
+    }
+}
+
+// The kind of value that represents a value of type `ty`.
+fn kind_of(ty: &low::ffi_type) -> &'static str {
+    match u32::from(ty.type_) {
+        raw::FFI_TYPE_VOID => "void",
+        raw::FFI_TYPE_UINT8 => "u8",
+        raw::FFI_TYPE_SINT8 => "i8",
+        raw::FFI_TYPE_UINT16 => "u16",
+        raw::FFI_TYPE_SINT16 => "i16",
+        raw::FFI_TYPE_UINT32 => "u32",
+        raw::FFI_TYPE_SINT32 | raw::FFI_TYPE_INT => "i32",
+        raw::FFI_TYPE_UINT64 => "u64",
+        raw::FFI_TYPE_SINT64 => "i64",
+        raw::FFI_TYPE_FLOAT => "f32",
+        raw::FFI_TYPE_DOUBLE => "f64",
+        raw::FFI_TYPE_POINTER => "pointer",
+        raw::FFI_TYPE_STRUCT => "struct",
+        _ => "bytes",
+    }
+}
+
+// Writes a value of type `ty` to exactly its bytes.
+unsafe fn write_value(
+    ty: &low::ffi_type,
+    bytes: &mut [u8],
+    value: &Value,
+) -> Result<(), ValueError> {
+    let expected = kind_of(ty);
+    if value.kind() != expected {
+        return Err(ValueError::Mismatch {
+            expected,
+            found: value.kind(),
+        });
+    }
+
+    match value {
+        Value::Void => {}
+        Value::U8(n) => bytes.copy_from_slice(&n.to_ne_bytes()),
+        Value::I8(n) => bytes.copy_from_slice(&n.to_ne_bytes()),
+        Value::U16(n) => bytes.copy_from_slice(&n.to_ne_bytes()),
+        Value::I16(n) => bytes.copy_from_slice(&n.to_ne_bytes()),
+        Value::U32(n) => bytes.copy_from_slice(&n.to_ne_bytes()),
+        Value::I32(n) => bytes.copy_from_slice(&n.to_ne_bytes()),
+        Value::U64(n) => bytes.copy_from_slice(&n.to_ne_bytes()),
+        Value::I64(n) => bytes.copy_from_slice(&n.to_ne_bytes()),
+        Value::F32(x) => bytes.copy_from_slice(&x.to_ne_bytes()),
+        Value::F64(x) => bytes.copy_from_slice(&x.to_ne_bytes()),
+        Value::Pointer(address) => {
+            let address: usize = (*address)
+                .try_into()
+                .map_err(|_| ValueError::Address(*address))?;
+            bytes.copy_from_slice(&address.to_ne_bytes());
+        }
+        Value::Struct(fields) => {
+            let layout = field_layout(ty);
+            if fields.len() != layout.len() {
+                return Err(ValueError::FieldCount {
+                    expected: layout.len(),
+                    actual: fields.len(),
+                });
+            }
+            for (i, (field, (offset, size))) in fields.iter().zip(layout).enumerate() {
+                write_value(
+                    &**ty.elements.add(i),
+                    &mut bytes[offset..offset + size],
+                    field,
+                )?;
+            }
+        }
+        Value::Bytes(value) => {
+            if value.len() != bytes.len() {
+                return Err(ValueError::Size {
+                    expected: bytes.len(),
+                    actual: value.len(),
+                });
+            }
+            bytes.copy_from_slice(value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_structs_round_trip() {
+        let inner = Type::structure(vec![Type::i16(), Type::pointer()]);
+        let outer = Type::structure(vec![Type::u8(), inner, Type::f32(), Type::c_int()]);
+        let value = Value::Struct(vec![
+            Value::U8(200),
+            Value::Struct(vec![Value::I16(-3), Value::Pointer(0x1000)]),
+            Value::F32(0.5),
+            Value::I32(-7),
+        ]);
+
+        let buffer = TypedBuffer::from_value(outer, &value).unwrap();
+        assert_eq!(value, buffer.to_value());
+    }
+
+    #[test]
+    fn mismatches_are_reported() {
+        let pair = Type::structure(vec![Type::u8(), Type::f64()]);
+
+        assert_eq!(
+            Err(ValueError::FieldCount {
+                expected: 2,
+                actual: 1
+            }),
+            TypedBuffer::from_value(pair.clone(), &Value::Struct(vec![Value::U8(1)])).map(drop)
+        );
+        assert_eq!(
+            Err(ValueError::Mismatch {
+                expected: "f64",
+                found: "f32"
+            }),
+            TypedBuffer::from_value(pair, &Value::Struct(vec![Value::U8(1), Value::F32(1.0)]))
+                .map(drop)
+        );
+        assert_eq!(
+            Value::Void,
+            TypedBuffer::from_value(Type::void(), &Value::Void)
+                .unwrap()
+                .to_value()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use super::{Cif, Type};
+
+impl Cif {
+    /// Creates a CIF for calling a variadic function, such as `printf`,
+    /// with the given argument and result types.
+    ///
+    /// The first `nfixed` types of `args` are the function’s fixed
+    /// parameters, and the rest are the types of the variable arguments
+    /// of one particular call; calls with different variable arguments
+    /// need different CIFs. The CIF uses the default calling convention
+    /// and is called like any other, with [`Cif::call`]. Its
+    /// [signature](Cif::signature) marks where the variable arguments
+    /// begin with `...`, as in `(pointer, ..., i32) -> i32`.
+    ///
+    /// C passes variable arguments after the default argument
+    /// promotions, so they should be given as `f64` rather than `f32`,
+    /// and as at least `i32` or `u32` rather than smaller integers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `nfixed` is greater than the number of arguments, or if
+    /// libffi rejects the types, which it may do for unpromoted variable
+    /// arguments.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::os::raw::{c_char, c_int};
+    ///
+    /// use libffi::middle::{arg, Cif, CodePtr, Type};
+    ///
+    /// extern "C" {
+    ///     fn snprintf(buf: *mut c_char, len: usize, format: *const c_char, ...) -> c_int;
+    /// }
+    ///
+    /// let cif = Cif::new_variadic(
+    ///     vec![Type::pointer(), Type::usize(), Type::pointer(), Type::i32(), Type::f64()],
+    ///     3,
+    ///     Type::i32(),
+    /// );
+    /// assert_eq!(Some(3), cif.fixed_args());
+    ///
+    /// let mut buf = [0u8; 32];
+    /// let format = b"%d and %.1f\0";
+    /// let n: c_int = unsafe {
+    ///     cif.call(
+    ///         CodePtr(snprintf as *mut _),
+    ///         &[
+    ///             arg(&buf.as_mut_ptr()),
+    ///             arg(&buf.len()),
+    ///             arg(&format.as_ptr()),
+    ///             arg(&7i32),
+    ///             arg(&2.5f64),
+    ///         ],
+    ///     )
+    /// };
+    /// assert_eq!(b"7 and 2.5", &buf[..n as usize]);
+    /// ```
+    pub fn new_variadic<I>(args: I, nfixed: usize, result: Type) -> Self
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        Cif::prepare(args, Some(nfixed), result, super::ffi_abi_FFI_DEFAULT_ABI)
+    }
+
+    /// Creates a CIF for calling a variadic function, as for
+    /// [`Cif::new_variadic`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if libffi rejects the types, with
+    /// [`Error::BadArgType`](crate::Error::BadArgType) if a variable
+    /// argument has not been promoted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `nfixed` is greater than the number of arguments.
+    pub fn try_new_variadic<I>(args: I, nfixed: usize, result: Type) -> Result<Self, crate::Error>
+    where
+        I: IntoIterator<Item = Type>,
+        I::IntoIter: ExactSizeIterator<Item = Type>,
+    {
+        Cif::try_prepare(args, Some(nfixed), result, super::ffi_abi_FFI_DEFAULT_ABI)
+    }
+
+    /// Gets the number of fixed arguments of a CIF created with
+    /// [`Cif::new_variadic`], or `None` if the CIF is not variadic.
+    pub fn fixed_args(&self) -> Option<usize> {
+        self.nfixed
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::os::raw::{c_char, c_int};
+
+    use super::*;
+    use crate::middle::{arg, Builder, CodePtr};
+
+    extern "C" {
+        fn snprintf(buf: *mut c_char, len: usize, format: *const c_char, ...) -> c_int;
+    }
+
+    // Formats the variable arguments with `format` through `cif`.
+    fn format(cif: &Cif, format: &[u8], args: &[super::super::Arg]) -> String {
+        let mut buf = [0u8; 64];
+        let (ptr, len, format) = (buf.as_mut_ptr(), buf.len(), format.as_ptr());
+        let mut all = vec![arg(&ptr), arg(&len), arg(&format)];
+        all.extend_from_slice(args);
+        let n: c_int = unsafe { cif.call(CodePtr(snprintf as *mut _), &all) };
+        String::from_utf8(buf[..n as usize].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn call_snprintf() {
+        let fixed = || vec![Type::pointer(), Type::usize(), Type::pointer()];
+
+        let cif = Cif::new_variadic(fixed(), 3, Type::i32());
+        assert_eq!(Some(3), cif.fixed_args());
+        assert_eq!("plain", format(&cif, b"plain\0", &[]));
+
+        let mut args = fixed();
+        args.extend(vec![Type::i64(), Type::pointer(), Type::f64()]);
+        let cif = Cif::new_variadic(args, 3, Type::i32());
+        let text = b"text\0";
+        assert_eq!(
+            "-5 text 0.25",
+            format(
+                &cif,
+                b"%lld %s %.2f\0",
+                &[arg(&-5i64), arg(&text.as_ptr()), arg(&0.25f64)]
+            )
+        );
+        assert!(cif
+            .clone()
+            .to_string()
+            .ends_with(", pointer, ..., i64, pointer, f64) -> i32"));
+
+        let cif = Builder::new()
+            .args(fixed())
+            .arg(Type::u32())
+            .res(Type::i32())
+            .into_variadic_cif(3);
+        assert_eq!("4294967295", format(&cif, b"%u\0", &[arg(&u32::MAX)]));
+        assert_eq!(None, Cif::new(fixed(), Type::i32()).fixed_args());
+    }
+
+    #[test]
+    fn parse_variadic() {
+        let cif: Cif = "(pointer, ..., i32) -> i32".parse().unwrap();
+        assert_eq!(Some(1), cif.fixed_args());
+        assert_eq!("(pointer, ..., i32) -> i32", cif.to_string());
+
+        let cif: Cif = "pointer, ... -> void".parse().unwrap();
+        assert_eq!(Some(1), cif.fixed_args());
+        assert_eq!("(pointer, ...) -> void", cif.to_string());
+    }
+
+    #[test]
+    fn unpromoted_variable_arguments() {
+        let fixed = vec![Type::pointer()];
+        assert!(Cif::try_new_variadic(fixed.clone(), 1, Type::i32()).is_ok());
+        for unpromoted in [Type::f32(), Type::u8(), Type::i16()] {
+            let mut args = fixed.clone();
+            args.push(unpromoted);
+            assert_eq!(
+                Err(crate::Error::BadArgType),
+                Cif::try_new_variadic(args, 1, Type::i32()).map(|_| ())
+            );
+        }
+    }
+
+    // Under `min-size`, the assertion aborts instead of panicking.
+    #[test]
+    #[cfg(not(feature = "min-size"))]
+    #[should_panic(expected = "more fixed arguments than arguments")]
+    fn too_many_fixed() {
+        Cif::new_variadic(vec![Type::pointer()], 2, Type::void());
+    }
+}
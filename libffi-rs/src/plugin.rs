@@ -0,0 +1,707 @@
+//! Signature tables, which let a host check the functions of a plugin
+//! against the signatures it expects before calling them.
+//!
+//! A plugin loaded at run time is bound by name alone, so a plugin built
+//! against an older or newer version of its host’s interface links
+//! without complaint, and calling a function whose signature has changed
+//! is undefined behavior. A plugin can instead embed a table of the
+//! signatures of the functions it exports, with
+//! [`signature_table!`](crate::signature_table), and the host can
+//! [load](SignatureTable::load) the table and [bind](SignatureTable::bind)
+//! each function through it, which fails with an [`Error`] rather than
+//! binding a function whose signature differs from the CIF the host
+//! calls it with.
+//!
+//! Signatures are written in the text form described at
+//! [`Cif::signature`], and compared by parsing them: two signatures
+//! match if they describe the same CIF, even if they are written
+//! differently.
+//!
+//! # Format
+//!
+//! A table is exported as the symbol `libffi_rs_signature_table`
+//! ([`TABLE_SYMBOL`]), an array of bytes. All integers are little-endian,
+//! whatever the target, and strings are UTF-8 without terminators. The
+//! table begins with a header of
+//!
+//!  - the magic bytes `FFISIGTB` ([`MAGIC`]),
+//!  - the format version as a `u32`, currently 1 ([`VERSION`]),
+//!  - the number of entries as a `u32`, and
+//!  - the size of the whole table in bytes, header included, as a
+//!    `u32`,
+//!
+//! followed by the entries. Each entry consists of
+//!
+//!  - the length of the function’s name as a `u32`, and the name,
+//!  - the length of its signature as a `u32`, and the signature, and
+//!  - the 64-bit FNV-1a hash of the signature’s bytes as a `u64`,
+//!
+//! which lets a table that was damaged or written by hand be told from
+//! one whose signatures merely differ. Names are unique within a table.
+//!
+//! # Examples
+//!
+//! A plugin declares its table once:
+//!
+//! ```
+//! libffi::signature_table! {
+//!     "plugin_init" => "(pointer) -> i32",
+//!     "plugin_scale" => "({f64, f64}, f64) -> {f64, f64}",
+//! }
+//! # let _ = &libffi_rs_signature_table;
+//! ```
+//!
+//! and its host binds the plugin’s functions through it:
+//!
+//! ```no_run
+//! # #[cfg(any(all(unix, feature = "libc"), windows))] {
+//! use libffi::library::Library;
+//! use libffi::middle::{Cif, Type};
+//! use libffi::plugin::SignatureTable;
+//!
+//! let plugin = unsafe { Library::open("libplugin.so") }.unwrap();
+//! let table = unsafe { SignatureTable::load(&plugin) }.unwrap();
+//!
+//! let init = table
+//!     .bind(&plugin, "plugin_init", Cif::new(vec![Type::pointer()], Type::i32()))
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::{error, fmt, slice};
+
+#[cfg(any(all(unix, feature = "libc"), windows))]
+use crate::library::{self, Library};
+#[cfg(any(all(unix, feature = "libc"), windows))]
+use crate::middle::BoundFn;
+use crate::middle::{canonical_signature, Cif, ParseError};
+
+/// The name of the symbol a plugin exports its table as.
+pub const TABLE_SYMBOL: &str = "libffi_rs_signature_table";
+
+/// The bytes a table begins with.
+pub const MAGIC: [u8; 8] = *b"FFISIGTB";
+
+/// The version of the format written by this crate.
+pub const VERSION: u32 = 1;
+
+// The magic bytes, version, entry count and size.
+const HEADER_LEN: usize = 8 + 4 + 4 + 4;
+
+/// Errors that occur while reading signature tables and binding
+/// functions through them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The table is not in the format described in the
+    /// [module documentation](self).
+    Malformed(&'static str),
+    /// The table is in a version of the format this crate cannot read.
+    UnsupportedVersion(u32),
+    /// The hash of an entry’s signature does not match the signature.
+    Corrupt {
+        /// The name of the function.
+        name: String,
+    },
+    /// The table has no entry for a function.
+    Missing {
+        /// The name of the function.
+        name: String,
+    },
+    /// An entry’s signature does not parse.
+    Invalid {
+        /// The name of the function.
+        name: String,
+        /// Why the signature does not parse.
+        error: ParseError,
+    },
+    /// A function’s signature differs from the one it was to be bound
+    /// with.
+    Mismatch {
+        /// The name of the function.
+        name: String,
+        /// The canonical signature it was to be bound with.
+        expected: Arc<str>,
+        /// The canonical signature in the table.
+        found: Arc<str>,
+    },
+    /// The table or a function could not be found in the library.
+    #[cfg(any(all(unix, feature = "libc"), windows))]
+    Library(library::Error),
+}
+
+#[cfg(any(all(unix, feature = "libc"), windows))]
+impl From<library::Error> for Error {
+    fn from(error: library::Error) -> Self {
+        Error::Library(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Malformed(reason) => write!(f, "malformed signature table: {}", reason),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported signature table version {}", version)
+            }
+            Error::Corrupt { name } => write!(f, "corrupt signature for `{}`", name),
+            Error::Missing { name } => write!(f, "no signature for `{}`", name),
+            Error::Invalid { name, error } => {
+                write!(f, "invalid signature for `{}`: {}", name, error)
+            }
+            Error::Mismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "signature mismatch for `{}`: expected `{}`, found `{}`",
+                name, expected, found
+            ),
+            #[cfg(any(all(unix, feature = "libc"), windows))]
+            Error::Library(error) => error.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A function’s entry in a [`SignatureTable`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureEntry {
+    /// The name of the function.
+    pub name: String,
+    /// Its signature, in the text form described at [`Cif::signature`].
+    pub signature: String,
+}
+
+impl SignatureEntry {
+    /// The hash of the signature stored in the table.
+    pub fn hash(&self) -> u64 {
+        fnv1a(self.signature.as_bytes())
+    }
+}
+
+/// A table of the signatures of a plugin’s functions.
+///
+/// See the [module documentation](self).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SignatureTable {
+    entries: Vec<SignatureEntry>,
+}
+
+impl SignatureTable {
+    /// Creates an empty table.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds the function `name` with the signature of `cif`, replacing
+    /// any entry it already has.
+    pub fn insert<S: Into<String>>(&mut self, name: S, cif: &Cif) {
+        let entry = SignatureEntry {
+            name: name.into(),
+            signature: cif.signature().to_string(),
+        };
+        match self.entries.iter_mut().find(|e| e.name == entry.name) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    /// The entries of the table, in order.
+    pub fn entries(&self) -> &[SignatureEntry] {
+        &self.entries
+    }
+
+    /// The entry for the function `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<&SignatureEntry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// Encodes the table in the format described in the
+    /// [module documentation](self).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the table is larger than the format allows, 4 GiB.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn put_u32(bytes: &mut Vec<u8>, n: usize) {
+            let n: u32 = n
+                .try_into()
+                .expect("SignatureTable::to_bytes: table too large");
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        put_u32(&mut bytes, self.entries.len());
+        put_u32(&mut bytes, 0);
+        for entry in &self.entries {
+            put_u32(&mut bytes, entry.name.len());
+            bytes.extend_from_slice(entry.name.as_bytes());
+            put_u32(&mut bytes, entry.signature.len());
+            bytes.extend_from_slice(entry.signature.as_bytes());
+            bytes.extend_from_slice(&entry.hash().to_le_bytes());
+        }
+        let size: u32 = bytes
+            .len()
+            .try_into()
+            .expect("SignatureTable::to_bytes: table too large");
+        bytes[16..HEADER_LEN].copy_from_slice(&size.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a table in the format described in the
+    /// [module documentation](self).
+    ///
+    /// The signatures are not parsed until they are
+    /// [verified](SignatureTable::verify).
+    ///
+    /// # Errors
+    ///
+    /// Fails if the bytes are not a table this crate can read, or the
+    /// hash of a signature does not match it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(Error::Malformed("bad magic bytes"));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let count = reader.u32()? as usize;
+        if reader.u32()? as usize != bytes.len() {
+            return Err(Error::Malformed("size does not match the table"));
+        }
+
+        let mut table = SignatureTable::new();
+        for _ in 0..count {
+            let name = reader.str()?;
+            let signature = reader.str()?;
+            let hash = reader.u64()?;
+            if table.get(name).is_some() {
+                return Err(Error::Malformed("duplicate name"));
+            }
+            if hash != fnv1a(signature.as_bytes()) {
+                return Err(Error::Corrupt { name: name.into() });
+            }
+            table.entries.push(SignatureEntry {
+                name: name.into(),
+                signature: signature.into(),
+            });
+        }
+        if !reader.0.is_empty() {
+            return Err(Error::Malformed("trailing bytes"));
+        }
+        Ok(table)
+    }
+
+    /// Decodes the table at `table`, whose size is read from its
+    /// header.
+    ///
+    /// # Errors
+    ///
+    /// As for [`SignatureTable::from_bytes`].
+    ///
+    /// # Safety
+    ///
+    /// `table` must point to a table’s header, which must be followed
+    /// by as many bytes as it says the table has.
+    pub unsafe fn from_ptr(table: *const u8) -> Result<Self, Error> {
+        let header = slice::from_raw_parts(table, HEADER_LEN);
+        if header[..MAGIC.len()] != MAGIC {
+            return Err(Error::Malformed("bad magic bytes"));
+        }
+        let size = u32::from_le_bytes(header[16..HEADER_LEN].try_into().unwrap()) as usize;
+        if size < HEADER_LEN {
+            return Err(Error::Malformed("size does not match the table"));
+        }
+        SignatureTable::from_bytes(slice::from_raw_parts(table, size))
+    }
+
+    /// Finds and decodes the table `library` exports as
+    /// [`TABLE_SYMBOL`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the library exports no table, or the table cannot be
+    /// decoded, as for [`SignatureTable::from_bytes`].
+    ///
+    /// # Safety
+    ///
+    /// If the library exports [`TABLE_SYMBOL`], it must be a table, as
+    /// [`signature_table!`](crate::signature_table) declares.
+    #[cfg(any(all(unix, feature = "libc"), windows))]
+    pub unsafe fn load(library: &Library) -> Result<Self, Error> {
+        let table = library.symbol(TABLE_SYMBOL)?;
+        SignatureTable::from_ptr(table.as_ptr() as *const u8)
+    }
+
+    /// Checks that the table gives the function `name` the signature of
+    /// `cif`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the table has no entry for the function, or its
+    /// signature does not parse or differs from that of `cif`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use libffi::middle::{Cif, Type};
+    /// use libffi::plugin::{Error, SignatureTable};
+    ///
+    /// let mut table = SignatureTable::new();
+    /// table.insert("area", &"({f64, f64}) -> f64".parse().unwrap());
+    ///
+    /// let pair = Type::structure(vec![Type::f64(), Type::f64()]);
+    /// assert_eq!(Ok(()), table.verify("area", &Cif::new(vec![pair], Type::f64())));
+    ///
+    /// let cif = Cif::new(vec![Type::f64(), Type::f64()], Type::f64());
+    /// assert!(matches!(table.verify("area", &cif), Err(Error::Mismatch { .. })));
+    /// ```
+    pub fn verify(&self, name: &str, cif: &Cif) -> Result<(), Error> {
+        let entry = self
+            .get(name)
+            .ok_or_else(|| Error::Missing { name: name.into() })?;
+        // The entry is only rewritten in canonical form, which the
+        // parser's limits bound, not prepared as a CIF.
+        let found: Arc<str> = canonical_signature(&entry.signature)
+            .map_err(|error| Error::Invalid {
+                name: name.into(),
+                error,
+            })?
+            .into();
+        let expected = cif.signature();
+        if found == expected {
+            Ok(())
+        } else {
+            Err(Error::Mismatch {
+                name: name.into(),
+                expected,
+                found,
+            })
+        }
+    }
+
+    /// Looks up the function `name` in `library` and pairs it with
+    /// `cif`, as [`Library::bind`] does, if the table gives it the
+    /// signature of `cif`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the signatures differ, as for
+    /// [`SignatureTable::verify`], or the function cannot be found.
+    #[cfg(any(all(unix, feature = "libc"), windows))]
+    pub fn bind<C: Into<Arc<Cif>>>(
+        &self,
+        library: &Library,
+        name: &str,
+        cif: C,
+    ) -> Result<BoundFn, Error> {
+        let cif = cif.into();
+        self.verify(name, &cif)?;
+        Ok(library.bind(name, cif)?)
+    }
+}
+
+// Reads the fields of a table in turn.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::Malformed("truncated table"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<&'a str, Error> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| Error::Malformed("invalid UTF-8"))
+    }
+}
+
+// The 64-bit FNV-1a hash.
+pub(crate) const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+// The size of the table of `entries`, as `(name, signature)` pairs.
+pub(crate) const fn encoded_len(entries: &[(&str, &str)]) -> usize {
+    let mut len = HEADER_LEN;
+    let mut i = 0;
+    while i < entries.len() {
+        len += 4 + entries[i].0.len() + 4 + entries[i].1.len() + 8;
+        i += 1;
+    }
+    len
+}
+
+// Encodes the table of `entries` at compile time, as
+// `SignatureTable::to_bytes` does at run time; `N` must be its
+// `encoded_len`.
+pub(crate) const fn encode<const N: usize>(entries: &[(&str, &str)]) -> [u8; N] {
+    const fn put<const N: usize>(mut out: [u8; N], at: usize, bytes: &[u8]) -> [u8; N] {
+        let mut i = 0;
+        while i < bytes.len() {
+            out[at + i] = bytes[i];
+            i += 1;
+        }
+        out
+    }
+
+    const fn len(n: usize) -> [u8; 4] {
+        assert!(n <= u32::MAX as usize, "signature_table!: table too large");
+        (n as u32).to_le_bytes()
+    }
+
+    assert!(
+        N == encoded_len(entries),
+        "signature_table!: wrong table size"
+    );
+    let mut out = [0u8; N];
+    out = put(out, 0, &MAGIC);
+    out = put(out, 8, &VERSION.to_le_bytes());
+    out = put(out, 12, &len(entries.len()));
+    out = put(out, 16, &len(N));
+    let mut at = HEADER_LEN;
+    let mut i = 0;
+    while i < entries.len() {
+        let (name, signature) = (entries[i].0.as_bytes(), entries[i].1.as_bytes());
+        let mut j = 0;
+        while j < i {
+            assert!(
+                !eq(entries[j].0.as_bytes(), name),
+                "signature_table!: duplicate name"
+            );
+            j += 1;
+        }
+        out = put(out, at, &len(name.len()));
+        out = put(out, at + 4, name);
+        at += 4 + name.len();
+        out = put(out, at, &len(signature.len()));
+        out = put(out, at + 4, signature);
+        at += 4 + signature.len();
+        out = put(out, at, &fnv1a(signature).to_le_bytes());
+        at += 8;
+        i += 1;
+    }
+    out
+}
+
+const fn eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Declares the signature table of a plugin, exported as
+/// [`TABLE_SYMBOL`](crate::plugin::TABLE_SYMBOL).
+///
+/// Each entry gives the name of a function the plugin exports and its
+/// signature, in the text form described at
+/// [`Cif::signature`](crate::middle::Cif::signature). The table is
+/// encoded at compile time; the signatures are parsed when the host
+/// verifies them. Use the macro once per plugin, at the top level of the
+/// crate that is built as the plugin.
+///
+/// # Examples
+///
+/// ```
+/// libffi::signature_table! {
+///     "plugin_version" => "() -> u32",
+///     "plugin_run" => "(pointer, *u8, u64) -> i32",
+/// }
+///
+/// use libffi::plugin::SignatureTable;
+///
+/// let table = SignatureTable::from_bytes(&libffi_rs_signature_table).unwrap();
+/// assert_eq!("() -> u32", table.get("plugin_version").unwrap().signature);
+/// ```
+#[macro_export]
+macro_rules! signature_table {
+    ( $( $name:expr => $signature:expr ),* $(,)? ) => {
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static libffi_rs_signature_table: [
+            u8;
+            $crate::__private::v1::signature_table_len(&[$( ($name, $signature) ),*])
+        ] = $crate::__private::v1::signature_table(&[$( ($name, $signature) ),*]);
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::Type;
+
+    signature_table! {
+        "scale" => "({f64, f64}, f64) -> {f64, f64}",
+        "name" => "(*u8, [u32; 2]) -> void",
+    }
+
+    fn scale_cif() -> Cif {
+        let pair = Type::structure(vec![Type::f64(), Type::f64()]);
+        Cif::new(vec![pair.clone(), Type::f64()], pair)
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut table = SignatureTable::new();
+        table.insert("scale", &scale_cif());
+        table.insert("name", &"(pointer, {u32, u32}) -> void".parse().unwrap());
+        let bytes = table.to_bytes();
+        assert_eq!(table, SignatureTable::from_bytes(&bytes).unwrap());
+        assert_eq!(
+            table,
+            unsafe { SignatureTable::from_ptr(bytes.as_ptr()) }.unwrap()
+        );
+        assert_eq!(&bytes[..8], b"FFISIGTB");
+
+        // The table is encoded the same way at compile time.
+        let embedded = SignatureTable::from_bytes(&libffi_rs_signature_table).unwrap();
+        assert_eq!("(*u8, [u32; 2]) -> void", embedded.entries()[1].signature);
+        assert_eq!(&libffi_rs_signature_table[..], &embedded.to_bytes()[..]);
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let table = SignatureTable::from_bytes(&libffi_rs_signature_table).unwrap();
+        assert_eq!(Ok(()), table.verify("scale", &scale_cif()));
+        assert_eq!(
+            Ok(()),
+            table.verify("name", &"(pointer, {u32, u32}) -> void".parse().unwrap())
+        );
+
+        let cif = Cif::new(vec![Type::f64(), Type::f64(), Type::f64()], Type::f64());
+        assert_eq!(
+            Err(Error::Mismatch {
+                name: "scale".into(),
+                expected: "(f64, f64, f64) -> f64".into(),
+                found: "({f64, f64}, f64) -> {f64, f64}".into(),
+            }),
+            table.verify("scale", &cif)
+        );
+        assert_eq!(
+            Err(Error::Missing {
+                name: "other".into()
+            }),
+            table.verify("other", &cif)
+        );
+
+        let mut table = table;
+        table.entries[0].signature = "(f64, quad) -> f64".into();
+        assert!(matches!(
+            table.verify("scale", &cif),
+            Err(Error::Invalid { .. })
+        ));
+        table.entries[0].signature = format!("{}u8{} -> void", "[".repeat(100), "; 2]".repeat(100));
+        assert!(matches!(
+            table.verify("scale", &cif),
+            Err(Error::Invalid {
+                error: ParseError::TooDeep { .. },
+                ..
+            })
+        ));
+
+        // Non-canonical entries are compared in canonical form.
+        table.entries[0].signature = "[f64; 3] -> *void".into();
+        let cif = Cif::new(vec![Type::array(Type::f64(), 3)], Type::pointer());
+        assert_eq!(Ok(()), table.verify("scale", &cif));
+    }
+
+    #[test]
+    fn rejects_bad_tables() {
+        let good = SignatureTable::from_bytes(&libffi_rs_signature_table)
+            .unwrap()
+            .to_bytes();
+        let check = |edit: &dyn Fn(&mut Vec<u8>), error: Error| {
+            let mut bytes = good.clone();
+            edit(&mut bytes);
+            assert_eq!(Err(error), SignatureTable::from_bytes(&bytes));
+        };
+
+        check(
+            &|bytes| bytes[0] = b'X',
+            Error::Malformed("bad magic bytes"),
+        );
+        check(&|bytes| bytes[8] = 2, Error::UnsupportedVersion(2));
+        check(
+            &|bytes| bytes.truncate(bytes.len() - 1),
+            Error::Malformed("size does not match the table"),
+        );
+        check(
+            &|bytes| {
+                bytes.truncate(bytes.len() - 1);
+                let size = (bytes.len() as u32).to_le_bytes();
+                bytes[16..20].copy_from_slice(&size);
+            },
+            Error::Malformed("truncated table"),
+        );
+        // Change a type in the first signature, `{f64, f64}` to
+        // `{f32, f64}`, without updating its hash.
+        check(
+            &|bytes| {
+                let at = bytes.windows(3).position(|w| w == b"f64").unwrap();
+                bytes[at + 1..at + 3].copy_from_slice(b"32");
+            },
+            Error::Corrupt {
+                name: "scale".into(),
+            },
+        );
+        check(&|bytes| bytes[12] = 1, Error::Malformed("trailing bytes"));
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "libc"))]
+    fn binds_through_the_table() {
+        let libm = unsafe { Library::open("libm.so.6") }.unwrap();
+        assert!(matches!(
+            unsafe { SignatureTable::load(&libm) },
+            Err(Error::Library(library::Error::Symbol { .. }))
+        ));
+
+        let this = Library::this_process().unwrap();
+
+        let mut table = SignatureTable::new();
+        table.insert("abs", &"(i32) -> i32".parse().unwrap());
+        let abs = table
+            .bind(&this, "abs", Cif::new(vec![Type::c_int()], Type::c_int()))
+            .unwrap();
+        assert_eq!(4, unsafe { abs.call::<i32>(&[crate::middle::arg(&-4i32)]) });
+        assert!(matches!(
+            table.bind(&this, "abs", Cif::new(vec![Type::f64()], Type::f64())),
+            Err(Error::Mismatch { .. })
+        ));
+    }
+}
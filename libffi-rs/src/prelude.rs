@@ -0,0 +1,54 @@
+//! Re-exports of the most commonly used items.
+//!
+//! Downstream code that imports from the prelude does not need to know
+//! which layer an item lives in, so it is unaffected if items move
+//! between the [`mod@low`](crate::low), [`mod@middle`](crate::middle),
+//! and [`mod@high`](crate::high) layers in the future.
+//!
+//! Where the layers define items with the same name, the prelude
+//! exports the [`middle`](crate::middle) layer’s version: [`Type`] is
+//! [`middle::Type`](crate::middle::Type) and [`Arg`] is
+//! [`middle::Arg`](crate::middle::Arg). The typed closures of the high
+//! layer are exported under their own names.
+//!
+//! # Examples
+//!
+//! ```
+//! use libffi::prelude::*;
+//!
+//! extern "C" fn add(x: u64, y: u64) -> u64 {
+//!     x + y
+//! }
+//!
+//! let cif = Cif::new(vec![Type::u64(), Type::u64()], Type::u64());
+//! let n: u64 = unsafe { cif.call(CodePtr(add as *mut _), &[arg(&5u64), arg(&6u64)]) };
+//! assert_eq!(11, n);
+//!
+//! let f = |x: u64| x * 2;
+//! let closure = Closure1::new(&f);
+//! assert_eq!(22, closure.code_ptr().call(n));
+//! ```
+
+pub use crate::{args, ffi_call, method_closure};
+
+pub use crate::low::CodePtr;
+
+pub use crate::middle::{arg, Arg, Builder, Cif, Closure, ClosureOnce, Type};
+
+pub use crate::high::CType;
+
+pub use crate::high::{
+    Closure0, Closure1, Closure10, Closure11, Closure12, Closure2, Closure3, Closure4, Closure5,
+    Closure6, Closure7, Closure8, Closure9,
+};
+
+pub use crate::high::{
+    ClosureMut0, ClosureMut1, ClosureMut10, ClosureMut11, ClosureMut12, ClosureMut2, ClosureMut3,
+    ClosureMut4, ClosureMut5, ClosureMut6, ClosureMut7, ClosureMut8, ClosureMut9,
+};
+
+pub use crate::high::{
+    ClosureOnce0, ClosureOnce1, ClosureOnce10, ClosureOnce11, ClosureOnce12, ClosureOnce2,
+    ClosureOnce3, ClosureOnce4, ClosureOnce5, ClosureOnce6, ClosureOnce7, ClosureOnce8,
+    ClosureOnce9,
+};
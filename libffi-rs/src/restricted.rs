@@ -0,0 +1,488 @@
+//! Calling only the functions and signatures a host has approved.
+//!
+//! A host that binds functions named at run time, for instance by a
+//! script or a configuration file, can bind any function of any loaded
+//! library, with any signature. A [`RestrictedCaller`] instead binds only
+//! the functions it was given as `(library, symbol, signature)` triples,
+//! with exactly the approved signature, and refuses everything else with
+//! an [`Error`]. The approved set is given to a
+//! [`RestrictedCallerBuilder`], and is fixed once the builder builds the
+//! caller: a `RestrictedCaller` has no methods for registering libraries
+//! or approving functions, so a caller shared between threads or handed
+//! to less trusted code cannot be widened.
+//!
+//! Each decision, whether a function was bound or called, or refused,
+//! is reported to the caller’s audit hooks as an [`AuditEvent`], for
+//! logging or alerting.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(unix)] {
+//! use std::sync::{Arc, Mutex};
+//!
+//! use libffi::library::Library;
+//! use libffi::middle::{arg, Cif, Type};
+//! use libffi::restricted::RestrictedCaller;
+//!
+//! let log = Arc::new(Mutex::new(Vec::new()));
+//! let caller = {
+//!     let log = log.clone();
+//!     RestrictedCaller::builder()
+//!         .library("c", Library::this_process().unwrap())
+//!         .allow("c", "abs", &Cif::new(vec![Type::c_int()], Type::c_int()))
+//!         .on_audit(move |event| log.lock().unwrap().push(event.to_string()))
+//!         .build()
+//! };
+//!
+//! let abs = Cif::new(vec![Type::c_int()], Type::c_int());
+//! let n: std::os::raw::c_int = unsafe { caller.call("c", "abs", &abs, &[arg(&-4)]) }.unwrap();
+//! assert_eq!(4, n);
+//!
+//! let getenv = Cif::new(vec![Type::pointer()], Type::pointer());
+//! assert!(caller.bind("c", "getenv", &getenv).is_err());
+//!
+//! assert_eq!(
+//!     vec![
+//!         "call c:abs (i32) -> i32: allowed",
+//!         "bind c:getenv (pointer) -> pointer: denied",
+//!     ],
+//!     *log.lock().unwrap()
+//! );
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::{error, fmt};
+
+use crate::library::{self, Library};
+use crate::middle::{Arg, BoundFn, Cif};
+
+/// Errors that occur while binding and calling functions through a
+/// [`RestrictedCaller`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The function, or the function with this signature, has not been
+    /// approved.
+    Denied {
+        /// The name the library was registered under.
+        library: String,
+        /// The name of the function.
+        symbol: String,
+        /// The canonical signature it was to be bound with.
+        signature: Arc<str>,
+    },
+    /// The function was approved, but could not be found in its library.
+    Library(library::Error),
+}
+
+impl From<library::Error> for Error {
+    fn from(error: library::Error) -> Self {
+        Error::Library(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Denied {
+                library,
+                symbol,
+                signature,
+            } => write!(
+                f,
+                "calling {}:{} with signature `{}` is not allowed",
+                library, symbol, signature
+            ),
+            Error::Library(error) => error.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// What a [`RestrictedCaller`] was asked to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Bind a function, with [`RestrictedCaller::bind`].
+    Bind,
+    /// Call a function, with [`RestrictedCaller::call`].
+    Call,
+}
+
+/// A decision of a [`RestrictedCaller`], reported to its audit hooks.
+///
+/// Its [`Display`](fmt::Display) form is a line suitable for a log,
+/// such as `call libc:abs (i32) -> i32: allowed`.
+#[derive(Clone, Copy, Debug)]
+pub struct AuditEvent<'a> {
+    /// What the caller was asked to do.
+    pub action: Action,
+    /// The name the library was registered under.
+    pub library: &'a str,
+    /// The name of the function.
+    pub symbol: &'a str,
+    /// The canonical signature of the CIF it was asked for with.
+    pub signature: &'a str,
+    /// Whether it was done, or why not.
+    pub outcome: Result<(), &'a Error>,
+}
+
+impl AuditEvent<'_> {
+    /// Whether the caller did what it was asked.
+    pub fn is_allowed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+impl fmt::Display for AuditEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.action {
+            Action::Bind => "bind",
+            Action::Call => "call",
+        };
+        write!(
+            f,
+            "{} {}:{} {}: ",
+            action, self.library, self.symbol, self.signature
+        )?;
+        match self.outcome {
+            Ok(()) => f.write_str("allowed"),
+            Err(Error::Denied { .. }) => f.write_str("denied"),
+            Err(error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+type AuditHook = Arc<dyn Fn(&AuditEvent) + Send + Sync>;
+
+// An approved function, bound the first time it is used.
+struct Approved {
+    library: Library,
+    cif: Arc<Cif>,
+    signature: Arc<str>,
+    bound: OnceLock<Result<BoundFn, library::Error>>,
+}
+
+type ApprovedSet = HashMap<(String, String), Arc<Approved>>;
+
+// The approved functions, sorted, for debugging.
+fn sorted(approved: &ApprovedSet) -> Vec<(&String, &String, &Arc<str>)> {
+    let mut sorted: Vec<_> = approved
+        .iter()
+        .map(|((library, symbol), approved)| (library, symbol, &approved.signature))
+        .collect();
+    sorted.sort();
+    sorted
+}
+
+/// Builds a [`RestrictedCaller`], by [registering](Self::library)
+/// libraries under names, [approving](Self::allow) functions of them,
+/// and adding [audit hooks](Self::on_audit).
+#[derive(Clone, Default)]
+pub struct RestrictedCallerBuilder {
+    libraries: HashMap<String, Library>,
+    approved: ApprovedSet,
+    hooks: Vec<AuditHook>,
+}
+
+impl fmt::Debug for RestrictedCallerBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut libraries: Vec<_> = self.libraries.keys().collect();
+        libraries.sort();
+        f.debug_struct("RestrictedCallerBuilder")
+            .field("libraries", &libraries)
+            .field("approved", &sorted(&self.approved))
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl RestrictedCallerBuilder {
+    /// Creates a builder that allows nothing.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers `library` under the name `name`, by which its functions
+    /// are approved and called.
+    ///
+    /// Registering another library under the same name replaces the
+    /// first for the functions approved afterwards.
+    pub fn library<S: Into<String>>(mut self, name: S, library: Library) -> Self {
+        self.libraries.insert(name.into(), library);
+        self
+    }
+
+    /// Approves the function `symbol` of the library registered as
+    /// `library`, with the signature of `cif`.
+    ///
+    /// A function has one approved signature; approving it again
+    /// replaces the signature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no library is registered as `library`.
+    pub fn allow(mut self, library: &str, symbol: &str, cif: &Cif) -> Self {
+        let registered = self.libraries.get(library).unwrap_or_else(|| {
+            panic!(
+                "RestrictedCallerBuilder::allow: no library registered as `{}`",
+                library
+            )
+        });
+        let approved = Approved {
+            library: registered.clone(),
+            cif: Arc::new(cif.clone()),
+            signature: cif.signature(),
+            bound: OnceLock::new(),
+        };
+        self.approved
+            .insert((library.to_owned(), symbol.to_owned()), Arc::new(approved));
+        self
+    }
+
+    /// Adds a hook that is called with each decision of the caller.
+    ///
+    /// Hooks are called in the order they were added, on the thread
+    /// that asked for the function, before the function is returned or
+    /// called.
+    pub fn on_audit<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuditEvent) + Send + Sync + 'static,
+    {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Builds the caller, which allows only the functions approved so
+    /// far.
+    pub fn build(self) -> RestrictedCaller {
+        RestrictedCaller {
+            approved: Arc::new(self.approved),
+            hooks: self.hooks.into(),
+        }
+    }
+}
+
+/// Binds and calls only approved functions of registered libraries,
+/// with their approved signatures.
+///
+/// A caller is built with a [`RestrictedCallerBuilder`], and cannot
+/// approve anything more once built; clones share its approved set.
+/// See the [module documentation](self).
+#[derive(Clone)]
+pub struct RestrictedCaller {
+    approved: Arc<ApprovedSet>,
+    hooks: Arc<[AuditHook]>,
+}
+
+impl fmt::Debug for RestrictedCaller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RestrictedCaller")
+            .field("approved", &sorted(&self.approved))
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl RestrictedCaller {
+    /// Creates a builder for a caller, which allows nothing until
+    /// functions are approved.
+    pub fn builder() -> RestrictedCallerBuilder {
+        RestrictedCallerBuilder::new()
+    }
+
+    /// Whether the function `symbol` of the library registered as
+    /// `library` is approved with the signature of `cif`.
+    pub fn is_allowed(&self, library: &str, symbol: &str, cif: &Cif) -> bool {
+        self.find(library, symbol, &cif.signature()).is_some()
+    }
+
+    /// Looks up the approved function `symbol` of the library registered
+    /// as `library`, and pairs it with its approved CIF, whose signature
+    /// is that of `cif`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the function is not approved with the signature of
+    /// `cif`, or cannot be found in its library.
+    pub fn bind(&self, library: &str, symbol: &str, cif: &Cif) -> Result<BoundFn, Error> {
+        let signature = cif.signature();
+        let result = self.bound(library, symbol, &signature);
+        self.audit(Action::Bind, library, symbol, &signature, &result);
+        result
+    }
+
+    /// Calls the approved function `symbol` of the library registered
+    /// as `library`, whose signature is that of `cif`, with the given
+    /// arguments.
+    ///
+    /// # Errors
+    ///
+    /// Fails, without calling anything, if the function is not approved
+    /// with the signature of `cif`, cannot be found in its library, or
+    /// its library has been [closed](Library::close_when_unused).
+    ///
+    /// # Safety
+    ///
+    /// As for [`Cif::call`]: the CIF must describe the function, and
+    /// `args` and `R` must match the CIF.
+    pub unsafe fn call<R>(
+        &self,
+        library: &str,
+        symbol: &str,
+        cif: &Cif,
+        args: &[Arg],
+    ) -> Result<R, Error> {
+        let signature = cif.signature();
+        let result = self.bound(library, symbol, &signature).and_then(|bound| {
+            if bound.is_closed() {
+                Err(library::Error::Closed.into())
+            } else {
+                Ok(bound)
+            }
+        });
+        self.audit(Action::Call, library, symbol, &signature, &result);
+        Ok(result?.call(args))
+    }
+
+    fn find(&self, library: &str, symbol: &str, signature: &Arc<str>) -> Option<&Approved> {
+        self.approved
+            .get(&(library.to_owned(), symbol.to_owned()))
+            .filter(|approved| approved.signature == *signature)
+            .map(|approved| &**approved)
+    }
+
+    fn bound(&self, library: &str, symbol: &str, signature: &Arc<str>) -> Result<BoundFn, Error> {
+        let approved = self
+            .find(library, symbol, signature)
+            .ok_or_else(|| Error::Denied {
+                library: library.to_owned(),
+                symbol: symbol.to_owned(),
+                signature: signature.clone(),
+            })?;
+        let bound = approved
+            .bound
+            .get_or_init(|| approved.library.bind(symbol, approved.cif.clone()));
+        Ok(bound.clone()?)
+    }
+
+    fn audit(
+        &self,
+        action: Action,
+        library: &str,
+        symbol: &str,
+        signature: &str,
+        result: &Result<BoundFn, Error>,
+    ) {
+        let event = AuditEvent {
+            action,
+            library,
+            symbol,
+            signature,
+            outcome: result.as_ref().map(|_| ()),
+        };
+        for hook in self.hooks.iter() {
+            hook(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::middle::{arg, Type};
+
+    fn unary(ty: Type) -> Cif {
+        Cif::new(vec![ty.clone()], ty)
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn allows_only_approved_triples() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let caller = {
+            let events = events.clone();
+            RestrictedCaller::builder()
+                .library("process", Library::this_process().unwrap())
+                .allow("process", "abs", &unary(Type::c_int()))
+                .allow("process", "no_such_function", &unary(Type::c_int()))
+                .on_audit(move |event| {
+                    events.lock().unwrap().push((
+                        event.action,
+                        event.symbol.to_owned(),
+                        event.is_allowed(),
+                    ))
+                })
+                .build()
+        };
+
+        // Approving `c_int` approves the same signature written as `i32`.
+        assert!(caller.is_allowed("process", "abs", &unary(Type::i32())));
+        let n: i32 =
+            unsafe { caller.call("process", "abs", &unary(Type::i32()), &[arg(&-3i32)]) }.unwrap();
+        assert_eq!(3, n);
+        let abs = caller
+            .bind("process", "abs", &unary(Type::c_int()))
+            .unwrap();
+        assert_eq!(5, unsafe { abs.call::<i32>(&[arg(&-5i32)]) });
+
+        // Another signature, function or library is denied.
+        for (library, symbol, cif) in [
+            ("process", "abs", unary(Type::i64())),
+            ("process", "labs", unary(Type::c_long())),
+            ("other", "abs", unary(Type::c_int())),
+        ] {
+            assert!(!caller.is_allowed(library, symbol, &cif));
+            assert_eq!(
+                Err(Error::Denied {
+                    library: library.into(),
+                    symbol: symbol.into(),
+                    signature: cif.signature(),
+                }),
+                caller.bind(library, symbol, &cif).map(|_| ())
+            );
+        }
+
+        // An approved function must still exist.
+        assert!(matches!(
+            caller.bind("process", "no_such_function", &unary(Type::c_int())),
+            Err(Error::Library(library::Error::Symbol { .. }))
+        ));
+
+        assert_eq!(
+            vec![
+                (Action::Call, "abs".to_owned(), true),
+                (Action::Bind, "abs".to_owned(), true),
+                (Action::Bind, "abs".to_owned(), false),
+                (Action::Bind, "labs".to_owned(), false),
+                (Action::Bind, "abs".to_owned(), false),
+                (Action::Bind, "no_such_function".to_owned(), false),
+            ],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn denied_calls_are_not_made() {
+        let caller = RestrictedCaller::builder()
+            .library("process", Library::this_process().unwrap())
+            .build();
+        let result =
+            unsafe { caller.call::<i32>("process", "abort", &Cif::new(vec![], Type::void()), &[]) };
+        assert_eq!(
+            "calling process:abort with signature `() -> void` is not allowed",
+            result.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn allow_needs_a_library() {
+        let _ = RestrictedCaller::builder().allow("missing", "abs", &unary(Type::i32()));
+    }
+}
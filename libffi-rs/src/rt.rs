@@ -0,0 +1,71 @@
+//! The subset of the API that is safe to use on real-time threads.
+//!
+//! Real-time code, such as the audio callback of a plugin, must not
+//! allocate, take locks or make system calls, since any of them may
+//! block for an unbounded time. Once set up, the operations listed here
+//! do none of these, so they may be used from such threads. Everything
+//! needed for them—CIFs, closures, argument values and result
+//! buffers—must be created beforehand, on another thread.
+//!
+//! That the operations do not allocate is checked by the `no_alloc`
+//! test, which runs each of them under a counting global allocator.
+//! That they do not lock or make system calls is checked by review: they
+//! only read the CIF and call through libffi, which prepares the call
+//! frame on the stack.
+//!
+//! This module contains no items; it exists to document the subset.
+//!
+//! # Calls
+//!
+//!   - [`Cif::call`](crate::middle::Cif::call) and
+//!     [`Cif::call_into`](crate::middle::Cif::call_into), given a
+//!     prepared [`TypedBuffer`](crate::middle::TypedBuffer);
+//!   - [`ArgBuffer::set`](crate::middle::ArgBuffer::set) and the calls
+//!     through an [`ArgBuffer`](crate::middle::ArgBuffer);
+//!   - [`BoundFn::call`](crate::middle::BoundFn::call) and
+//!     [`BoundFn::try_call`](crate::middle::BoundFn::try_call), which
+//!     check whether the function’s library has been closed with a
+//!     single atomic load;
+//!   - [`ReturnedFn::call`](crate::middle::ReturnedFn::call);
+//!   - [`arg`](crate::middle::arg) and the constructors of
+//!     [`middle::Arg`](crate::middle::Arg), which only take addresses.
+//!
+//! # Closures
+//!
+//! Calling the code pointer of an existing closure, by any means:
+//!
+//!   - the `call` methods of the function pointers returned by the
+//!     `code_ptr` methods of [`high`](crate::high) closures, such as
+//!     [`FnPtr1::call`](crate::high::FnPtr1::call), for immutable,
+//!     mutable, borrowing and once-only closures;
+//!   - the closures of `high::scalar`, with the `high-only` feature;
+//!   - the code pointers of [`middle::Closure`](crate::middle::Closure)
+//!     and [`middle::ClosureOnce`](crate::middle::ClosureOnce), called
+//!     from C or from Rust.
+//!
+//! The callback itself must of course be real-time safe as well. Calling
+//! a once-only closure a second time panics, which allocates.
+//!
+//! # Features
+//!
+//! With the `stats` feature, each closure call reads the monotonic clock
+//! twice to record its latency, which is usually, but not on every
+//! platform, done without a system call. With the `safe-reclaim`
+//! feature, each closure call updates an atomic counter twice. The
+//! `metrics` and `trampoline-registry` features do no work on calls.
+//! A closure pinned with
+//! [`Builder::thread_affinity`](crate::middle::Builder::thread_affinity)
+//! looks up the calling thread on each call, which may allocate on a
+//! thread’s first call.
+//!
+//! # Not included
+//!
+//! Creating or dropping CIFs, types and closures allocates, and may map
+//! or unmap executable memory. With the `deferred-reclaim` feature,
+//! dropping a closure is allocation- and lock-free instead, as described
+//! in `middle::reclaim`. Dynamic calls through
+//! [`high::call`](fn@crate::high::call) and [`ffi_call!`](crate::ffi_call)
+//! build their argument lists, and sometimes their CIFs, on the heap.
+//! The closures of [`middle::dispatch`](crate::middle::dispatch) and
+//! [`middle::notify`](crate::middle::notify) synchronize with other
+//! threads by design.
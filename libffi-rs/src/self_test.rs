@@ -0,0 +1,183 @@
+//! A run-time check that libffi works on the current machine.
+
+use std::os::raw::c_void;
+use std::{error, fmt, ptr};
+
+use crate::low;
+
+/// The steps of [`self_test`], in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// Preparing a CIF for `uint64_t (uint64_t, uint64_t)`.
+    PrepCif,
+    /// Calling a C function through the CIF.
+    Call,
+    /// Allocating a closure’s writable and executable memory.
+    ClosureAlloc,
+    /// Initializing the closure.
+    PrepClosure,
+    /// Calling the closure through the CIF.
+    ClosureCall,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Step::PrepCif => "preparing a CIF",
+            Step::Call => "calling through a CIF",
+            Step::ClosureAlloc => "allocating a closure",
+            Step::PrepClosure => "preparing a closure",
+            Step::ClosureCall => "calling a closure",
+        })
+    }
+}
+
+/// Why a step of [`self_test`] failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Failure {
+    /// libffi reported an error.
+    Libffi(low::Error),
+    /// libffi could not allocate closure memory. This usually means
+    /// that the system forbids writable executable memory, as under
+    /// some SELinux policies and hardened containers.
+    OutOfMemory,
+    /// The call returned, but with the wrong result.
+    WrongResult {
+        /// The correct result.
+        expected: u64,
+        /// The result returned.
+        actual: u64,
+    },
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Libffi(low::Error::Typedef) => f.write_str("libffi rejected the type"),
+            Failure::Libffi(low::Error::Abi) => f.write_str("libffi rejected the ABI"),
+            Failure::OutOfMemory => f.write_str("no executable memory is available"),
+            Failure::WrongResult { expected, actual } => {
+                write!(f, "expected {} but got {}", expected, actual)
+            }
+        }
+    }
+}
+
+/// The error returned by [`self_test`]: the step that failed, and why.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SelfTestError {
+    /// The step that failed.
+    pub step: Step,
+    /// Why it failed.
+    pub failure: Failure,
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "libffi self-test failed {}: {}", self.step, self.failure)
+    }
+}
+
+impl error::Error for SelfTestError {}
+
+extern "C" fn add(x: u64, y: u64) -> u64 {
+    x.wrapping_add(y)
+}
+
+unsafe extern "C" fn add_closure(
+    _cif: &low::ffi_cif,
+    result: &mut u64,
+    args: *const *const c_void,
+    offset: &u64,
+) {
+    let x = *(*args as *const u64);
+    let y = *(*args.add(1) as *const u64);
+    *result = x.wrapping_add(y).wrapping_add(*offset);
+}
+
+/// Checks that libffi works on the current machine.
+///
+/// Some deployments, such as locked-down containers and SELinux
+/// policies that forbid writable executable memory, break closures at
+/// run time although calls still work. This function prepares a trivial
+/// CIF, calls a C function through it, then creates a closure and calls
+/// it, so that a host can report such a problem before loading plugins
+/// that need libffi, instead of failing later. It reports the first
+/// step that fails.
+///
+/// A system that allocates closure memory but refuses to execute it
+/// may still crash the process, which this check cannot prevent.
+///
+/// # Examples
+///
+/// ```
+/// if let Err(error) = libffi::self_test() {
+///     eprintln!("{}", error);
+///     // Disable plugins, for instance.
+/// }
+/// ```
+pub fn self_test() -> Result<(), SelfTestError> {
+    let fail = |step, failure| SelfTestError { step, failure };
+    let check = |step, expected, actual| {
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(fail(step, Failure::WrongResult { expected, actual }))
+        }
+    };
+
+    unsafe {
+        let mut arg_types = [ptr::addr_of_mut!(low::types::uint64); 2];
+        let mut cif: low::ffi_cif = Default::default();
+        low::prep_cif(
+            &mut cif,
+            low::ffi_abi_FFI_DEFAULT_ABI,
+            2,
+            ptr::addr_of_mut!(low::types::uint64),
+            arg_types.as_mut_ptr(),
+        )
+        .map_err(|error| fail(Step::PrepCif, Failure::Libffi(error)))?;
+
+        let (x, y) = (40u64, 2u64);
+        let mut args = [
+            &x as *const u64 as *mut c_void,
+            &y as *const u64 as *mut c_void,
+        ];
+        let sum: u64 = low::call(&mut cif, low::CodePtr(add as *mut _), args.as_mut_ptr());
+        check(Step::Call, 42, sum)?;
+
+        let (alloc, code) = low::closure_alloc();
+        if alloc.is_null() {
+            return Err(fail(Step::ClosureAlloc, Failure::OutOfMemory));
+        }
+        let offset = 100u64;
+        let result = low::prep_closure(alloc, &mut cif, add_closure, &offset, code)
+            .map_err(|error| fail(Step::PrepClosure, Failure::Libffi(error)))
+            .map(|()| low::call::<u64>(&mut cif, code, args.as_mut_ptr()));
+        low::closure_free(alloc);
+        check(Step::ClosureCall, 142, result?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn passes() {
+        assert_eq!(Ok(()), self_test());
+    }
+
+    #[test]
+    fn errors_describe_the_step() {
+        let error = SelfTestError {
+            step: Step::ClosureAlloc,
+            failure: Failure::OutOfMemory,
+        };
+        assert_eq!(
+            "libffi self-test failed allocating a closure: \
+             no executable memory is available",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! The operating-system services the crate needs outside of libffi
+//! itself.
+//!
+//! Only the reporting of internal errors is left here: memory for type
+//! descriptions comes from Rust’s allocator, and dynamic loading, which
+//! has no portable equivalent, lives in [`library`](crate::library).
+
+// Checks for internal errors. They panic as usual, except with the
+// `min-size` feature, where they call `fail` instead, which needs none
+// of the formatting machinery.
+
+#[cfg(not(feature = "min-size"))]
+macro_rules! ffi_assert {
+    ( $cond:expr, $msg:literal ) => {
+        assert!($cond, $msg)
+    };
+}
+
+#[cfg(feature = "min-size")]
+macro_rules! ffi_assert {
+    ( $cond:expr, $msg:literal ) => {
+        if !$cond {
+            $crate::sys::fail($msg)
+        }
+    };
+}
+
+#[cfg(not(feature = "min-size"))]
+macro_rules! ffi_assert_eq {
+    ( $left:expr, $right:expr, $msg:literal ) => {
+        assert_eq!($left, $right, $msg)
+    };
+}
+
+#[cfg(feature = "min-size")]
+macro_rules! ffi_assert_eq {
+    ( $left:expr, $right:expr, $msg:literal ) => {
+        if $left != $right {
+            $crate::sys::fail($msg)
+        }
+    };
+}
+
+#[cfg(not(feature = "min-size"))]
+macro_rules! ffi_expect {
+    ( $result:expr, $msg:literal ) => {
+        $result.expect($msg)
+    };
+}
+
+#[cfg(feature = "min-size")]
+macro_rules! ffi_expect {
+    ( $result:expr, $msg:literal ) => {
+        match $result {
+            Ok(value) => value,
+            Err(_) => $crate::sys::fail($msg),
+        }
+    };
+}
+
+// Implements `Debug` by writing only the type’s name, with the
+// `min-size` feature; otherwise the type’s own implementation is used.
+#[cfg(feature = "min-size")]
+macro_rules! opaque_debug {
+    ( $type_:ident $( < $lt:lifetime $( , $param:ident )? > )? ) => {
+        impl $( <$lt $( , $param )?> )? core::fmt::Debug for $type_ $( <$lt $( , $param )?> )? {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+                f.write_str(stringify!($type_))
+            }
+        }
+    };
+}
+
+/// Writes `msg` to standard error and aborts the process.
+#[cfg(all(feature = "min-size", feature = "std"))]
+#[cold]
+pub(crate) fn fail(msg: &str) -> ! {
+    use std::io::Write;
+
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(msg.as_bytes());
+    let _ = stderr.write_all(b"\n");
+    std::process::abort()
+}
+
+/// Panics with `msg`, leaving what follows to the panic handler, since
+/// without `std` there is no standard error to write to or process to
+/// abort.
+#[cfg(all(feature = "min-size", not(feature = "std")))]
+#[cold]
+pub(crate) fn fail(msg: &str) -> ! {
+    panic!("{}", msg)
+}
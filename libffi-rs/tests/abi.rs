@@ -0,0 +1,374 @@
+//! Golden ABI tests for passing and returning tricky struct layouts.
+//!
+//! Each case in [`cases`] names a layout defined in `abi/reference.c`,
+//! and [`unions`] generates C unions of members of like kinds, described
+//! with `Type::union_`. Both are compiled with the system C compiler
+//! (`$CC`, or `cc`) when the tests run. The tests call the C functions
+//! through libffi and through libffi closures, and check the results
+//! against the C compiler’s. Downstream packagers can run them with
+//!
+//! ```text
+//! cargo test -p libffi --test abi
+//! ```
+//!
+//! When no C compiler for the target is available, as when
+//! cross-compiling, the tests fail, unless the `LIBFFI_SKIP_ABI_TESTS`
+//! environment variable is set, in which case they are skipped with a
+//! message.
+#![cfg(all(unix, feature = "libc"))]
+
+use std::fmt::{self, Write};
+use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+use std::sync::OnceLock;
+
+use libffi::library::Library;
+use libffi::low;
+use libffi::middle::{Arg, Cif, Closure, Type, TypedBuffer, Value};
+
+struct Case {
+    name: &'static str,
+    /// The layout, in the signature syntax of `Type::from_str`.
+    ty: &'static str,
+    value: Value,
+    /// What `bump_<name>` does to `value`, if not incrementing every
+    /// member.
+    bump: Option<fn(&Value) -> Value>,
+}
+
+fn fields(values: Vec<Value>) -> Value {
+    Value::Struct(values)
+}
+
+fn cases() -> Vec<Case> {
+    use Value::*;
+
+    fn case(name: &'static str, ty: &'static str, value: Value) -> Case {
+        Case {
+            name,
+            ty,
+            value,
+            bump: None,
+        }
+    }
+
+    vec![
+        case("hfa2f", "{f32, f32}", fields(vec![F32(1.5), F32(-2.25)])),
+        case(
+            "hfa3f",
+            "{f32, f32, f32}",
+            fields(vec![F32(1.5), F32(2.5), F32(3.5)]),
+        ),
+        case(
+            "hfa4f",
+            "{f32, f32, f32, f32}",
+            fields(vec![F32(1.5), F32(2.5), F32(3.5), F32(4.5)]),
+        ),
+        case("hfa2d", "{f64, f64}", fields(vec![F64(0.125), F64(1e100)])),
+        case(
+            "hfa4d",
+            "{f64, f64, f64, f64}",
+            fields(vec![F64(1.0), F64(2.0), F64(3.0), F64(4.0)]),
+        ),
+        case(
+            "hfa_nested",
+            "{{f32, f32}, f32}",
+            fields(vec![fields(vec![F32(1.0), F32(2.0)]), F32(3.0)]),
+        ),
+        case("mixed_fd", "{f32, f64}", fields(vec![F32(1.5), F64(2.5)])),
+        case("s1", "{u8}", fields(vec![U8(7)])),
+        case("s3", "{u8, u8, u8}", fields(vec![U8(1), U8(2), U8(254)])),
+        case(
+            "s5",
+            "{u8, u8, u8, u8, u8}",
+            fields(vec![U8(1), U8(2), U8(3), U8(4), U8(5)]),
+        ),
+        case("s_pad", "{u16, u8}", fields(vec![U16(1000), U8(200)])),
+        case(
+            "s6",
+            "{u16, u16, u16}",
+            fields(vec![U16(1), U16(2), U16(65000)]),
+        ),
+        case("fi", "{f32, i32}", fields(vec![F32(0.5), I32(-7)])),
+        case(
+            "ifg",
+            "{i32, f32, f32}",
+            fields(vec![I32(-1), F32(2.5), F32(3.5)]),
+        ),
+        case("cd", "{i8, f64}", fields(vec![I8(-100), F64(6.25)])),
+        case(
+            "big",
+            "{i64, i64, i64}",
+            fields(vec![I64(-1), I64(1 << 40), I64(i64::MAX - 1)]),
+        ),
+        case("u_if", "{u32}", fields(vec![U32(0xdead_beef)])),
+        Case {
+            name: "u_cd",
+            ty: "{u64}",
+            value: fields(vec![U64(2.5f64.to_bits())]),
+            bump: Some(|value| match value {
+                Struct(fields) => match fields[..] {
+                    [U64(bits)] => Struct(vec![U64((f64::from_bits(bits) + 1.0).to_bits())]),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            }),
+        },
+        Case {
+            name: "u_bs",
+            ty: "{u16, u16}",
+            value: fields(vec![U16(41), U16(0)]),
+            bump: Some(|value| match value {
+                Struct(fields) => match fields[..] {
+                    [U16(s), U16(pad)] => Struct(vec![U16(s + 1), U16(pad)]),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            }),
+        },
+    ]
+}
+
+// Increments every member of a value.
+fn bump(value: &Value) -> Value {
+    match value {
+        Value::U8(n) => Value::U8(n.wrapping_add(1)),
+        Value::I8(n) => Value::I8(n.wrapping_add(1)),
+        Value::U16(n) => Value::U16(n.wrapping_add(1)),
+        Value::I16(n) => Value::I16(n.wrapping_add(1)),
+        Value::U32(n) => Value::U32(n.wrapping_add(1)),
+        Value::I32(n) => Value::I32(n.wrapping_add(1)),
+        Value::U64(n) => Value::U64(n.wrapping_add(1)),
+        Value::I64(n) => Value::I64(n.wrapping_add(1)),
+        Value::F32(x) => Value::F32(x + 1.0),
+        Value::F64(x) => Value::F64(x + 1.0),
+        Value::Struct(fields) => Value::Struct(fields.iter().map(bump).collect()),
+        other => panic!("cannot bump {:?}", other),
+    }
+}
+
+// A member of a generated union: a C scalar type, the number of them
+// it is an array of (or 1 for a plain scalar), and its libffi type.
+type Member = (&'static str, usize, fn() -> Type);
+
+// The members that generated unions draw from, by kind.
+const INTEGERS: &[Member] = &[
+    ("uint8_t", 1, Type::u8),
+    ("uint8_t", 2, Type::u8),
+    ("uint8_t", 3, Type::u8),
+    ("uint16_t", 1, Type::u16),
+    ("uint16_t", 3, Type::u16),
+    ("uint32_t", 1, Type::u32),
+    ("uint32_t", 2, Type::u32),
+    ("uint32_t", 3, Type::u32),
+    ("uint64_t", 1, Type::u64),
+    ("uint64_t", 2, Type::u64),
+    ("uint64_t", 3, Type::u64),
+];
+const FLOATS: &[Member] = &[
+    ("float", 1, Type::f32),
+    ("float", 2, Type::f32),
+    ("float", 3, Type::f32),
+    ("float", 4, Type::f32),
+];
+const DOUBLES: &[Member] = &[("double", 1, Type::f64), ("double", 2, Type::f64)];
+
+fn member_type(&(_, count, ty): &Member) -> Type {
+    if count == 1 {
+        ty()
+    } else {
+        Type::array(ty(), count)
+    }
+}
+
+fn member_layout(member: &Member) -> (usize, usize) {
+    member_type(member).layout()
+}
+
+// Generates the pairs of members that `Type::union_` describes as C
+// does: members of one kind, either all integers or all floating point
+// of one precision, and integers at least as large as a floating-point
+// member they share the union with. Unions whose size is rounded up to
+// their alignment are left out, since their padding bytes need not
+// survive a call.
+fn unions() -> Vec<[Member; 2]> {
+    let mut pairs = vec![];
+    for kind in [INTEGERS, FLOATS, DOUBLES] {
+        for (i, &a) in kind.iter().enumerate() {
+            pairs.extend(kind[i + 1..].iter().map(|&b| [a, b]));
+        }
+    }
+    for &int in INTEGERS {
+        for &float in FLOATS.iter().chain(DOUBLES) {
+            if member_layout(&int).0 >= member_layout(&float).0 {
+                pairs.push([int, float]);
+            }
+        }
+    }
+    pairs.retain(|members| {
+        let (size, align) = members
+            .iter()
+            .map(member_layout)
+            .fold((0, 1), |(size, align), (s, a)| (size.max(s), align.max(a)));
+        size % align == 0
+    });
+    pairs
+}
+
+// Writes the C definitions of the generated unions, numbered in the
+// order of `unions()`. For each union `gu_N`, `bump_gu_N` increments
+// every byte, `apply_gu_N` is as for the cases of `abi/reference.c`,
+// and `layout_gu_N` holds the union’s size and alignment.
+fn union_source() -> String {
+    let mut source = String::from("#include <stddef.h>\n#include <stdint.h>\n\n");
+    for (n, members) in unions().iter().enumerate() {
+        source.push_str("typedef union {");
+        for (i, &(c_type, count, _)) in members.iter().enumerate() {
+            write!(source, " {} m{}", c_type, i).unwrap();
+            if count > 1 {
+                write!(source, "[{}]", count).unwrap();
+            }
+            source.push(';');
+        }
+        writeln!(source, " }} gu_{};", n).unwrap();
+        writeln!(
+            source,
+            "gu_{n} bump_gu_{n}(gu_{n} x) {{ \
+             for (size_t i = 0; i < sizeof x; i++) ((unsigned char *)&x)[i] += 1; \
+             return x; }}\n\
+             gu_{n} apply_gu_{n}(gu_{n} (*f)(gu_{n}), gu_{n} x) {{ return bump_gu_{n}(f(x)); }}\n\
+             const size_t layout_gu_{n}[2] = {{ sizeof(gu_{n}), _Alignof(gu_{n}) }};\n",
+            n = n
+        )
+        .unwrap();
+    }
+    source
+}
+
+// Fails the test, or with `LIBFFI_SKIP_ABI_TESTS` set, reports that it
+// is skipped.
+fn skip(reason: fmt::Arguments) {
+    if std::env::var_os("LIBFFI_SKIP_ABI_TESTS").is_none() {
+        panic!(
+            "cannot run the ABI tests: {}; set LIBFFI_SKIP_ABI_TESTS to skip them",
+            reason
+        );
+    }
+    eprintln!("skipping ABI tests: {}", reason);
+}
+
+// Compiles `abi/reference.c` and the generated unions into a shared
+// library, or returns `None` if there is no working C compiler and the
+// tests are to be skipped.
+fn compile_reference() -> Option<PathBuf> {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/abi/reference.c");
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let generated = tmp.join("abi_unions.c");
+    let output = tmp.join("libabi_reference.so");
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+
+    std::fs::write(&generated, union_source()).unwrap();
+    let status = Command::new(&compiler)
+        .args(["-shared", "-fPIC", "-O1", "-o"])
+        .arg(&output)
+        .arg(&source)
+        .arg(&generated)
+        .status();
+    match status {
+        Ok(status) if status.success() => Some(output),
+        other => {
+            skip(format_args!("`{}` failed: {:?}", compiler, other));
+            None
+        }
+    }
+}
+
+// Opens the compiled reference library, compiling it the first time.
+fn reference() -> Option<Library> {
+    static PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+    let path = PATH.get_or_init(compile_reference).as_ref()?;
+    match unsafe { Library::open(path) } {
+        Ok(library) => Some(library),
+        Err(error) => {
+            skip(format_args!("{}", error));
+            None
+        }
+    }
+}
+
+// A closure callback that returns its only argument, whose size is the
+// userdata.
+unsafe extern "C" fn identity(
+    _cif: &low::ffi_cif,
+    result: &mut u8,
+    args: *const *const c_void,
+    size: &usize,
+) {
+    ptr::copy_nonoverlapping(*args as *const u8, result, *size);
+}
+
+#[test]
+fn golden_layouts() {
+    let library = match reference() {
+        Some(library) => library,
+        None => return,
+    };
+
+    for case in cases() {
+        let ty: Type = case.ty.parse().unwrap();
+        let input = TypedBuffer::from_value(ty.clone(), &case.value).unwrap();
+        let expected = case.bump.unwrap_or(bump)(&case.value);
+
+        let bump_fn = library.symbol(&format!("bump_{}", case.name)).unwrap();
+        let cif = Cif::new(vec![ty.clone()], ty.clone());
+        let mut output = TypedBuffer::new(ty.clone());
+        unsafe { cif.call_into(bump_fn, &[input.arg()], &mut output) };
+        assert_eq!(expected, output.to_value(), "bump_{}", case.name);
+
+        let size = input.size();
+        let closure = Closure::new(Cif::new(vec![ty.clone()], ty.clone()), identity, &size);
+        let f = *closure.code_ptr() as *const c_void;
+        let apply_fn = library.symbol(&format!("apply_{}", case.name)).unwrap();
+        let cif = Cif::new(vec![Type::pointer(), ty.clone()], ty.clone());
+        let mut output = TypedBuffer::new(ty);
+        unsafe { cif.call_into(apply_fn, &[Arg::ptr(&f), input.arg()], &mut output) };
+        assert_eq!(expected, output.to_value(), "apply_{}", case.name);
+    }
+}
+
+#[test]
+fn generated_unions() {
+    let library = match reference() {
+        Some(library) => library,
+        None => return,
+    };
+
+    for (n, members) in unions().iter().enumerate() {
+        let name = format!("gu_{}", n);
+        let ty = Type::union_(members.iter().map(member_type).collect());
+        let layout = library.symbol(&format!("layout_{}", name)).unwrap();
+        let layout = unsafe { *(layout.as_ptr() as *const [usize; 2]) };
+        assert_eq!((layout[0], layout[1]), ty.layout(), "layout of {}", name);
+
+        let bytes: Vec<u8> = (0..layout[0]).map(|i| 0x10 + i as u8).collect();
+        let expected: Vec<u8> = bytes.iter().map(|b| b + 1).collect();
+        let input = TypedBuffer::from_bytes(ty.clone(), &bytes).unwrap();
+
+        let bump_fn = library.symbol(&format!("bump_{}", name)).unwrap();
+        let cif = Cif::new(vec![ty.clone()], ty.clone());
+        let mut output = TypedBuffer::new(ty.clone());
+        unsafe { cif.call_into(bump_fn, &[input.arg()], &mut output) };
+        assert_eq!(expected, output.as_bytes(), "bump_{}", name);
+
+        let size = input.size();
+        let closure = Closure::new(Cif::new(vec![ty.clone()], ty.clone()), identity, &size);
+        let f = *closure.code_ptr() as *const c_void;
+        let apply_fn = library.symbol(&format!("apply_{}", name)).unwrap();
+        let cif = Cif::new(vec![Type::pointer(), ty.clone()], ty.clone());
+        let mut output = TypedBuffer::new(ty);
+        unsafe { cif.call_into(apply_fn, &[Arg::ptr(&f), input.arg()], &mut output) };
+        assert_eq!(expected, output.as_bytes(), "apply_{}", name);
+    }
+}
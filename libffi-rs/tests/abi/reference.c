@@ -0,0 +1,93 @@
+/*
+ * Reference implementations for the golden ABI tests in tests/abi.rs.
+ *
+ * For each layout NAME, the C compiler defines
+ *
+ *   NAME bump_NAME(NAME x);
+ *       returns x with every member incremented by one, and
+ *   NAME apply_NAME(NAME (*f)(NAME), NAME x);
+ *       returns bump_NAME(f(x)),
+ *
+ * so that calling them through libffi checks that the layout is passed
+ * and returned correctly in both directions.
+ */
+
+#include <stdint.h>
+
+#define CASE(NAME, BODY)                                                    \
+    NAME bump_##NAME(NAME x) {                                              \
+        BODY;                                                               \
+        return x;                                                           \
+    }                                                                       \
+    NAME apply_##NAME(NAME (*f)(NAME), NAME x) { return bump_##NAME(f(x)); }
+
+/* Homogeneous floating-point aggregates, passed in SIMD registers on
+ * aarch64 (and split between SSE registers on x86-64). */
+
+typedef struct { float a, b; } hfa2f;
+CASE(hfa2f, x.a += 1; x.b += 1)
+
+typedef struct { float a, b, c; } hfa3f;
+CASE(hfa3f, x.a += 1; x.b += 1; x.c += 1)
+
+typedef struct { float a, b, c, d; } hfa4f;
+CASE(hfa4f, x.a += 1; x.b += 1; x.c += 1; x.d += 1)
+
+typedef struct { double a, b; } hfa2d;
+CASE(hfa2d, x.a += 1; x.b += 1)
+
+typedef struct { double a, b, c, d; } hfa4d;
+CASE(hfa4d, x.a += 1; x.b += 1; x.c += 1; x.d += 1)
+
+typedef struct { struct { float x, y; } p; float z; } hfa_nested;
+CASE(hfa_nested, x.p.x += 1; x.p.y += 1; x.z += 1)
+
+/* Mixed floating-point members: not an HFA. */
+
+typedef struct { float f; double d; } mixed_fd;
+CASE(mixed_fd, x.f += 1; x.d += 1)
+
+/* Small aggregates of odd sizes, passed in parts of registers. */
+
+typedef struct { uint8_t a; } s1;
+CASE(s1, x.a += 1)
+
+typedef struct { uint8_t a, b, c; } s3;
+CASE(s3, x.a += 1; x.b += 1; x.c += 1)
+
+typedef struct { uint8_t a, b, c, d, e; } s5;
+CASE(s5, x.a += 1; x.b += 1; x.c += 1; x.d += 1; x.e += 1)
+
+typedef struct { uint16_t a; uint8_t b; } s_pad;
+CASE(s_pad, x.a += 1; x.b += 1)
+
+typedef struct { uint16_t a, b, c; } s6;
+CASE(s6, x.a += 1; x.b += 1; x.c += 1)
+
+/* Integer and floating-point members sharing an eightbyte. */
+
+typedef struct { float f; int32_t i; } fi;
+CASE(fi, x.f += 1; x.i += 1)
+
+typedef struct { int32_t i; float f, g; } ifg;
+CASE(ifg, x.i += 1; x.f += 1; x.g += 1)
+
+typedef struct { int8_t c; double d; } cd;
+CASE(cd, x.c += 1; x.d += 1)
+
+/* Too large for registers on most targets. */
+
+typedef struct { int64_t a, b, c; } big;
+CASE(big, x.a += 1; x.b += 1; x.c += 1)
+
+/* Unions. libffi has no union type, so the tests describe each union
+ * by a struct of the same size, alignment, and register class. */
+
+typedef union { uint32_t i; float f; } u_if;
+CASE(u_if, x.i += 1)
+
+typedef union { char c; double d; } u_cd;
+CASE(u_cd, x.d += 1)
+
+typedef union { uint8_t bytes[3]; uint16_t s; } u_bs;
+CASE(u_bs, x.s += 1)
@@ -0,0 +1,210 @@
+//! Checks that calls and closure calls do not allocate.
+//!
+//! Callbacks from real-time threads, such as audio callbacks, must not
+//! allocate, so calling through a prepared CIF or a closure that has
+//! already been created must not touch the heap. These tests install a
+//! global allocator that counts the allocations made by the current
+//! thread, and exercise each operation listed in `libffi::rt` while
+//! counting.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::os::raw::c_void;
+
+use libffi::high::{CType, Closure1, Closure2, ClosureMut1, ClosureOnce1};
+use libffi::low;
+use libffi::middle::{
+    arg, ArgBuffer, BoundFn, Builder, Cif, CodePtr, ReturnedFn, Type, TypedBuffer,
+};
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // `try_with` fails only while the thread is being torn down.
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// Runs `body`, returning the number of allocations it made on this
+// thread.
+fn allocations(body: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    body();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+struct Frame {
+    left: f32,
+    right: f32,
+}
+
+unsafe impl CType for Frame {
+    fn reify() -> libffi::high::Type<Self> {
+        unsafe {
+            libffi::high::Type::structure(vec![
+                f32::reify().into_middle(),
+                f32::reify().into_middle(),
+            ])
+        }
+    }
+    type RetType = Self;
+}
+
+unsafe extern "C" fn add_one(
+    _cif: &low::ffi_cif,
+    result: &mut u64,
+    args: *const *const c_void,
+    _userdata: &(),
+) {
+    *result = *(*args as *const u64) + 1;
+}
+
+#[test]
+fn calls_do_not_allocate() {
+    assert_eq!(1, allocations(|| drop(Box::new(0u8))));
+
+    let gain = |f: Frame, k: f32| Frame {
+        left: f.left * k,
+        right: f.right * k,
+    };
+    let closure = Closure2::new(&gain);
+    let fun = closure.code_ptr();
+    let frame = Frame {
+        left: 0.5,
+        right: -0.25,
+    };
+    assert_eq!(
+        0,
+        allocations(|| assert_eq!(1.0, fun.call(frame, 2.0).left))
+    );
+
+    let mut total = 0u64;
+    let mut count = |x: u8| total += u64::from(x);
+    let closure = ClosureMut1::new(&mut count);
+    let fun = closure.code_ptr();
+    assert_eq!(
+        0,
+        allocations(|| {
+            for x in 0..100 {
+                fun.call(x);
+            }
+        })
+    );
+    drop(closure);
+    assert_eq!(4950, total);
+
+    let by_ref = |x: &u32| *x * 2;
+    let closure = Closure1::new_borrowed(&by_ref);
+    let fun = closure.code_ptr();
+    assert_eq!(0, allocations(|| assert_eq!(10, fun.call(5))));
+
+    let once = ClosureOnce1::new(|x: i16| i32::from(x) - 1);
+    let fun = once.code_ptr();
+    assert_eq!(0, allocations(|| assert_eq!(6, fun.call(7))));
+
+    let closure = Builder::new()
+        .arg(Type::u64())
+        .res(Type::u64())
+        .into_closure(add_one, &());
+    let fun: &unsafe extern "C" fn(u64) -> u64 = unsafe { closure.instantiate_code_ptr() };
+    assert_eq!(0, allocations(|| assert_eq!(42, unsafe { fun(41) })));
+
+    #[cfg(feature = "high-only")]
+    {
+        let double = |x: u32| x * 2;
+        let closure = libffi::high::scalar::ScalarClosure1::new(&double);
+        let fun = closure.code_ptr();
+        assert_eq!(0, allocations(|| assert_eq!(8, fun.call(4))));
+    }
+}
+
+extern "C" fn mix(a: i32, b: f64) -> f64 {
+    f64::from(a) + b
+}
+
+extern "C" fn get_mix() -> extern "C" fn(i32, f64) -> f64 {
+    mix
+}
+
+#[test]
+fn cif_calls_do_not_allocate() {
+    let cif = Cif::new(vec![Type::i32(), Type::f64()], Type::f64());
+    let fun = CodePtr(mix as *mut _);
+    let (a, b) = (2i32, 0.5f64);
+    let args = [arg(&a), arg(&b)];
+    assert_eq!(
+        0,
+        allocations(|| assert_eq!(2.5, unsafe { cif.call::<f64>(fun, &args) }))
+    );
+
+    let mut result = TypedBuffer::new(Type::f64());
+    assert_eq!(
+        0,
+        allocations(|| unsafe { cif.call_into(fun, &args, &mut result) })
+    );
+
+    let mut buffer = ArgBuffer::new(cif.clone());
+    assert_eq!(
+        0,
+        allocations(|| {
+            buffer.set(0, a).unwrap();
+            buffer.set(1, b).unwrap();
+            assert_eq!(2.5, unsafe { buffer.call::<f64>(fun) });
+            unsafe { buffer.call_into(fun, &mut result) };
+        })
+    );
+
+    let bound = BoundFn::new(cif.clone(), fun);
+    assert_eq!(
+        0,
+        allocations(|| {
+            assert_eq!(2.5, unsafe { bound.call::<f64>(&args) });
+            assert_eq!(Ok(2.5), unsafe { bound.try_call::<f64>(&args) });
+        })
+    );
+
+    let getter = Cif::new(vec![], Type::pointer());
+    let returned: ReturnedFn = unsafe { getter.call(CodePtr(get_mix as *mut _), &[]) };
+    assert_eq!(
+        0,
+        allocations(|| assert_eq!(2.5, unsafe { returned.call::<f64>(&cif, &args) }))
+    );
+}
+
+#[cfg(all(feature = "deferred-reclaim", not(feature = "trampoline-registry")))]
+#[test]
+fn deferred_drops_do_not_allocate() {
+    let closure = Builder::new()
+        .arg(Type::u64())
+        .res(Type::u64())
+        .into_closure(add_one, &());
+    let double = |x: u32| x * 2;
+    let high = Closure1::new(&double);
+    assert_eq!(
+        0,
+        allocations(|| {
+            drop(closure);
+            drop(high);
+        })
+    );
+}
@@ -12,6 +12,13 @@ use not_msvc::*;
 fn main() {
     if cfg!(feature = "system") {
         probe_and_link();
+    } else if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("wasm32") {
+        // The bundled libffi predates its WebAssembly port.
+        panic!(
+            "libffi-sys cannot build the bundled libffi for WebAssembly; \
+             enable the `system` feature and link a libffi (3.4.5 or later) \
+             built for the target with Emscripten"
+        );
     } else {
         build_and_link();
     }
@@ -104,11 +104,11 @@ pub fn configure_libffi(prefix: PathBuf, build_dir: &Path) {
     }
     command.env("CFLAGS", cflags);
 
-    for (k, v) in c_compiler.env().iter() {
+    for (k, v) in c_compiler.get_envs() {
         command.env(k, v);
     }
 
-    command.current_dir(&build_dir);
+    command.current_dir(build_dir);
 
     if cfg!(windows) {
         // When using MSYS2, OUT_DIR will be a Windows like path such as
@@ -363,3 +363,26 @@ mod loongarch64 {
 
 #[cfg(target_arch = "loongarch64")]
 pub use loongarch64::*;
+
+/// From libffi:src/wasm32/ffitarget.h, which is in libffi 3.4.5 and later.
+mod wasm32 {
+    use crate::ffi_abi;
+
+    pub const ffi_abi_FFI_FIRST_ABI: ffi_abi = 0;
+    pub const ffi_abi_FFI_WASM32: ffi_abi = 1;
+    pub const ffi_abi_FFI_WASM32_EMSCRIPTEN: ffi_abi = 2;
+    pub const ffi_abi_FFI_LAST_ABI: ffi_abi = 3;
+
+    #[cfg(target_os = "emscripten")]
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_WASM32_EMSCRIPTEN;
+    #[cfg(not(target_os = "emscripten"))]
+    pub const ffi_abi_FFI_DEFAULT_ABI: ffi_abi = ffi_abi_FFI_WASM32;
+
+    // The trampoline holds the closure's index in the function table
+    // rather than code.
+    pub const FFI_TRAMPOLINE_SIZE: usize = 4;
+    pub const FFI_NATIVE_RAW_API: u32 = 0;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use wasm32::*;
@@ -38,15 +38,26 @@
 //! to your `Cargo.toml` instead.
 //!
 //! This crate supports Rust version 1.32 and later.
+//!
+//! The bindings need only `core` when the default `std` feature is
+//! turned off, which takes the C types from `core::ffi` and so needs
+//! Rust 1.64 or later.
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(non_upper_case_globals)]
 #![allow(improper_ctypes)]
 #![allow(unused_imports)]
-
-use std::fmt::{self, Debug};
-use std::mem::zeroed;
+#![allow(unexpected_cfgs)]
+#![allow(clippy::module_inception)]
+#![allow(clippy::non_minimal_cfg)]
+
+#[cfg(not(feature = "std"))]
+use core::ffi::{c_char, c_int, c_long, c_schar, c_uint, c_ulong, c_ushort, c_void};
+use core::fmt::{self, Debug};
+use core::mem::zeroed;
+#[cfg(feature = "std")]
 use std::os::raw::{c_char, c_int, c_long, c_schar, c_uint, c_ulong, c_ushort, c_void};
 
 mod arch;
@@ -61,7 +72,7 @@ pub type ffi_type_enum = u32;
 
 pub const FFI_64_BIT_MAX: u64 = 9223372036854775807;
 pub const FFI_CLOSURES: u32 = 1;
-pub const FFI_SIZEOF_ARG: usize = std::mem::size_of::<c_long>();
+pub const FFI_SIZEOF_ARG: usize = core::mem::size_of::<c_long>();
 // NOTE: This only differs from FFI_SIZEOF_ARG on ILP platforms, which Rust does not support
 pub const FFI_SIZEOF_JAVA_RAW: usize = FFI_SIZEOF_ARG;
 
@@ -127,7 +138,11 @@ pub struct ffi_cif {
     pub vfp_nargs: c_ushort,
     #[cfg(all(target_arch = "arm"))]
     pub vfp_args: [c_schar; 16],
-    #[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+    #[cfg(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "wasm32"
+    ))]
     pub nfixedargs: c_uint,
     #[cfg(any(target_arch = "riscv", target_arch = "riscv64"))]
     pub riscv_nfixedargs: c_uint,
@@ -545,6 +560,7 @@ extern "C" {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::ptr::addr_of_mut;
 
     extern "C" fn add(x: u64, y: u64) -> u64 {
         x + y
@@ -555,13 +571,13 @@ mod test {
         unsafe {
             let mut cif: ffi_cif = Default::default();
             let mut arg_types: Vec<*mut ffi_type> =
-                vec![&mut ffi_type_uint64, &mut ffi_type_uint64];
+                vec![addr_of_mut!(ffi_type_uint64), addr_of_mut!(ffi_type_uint64)];
 
             let prep_status = ffi_prep_cif(
                 &mut cif,
                 ffi_abi_FFI_DEFAULT_ABI,
                 2,
-                &mut ffi_type_uint64,
+                addr_of_mut!(ffi_type_uint64),
                 arg_types.as_mut_ptr(),
             );
 